@@ -0,0 +1,223 @@
+//! Write-back buffer cache with LRU eviction.
+
+use alloc::{
+    boxed::Box, collections::btree_map::BTreeMap, format, string::String, sync::Arc, vec, vec::Vec,
+};
+use core::ops::Range;
+use drivers_base::{BlkDriver, BlkError, DeviceType, Driver};
+use lock_api::{Mutex, RawMutex};
+
+/// A cached block.
+struct CacheEntry {
+    data: Box<[u8]>,
+    /// The data is modified and not written back to the device.
+    dirty: bool,
+    /// The tick of the last access, used as the key of the lru list.
+    tick: u64,
+}
+
+struct CacheInner {
+    /// Cached blocks, indexed by block id.
+    blocks: BTreeMap<usize, CacheEntry>,
+    /// Block ids ordered by the last access, the first one is least recently used.
+    lru: BTreeMap<u64, usize>,
+    /// Increases on every access.
+    tick: u64,
+}
+
+/// Write-back buffer cache for a block device.
+///
+/// Writes only modify the cached blocks, dirty blocks are written to the device
+/// when they are evicted or [BlockCache::sync] is called.
+/// The cache implements [BlkDriver] itself, so it can be used in place of the device.
+pub struct BlockCache<R: RawMutex> {
+    id: String,
+    device: Arc<dyn BlkDriver>,
+    /// Max number of the cached blocks.
+    max_blocks: usize,
+    inner: Mutex<R, CacheInner>,
+}
+
+unsafe impl<R: RawMutex> Sync for BlockCache<R> {}
+unsafe impl<R: RawMutex> Send for BlockCache<R> {}
+
+impl<R: RawMutex> BlockCache<R> {
+    /// Create a new buffer cache holding at most max_blocks blocks of the device.
+    pub fn new(device: Arc<dyn BlkDriver>, max_blocks: usize) -> Arc<Self> {
+        assert!(max_blocks > 0);
+        Arc::new(Self {
+            id: format!("{}-cache", device.get_id()),
+            device,
+            max_blocks,
+            inner: Mutex::new(CacheInner {
+                blocks: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                tick: 0,
+            }),
+        })
+    }
+
+    /// Get the cached block, read it from the device if it isn't cached.
+    ///
+    /// The block isn't read if `fill` is false, the caller will overwrite the whole block.
+    fn get_block<'a>(
+        &self,
+        inner: &'a mut CacheInner,
+        block_id: usize,
+        fill: bool,
+    ) -> Result<&'a mut CacheEntry, BlkError> {
+        if block_id >= self.device.num_blocks() {
+            return Err(BlkError::InvalidParam);
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        if inner.blocks.contains_key(&block_id) {
+            let entry = inner.blocks.get_mut(&block_id).unwrap();
+            inner.lru.remove(&entry.tick);
+            inner.lru.insert(tick, block_id);
+            entry.tick = tick;
+            return Ok(entry);
+        }
+
+        if inner.blocks.len() >= self.max_blocks {
+            self.evict(inner)?;
+        }
+
+        let mut data = vec![0u8; self.device.block_size()].into_boxed_slice();
+        if fill {
+            self.device.read_blocks(block_id, &mut data)?;
+        }
+        inner.lru.insert(tick, block_id);
+        Ok(inner.blocks.entry(block_id).or_insert(CacheEntry {
+            data,
+            dirty: false,
+            tick,
+        }))
+    }
+
+    /// Evict the least recently used block, write it back if it is dirty.
+    fn evict(&self, inner: &mut CacheInner) -> Result<(), BlkError> {
+        let (&tick, &block_id) = match inner.lru.first_key_value() {
+            Some(lru) => lru,
+            None => return Ok(()),
+        };
+        let entry = &inner.blocks[&block_id];
+        if entry.dirty {
+            self.device.write_blocks(block_id, &entry.data)?;
+        }
+        inner.lru.remove(&tick);
+        inner.blocks.remove(&block_id);
+        Ok(())
+    }
+
+    /// Write all dirty blocks back to the device and flush the device.
+    pub fn sync(&self) -> Result<(), BlkError> {
+        let mut inner = self.inner.lock();
+        // Blocks are written in the order of block id.
+        for (block_id, entry) in inner.blocks.iter_mut().filter(|(_, x)| x.dirty) {
+            self.device.write_blocks(*block_id, &entry.data)?;
+            entry.dirty = false;
+        }
+        drop(inner);
+        self.device.flush()
+    }
+
+    /// Drop all cached blocks without writing them back.
+    pub fn invalidate(&self) {
+        let mut inner = self.inner.lock();
+        inner.blocks.clear();
+        inner.lru.clear();
+    }
+
+    /// Get the number of dirty blocks in the cache.
+    pub fn dirty_blocks(&self) -> usize {
+        self.inner
+            .lock()
+            .blocks
+            .values()
+            .filter(|x| x.dirty)
+            .count()
+    }
+
+    /// Get the block device under the cache.
+    #[inline]
+    pub fn device(&self) -> &Arc<dyn BlkDriver> {
+        &self.device
+    }
+}
+
+impl<R: RawMutex> Drop for BlockCache<R> {
+    fn drop(&mut self) {
+        if let Err(err) = self.sync() {
+            error!("can't write back the cache of {}: {:?}", self.id, err);
+        }
+    }
+}
+
+impl<R: RawMutex + 'static> Driver for BlockCache<R> {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::BLOCK(self.clone())
+    }
+}
+
+impl<R: RawMutex + 'static> BlkDriver for BlockCache<R> {
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlkError> {
+        let block_size = self.block_size();
+        if buf.len() % block_size != 0 {
+            return Err(BlkError::InvalidParam);
+        }
+        let mut inner = self.inner.lock();
+        for (i, chunk) in buf.chunks_exact_mut(block_size).enumerate() {
+            chunk.copy_from_slice(&self.get_block(&mut inner, block_id + i, true)?.data);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), BlkError> {
+        let block_size = self.block_size();
+        if buf.len() % block_size != 0 {
+            return Err(BlkError::InvalidParam);
+        }
+        let mut inner = self.inner.lock();
+        for (i, chunk) in buf.chunks_exact(block_size).enumerate() {
+            let entry = self.get_block(&mut inner, block_id + i, false)?;
+            entry.data.copy_from_slice(chunk);
+            entry.dirty = true;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    #[inline]
+    fn num_blocks(&self) -> usize {
+        self.device.num_blocks()
+    }
+
+    fn flush(&self) -> Result<(), BlkError> {
+        self.sync()
+    }
+
+    fn discard(&self, range: Range<usize>) -> Result<(), BlkError> {
+        let mut inner = self.inner.lock();
+        let discarded: Vec<_> = inner
+            .blocks
+            .range(range.clone())
+            .map(|(block_id, entry)| (*block_id, entry.tick))
+            .collect();
+        for (block_id, tick) in discarded {
+            inner.blocks.remove(&block_id);
+            inner.lru.remove(&tick);
+        }
+        drop(inner);
+        self.device.discard(range)
+    }
+}
@@ -0,0 +1,17 @@
+//! Block subsystem.
+//!
+//! Sits between the filesystems and the [BlkDriver]s, includes the
+//! buffer cache and the partition table parsing.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+pub mod cache;
+pub mod partition;
+
+pub use cache::BlockCache;
+pub use drivers_base::{BlkDriver, BlkError};
+pub use partition::{parse_partitions, Partition, PartitionKind};
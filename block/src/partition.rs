@@ -0,0 +1,379 @@
+//! MBR and GPT partition table parsing.
+//!
+//! Every partition is exposed as its own [BlkDriver], requests are
+//! translated to the blocks of the whole disk.
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::ops::Range;
+use drivers_base::{BlkDriver, BlkError, DeviceType, Driver};
+
+/// Offset of the partition entries in the MBR.
+const MBR_ENTRIES_OFFSET: usize = 0x1be;
+/// Size of a MBR partition entry.
+const MBR_ENTRY_SIZE: usize = 16;
+/// Signature at the end of the MBR.
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+/// Partition type of the GPT protective MBR.
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+/// Partition types of the extended partition.
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+/// Max logical partitions walked in the extended partition, avoids loops in broken tables.
+const MBR_MAX_LOGICAL: usize = 128;
+
+/// Signature of the GPT header.
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Max size of the partition entries array, avoids allocating huge buffers for broken tables.
+const GPT_MAX_ENTRIES_SIZE: usize = 0x10_0000;
+
+/// The type of the partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// MBR partition with the partition type.
+    Mbr(u8),
+    /// GPT partition with the partition type GUID.
+    Gpt([u8; 16]),
+}
+
+/// A partition of the block device.
+pub struct Partition {
+    id: String,
+    device: Arc<dyn BlkDriver>,
+    /// The partition number, starting from 1.
+    index: usize,
+    /// The blocks of the partition in the device.
+    blocks: Range<usize>,
+    kind: PartitionKind,
+    /// Partition name, only GPT partitions have names.
+    name: String,
+}
+
+impl Partition {
+    fn new(
+        device: &Arc<dyn BlkDriver>,
+        index: usize,
+        blocks: Range<usize>,
+        kind: PartitionKind,
+        name: String,
+    ) -> Self {
+        Self {
+            id: format!("{}p{}", device.get_id(), index),
+            device: device.clone(),
+            index,
+            blocks,
+            kind,
+            name,
+        }
+    }
+
+    /// The partition number, starting from 1.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The blocks of the partition in the whole device.
+    #[inline]
+    pub fn blocks(&self) -> Range<usize> {
+        self.blocks.clone()
+    }
+
+    /// The type of the partition.
+    #[inline]
+    pub fn kind(&self) -> PartitionKind {
+        self.kind
+    }
+
+    /// The partition name, only GPT partitions have names.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Translate the request to the blocks of the device.
+    fn translate(&self, block_id: usize, len: usize) -> Result<usize, BlkError> {
+        let block_size = self.device.block_size();
+        if len % block_size != 0 {
+            return Err(BlkError::InvalidParam);
+        }
+        match block_id + len / block_size <= self.blocks.len() {
+            true => Ok(self.blocks.start + block_id),
+            false => Err(BlkError::InvalidParam),
+        }
+    }
+}
+
+impl Driver for Partition {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::BLOCK(self.clone())
+    }
+}
+
+impl BlkDriver for Partition {
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlkError> {
+        self.device
+            .read_blocks(self.translate(block_id, buf.len())?, buf)
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), BlkError> {
+        self.device
+            .write_blocks(self.translate(block_id, buf.len())?, buf)
+    }
+
+    #[inline]
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    #[inline]
+    fn num_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn flush(&self) -> Result<(), BlkError> {
+        self.device.flush()
+    }
+
+    fn discard(&self, range: Range<usize>) -> Result<(), BlkError> {
+        if range.start > range.end || range.end > self.blocks.len() {
+            return Err(BlkError::InvalidParam);
+        }
+        self.device
+            .discard(self.blocks.start + range.start..self.blocks.start + range.end)
+    }
+}
+
+#[inline]
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// CRC32 (IEEE 802.3) used by the GPT header and entries.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(u32::MAX, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ 0xedb8_8320,
+            _ => crc >> 1,
+        })
+    })
+}
+
+/// Get the blocks `first..=last` of the partition entry, they must be in the usable blocks.
+///
+/// Returns None and warns about the broken entry otherwise.
+fn entry_blocks(
+    device: &Arc<dyn BlkDriver>,
+    index: usize,
+    first: u64,
+    last: u64,
+    usable: &Range<u64>,
+) -> Option<Range<usize>> {
+    if first <= last && usable.contains(&first) && usable.contains(&last) {
+        return Some(first as usize..last as usize + 1);
+    }
+    warn!(
+        "{}p{}: blocks {:#x}..={:#x} out of the usable blocks {:#x?}, skipped",
+        device.get_id(),
+        index,
+        first,
+        last,
+        usable
+    );
+    None
+}
+
+/// Read a block from the device.
+fn read_block(device: &Arc<dyn BlkDriver>, block_id: usize) -> Result<Vec<u8>, BlkError> {
+    let mut buffer = vec![0u8; device.block_size()];
+    device.read_blocks(block_id, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Parse the partition table of the device.
+///
+/// Both the MBR (includes the logical partitions in the extended partition)
+/// and the GPT are supported. Returns an empty list if the device
+/// doesn't have a partition table.
+pub fn parse_partitions(device: &Arc<dyn BlkDriver>) -> Result<Vec<Arc<Partition>>, BlkError> {
+    let mbr = read_block(device, 0)?;
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+    let is_gpt =
+        (0..4).any(|i| mbr[MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE + 4] == MBR_TYPE_GPT_PROTECTIVE);
+    let partitions = match is_gpt {
+        true => parse_gpt(device)?,
+        false => parse_mbr(device, &mbr)?,
+    };
+    partitions.iter().for_each(|x| {
+        info!(
+            "{}: {:?} blocks {:#x?} {}",
+            x.get_id(),
+            x.kind,
+            x.blocks,
+            x.name
+        )
+    });
+    Ok(partitions)
+}
+
+/// Parse the MBR partition entries.
+fn parse_mbr(device: &Arc<dyn BlkDriver>, mbr: &[u8]) -> Result<Vec<Arc<Partition>>, BlkError> {
+    let mut partitions = Vec::new();
+    // (type, start, count)
+    let entry = |data: &[u8], i: usize| {
+        let offset = MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE;
+        (
+            data[offset + 4],
+            read_u32(data, offset + 8) as u64,
+            read_u32(data, offset + 12) as u64,
+        )
+    };
+    // The first block is the MBR.
+    let usable = 1..device.num_blocks() as u64;
+
+    for i in 0..4 {
+        let (kind, start, count) = entry(mbr, i);
+        if kind == 0 || count == 0 {
+            continue;
+        }
+        let Some(blocks) = entry_blocks(device, i + 1, start, start + count - 1, &usable) else {
+            continue;
+        };
+        if !MBR_TYPE_EXTENDED.contains(&kind) {
+            partitions.push(Arc::new(Partition::new(
+                device,
+                i + 1,
+                blocks,
+                PartitionKind::Mbr(kind),
+                String::new(),
+            )));
+            continue;
+        }
+
+        // Walk the EBR chain, logical partitions are numbered from 5.
+        // The first entry is relative to the EBR, the second one links the
+        // next EBR and is relative to the extended partition.
+        // The logical partitions are in the extended partition.
+        let extended = blocks.start as u64..blocks.end as u64;
+        let mut ebr_block = start;
+        for index in 5..5 + MBR_MAX_LOGICAL {
+            let ebr = read_block(device, ebr_block as usize)?;
+            if ebr[510..512] != MBR_SIGNATURE {
+                break;
+            }
+            let (kind, offset, count) = entry(&ebr, 0);
+            let first = ebr_block + offset;
+            if kind != 0 && count != 0 {
+                if let Some(blocks) =
+                    entry_blocks(device, index, first, first + count - 1, &extended)
+                {
+                    partitions.push(Arc::new(Partition::new(
+                        device,
+                        index,
+                        blocks,
+                        PartitionKind::Mbr(kind),
+                        String::new(),
+                    )));
+                }
+            }
+            let (next_kind, next_offset, _) = entry(&ebr, 1);
+            if next_kind == 0 || next_offset == 0 {
+                break;
+            }
+            ebr_block = start + next_offset;
+            if !extended.contains(&ebr_block) {
+                warn!(
+                    "{}: EBR {:#x} out of the extended partition",
+                    device.get_id(),
+                    ebr_block
+                );
+                break;
+            }
+        }
+    }
+    Ok(partitions)
+}
+
+/// Parse the GPT header and partition entries.
+fn parse_gpt(device: &Arc<dyn BlkDriver>) -> Result<Vec<Arc<Partition>>, BlkError> {
+    let block_size = device.block_size();
+    let mut header = read_block(device, 1)?;
+    if &header[..8] != GPT_SIGNATURE {
+        warn!("{}: invalid GPT signature", device.get_id());
+        return Err(BlkError::InvalidParam);
+    }
+    let header_size = read_u32(&header, 12) as usize;
+    let header_crc = read_u32(&header, 16);
+    if header_size < 92 || header_size > block_size {
+        return Err(BlkError::InvalidParam);
+    }
+    // The CRC is calculated with the CRC field zeroed.
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != header_crc {
+        warn!("{}: GPT header CRC mismatch", device.get_id());
+        return Err(BlkError::InvalidParam);
+    }
+
+    // The usable blocks exclude the tables, the last one is inclusive.
+    let first_usable = read_u64(&header, 40);
+    let last_usable = read_u64(&header, 48);
+    let usable = first_usable
+        ..last_usable
+            .saturating_add(1)
+            .min(device.num_blocks() as u64);
+    let entries_block = read_u64(&header, 72) as usize;
+    let entries_count = read_u32(&header, 80) as usize;
+    let entry_size = read_u32(&header, 84) as usize;
+    let entries_crc = read_u32(&header, 88);
+    let entries_size = entries_count * entry_size;
+    if entry_size < 128 || entries_size > GPT_MAX_ENTRIES_SIZE {
+        return Err(BlkError::InvalidParam);
+    }
+
+    let mut entries = vec![0u8; entries_size.div_ceil(block_size) * block_size];
+    device.read_blocks(entries_block, &mut entries)?;
+    if crc32(&entries[..entries_size]) != entries_crc {
+        warn!("{}: GPT entries CRC mismatch", device.get_id());
+        return Err(BlkError::InvalidParam);
+    }
+
+    Ok(entries[..entries_size]
+        .chunks_exact(entry_size)
+        .enumerate()
+        .filter(|(_, entry)| entry[..16].iter().any(|x| *x != 0))
+        .filter_map(|(i, entry)| {
+            let blocks = entry_blocks(
+                device,
+                i + 1,
+                read_u64(entry, 32),
+                read_u64(entry, 40),
+                &usable,
+            )?;
+            // Partition name is encoded in UTF-16LE, ends with 0.
+            let name = char::decode_utf16(
+                entry[56..128]
+                    .chunks_exact(2)
+                    .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                    .take_while(|x| *x != 0),
+            )
+            .map(|x| x.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+            Some(Arc::new(Partition::new(
+                device,
+                i + 1,
+                blocks,
+                PartitionKind::Gpt(entry[..16].try_into().unwrap()),
+                name,
+            )))
+        })
+        .collect())
+}
@@ -0,0 +1,531 @@
+//! AHCI SATA driver.
+//!
+//! Every port with an ATA disk attached is a block device. Commands are
+//! issued one at a time through the first slot of the command list, the
+//! data is described by the PRD table of its command table.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+use core::{
+    cmp,
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use drivers_base::{BlkDriver, BlkError, DAlloc, DSched, DeviceType, Driver};
+use lock_api::{Mutex, RawMutex};
+use regs::{PortRegister, PxCMD, PxIS, PxSSTS, PxTFD, Register, CAP, GHC};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+
+mod regs;
+
+pub const SUPPORT_PCI_CLASS: (u8, u8, u8) = (0x01, 0x06, 0x01);
+
+/// Page size of the DMA buffers.
+const PAGE_SIZE: usize = 0x1000;
+/// Offset of the received FIS area in the command list page.
+const RECEIVED_FIS_OFFSET: usize = 0x400;
+/// Offset of the PRD table in the command table.
+const PRDT_OFFSET: usize = 0x80;
+/// Entries of the PRD table, the command table takes a page.
+const PRDT_LEN: usize = (PAGE_SIZE - PRDT_OFFSET) / 16;
+/// Max bytes of a PRD entry.
+const PRD_MAX_LEN: usize = 0x40_0000;
+/// Max bytes transferred by a command, a PRD entry is left for the unaligned start.
+const MAX_TRANSFER: usize = (PRDT_LEN - 1) * PAGE_SIZE;
+/// Max polls of the registers before timing out.
+const TIMEOUT_POLLS: usize = 0x100_0000;
+/// Time waiting for a command before timing out.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Signature of the ATA devices.
+const SIG_ATA: u32 = 0x0000_0101;
+/// Host to device register FIS.
+const FIS_TYPE_REG_H2D: u8 = 0x27;
+/// Length of the host to device register FIS in dwords.
+const FIS_REG_H2D_LEN: u32 = 5;
+/// The FIS is a command, not a control register update.
+const FIS_COMMAND: u8 = 1 << 7;
+/// The LBA bit of the device register.
+const DEVICE_LBA: u8 = 1 << 6;
+/// Bits of the command header.
+const HEADER_WRITE: u32 = 1 << 6;
+
+/// ATA commands.
+const ATA_READ_DMA_EXT: u8 = 0x25;
+const ATA_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_FLUSH_CACHE_EXT: u8 = 0xea;
+const ATA_IDENTIFY: u8 = 0xec;
+
+/// The index of the next disk, used in the device names.
+static NEXT_DISK: AtomicUsize = AtomicUsize::new(0);
+
+/// AHCI error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AhciError {
+    /// The port or the command doesn't finish in time.
+    Timeout,
+    /// The device fails the command with the task file data.
+    Device(u32),
+    /// The device doesn't support 48-bit addressing or its sector size.
+    Unsupported,
+}
+
+impl From<AhciError> for BlkError {
+    fn from(err: AhciError) -> Self {
+        match err {
+            AhciError::Unsupported => BlkError::Unsupported,
+            AhciError::Timeout | AhciError::Device(_) => BlkError::Io,
+        }
+    }
+}
+
+pub struct Ahci<R, D: DAlloc, S: DSched> {
+    regs: &'static Register,
+    irqs: Vec<u32>,
+    phantom: PhantomData<(R, D, S)>,
+}
+
+unsafe impl<R, D: DAlloc, S: DSched> Sync for Ahci<R, D, S> {}
+unsafe impl<R, D: DAlloc, S: DSched> Send for Ahci<R, D, S> {}
+
+impl<R: RawMutex, D: DAlloc, S: DSched> Ahci<R, D, S> {
+    /// Create the driver from the virtual address of the ABAR (BAR 5).
+    ///
+    /// The ports only raise interrupts if `irqs` isn't empty.
+    pub fn new(addr: usize, irqs: Vec<u32>) -> Result<Self, AhciError> {
+        let regs = unsafe { (addr as *const Register).as_ref().unwrap() };
+        regs.ghc.modify(GHC::AE::SET);
+        regs.ghc.modify(GHC::HR::SET);
+        (0..TIMEOUT_POLLS)
+            .find(|_| !regs.ghc.is_set(GHC::HR))
+            .ok_or(AhciError::Timeout)?;
+        // The reset clears the AHCI enable bit.
+        regs.ghc.modify(GHC::AE::SET);
+        info!(
+            "AHCI {:#x} with {} ports, {} command slots",
+            regs.vs.get(),
+            regs.cap.read(CAP::NP) + 1,
+            regs.cap.read(CAP::NCS) + 1
+        );
+        regs.is.set(u32::MAX);
+        if !irqs.is_empty() {
+            regs.ghc.modify(GHC::IE::SET);
+        }
+        Ok(Self {
+            regs,
+            irqs,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Start the implemented ports with ATA disks attached and identify the disks.
+    pub fn disks(&self) -> Vec<AhciDisk<R, D, S>> {
+        let implemented = self.regs.pi.get();
+        (0..32)
+            .filter(|port| implemented & (1 << port) != 0)
+            .filter_map(|port| {
+                let regs = &self.regs.ports[port];
+                let ssts = regs.ssts.extract();
+                if !ssts.matches_all(PxSSTS::DET::Present + PxSSTS::IPM::Active) {
+                    return None;
+                }
+                if regs.sig.get() != SIG_ATA {
+                    info!(
+                        "AHCI port {}: skip the device of signature {:#x}",
+                        port,
+                        regs.sig.get()
+                    );
+                    return None;
+                }
+                match AhciDisk::new(regs, port, !self.irqs.is_empty()) {
+                    Ok(disk) => Some(disk),
+                    Err(err) => {
+                        warn!("AHCI port {}: can't initialize the disk: {:?}", port, err);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> Driver for Ahci<R, D, S> {
+    fn get_id(&self) -> &str {
+        "ahci"
+    }
+
+    fn interrupts(&self) -> &[u32] {
+        &self.irqs
+    }
+
+    fn try_handle_interrupt(&self, irq: u32) -> bool {
+        if !self.irqs.contains(&irq) {
+            return false;
+        }
+        let pending = self.regs.is.get();
+        if pending == 0 {
+            return false;
+        }
+        // Clear the port status before the HBA status, the waiting tasks check the command slots.
+        for port in (0..32).filter(|port| pending & (1 << port) != 0) {
+            let is = &self.regs.ports[port].is;
+            is.set(is.get());
+        }
+        self.regs.is.set(pending);
+        S::wake();
+        true
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::None
+    }
+}
+
+/// An ATA disk attached to a port of the AHCI controller.
+pub struct AhciDisk<R, D: DAlloc, S: DSched> {
+    name: String,
+    regs: &'static PortRegister,
+    /// Physical address of the page of the command list and the received FIS.
+    list_paddr: usize,
+    /// Physical address of the command table of the slot 0.
+    table_paddr: usize,
+    /// Physical address of the page bouncing the unaligned buffers.
+    bounce: usize,
+    blocks: usize,
+    block_size: usize,
+    /// Whether the port raises interrupts, the commands are polled by spinning otherwise.
+    irq: bool,
+    lock: Mutex<R, ()>,
+    phantom: PhantomData<(D, S)>,
+}
+
+unsafe impl<R, D: DAlloc, S: DSched> Sync for AhciDisk<R, D, S> {}
+unsafe impl<R, D: DAlloc, S: DSched> Send for AhciDisk<R, D, S> {}
+
+impl<R: RawMutex, D: DAlloc, S: DSched> AhciDisk<R, D, S> {
+    fn new(regs: &'static PortRegister, port: usize, irq: bool) -> Result<Self, AhciError> {
+        let mut disk = Self {
+            name: format!(
+                "sd{}",
+                (b'a' + NEXT_DISK.fetch_add(1, Ordering::Relaxed) as u8) as char
+            ),
+            regs,
+            list_paddr: D::alloc(1),
+            table_paddr: D::alloc(1),
+            bounce: D::alloc(1),
+            blocks: 0,
+            block_size: 0x200,
+            irq,
+            lock: Mutex::new(()),
+            phantom: PhantomData,
+        };
+        disk.start()?;
+        if irq {
+            regs.ie.write(
+                PxIS::DHRS::SET
+                    + PxIS::PSS::SET
+                    + PxIS::SDBS::SET
+                    + PxIS::TFES::SET
+                    + PxIS::HBFS::SET
+                    + PxIS::HBDS::SET
+                    + PxIS::IFS::SET,
+            );
+        }
+        disk.identify()?;
+        info!(
+            "AHCI port {}: {} with {} blocks of {:#x} bytes",
+            port, disk.name, disk.blocks, disk.block_size
+        );
+        Ok(disk)
+    }
+
+    /// Stop the port, set up the command list and the received FIS area and start it again.
+    fn start(&self) -> Result<(), AhciError> {
+        let regs = self.regs;
+        regs.cmd.modify(PxCMD::ST::CLEAR);
+        Self::wait(|| !regs.cmd.is_set(PxCMD::CR))?;
+        regs.cmd.modify(PxCMD::FRE::CLEAR);
+        Self::wait(|| !regs.cmd.is_set(PxCMD::FR))?;
+
+        unsafe { (D::phys_to_virt(self.list_paddr) as *mut u8).write_bytes(0, PAGE_SIZE) };
+        unsafe { (D::phys_to_virt(self.table_paddr) as *mut u8).write_bytes(0, PAGE_SIZE) };
+        // Only the slot 0 is used, its command table never changes.
+        let header = D::phys_to_virt(self.list_paddr) as *mut u32;
+        unsafe {
+            header.add(2).write_volatile(self.table_paddr as u32);
+            header
+                .add(3)
+                .write_volatile((self.table_paddr as u64 >> 32) as u32);
+        }
+        let fis = self.list_paddr + RECEIVED_FIS_OFFSET;
+        regs.clb.set(self.list_paddr as u32);
+        regs.clbu.set((self.list_paddr as u64 >> 32) as u32);
+        regs.fb.set(fis as u32);
+        regs.fbu.set((fis as u64 >> 32) as u32);
+
+        regs.serr.set(u32::MAX);
+        regs.is.set(u32::MAX);
+        regs.cmd
+            .modify(PxCMD::FRE::SET + PxCMD::POD::SET + PxCMD::SUD::SET);
+        Self::wait(|| !regs.tfd.is_set(PxTFD::STS_BSY) && !regs.tfd.is_set(PxTFD::STS_DRQ))?;
+        regs.cmd.modify(PxCMD::ST::SET);
+        Ok(())
+    }
+
+    /// Poll until the condition holds.
+    fn wait(condition: impl Fn() -> bool) -> Result<(), AhciError> {
+        (0..TIMEOUT_POLLS)
+            .find(|_| condition())
+            .map(|_| ())
+            .ok_or(AhciError::Timeout)
+    }
+
+    /// Identify the disk, gets the number of sectors and the logical sector size.
+    fn identify(&mut self) -> Result<(), AhciError> {
+        let bounce = D::phys_to_virt(self.bounce);
+        let prds = self.fill_prdt(bounce, 0x200);
+        self.issue(ATA_IDENTIFY, 0, 0, prds, false)?;
+        let words = unsafe { core::slice::from_raw_parts(bounce as *const u16, 0x100) };
+
+        // 48-bit address feature set.
+        if words[83] & (1 << 10) == 0 {
+            return Err(AhciError::Unsupported);
+        }
+        self.blocks = (0..4).fold(0, |acc, i| acc | (words[100 + i] as usize) << (i * 16));
+        // The logical sector size in words is valid if the bit 12 is set.
+        if words[106] & 0xc000 == 0x4000 && words[106] & (1 << 12) != 0 {
+            self.block_size = (words[117] as usize | (words[118] as usize) << 16) * 2;
+        }
+        if !(0x200..=PAGE_SIZE).contains(&self.block_size) {
+            return Err(AhciError::Unsupported);
+        }
+        // The strings swap the bytes of every word.
+        let model: Vec<u8> = words[27..47].iter().flat_map(|x| x.to_be_bytes()).collect();
+        info!("{}: {}", self.name, String::from_utf8_lossy(&model).trim());
+        Ok(())
+    }
+
+    /// Describe the buffer in the PRD table, returns the number of entries.
+    ///
+    /// The buffer must be 2-byte aligned and take at most `PRDT_LEN` pages.
+    fn fill_prdt(&self, vaddr: usize, len: usize) -> usize {
+        let prdt = (D::phys_to_virt(self.table_paddr) + PRDT_OFFSET) as *mut u32;
+        let mut count = 0;
+        let mut offset = 0;
+        // The last entry, physical address and length.
+        let mut last: Option<(usize, usize)> = None;
+        while offset < len {
+            let addr = vaddr + offset;
+            let size = cmp::min(PAGE_SIZE - addr % PAGE_SIZE, len - offset);
+            let paddr = D::virt_to_phys(addr);
+            offset += size;
+            // Merge the physically contiguous pages.
+            last = match last {
+                Some((start, last_len))
+                    if start + last_len == paddr && last_len + size <= PRD_MAX_LEN =>
+                {
+                    Some((start, last_len + size))
+                }
+                Some(entry) => {
+                    Self::write_prd(prdt, count, entry);
+                    count += 1;
+                    Some((paddr, size))
+                }
+                None => Some((paddr, size)),
+            };
+        }
+        if let Some(entry) = last {
+            Self::write_prd(prdt, count, entry);
+            count += 1;
+        }
+        count
+    }
+
+    /// Write the PRD entry of the physical address and the byte count.
+    fn write_prd(prdt: *mut u32, index: usize, (paddr, len): (usize, usize)) {
+        unsafe {
+            let entry = prdt.add(index * 4);
+            entry.write_volatile(paddr as u32);
+            entry.add(1).write_volatile((paddr as u64 >> 32) as u32);
+            entry.add(2).write_volatile(0);
+            // Byte count, 0's based.
+            entry.add(3).write_volatile(len as u32 - 1);
+        }
+    }
+
+    /// Issue the ATA command through the slot 0 and wait for its completion.
+    ///
+    /// The task sleeps between polls and it is woken up by the interrupt
+    /// if the controller has irqs, otherwise it spins.
+    fn issue(
+        &self,
+        command: u8,
+        lba: usize,
+        sectors: usize,
+        prds: usize,
+        write: bool,
+    ) -> Result<(), AhciError> {
+        let mut fis = [0u8; 20];
+        fis[0] = FIS_TYPE_REG_H2D;
+        fis[1] = FIS_COMMAND;
+        fis[2] = command;
+        fis[4..7].copy_from_slice(&lba.to_le_bytes()[0..3]);
+        fis[7] = DEVICE_LBA;
+        fis[8..11].copy_from_slice(&lba.to_le_bytes()[3..6]);
+        // 0 means 65536 sectors.
+        fis[12..14].copy_from_slice(&(sectors as u16).to_le_bytes());
+        unsafe {
+            let table = D::phys_to_virt(self.table_paddr) as *mut u8;
+            ptr::copy_nonoverlapping(fis.as_ptr(), table, fis.len());
+            let header = D::phys_to_virt(self.list_paddr) as *mut u32;
+            let flags = match write {
+                true => HEADER_WRITE,
+                false => 0,
+            };
+            header.write_volatile(FIS_REG_H2D_LEN | flags | (prds as u32) << 16);
+            // Bytes transferred, updated by the HBA.
+            header.add(1).write_volatile(0);
+        }
+        fence(Ordering::SeqCst);
+
+        let regs = self.regs;
+        regs.is.set(u32::MAX);
+        regs.ci.set(1);
+        let mut done = false;
+        let deadline = S::now() + COMMAND_TIMEOUT;
+        while S::now() < deadline {
+            if regs.is.is_set(PxIS::TFES) || regs.tfd.is_set(PxTFD::STS_ERR) {
+                break;
+            }
+            if regs.ci.get() & 1 == 0 {
+                done = true;
+                break;
+            }
+            match self.irq {
+                true => S::sleep(),
+                false => core::hint::spin_loop(),
+            }
+        }
+        fence(Ordering::SeqCst);
+        let tfd = regs.tfd.get();
+        if regs.tfd.is_set(PxTFD::STS_ERR) || regs.is.is_set(PxIS::TFES) {
+            warn!(
+                "{}: command {:#x} fails, task file {:#x}",
+                self.name, command, tfd
+            );
+            // Restart the port to clear the error.
+            regs.is.set(u32::MAX);
+            self.start()?;
+            return Err(AhciError::Device(tfd));
+        }
+        match done {
+            true => Ok(()),
+            false => Err(AhciError::Timeout),
+        }
+    }
+
+    /// Check the block range of the buffer.
+    fn check_range(&self, block_id: usize, len: usize) -> Result<(), BlkError> {
+        match len % self.block_size == 0 && block_id + len / self.block_size <= self.blocks {
+            true => Ok(()),
+            false => Err(BlkError::InvalidParam),
+        }
+    }
+
+    /// Read or write the blocks, the buffer is split into commands by the PRD table size.
+    ///
+    /// Buffers not 2-byte aligned are bounced through a page.
+    fn transfer(
+        &self,
+        block_id: usize,
+        vaddr: usize,
+        len: usize,
+        write: bool,
+    ) -> Result<(), AhciError> {
+        let _lock = self.lock.lock();
+        let bounce = D::phys_to_virt(self.bounce);
+        let mut offset = 0;
+        while offset < len {
+            let addr = vaddr + offset;
+            let aligned = addr % 2 == 0;
+            let max_len = match aligned {
+                true => MAX_TRANSFER,
+                false => PAGE_SIZE,
+            };
+            let size = cmp::min(len - offset, max_len) / self.block_size * self.block_size;
+            if !aligned && write {
+                unsafe { ptr::copy_nonoverlapping(addr as *const u8, bounce as *mut u8, size) };
+            }
+            let prds = match aligned {
+                true => self.fill_prdt(addr, size),
+                false => self.fill_prdt(bounce, size),
+            };
+            let command = match write {
+                true => ATA_WRITE_DMA_EXT,
+                false => ATA_READ_DMA_EXT,
+            };
+            let lba = block_id + offset / self.block_size;
+            self.issue(command, lba, size / self.block_size, prds, write)?;
+            if !aligned && !write {
+                unsafe { ptr::copy_nonoverlapping(bounce as *const u8, addr as *mut u8, size) };
+            }
+            offset += size;
+        }
+        Ok(())
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> Driver for AhciDisk<R, D, S> {
+    fn get_id(&self) -> &str {
+        &self.name
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::BLOCK(self.clone())
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> BlkDriver for AhciDisk<R, D, S> {
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlkError> {
+        self.check_range(block_id, buf.len())?;
+        self.transfer(block_id, buf.as_mut_ptr() as usize, buf.len(), false)?;
+        Ok(())
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), BlkError> {
+        self.check_range(block_id, buf.len())?;
+        self.transfer(block_id, buf.as_ptr() as usize, buf.len(), true)?;
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.blocks
+    }
+
+    fn flush(&self) -> Result<(), BlkError> {
+        let _lock = self.lock.lock();
+        self.issue(ATA_FLUSH_CACHE_EXT, 0, 0, 0, false)?;
+        Ok(())
+    }
+}
+
+impl<R, D: DAlloc, S: DSched> Drop for AhciDisk<R, D, S> {
+    fn drop(&mut self) {
+        self.regs.cmd.modify(PxCMD::ST::CLEAR + PxCMD::FRE::CLEAR);
+        D::dealloc(self.list_paddr, 1);
+        D::dealloc(self.table_paddr, 1);
+        D::dealloc(self.bounce, 1);
+    }
+}
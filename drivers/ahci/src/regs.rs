@@ -0,0 +1,127 @@
+//! HBA memory registers of AHCI.
+//!
+//! See the Serial ATA AHCI Specification, HBA Memory Registers.
+
+use tock_registers::{
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+register_structs! {
+    pub(crate) Register {
+        (0x00 => pub cap: ReadOnly<u32, CAP::Register>),
+        (0x04 => pub ghc: ReadWrite<u32, GHC::Register>),
+        // Pending interrupts of the ports, write 1 to clear.
+        (0x08 => pub is: ReadWrite<u32>),
+        // Ports implemented.
+        (0x0c => pub pi: ReadOnly<u32>),
+        (0x10 => pub vs: ReadOnly<u32>),
+        (0x14 => _reserved),
+        (0x100 => pub ports: [PortRegister; 32]),
+        (0x1100 => @END),
+    }
+}
+
+register_structs! {
+    pub(crate) PortRegister {
+        (0x00 => pub clb: ReadWrite<u32>),
+        (0x04 => pub clbu: ReadWrite<u32>),
+        (0x08 => pub fb: ReadWrite<u32>),
+        (0x0c => pub fbu: ReadWrite<u32>),
+        (0x10 => pub is: ReadWrite<u32, PxIS::Register>),
+        (0x14 => pub ie: ReadWrite<u32, PxIS::Register>),
+        (0x18 => pub cmd: ReadWrite<u32, PxCMD::Register>),
+        (0x1c => _reserved0),
+        (0x20 => pub tfd: ReadOnly<u32, PxTFD::Register>),
+        (0x24 => pub sig: ReadOnly<u32>),
+        (0x28 => pub ssts: ReadOnly<u32, PxSSTS::Register>),
+        (0x2c => pub sctl: ReadWrite<u32>),
+        (0x30 => pub serr: ReadWrite<u32>),
+        (0x34 => pub sact: ReadWrite<u32>),
+        // Commands issued, a bit for every command slot.
+        (0x38 => pub ci: ReadWrite<u32>),
+        (0x3c => _reserved1),
+        (0x80 => @END),
+    }
+}
+
+register_bitfields! [
+    u32,
+    // HBA capabilities.
+    pub CAP [
+        // Number of ports, 0's based.
+        NP OFFSET(0) NUMBITS(5) [],
+        // Number of command slots, 0's based.
+        NCS OFFSET(8) NUMBITS(5) [],
+        // Supports 64-bit addressing.
+        S64A OFFSET(31) NUMBITS(1) [],
+    ],
+    // Global HBA control.
+    pub GHC [
+        // HBA reset.
+        HR OFFSET(0) NUMBITS(1) [],
+        // Interrupt enable.
+        IE OFFSET(1) NUMBITS(1) [],
+        // AHCI enable.
+        AE OFFSET(31) NUMBITS(1) [],
+    ],
+    // Port interrupt status and enable.
+    pub PxIS [
+        // Device to host register FIS received.
+        DHRS OFFSET(0) NUMBITS(1) [],
+        // PIO setup FIS received.
+        PSS OFFSET(1) NUMBITS(1) [],
+        // DMA setup FIS received.
+        DSS OFFSET(2) NUMBITS(1) [],
+        // Set device bits FIS received.
+        SDBS OFFSET(3) NUMBITS(1) [],
+        // Descriptor processed.
+        DPS OFFSET(5) NUMBITS(1) [],
+        // Port connect change.
+        PCS OFFSET(6) NUMBITS(1) [],
+        // Interface fatal error.
+        IFS OFFSET(27) NUMBITS(1) [],
+        // Host bus data error.
+        HBDS OFFSET(28) NUMBITS(1) [],
+        // Host bus fatal error.
+        HBFS OFFSET(29) NUMBITS(1) [],
+        // Task file error.
+        TFES OFFSET(30) NUMBITS(1) [],
+    ],
+    // Port command and status.
+    pub PxCMD [
+        // Start processing the command list.
+        ST OFFSET(0) NUMBITS(1) [],
+        // Spin-up device.
+        SUD OFFSET(1) NUMBITS(1) [],
+        // Power on device.
+        POD OFFSET(2) NUMBITS(1) [],
+        // FIS receive enable.
+        FRE OFFSET(4) NUMBITS(1) [],
+        // FIS receive running.
+        FR OFFSET(14) NUMBITS(1) [],
+        // Command list running.
+        CR OFFSET(15) NUMBITS(1) [],
+    ],
+    // Port task file data, the ATA status and error registers.
+    pub PxTFD [
+        STS_ERR OFFSET(0) NUMBITS(1) [],
+        STS_DRQ OFFSET(3) NUMBITS(1) [],
+        STS_BSY OFFSET(7) NUMBITS(1) [],
+        ERR OFFSET(8) NUMBITS(8) [],
+    ],
+    // Port SATA status.
+    pub PxSSTS [
+        // Device detection.
+        DET OFFSET(0) NUMBITS(4) [
+            NoDevice = 0,
+            NoPhy = 1,
+            Present = 3
+        ],
+        SPD OFFSET(4) NUMBITS(4) [],
+        // Interface power management.
+        IPM OFFSET(8) NUMBITS(4) [
+            Active = 1
+        ],
+    ],
+];
@@ -1,10 +1,11 @@
 #![no_std]
 
+#[macro_use]
 extern crate alloc;
 
-use core::fmt::Debug;
+use core::{fmt::Debug, ops::Range, time::Duration};
 
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 
 /// Device Type Enumerator
 pub enum DeviceType {
@@ -48,36 +49,295 @@ pub trait RtcDriver: Driver {
     fn read(&self) -> u64;
 }
 
+/// Block device error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlkError {
+    /// The device failed to complete the request.
+    Io,
+    /// The block range or the buffer is invalid.
+    InvalidParam,
+    /// The operation is not supported by the device.
+    Unsupported,
+    /// The device is not ready to handle the request.
+    NotReady,
+}
+
 /// Block device driver trait.
+///
+/// Block ids are counted in [BlkDriver::block_size], and the buffer length
+/// should be a multiple of it.
 pub trait BlkDriver: Driver {
-    fn read_blocks(&self, block_id: usize, buf: &mut [u8]);
-    fn write_blocks(&self, block_id: usize, buf: &[u8]);
+    /// Read blocks starting from block_id to the buffer.
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlkError>;
+
+    /// Write the buffer to blocks starting from block_id.
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), BlkError>;
+
+    /// Get the size of a block in bytes.
+    fn block_size(&self) -> usize {
+        0x200
+    }
+
+    /// Get the number of blocks of the device.
+    fn num_blocks(&self) -> usize;
+
+    /// Get the capacity of the device in bytes.
     fn capacity(&self) -> usize {
-        0
+        self.num_blocks() * self.block_size()
+    }
+
+    /// Flush the volatile write cache, written blocks are durable after it returns.
+    ///
+    /// Devices without a write cache don't need to implement it.
+    fn flush(&self) -> Result<(), BlkError> {
+        Ok(())
+    }
+
+    /// Discard the blocks in the range, the contents of them are undefined after it.
+    fn discard(&self, _range: Range<usize>) -> Result<(), BlkError> {
+        Err(BlkError::Unsupported)
     }
 }
 
-#[derive(Debug)]
+/// Net device error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NetError {
+    /// No packet is received.
     NoData,
+    /// The transmit queue is full, try again after the device sends packets.
+    Busy,
+    /// The packet is larger than the device can send.
+    TooLarge,
+    /// The offload is not supported by the device.
+    Unsupported,
+    /// The device failed to handle the packet.
+    Io,
+}
+
+/// Offloads supported by the net device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetOffloads {
+    /// The device computes the TCP/UDP checksums of the sent packets.
+    pub tx_csum: bool,
+    /// The device verifies the TCP/UDP checksums of the received packets.
+    pub rx_csum: bool,
+    /// The device segments the sent TCP packets over IPv4.
+    pub tso4: bool,
+    /// The device segments the sent TCP packets over IPv6.
+    pub tso6: bool,
+}
+
+/// The offloads requested for a sent packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxOffload {
+    /// The checksum is computed from csum_start to the end of the packet,
+    /// and stored at csum_start + csum_offset. The field in the packet
+    /// should hold the checksum of the pseudo header.
+    pub csum_start: u16,
+    pub csum_offset: u16,
+    /// Segment the TCP packet, None if the packet is sent as is.
+    pub tso: Option<Tso>,
+}
+
+/// TCP segmentation of a sent packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tso {
+    pub ipv6: bool,
+    /// The length of the ethernet, IP and TCP headers.
+    pub header_len: u16,
+    /// The max payload of every segment.
+    pub mss: u16,
+}
+
+/// A packet buffer of the net driver.
+///
+/// The packet is a range of the buffer, drivers reserve the space before
+/// the packet for their headers.
+pub struct NetBuf {
+    buf: Vec<u8>,
+    offset: usize,
+    len: usize,
+    /// The device verified the checksums of the received packet.
+    pub csum_valid: bool,
+}
+
+impl NetBuf {
+    /// Create a buffer with the capacity, the packet is empty.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buf: vec![0; capacity],
+            offset: 0,
+            len: 0,
+            csum_valid: false,
+        }
+    }
+
+    /// Get the capacity of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Get the whole buffer.
+    pub fn buffer(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// Set the range of the packet in the buffer.
+    pub fn set_packet(&mut self, offset: usize, len: usize) {
+        assert!(offset + len <= self.buf.len());
+        self.offset = offset;
+        self.len = len;
+    }
+
+    pub fn packet(&self) -> &[u8] {
+        &self.buf[self.offset..self.offset + self.len]
+    }
+
+    pub fn packet_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[self.offset..self.offset + self.len]
+    }
 }
 
 /// Net Interface Card
+///
+/// Sending and receiving don't block, the device interrupts wake up the
+/// tasks sleeping in [DSched::sleep] once packets are received or sent.
 pub trait NetDriver: Driver {
-    fn recv(&self, buf: &mut [u8]) -> Result<usize, NetError>;
+    /// Take a received packet.
+    ///
+    /// Give the buffer back by [NetDriver::recycle] after the packet is handled.
+    fn recv(&self) -> Result<NetBuf, NetError>;
+
+    /// Give the buffer of a received packet back to the driver.
+    fn recycle(&self, _buf: NetBuf) {}
+
+    /// Send the packet.
     fn send(&self, buf: &[u8]) -> Result<(), NetError>;
+
+    /// Send the packet with the offloads.
+    fn send_offload(&self, _buf: &[u8], _offload: &TxOffload) -> Result<(), NetError> {
+        Err(NetError::Unsupported)
+    }
+
+    /// Get the MAC address of the device.
+    fn mac_address(&self) -> [u8; 6];
+
+    /// Check whether the link is up.
+    fn link_up(&self) -> bool {
+        true
+    }
+
+    /// Check whether the device is a loopback, the sent frames are received
+    /// by itself.
+    fn is_loopback(&self) -> bool {
+        false
+    }
+
+    /// Get the offloads supported by the device.
+    fn offloads(&self) -> NetOffloads {
+        NetOffloads::default()
+    }
 }
 
 /// Interrupt controller driver trait.
 pub trait IntDriver: Driver {
+    /// Register the driver as a handler of the irq, and unmask the irq.
     fn register_irq(&self, irq: u32, driver: Arc<dyn Driver>);
+
+    /// Claim the pending interrupts and dispatch them through
+    /// [Driver::try_handle_interrupt] of the registered drivers.
+    fn handle_irq(&self);
+
+    /// Allocate an irq raised by a message signalled interrupt.
+    ///
+    /// Returns the irq and the message the device writes to raise it,
+    /// None if the controller doesn't support MSIs or runs out of irqs.
+    fn alloc_msi(&self) -> Option<(u32, MsiMessage)> {
+        None
+    }
+
+    /// Release the irq of [IntDriver::alloc_msi] the device can't use.
+    fn free_msi(&self, _irq: u32) {}
+}
+
+/// The message of a message signalled interrupt,
+/// the device writes the data to the address to raise the interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    /// The cpu physical address written by the device.
+    pub addr: u64,
+    pub data: u32,
+}
+
+/// An input event, the type, code and value follow the Linux evdev codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// The time since boot when the event is received.
+    pub time: Duration,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// The identity of an input device, the same as the Linux `input_id`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputId {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+/// The range of an absolute axis, the same as the Linux `input_absinfo`
+/// without the current value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AbsInfo {
+    pub min: i32,
+    pub max: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
 }
 
 /// Input driver Trait.
+///
+/// Events are queued by the interrupt handler and taken by [InputDriver::read_event].
 pub trait InputDriver: Driver {
-    fn read_event(&self) -> u64;
+    /// Take the oldest pending event, None if there is no event.
+    fn read_event(&self) -> Option<InputEvent>;
+
+    /// Move the events reported by the device to the pending events.
     fn handle_irq(&self);
+
+    /// Whether there is no pending event.
     fn is_empty(&self) -> bool;
+
+    /// Get the name of the device.
+    fn name(&self) -> &str {
+        self.get_id()
+    }
+
+    /// Get the identity of the device.
+    fn input_id(&self) -> InputId {
+        InputId::default()
+    }
+
+    /// Get the bitmap of the codes supported by the event type.
+    ///
+    /// The event type 0 gets the bitmap of the supported event types.
+    fn event_bits(&self, _event_type: u8) -> &[u8] {
+        &[]
+    }
+
+    /// Get the bitmap of the device properties.
+    fn prop_bits(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Get the range of the absolute axis.
+    fn abs_info(&self, _axis: u8) -> Option<AbsInfo> {
+        None
+    }
 }
 
 /// Uart driver trait.
@@ -115,3 +375,18 @@ pub trait DAlloc: 'static {
     /// Convert virtual address to physical address.
     fn virt_to_phys(vaddr: usize) -> usize;
 }
+
+/// Driver scheduling interface.
+pub trait DSched: 'static {
+    /// Put the current task to sleep until [DSched::wake] is called.
+    ///
+    /// The task may be woken up for other reasons, so drivers should check
+    /// their condition again after sleeping.
+    fn sleep();
+
+    /// Wake up the tasks sleeping in [DSched::sleep].
+    fn wake();
+
+    /// Get the time since boot.
+    fn now() -> Duration;
+}
@@ -0,0 +1,453 @@
+//! Intel 8254x (e1000) gigabit ethernet driver.
+//!
+//! Packets are received and transmitted through legacy descriptor rings,
+//! every descriptor owns a fixed packet buffer. Without irqs the driver
+//! works by polling the rings.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+use core::{
+    cmp,
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{fence, Ordering},
+};
+
+use alloc::{sync::Arc, vec::Vec};
+use drivers_base::{
+    DAlloc, DSched, DeviceType, Driver, NetBuf, NetDriver, NetError, NetOffloads, TxOffload,
+};
+use lock_api::{Mutex, RawMutex};
+use regs::{Register, CTRL, EERD, INT, RAH, RCTL, STATUS, TCTL, TIPG};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+
+mod regs;
+
+pub const SUPPORT_PCI_DEVICE: &[(u32, u32)] = &[(0x8086, 0x100e)];
+
+/// Page size of the DMA buffers.
+const PAGE_SIZE: usize = 0x1000;
+/// Number of descriptors in the RX ring.
+const RX_RING_LEN: usize = 32;
+/// Number of descriptors in the TX ring.
+const TX_RING_LEN: usize = 32;
+/// Size of the packet buffer of every descriptor.
+const BUF_SIZE: usize = 2048;
+/// Offset of the TX ring in the descriptor page, the RX ring is at 0.
+const TX_RING_OFFSET: usize = PAGE_SIZE / 2;
+/// Pages of the packet buffers, RX buffers come first.
+const BUF_PAGES: usize = (RX_RING_LEN + TX_RING_LEN) * BUF_SIZE / PAGE_SIZE;
+/// Max polls of the registers before timing out.
+const TIMEOUT_POLLS: usize = 0x10_0000;
+
+/// Bits of the descriptor status.
+const DESC_STATUS_DD: u8 = 1 << 0;
+const DESC_STATUS_EOP: u8 = 1 << 1;
+/// Bits of the TX descriptor command.
+const TX_CMD_EOP: u8 = 1 << 0;
+/// Insert the ethernet CRC.
+const TX_CMD_IFCS: u8 = 1 << 1;
+/// Insert the checksum.
+const TX_CMD_IC: u8 = 1 << 2;
+/// Report the status.
+const TX_CMD_RS: u8 = 1 << 3;
+
+/// e1000 error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum E1000Error {
+    /// The controller doesn't finish the reset.
+    Timeout,
+}
+
+/// Legacy receive descriptor.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct RxDesc {
+    addr: u64,
+    len: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+/// Legacy transmit descriptor.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct TxDesc {
+    addr: u64,
+    len: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+pub struct E1000<R, D: DAlloc, S: DSched> {
+    regs: &'static Register,
+    irqs: Vec<u32>,
+    mac: [u8; 6],
+    /// Physical address of the page of the RX and TX rings.
+    desc_paddr: usize,
+    /// Physical address of the packet buffers.
+    buf_paddr: usize,
+    /// The next RX descriptor filled by the controller.
+    rx_next: Mutex<R, usize>,
+    /// The next free TX descriptor.
+    tx_next: Mutex<R, usize>,
+    /// Recycled buffers of the received packets.
+    rx_pool: Mutex<R, Vec<NetBuf>>,
+    phantom: PhantomData<(D, S)>,
+}
+
+unsafe impl<R, D: DAlloc, S: DSched> Sync for E1000<R, D, S> {}
+unsafe impl<R, D: DAlloc, S: DSched> Send for E1000<R, D, S> {}
+
+impl<R: RawMutex, D: DAlloc, S: DSched> E1000<R, D, S> {
+    /// Create the driver from the virtual address of the BAR 0.
+    ///
+    /// The interrupts are only enabled if `irqs` isn't empty.
+    pub fn new(addr: usize, irqs: Vec<u32>) -> Result<Self, E1000Error> {
+        let mut e1000 = Self {
+            regs: unsafe { (addr as *const Register).as_ref().unwrap() },
+            irqs,
+            mac: [0; 6],
+            desc_paddr: D::alloc(1),
+            buf_paddr: D::alloc(BUF_PAGES),
+            rx_next: Mutex::new(0),
+            tx_next: Mutex::new(0),
+            rx_pool: Mutex::new(Vec::new()),
+            phantom: PhantomData,
+        };
+        e1000.reset()?;
+        e1000.mac = e1000.read_mac();
+        info!(
+            "e1000 mac {:02x?}, link up: {}",
+            e1000.mac,
+            e1000.regs.status.is_set(STATUS::LU)
+        );
+        e1000.init_rx();
+        e1000.init_tx();
+        if !e1000.irqs.is_empty() {
+            e1000.regs.ims.write(
+                INT::RXT0::SET + INT::RXO::SET + INT::RXDMT0::SET + INT::LSC::SET + INT::TXDW::SET,
+            );
+        }
+        Ok(e1000)
+    }
+
+    /// Reset the controller and set the link up.
+    fn reset(&self) -> Result<(), E1000Error> {
+        self.regs.imc.set(u32::MAX);
+        self.regs.ctrl.modify(CTRL::RST::SET);
+        // Software must not access the registers in the first microsecond of the reset.
+        for _ in 0..0x1000 {
+            core::hint::spin_loop();
+        }
+        (0..TIMEOUT_POLLS)
+            .find(|_| !self.regs.ctrl.is_set(CTRL::RST))
+            .ok_or(E1000Error::Timeout)?;
+        self.regs.imc.set(u32::MAX);
+        self.regs.icr.get();
+
+        self.regs.ctrl.modify(
+            CTRL::SLU::SET
+                + CTRL::ASDE::SET
+                + CTRL::LRST::CLEAR
+                + CTRL::PHY_RST::CLEAR
+                + CTRL::ILOS::CLEAR
+                + CTRL::VME::CLEAR,
+        );
+        Ok(())
+    }
+
+    /// Read a word of the EEPROM, None if the read times out.
+    fn read_eeprom(&self, word: u8) -> Option<u16> {
+        self.regs
+            .eerd
+            .write(EERD::ADDR.val(word as u32) + EERD::START::SET);
+        (0..TIMEOUT_POLLS).find_map(|_| {
+            let eerd = self.regs.eerd.extract();
+            eerd.is_set(EERD::DONE)
+                .then(|| eerd.read(EERD::DATA) as u16)
+        })
+    }
+
+    /// Read the MAC address from the EEPROM and accept packets to it.
+    ///
+    /// Falls back to the receive address loaded by the controller
+    /// if the EEPROM isn't readable.
+    fn read_mac(&self) -> [u8; 6] {
+        let words: Option<Vec<u16>> = (0..3).map(|x| self.read_eeprom(x)).collect();
+        let mac = match words {
+            Some(words) => {
+                let mut mac = [0; 6];
+                for (i, word) in words.iter().enumerate() {
+                    mac[i * 2..i * 2 + 2].copy_from_slice(&word.to_le_bytes());
+                }
+                mac
+            }
+            None => {
+                warn!("e1000 can't read the EEPROM, use the receive address");
+                let low = self.regs.ral.get().to_le_bytes();
+                let high = (self.regs.rah.read(RAH::RAH) as u16).to_le_bytes();
+                [low[0], low[1], low[2], low[3], high[0], high[1]]
+            }
+        };
+        self.regs
+            .ral
+            .set(u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]));
+        self.regs
+            .rah
+            .write(RAH::RAH.val(u16::from_le_bytes([mac[4], mac[5]]) as u32) + RAH::AV::SET);
+        mac
+    }
+
+    #[inline]
+    fn rx_desc(&self, index: usize) -> *mut RxDesc {
+        (D::phys_to_virt(self.desc_paddr) as *mut RxDesc).wrapping_add(index)
+    }
+
+    #[inline]
+    fn tx_desc(&self, index: usize) -> *mut TxDesc {
+        (D::phys_to_virt(self.desc_paddr + TX_RING_OFFSET) as *mut TxDesc).wrapping_add(index)
+    }
+
+    /// Physical address of the packet buffer of the RX descriptor.
+    #[inline]
+    fn rx_buf(&self, index: usize) -> usize {
+        self.buf_paddr + index * BUF_SIZE
+    }
+
+    /// Physical address of the packet buffer of the TX descriptor.
+    #[inline]
+    fn tx_buf(&self, index: usize) -> usize {
+        self.buf_paddr + (RX_RING_LEN + index) * BUF_SIZE
+    }
+
+    /// Put the packet to the next TX descriptor.
+    ///
+    /// The checksum from `css` to the end is inserted at `cso` if the
+    /// command has [TX_CMD_IC].
+    fn transmit(&self, buf: &[u8], cso: u8, css: u8, cmd: u8) -> Result<(), NetError> {
+        if buf.len() > BUF_SIZE {
+            return Err(NetError::TooLarge);
+        }
+        let mut next = self.tx_next.lock();
+        let index = *next;
+        let desc = self.tx_desc(index);
+        // The controller hasn't sent the packet in the descriptor.
+        if unsafe { ptr::addr_of!((*desc).status).read_volatile() } & DESC_STATUS_DD == 0 {
+            return Err(NetError::Busy);
+        }
+        let packet = D::phys_to_virt(self.tx_buf(index)) as *mut u8;
+        unsafe {
+            ptr::copy_nonoverlapping(buf.as_ptr(), packet, buf.len());
+            desc.write_volatile(TxDesc {
+                addr: self.tx_buf(index) as u64,
+                len: buf.len() as u16,
+                cso,
+                cmd: TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS | cmd,
+                status: 0,
+                css,
+                special: 0,
+            });
+        }
+        fence(Ordering::Release);
+        *next = (index + 1) % TX_RING_LEN;
+        self.regs.tdt.set(*next as u32);
+        Ok(())
+    }
+
+    fn init_rx(&self) {
+        for i in 0..RX_RING_LEN {
+            let desc = RxDesc {
+                addr: self.rx_buf(i) as u64,
+                len: 0,
+                checksum: 0,
+                status: 0,
+                errors: 0,
+                special: 0,
+            };
+            unsafe { self.rx_desc(i).write_volatile(desc) };
+        }
+        // No multicast addresses.
+        for mta in self.regs.mta.iter() {
+            mta.set(0);
+        }
+        self.regs.rdbal.set(self.desc_paddr as u32);
+        self.regs.rdbah.set((self.desc_paddr as u64 >> 32) as u32);
+        self.regs
+            .rdlen
+            .set((RX_RING_LEN * core::mem::size_of::<RxDesc>()) as u32);
+        // The controller owns the descriptors from the head to the one before the tail.
+        self.regs.rdh.set(0);
+        self.regs.rdt.set(RX_RING_LEN as u32 - 1);
+        self.regs.rctl.write(
+            RCTL::EN::SET
+                + RCTL::BAM::SET
+                + RCTL::RDMTS::Half
+                + RCTL::BSIZE::Size2048
+                + RCTL::SECRC::SET,
+        );
+    }
+
+    fn init_tx(&self) {
+        for i in 0..TX_RING_LEN {
+            // Free descriptors are marked done.
+            let desc = TxDesc {
+                addr: self.tx_buf(i) as u64,
+                len: 0,
+                cso: 0,
+                cmd: 0,
+                status: DESC_STATUS_DD,
+                css: 0,
+                special: 0,
+            };
+            unsafe { self.tx_desc(i).write_volatile(desc) };
+        }
+        self.regs
+            .tdbal
+            .set((self.desc_paddr + TX_RING_OFFSET) as u32);
+        self.regs
+            .tdbah
+            .set(((self.desc_paddr + TX_RING_OFFSET) as u64 >> 32) as u32);
+        self.regs
+            .tdlen
+            .set((TX_RING_LEN * core::mem::size_of::<TxDesc>()) as u32);
+        self.regs.tdh.set(0);
+        self.regs.tdt.set(0);
+        // The recommended values of the full duplex copper link.
+        self.regs
+            .tipg
+            .write(TIPG::IPGT.val(10) + TIPG::IPGR1.val(8) + TIPG::IPGR2.val(6));
+        self.regs
+            .tctl
+            .write(TCTL::EN::SET + TCTL::PSP::SET + TCTL::CT.val(0x10) + TCTL::COLD.val(0x40));
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> Driver for E1000<R, D, S> {
+    fn get_id(&self) -> &str {
+        "e1000"
+    }
+
+    fn interrupts(&self) -> &[u32] {
+        &self.irqs
+    }
+
+    fn try_handle_interrupt(&self, irq: u32) -> bool {
+        if !self.irqs.contains(&irq) {
+            return false;
+        }
+        // The irq line may be shared, no cause means the interrupt isn't ours.
+        let icr = self.regs.icr.extract();
+        if icr.get() == 0 {
+            return false;
+        }
+        if icr.is_set(INT::LSC) {
+            info!("e1000 link {}", if self.link_up() { "up" } else { "down" });
+        }
+        if icr.is_set(INT::RXO) {
+            warn!("e1000 receiver overrun");
+        }
+        S::wake();
+        true
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::NET(self.clone())
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> NetDriver for E1000<R, D, S> {
+    fn recv(&self) -> Result<NetBuf, NetError> {
+        let mut next = self.rx_next.lock();
+        loop {
+            let desc = unsafe { self.rx_desc(*next).read_volatile() };
+            if desc.status & DESC_STATUS_DD == 0 {
+                return Err(NetError::NoData);
+            }
+            // Read the packet after the controller writes back the descriptor.
+            fence(Ordering::Acquire);
+            let index = *next;
+            // Packets fit in a buffer, so a packet without EOP is broken.
+            let valid = desc.status & DESC_STATUS_EOP != 0 && desc.errors == 0;
+            let mut buf = None;
+            if valid {
+                let mut packet = self
+                    .rx_pool
+                    .lock()
+                    .pop()
+                    .unwrap_or_else(|| NetBuf::new(BUF_SIZE));
+                let len = cmp::min(packet.capacity(), desc.len as usize);
+                let data = D::phys_to_virt(self.rx_buf(index)) as *const u8;
+                unsafe { ptr::copy_nonoverlapping(data, packet.buffer().as_mut_ptr(), len) };
+                packet.set_packet(0, len);
+                packet.csum_valid = false;
+                buf = Some(packet);
+            } else {
+                warn!("e1000 drops a broken packet, errors {:#x}", desc.errors);
+            }
+
+            // Give the descriptor back to the controller.
+            unsafe { ptr::addr_of_mut!((*self.rx_desc(index)).status).write_volatile(0) };
+            fence(Ordering::Release);
+            self.regs.rdt.set(index as u32);
+            *next = (index + 1) % RX_RING_LEN;
+            if let Some(buf) = buf {
+                return Ok(buf);
+            }
+        }
+    }
+
+    fn recycle(&self, buf: NetBuf) {
+        let mut pool = self.rx_pool.lock();
+        if pool.len() < RX_RING_LEN && buf.capacity() == BUF_SIZE {
+            pool.push(buf);
+        }
+    }
+
+    fn send(&self, buf: &[u8]) -> Result<(), NetError> {
+        self.transmit(buf, 0, 0, 0)
+    }
+
+    fn send_offload(&self, buf: &[u8], offload: &TxOffload) -> Result<(), NetError> {
+        let css = offload.csum_start as usize;
+        let cso = css + offload.csum_offset as usize;
+        if offload.tso.is_some() || cso > u8::MAX as usize {
+            return Err(NetError::Unsupported);
+        }
+        self.transmit(buf, cso as u8, css as u8, TX_CMD_IC)
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn link_up(&self) -> bool {
+        self.regs.status.is_set(STATUS::LU)
+    }
+
+    fn offloads(&self) -> NetOffloads {
+        NetOffloads {
+            tx_csum: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl<R, D: DAlloc, S: DSched> Drop for E1000<R, D, S> {
+    fn drop(&mut self) {
+        self.regs.imc.set(u32::MAX);
+        self.regs.rctl.set(0);
+        self.regs.tctl.set(0);
+        D::dealloc(self.desc_paddr, 1);
+        D::dealloc(self.buf_paddr, BUF_PAGES);
+    }
+}
@@ -0,0 +1,151 @@
+//! Registers of the 8254x controllers.
+//!
+//! See the PCI/PCI-X Family of Gigabit Ethernet Controllers Software Developer's Manual.
+
+use tock_registers::{
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+
+register_structs! {
+    pub(crate) Register {
+        (0x0000 => pub ctrl: ReadWrite<u32, CTRL::Register>),
+        (0x0004 => _reserved0),
+        (0x0008 => pub status: ReadOnly<u32, STATUS::Register>),
+        (0x000c => _reserved1),
+        (0x0014 => pub eerd: ReadWrite<u32, EERD::Register>),
+        (0x0018 => _reserved2),
+        // Reading the cause register clears it.
+        (0x00c0 => pub icr: ReadOnly<u32, INT::Register>),
+        (0x00c4 => _reserved3),
+        (0x00d0 => pub ims: ReadWrite<u32, INT::Register>),
+        (0x00d4 => _reserved4),
+        (0x00d8 => pub imc: WriteOnly<u32, INT::Register>),
+        (0x00dc => _reserved5),
+        (0x0100 => pub rctl: ReadWrite<u32, RCTL::Register>),
+        (0x0104 => _reserved6),
+        (0x0400 => pub tctl: ReadWrite<u32, TCTL::Register>),
+        (0x0404 => _reserved7),
+        (0x0410 => pub tipg: ReadWrite<u32, TIPG::Register>),
+        (0x0414 => _reserved8),
+        (0x2800 => pub rdbal: ReadWrite<u32>),
+        (0x2804 => pub rdbah: ReadWrite<u32>),
+        (0x2808 => pub rdlen: ReadWrite<u32>),
+        (0x280c => _reserved9),
+        (0x2810 => pub rdh: ReadWrite<u32>),
+        (0x2814 => _reserved10),
+        (0x2818 => pub rdt: ReadWrite<u32>),
+        (0x281c => _reserved11),
+        (0x3800 => pub tdbal: ReadWrite<u32>),
+        (0x3804 => pub tdbah: ReadWrite<u32>),
+        (0x3808 => pub tdlen: ReadWrite<u32>),
+        (0x380c => _reserved12),
+        (0x3810 => pub tdh: ReadWrite<u32>),
+        (0x3814 => _reserved13),
+        (0x3818 => pub tdt: ReadWrite<u32>),
+        (0x381c => _reserved14),
+        (0x5200 => pub mta: [ReadWrite<u32>; 128]),
+        (0x5400 => pub ral: ReadWrite<u32>),
+        (0x5404 => pub rah: ReadWrite<u32, RAH::Register>),
+        (0x5408 => @END),
+    }
+}
+
+register_bitfields! [
+    u32,
+    pub CTRL [
+        FD OFFSET(0) NUMBITS(1) [],
+        LRST OFFSET(3) NUMBITS(1) [],
+        // Auto-speed detection enable.
+        ASDE OFFSET(5) NUMBITS(1) [],
+        // Set link up.
+        SLU OFFSET(6) NUMBITS(1) [],
+        // Invert loss-of-signal.
+        ILOS OFFSET(7) NUMBITS(1) [],
+        RST OFFSET(26) NUMBITS(1) [],
+        VME OFFSET(30) NUMBITS(1) [],
+        PHY_RST OFFSET(31) NUMBITS(1) [],
+    ],
+    pub STATUS [
+        FD OFFSET(0) NUMBITS(1) [],
+        // Link up.
+        LU OFFSET(1) NUMBITS(1) [],
+        SPEED OFFSET(6) NUMBITS(2) [
+            Speed10 = 0,
+            Speed100 = 1,
+            Speed1000 = 2
+        ],
+    ],
+    // EEPROM read register.
+    pub EERD [
+        START OFFSET(0) NUMBITS(1) [],
+        DONE OFFSET(4) NUMBITS(1) [],
+        ADDR OFFSET(8) NUMBITS(8) [],
+        DATA OFFSET(16) NUMBITS(16) [],
+    ],
+    // Interrupt cause, mask set and mask clear registers.
+    pub INT [
+        // Transmit descriptor written back.
+        TXDW OFFSET(0) NUMBITS(1) [],
+        TXQE OFFSET(1) NUMBITS(1) [],
+        // Link status change.
+        LSC OFFSET(2) NUMBITS(1) [],
+        RXSEQ OFFSET(3) NUMBITS(1) [],
+        // Receive descriptor minimum threshold reached.
+        RXDMT0 OFFSET(4) NUMBITS(1) [],
+        // Receiver overrun.
+        RXO OFFSET(6) NUMBITS(1) [],
+        // Receiver timer interrupt.
+        RXT0 OFFSET(7) NUMBITS(1) [],
+    ],
+    pub RCTL [
+        EN OFFSET(1) NUMBITS(1) [],
+        // Store bad packets.
+        SBP OFFSET(2) NUMBITS(1) [],
+        // Unicast and multicast promiscuous.
+        UPE OFFSET(3) NUMBITS(1) [],
+        MPE OFFSET(4) NUMBITS(1) [],
+        // Long packet enable.
+        LPE OFFSET(5) NUMBITS(1) [],
+        LBM OFFSET(6) NUMBITS(2) [],
+        // Free descriptor threshold of RXDMT0, in the ring length.
+        RDMTS OFFSET(8) NUMBITS(2) [
+            Half = 0,
+            Quarter = 1,
+            Eighth = 2
+        ],
+        // Broadcast accept mode.
+        BAM OFFSET(15) NUMBITS(1) [],
+        // Receive buffer size, multiplied by 16 if BSEX is set.
+        BSIZE OFFSET(16) NUMBITS(2) [
+            Size2048 = 0,
+            Size1024 = 1,
+            Size512 = 2,
+            Size256 = 3
+        ],
+        BSEX OFFSET(25) NUMBITS(1) [],
+        // Strip the ethernet CRC.
+        SECRC OFFSET(26) NUMBITS(1) [],
+    ],
+    pub TCTL [
+        EN OFFSET(1) NUMBITS(1) [],
+        // Pad short packets.
+        PSP OFFSET(3) NUMBITS(1) [],
+        // Collision threshold.
+        CT OFFSET(4) NUMBITS(8) [],
+        // Collision distance.
+        COLD OFFSET(12) NUMBITS(10) [],
+    ],
+    // Transmit inter packet gap.
+    pub TIPG [
+        IPGT OFFSET(0) NUMBITS(10) [],
+        IPGR1 OFFSET(10) NUMBITS(10) [],
+        IPGR2 OFFSET(20) NUMBITS(10) [],
+    ],
+    // Receive address high.
+    pub RAH [
+        RAH OFFSET(0) NUMBITS(16) [],
+        // Address valid.
+        AV OFFSET(31) NUMBITS(1) [],
+    ],
+];
@@ -0,0 +1,224 @@
+//! x86 IO-APIC and Local APIC.
+//!
+//! The IO-APIC routes the external irqs to vectors of the Local APIC,
+//! the Local APIC is accessed through the xAPIC mmio interface.
+//! Irqs above the IO-APIC pins are message signalled interrupts sent
+//! to the Local APIC directly.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, Ordering};
+use drivers_base::{DeviceType, Driver, IntDriver, MsiMessage};
+use lock_api::RawMutex;
+
+use crate::IrqTable;
+
+/// Default physical address of the IO-APIC.
+pub const IOAPIC_ADDR: usize = 0xfec0_0000;
+/// Default physical address of the Local APIC.
+pub const LAPIC_ADDR: usize = 0xfee0_0000;
+
+/// The vector of the irq 0, vectors below it are reserved for the exceptions and the timer.
+pub const IRQ_VECTOR_START: u32 = 0x30;
+/// The spurious interrupt vector.
+const SPURIOUS_VECTOR: u32 = 0xff;
+/// MSI address of the Local APIC, the destination APIC id is in bits 12-19.
+const MSI_ADDR: u64 = 0xfee0_0000;
+
+/// IO-APIC register select.
+const IOAPIC_REGSEL: usize = 0x00;
+/// IO-APIC register window.
+const IOAPIC_WIN: usize = 0x10;
+/// IO-APIC version register, includes the max redirection entry.
+const IOAPIC_VER: u32 = 0x01;
+/// IO-APIC redirection table.
+const IOAPIC_REDTBL: u32 = 0x10;
+/// Redirection entry, the interrupt is active low.
+const IOAPIC_ACTIVE_LOW: u32 = 1 << 13;
+/// Redirection entry, the interrupt is level triggered.
+const IOAPIC_LEVEL: u32 = 1 << 15;
+/// Redirection entry, the interrupt is masked.
+const IOAPIC_MASKED: u32 = 1 << 16;
+/// The first irq of the PCI interrupt lines, the ISA irqs are below it.
+const PCI_IRQ_START: u32 = 16;
+
+/// Local APIC ID register.
+const LAPIC_ID: usize = 0x20;
+/// Local APIC task priority register.
+const LAPIC_TPR: usize = 0x80;
+/// Local APIC end of interrupt register.
+const LAPIC_EOI: usize = 0xb0;
+/// Local APIC spurious interrupt vector register.
+const LAPIC_SVR: usize = 0xf0;
+/// Local APIC in-service registers, 8 registers for 256 vectors.
+const LAPIC_ISR: usize = 0x100;
+/// Local APIC software enable bit in the SVR.
+const LAPIC_SVR_ENABLE: u32 = 1 << 8;
+
+pub struct Apic<R: RawMutex> {
+    ioapic: usize,
+    lapic: usize,
+    irqs: IrqTable<R>,
+    /// The next irq allocated for MSIs.
+    next_msi: AtomicU32,
+}
+
+impl<R: RawMutex> Apic<R> {
+    /// Create a new APIC driver from the IO-APIC and Local APIC address.
+    pub fn new(ioapic: usize, lapic: usize) -> Self {
+        let apic = Self {
+            ioapic,
+            lapic,
+            irqs: IrqTable::new(),
+            next_msi: AtomicU32::new(0),
+        };
+        apic.init();
+        apic.next_msi.store(apic.max_irqs(), Ordering::Relaxed);
+        apic
+    }
+
+    #[inline]
+    fn lapic_read(&self, offset: usize) -> u32 {
+        unsafe { ((self.lapic + offset) as *const u32).read_volatile() }
+    }
+
+    #[inline]
+    fn lapic_write(&self, offset: usize, value: u32) {
+        unsafe { ((self.lapic + offset) as *mut u32).write_volatile(value) }
+    }
+
+    #[inline]
+    fn ioapic_read(&self, reg: u32) -> u32 {
+        unsafe {
+            ((self.ioapic + IOAPIC_REGSEL) as *mut u32).write_volatile(reg);
+            ((self.ioapic + IOAPIC_WIN) as *const u32).read_volatile()
+        }
+    }
+
+    #[inline]
+    fn ioapic_write(&self, reg: u32, value: u32) {
+        unsafe {
+            ((self.ioapic + IOAPIC_REGSEL) as *mut u32).write_volatile(reg);
+            ((self.ioapic + IOAPIC_WIN) as *mut u32).write_volatile(value);
+        }
+    }
+
+    /// Max irq numbers supported by the IO-APIC.
+    #[inline]
+    pub fn max_irqs(&self) -> u32 {
+        ((self.ioapic_read(IOAPIC_VER) >> 16) & 0xff) + 1
+    }
+
+    /// Get the Local APIC id of the current cpu.
+    #[inline]
+    pub fn lapic_id(&self) -> u32 {
+        self.lapic_read(LAPIC_ID) >> 24
+    }
+
+    fn init(&self) {
+        info!("IO-APIC with {} irqs", self.max_irqs());
+        // Enable the Local APIC and accept all interrupts.
+        self.lapic_write(LAPIC_SVR, LAPIC_SVR_ENABLE | SPURIOUS_VECTOR);
+        self.lapic_write(LAPIC_TPR, 0);
+
+        // Mask all redirection entries.
+        for irq in 0..self.max_irqs() {
+            self.ioapic_write(IOAPIC_REDTBL + irq * 2, IOAPIC_MASKED);
+            self.ioapic_write(IOAPIC_REDTBL + irq * 2 + 1, 0);
+        }
+    }
+
+    /// Route the irq to the current cpu and unmask it.
+    ///
+    /// The ISA irqs are edge triggered and active high, the PCI interrupt
+    /// lines are level triggered and active low.
+    pub fn enable(&self, irq: u32) {
+        let pci = irq >= PCI_IRQ_START;
+        self.enable_as(irq, pci, pci);
+    }
+
+    /// Route the irq to the current cpu with the trigger mode and the polarity, and unmask it.
+    ///
+    /// The interrupt source overrides of the firmware change the defaults of [Apic::enable].
+    pub fn enable_as(&self, irq: u32, level: bool, active_low: bool) {
+        assert!(irq < self.max_irqs());
+        let mut entry = IRQ_VECTOR_START + irq;
+        if level {
+            entry |= IOAPIC_LEVEL;
+        }
+        if active_low {
+            entry |= IOAPIC_ACTIVE_LOW;
+        }
+        // Fixed delivery mode, physical destination.
+        self.ioapic_write(IOAPIC_REDTBL + irq * 2 + 1, self.lapic_id() << 24);
+        self.ioapic_write(IOAPIC_REDTBL + irq * 2, entry);
+    }
+
+    /// Mask the irq in the IO-APIC.
+    pub fn disable(&self, irq: u32) {
+        assert!(irq < self.max_irqs());
+        self.ioapic_write(IOAPIC_REDTBL + irq * 2, IOAPIC_MASKED);
+    }
+
+    /// Get the highest priority vector that is in service.
+    fn in_service_vector(&self) -> Option<u32> {
+        (0..8).rev().find_map(|i| {
+            let isr = self.lapic_read(LAPIC_ISR + i * 0x10);
+            (isr != 0).then(|| i as u32 * 32 + 31 - isr.leading_zeros())
+        })
+    }
+}
+
+impl<R: RawMutex + 'static> Driver for Apic<R> {
+    fn get_id(&self) -> &str {
+        "x86-apic"
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::INT(self.clone())
+    }
+}
+
+unsafe impl<R: RawMutex> Sync for Apic<R> {}
+unsafe impl<R: RawMutex> Send for Apic<R> {}
+
+impl<R: RawMutex + 'static> IntDriver for Apic<R> {
+    fn register_irq(&self, irq: u32, driver: Arc<dyn Driver>) {
+        self.irqs.register(irq, driver);
+        // MSIs are delivered to the Local APIC without the IO-APIC.
+        if irq < self.max_irqs() {
+            self.enable(irq);
+        }
+    }
+
+    fn handle_irq(&self) {
+        // Vectors below IRQ_VECTOR_START are not routed by the IO-APIC.
+        let vector = match self.in_service_vector() {
+            Some(vector) if vector >= IRQ_VECTOR_START && vector != SPURIOUS_VECTOR => vector,
+            _ => return,
+        };
+        self.irqs.dispatch(vector - IRQ_VECTOR_START);
+        self.lapic_write(LAPIC_EOI, 0);
+    }
+
+    fn alloc_msi(&self) -> Option<(u32, MsiMessage)> {
+        let irq = self
+            .next_msi
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |irq| {
+                (IRQ_VECTOR_START + irq < SPURIOUS_VECTOR).then_some(irq + 1)
+            })
+            .ok()?;
+        // Fixed delivery mode, physical destination, edge triggered.
+        let message = MsiMessage {
+            addr: MSI_ADDR | ((self.lapic_id() as u64) << 12),
+            data: IRQ_VECTOR_START + irq,
+        };
+        Some((irq, message))
+    }
+
+    fn free_msi(&self, irq: u32) {
+        // Only the last allocated irq goes back to the allocator.
+        let _ = self
+            .next_msi
+            .compare_exchange(irq + 1, irq, Ordering::Relaxed, Ordering::Relaxed);
+    }
+}
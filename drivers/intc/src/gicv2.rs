@@ -0,0 +1,215 @@
+//! ARM Generic Interrupt Controller v2.
+//!
+//! See the https://developer.arm.com/documentation/ihi0048/latest/
+//!
+//! MSIs are supported by the GICv2m frame, which turns the writes to its
+//! `SETSPI` register into SPIs.
+
+use alloc::sync::Arc;
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use drivers_base::{DeviceType, Driver, IntDriver, MsiMessage};
+use lock_api::RawMutex;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+
+use crate::IrqTable;
+
+/// The first shared peripheral interrupt. Irqs smaller than it are SGIs and PPIs.
+pub const SPI_START: u32 = 32;
+/// Interrupt ids bigger than or equal to it are special, 1023 means spurious.
+const SPECIAL_IRQ_START: u32 = 1020;
+/// Offset of the MSI type register in the GICv2m frame, the base SPI and the SPI count.
+const V2M_MSI_TYPER: usize = 0x08;
+/// Offset of the register in the GICv2m frame raising the SPI written to it.
+const V2M_MSI_SETSPI: u64 = 0x40;
+
+register_structs! {
+    /// GIC Distributor registers.
+    pub(crate) GicDistributor {
+        (0x0000 => ctlr: ReadWrite<u32>),
+        (0x0004 => typer: ReadOnly<u32>),
+        (0x0008 => _reserved0),
+        (0x0100 => isenabler: [ReadWrite<u32>; 0x20]),
+        (0x0180 => icenabler: [ReadWrite<u32>; 0x20]),
+        (0x0200 => _reserved1),
+        (0x0400 => ipriorityr: [ReadWrite<u32>; 0x100]),
+        (0x0800 => itargetsr: [ReadWrite<u32>; 0x100]),
+        (0x0c00 => icfgr: [ReadWrite<u32>; 0x40]),
+        (0x0d00 => @END),
+    }
+}
+
+register_structs! {
+    /// GIC CPU Interface registers.
+    pub(crate) GicCpuInterface {
+        (0x0000 => ctlr: ReadWrite<u32>),
+        (0x0004 => pmr: ReadWrite<u32>),
+        (0x0008 => bpr: ReadWrite<u32>),
+        (0x000c => iar: ReadOnly<u32>),
+        (0x0010 => eoir: WriteOnly<u32>),
+        (0x0014 => @END),
+    }
+}
+
+/// The GICv2m MSI frame.
+struct V2m {
+    /// Physical address of the `SETSPI` register.
+    setspi: u64,
+    /// The SPIs raised by the frame.
+    spis: Range<u32>,
+    /// The next SPI allocated for MSIs.
+    next: AtomicU32,
+}
+
+pub struct GicV2<R: RawMutex> {
+    gicd: &'static GicDistributor,
+    gicc: &'static GicCpuInterface,
+    irqs: IrqTable<R>,
+    v2m: Option<V2m>,
+}
+
+impl<R: RawMutex> GicV2<R> {
+    /// Create a new GICv2 driver from the distributor and cpu interface address.
+    pub fn new(gicd: usize, gicc: usize) -> Self {
+        let gic = Self {
+            gicd: unsafe { (gicd as *const GicDistributor).as_ref().unwrap() },
+            gicc: unsafe { (gicc as *const GicCpuInterface).as_ref().unwrap() },
+            irqs: IrqTable::new(),
+            v2m: None,
+        };
+        gic.init();
+        gic
+    }
+
+    /// Use the GICv2m frame for MSIs.
+    ///
+    /// `frame` is the virtual address of the frame and `frame_phys` is its
+    /// physical address, which is written by the devices.
+    pub fn set_v2m(&mut self, frame: usize, frame_phys: u64) {
+        let typer = unsafe { ((frame + V2M_MSI_TYPER) as *const u32).read_volatile() };
+        let base = (typer >> 16) & 0x3ff;
+        let spis = base..base + (typer & 0x3ff);
+        info!("GICv2m with SPIs {:?}", spis);
+        self.v2m = Some(V2m {
+            setspi: frame_phys + V2M_MSI_SETSPI,
+            next: AtomicU32::new(spis.start),
+            spis,
+        });
+    }
+
+    /// Max irq numbers supported by the distributor.
+    #[inline]
+    pub fn max_irqs(&self) -> u32 {
+        ((self.gicd.typer.get() & 0x1f) + 1) * 32
+    }
+
+    fn init(&self) {
+        let max_irqs = self.max_irqs();
+        info!("GICv2 with {} irqs", max_irqs);
+
+        self.gicd.ctlr.set(0);
+        // Disable all SPIs, route them to cpu 0 and make them level-sensitive.
+        for i in (SPI_START..max_irqs).step_by(32) {
+            self.gicd.icenabler[i as usize / 32].set(u32::MAX);
+        }
+        for i in (SPI_START..max_irqs).step_by(4) {
+            self.gicd.ipriorityr[i as usize / 4].set(0xa0a0_a0a0);
+            self.gicd.itargetsr[i as usize / 4].set(0x0101_0101);
+        }
+        for i in (SPI_START..max_irqs).step_by(16) {
+            self.gicd.icfgr[i as usize / 16].set(0);
+        }
+        self.gicd.ctlr.set(1);
+
+        // Accept interrupts of all priorities.
+        self.gicc.pmr.set(0xff);
+        self.gicc.bpr.set(0);
+        self.gicc.ctlr.set(1);
+    }
+
+    /// Enable the irq in the distributor.
+    pub fn enable(&self, irq: u32) {
+        assert!(irq < self.max_irqs());
+        let shift = irq % 4 * 8;
+        let priority = &self.gicd.ipriorityr[irq as usize / 4];
+        priority.set(priority.get() & !(0xff << shift) | (0xa0 << shift));
+        self.gicd.isenabler[irq as usize / 32].set(1 << (irq % 32));
+    }
+
+    /// Make the irq edge-triggered, MSIs don't hold the level.
+    fn set_edge_triggered(&self, irq: u32) {
+        let cfg = &self.gicd.icfgr[irq as usize / 16];
+        cfg.set(cfg.get() | (0x2 << (irq % 16 * 2)));
+    }
+
+    /// Disable the irq in the distributor.
+    pub fn disable(&self, irq: u32) {
+        assert!(irq < self.max_irqs());
+        self.gicd.icenabler[irq as usize / 32].set(1 << (irq % 32));
+    }
+}
+
+impl<R: RawMutex + 'static> Driver for GicV2<R> {
+    fn get_id(&self) -> &str {
+        "arm-gicv2"
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::INT(self.clone())
+    }
+}
+
+unsafe impl<R: RawMutex> Sync for GicV2<R> {}
+unsafe impl<R: RawMutex> Send for GicV2<R> {}
+
+impl<R: RawMutex + 'static> IntDriver for GicV2<R> {
+    fn register_irq(&self, irq: u32, driver: Arc<dyn Driver>) {
+        self.irqs.register(irq, driver);
+        if self.v2m.as_ref().is_some_and(|v2m| v2m.spis.contains(&irq)) {
+            self.set_edge_triggered(irq);
+        }
+        self.enable(irq);
+    }
+
+    fn handle_irq(&self) {
+        loop {
+            let iar = self.gicc.iar.get();
+            let irq = iar & 0x3ff;
+            if irq >= SPECIAL_IRQ_START {
+                break;
+            }
+            self.irqs.dispatch(irq);
+            self.gicc.eoir.set(iar);
+        }
+    }
+
+    fn alloc_msi(&self) -> Option<(u32, MsiMessage)> {
+        let v2m = self.v2m.as_ref()?;
+        let irq = v2m
+            .next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |irq| {
+                (irq < v2m.spis.end).then_some(irq + 1)
+            })
+            .ok()?;
+        let message = MsiMessage {
+            addr: v2m.setspi,
+            data: irq,
+        };
+        Some((irq, message))
+    }
+
+    fn free_msi(&self, irq: u32) {
+        // Only the last allocated irq goes back to the allocator.
+        if let Some(v2m) = self.v2m.as_ref() {
+            let _ = v2m
+                .next
+                .compare_exchange(irq + 1, irq, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+}
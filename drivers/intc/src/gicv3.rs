@@ -0,0 +1,238 @@
+//! ARM Generic Interrupt Controller v3.
+//!
+//! The distributor and redistributor are memory mapped, the cpu interface
+//! is accessed through the ICC system registers.
+//! See the https://developer.arm.com/documentation/ihi0069/latest/
+
+use core::arch::asm;
+
+use alloc::sync::Arc;
+use drivers_base::{DeviceType, Driver, IntDriver};
+use lock_api::RawMutex;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+use crate::{gicv2::SPI_START, IrqTable};
+
+/// Interrupt ids bigger than or equal to it are special, 1023 means spurious.
+const SPECIAL_IRQ_START: u32 = 1020;
+/// Offset of the SGI and PPI frame in the redistributor.
+const GICR_SGI_OFFSET: usize = 0x1_0000;
+
+/// GICD_CTLR, Enable group 0 and non-secure group 1, affinity routing.
+const GICD_CTLR_ENABLE: u32 = (1 << 4) | (1 << 1) | (1 << 0);
+/// GICD_CTLR, Register write pending.
+const GICD_CTLR_RWP: u32 = 1 << 31;
+/// GICR_WAKER, Processor sleep.
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+/// GICR_WAKER, Children asleep.
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+
+register_structs! {
+    /// GIC Distributor registers.
+    pub(crate) GicDistributor {
+        (0x0000 => ctlr: ReadWrite<u32>),
+        (0x0004 => typer: ReadOnly<u32>),
+        (0x0008 => _reserved0),
+        (0x0080 => igroupr: [ReadWrite<u32>; 0x20]),
+        (0x0100 => isenabler: [ReadWrite<u32>; 0x20]),
+        (0x0180 => icenabler: [ReadWrite<u32>; 0x20]),
+        (0x0200 => _reserved1),
+        (0x0400 => ipriorityr: [ReadWrite<u32>; 0x100]),
+        (0x0800 => _reserved2),
+        (0x0c00 => icfgr: [ReadWrite<u32>; 0x40]),
+        (0x0d00 => _reserved3),
+        (0x6000 => irouter: [ReadWrite<u64>; 0x400]),
+        (0x8000 => @END),
+    }
+}
+
+register_structs! {
+    /// GIC Redistributor control frame registers.
+    pub(crate) GicRedistributor {
+        (0x0000 => _reserved0),
+        (0x0014 => waker: ReadWrite<u32>),
+        (0x0018 => @END),
+    }
+}
+
+register_structs! {
+    /// GIC Redistributor SGI and PPI frame registers.
+    pub(crate) GicRedistributorSgi {
+        (0x0000 => _reserved0),
+        (0x0080 => igroupr0: ReadWrite<u32>),
+        (0x0084 => _reserved1),
+        (0x0100 => isenabler0: ReadWrite<u32>),
+        (0x0104 => _reserved2),
+        (0x0180 => icenabler0: ReadWrite<u32>),
+        (0x0184 => _reserved3),
+        (0x0400 => ipriorityr: [ReadWrite<u32>; 8]),
+        (0x0420 => @END),
+    }
+}
+
+/// Read a ICC system register.
+macro_rules! read_sysreg {
+    ($reg:literal) => {{
+        let value: u64;
+        unsafe { asm!(concat!("mrs {}, ", $reg), out(reg) value) };
+        value
+    }};
+}
+
+/// Write a ICC system register.
+macro_rules! write_sysreg {
+    ($reg:literal, $value:expr) => {{
+        let value: u64 = $value;
+        unsafe { asm!(concat!("msr ", $reg, ", {}"), "isb", in(reg) value) };
+    }};
+}
+
+pub struct GicV3<R: RawMutex> {
+    gicd: &'static GicDistributor,
+    gicr: &'static GicRedistributor,
+    gicr_sgi: &'static GicRedistributorSgi,
+    irqs: IrqTable<R>,
+}
+
+impl<R: RawMutex> GicV3<R> {
+    /// Create a new GICv3 driver from the distributor address and the
+    /// redistributor address of the current cpu.
+    pub fn new(gicd: usize, gicr: usize) -> Self {
+        let gic = Self {
+            gicd: unsafe { (gicd as *const GicDistributor).as_ref().unwrap() },
+            gicr: unsafe { (gicr as *const GicRedistributor).as_ref().unwrap() },
+            gicr_sgi: unsafe {
+                ((gicr + GICR_SGI_OFFSET) as *const GicRedistributorSgi)
+                    .as_ref()
+                    .unwrap()
+            },
+            irqs: IrqTable::new(),
+        };
+        gic.init();
+        gic
+    }
+
+    /// Max irq numbers supported by the distributor.
+    #[inline]
+    pub fn max_irqs(&self) -> u32 {
+        core::cmp::min(((self.gicd.typer.get() & 0x1f) + 1) * 32, SPECIAL_IRQ_START)
+    }
+
+    /// Wait until the distributor register writes take effect.
+    #[inline]
+    fn wait_for_rwp(&self) {
+        while self.gicd.ctlr.get() & GICD_CTLR_RWP != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn init(&self) {
+        let max_irqs = self.max_irqs();
+        info!("GICv3 with {} irqs", max_irqs);
+
+        // Initialize the distributor.
+        self.gicd.ctlr.set(0);
+        self.wait_for_rwp();
+        for i in (SPI_START..max_irqs).step_by(32) {
+            self.gicd.icenabler[i as usize / 32].set(u32::MAX);
+            self.gicd.igroupr[i as usize / 32].set(u32::MAX);
+        }
+        for i in (SPI_START..max_irqs).step_by(4) {
+            self.gicd.ipriorityr[i as usize / 4].set(0xa0a0_a0a0);
+        }
+        for i in (SPI_START..max_irqs).step_by(16) {
+            self.gicd.icfgr[i as usize / 16].set(0);
+        }
+        // Route all SPIs to the cpu with affinity 0.0.0.0
+        for i in SPI_START..max_irqs {
+            self.gicd.irouter[i as usize].set(0);
+        }
+        self.gicd.ctlr.set(GICD_CTLR_ENABLE);
+        self.wait_for_rwp();
+
+        // Wake up the redistributor of the current cpu.
+        self.gicr
+            .waker
+            .set(self.gicr.waker.get() & !GICR_WAKER_PROCESSOR_SLEEP);
+        while self.gicr.waker.get() & GICR_WAKER_CHILDREN_ASLEEP != 0 {
+            core::hint::spin_loop();
+        }
+        self.gicr_sgi.icenabler0.set(u32::MAX);
+        self.gicr_sgi.igroupr0.set(u32::MAX);
+        self.gicr_sgi
+            .ipriorityr
+            .iter()
+            .for_each(|x| x.set(0xa0a0_a0a0));
+
+        // Enable the system register interface, ICC_SRE_EL1.SRE
+        write_sysreg!("S3_0_C12_C12_5", read_sysreg!("S3_0_C12_C12_5") | 1);
+        // Accept interrupts of all priorities, ICC_PMR_EL1
+        write_sysreg!("S3_0_C4_C6_0", 0xff);
+        // Use a single priority group, ICC_BPR1_EL1
+        write_sysreg!("S3_0_C12_C12_3", 0);
+        // Enable group 1 interrupts, ICC_IGRPEN1_EL1
+        write_sysreg!("S3_0_C12_C12_7", 1);
+    }
+
+    /// Enable the irq in the distributor or the redistributor.
+    pub fn enable(&self, irq: u32) {
+        assert!(irq < self.max_irqs());
+        match irq < SPI_START {
+            true => self.gicr_sgi.isenabler0.set(1 << irq),
+            false => {
+                self.gicd.isenabler[irq as usize / 32].set(1 << (irq % 32));
+                self.wait_for_rwp();
+            }
+        }
+    }
+
+    /// Disable the irq in the distributor or the redistributor.
+    pub fn disable(&self, irq: u32) {
+        assert!(irq < self.max_irqs());
+        match irq < SPI_START {
+            true => self.gicr_sgi.icenabler0.set(1 << irq),
+            false => {
+                self.gicd.icenabler[irq as usize / 32].set(1 << (irq % 32));
+                self.wait_for_rwp();
+            }
+        }
+    }
+}
+
+impl<R: RawMutex + 'static> Driver for GicV3<R> {
+    fn get_id(&self) -> &str {
+        "arm-gicv3"
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::INT(self.clone())
+    }
+}
+
+unsafe impl<R: RawMutex> Sync for GicV3<R> {}
+unsafe impl<R: RawMutex> Send for GicV3<R> {}
+
+impl<R: RawMutex + 'static> IntDriver for GicV3<R> {
+    fn register_irq(&self, irq: u32, driver: Arc<dyn Driver>) {
+        self.irqs.register(irq, driver);
+        self.enable(irq);
+    }
+
+    fn handle_irq(&self) {
+        loop {
+            // ICC_IAR1_EL1
+            let iar = read_sysreg!("S3_0_C12_C12_0");
+            let irq = (iar & 0xff_ffff) as u32;
+            if irq >= SPECIAL_IRQ_START {
+                break;
+            }
+            self.irqs.dispatch(irq);
+            // ICC_EOIR1_EL1
+            write_sysreg!("S3_0_C12_C12_1", iar);
+        }
+    }
+}
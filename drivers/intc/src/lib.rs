@@ -0,0 +1,52 @@
+//! Interrupt controller drivers.
+//!
+//! Includes the RISC-V PLIC, ARM GICv2/GICv3 and x86 IO-APIC/Local APIC.
+//! All of them implement [drivers_base::IntDriver].
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+#[cfg(target_arch = "x86_64")]
+pub mod apic;
+pub mod gicv2;
+#[cfg(target_arch = "aarch64")]
+pub mod gicv3;
+pub mod plic;
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use drivers_base::Driver;
+use lock_api::{Mutex, RawMutex};
+
+/// The drivers registered for every irq number.
+pub(crate) struct IrqTable<R: RawMutex>(Mutex<R, BTreeMap<u32, Vec<Arc<dyn Driver>>>>);
+
+impl<R: RawMutex> IrqTable<R> {
+    /// Create a new empty irq table.
+    pub(crate) const fn new() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+
+    /// Add the driver to the handler list of the irq.
+    pub(crate) fn register(&self, irq: u32, driver: Arc<dyn Driver>) {
+        self.0.lock().entry(irq).or_default().push(driver);
+    }
+
+    /// Dispatch the irq to the registered drivers.
+    ///
+    /// The handler list is cloned, so drivers are called without holding the lock.
+    pub(crate) fn dispatch(&self, irq: u32) -> bool {
+        let drivers = self.0.lock().get(&irq).cloned().unwrap_or_default();
+        // The irq line may be shared, every driver should check its device.
+        let mut handled = false;
+        for driver in drivers {
+            handled |= driver.try_handle_interrupt(irq);
+        }
+        if !handled {
+            warn!("unhandled irq: {}", irq);
+        }
+        handled
+    }
+}
@@ -0,0 +1,121 @@
+//! RISC-V Platform-Level Interrupt Controller.
+//!
+//! See the https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc
+
+use alloc::sync::Arc;
+use drivers_base::{DeviceType, Driver, IntDriver};
+use lock_api::RawMutex;
+
+use crate::IrqTable;
+
+/// Offset of the interrupt source priority registers.
+const PRIORITY_OFFSET: usize = 0;
+/// Offset of the enable bits for sources on context 0.
+const ENABLE_OFFSET: usize = 0x2000;
+/// Size of the enable bits for every context.
+const ENABLE_STRIDE: usize = 0x80;
+/// Offset of the priority threshold of context 0.
+const CONTEXT_OFFSET: usize = 0x20_0000;
+/// Size of the threshold and claim registers for every context.
+const CONTEXT_STRIDE: usize = 0x1000;
+/// Offset of the claim/complete register in the context.
+const CLAIM_OFFSET: usize = 4;
+
+/// Max interrupt sources supported by the PLIC.
+pub const MAX_IRQS: u32 = 1024;
+
+pub struct Plic<R: RawMutex> {
+    /// Virtual address of the PLIC registers.
+    base: usize,
+    /// Context of the current hart in supervisor mode.
+    context: usize,
+    irqs: IrqTable<R>,
+}
+
+impl<R: RawMutex> Plic<R> {
+    /// Create a new PLIC driver.
+    ///
+    /// `context` is the PLIC context of the hart handling the interrupts,
+    /// QEMU virt machine uses `hart_id * 2 + 1` for the supervisor mode.
+    pub fn new(base: usize, context: usize) -> Self {
+        let plic = Self {
+            base,
+            context,
+            irqs: IrqTable::new(),
+        };
+        // Accept all interrupts with priority bigger than 0.
+        plic.write(CONTEXT_OFFSET + CONTEXT_STRIDE * context, 0);
+        plic
+    }
+
+    #[inline]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Enable the irq for the current context.
+    pub fn enable(&self, irq: u32) {
+        assert!(irq > 0 && irq < MAX_IRQS);
+        self.write(PRIORITY_OFFSET + irq as usize * 4, 1);
+        let offset = ENABLE_OFFSET + ENABLE_STRIDE * self.context + irq as usize / 32 * 4;
+        self.write(offset, self.read(offset) | (1 << (irq % 32)));
+    }
+
+    /// Disable the irq for the current context.
+    pub fn disable(&self, irq: u32) {
+        assert!(irq > 0 && irq < MAX_IRQS);
+        let offset = ENABLE_OFFSET + ENABLE_STRIDE * self.context + irq as usize / 32 * 4;
+        self.write(offset, self.read(offset) & !(1 << (irq % 32)));
+    }
+
+    /// Claim the highest priority pending irq, 0 means there is no pending irq.
+    #[inline]
+    fn claim(&self) -> u32 {
+        self.read(CONTEXT_OFFSET + CONTEXT_STRIDE * self.context + CLAIM_OFFSET)
+    }
+
+    /// Notify the PLIC the irq was handled.
+    #[inline]
+    fn complete(&self, irq: u32) {
+        self.write(
+            CONTEXT_OFFSET + CONTEXT_STRIDE * self.context + CLAIM_OFFSET,
+            irq,
+        )
+    }
+}
+
+impl<R: RawMutex + 'static> Driver for Plic<R> {
+    fn get_id(&self) -> &str {
+        "riscv-plic"
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::INT(self.clone())
+    }
+}
+
+unsafe impl<R: RawMutex> Sync for Plic<R> {}
+unsafe impl<R: RawMutex> Send for Plic<R> {}
+
+impl<R: RawMutex + 'static> IntDriver for Plic<R> {
+    fn register_irq(&self, irq: u32, driver: Arc<dyn Driver>) {
+        self.irqs.register(irq, driver);
+        self.enable(irq);
+    }
+
+    fn handle_irq(&self) {
+        loop {
+            let irq = self.claim();
+            if irq == 0 {
+                break;
+            }
+            self.irqs.dispatch(irq);
+            self.complete(irq);
+        }
+    }
+}
@@ -0,0 +1,537 @@
+//! NVMe driver.
+//!
+//! The controller is brought up with an admin queue and several I/O queue
+//! pairs, every active namespace is exposed as a block device. Requests are
+//! submitted to a free I/O queue, so the tasks don't wait for each other.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+use core::{
+    cmp,
+    marker::PhantomData,
+    ops::Range,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use drivers_base::{BlkDriver, BlkError, DAlloc, DSched, DeviceType, Driver};
+use lock_api::{Mutex, MutexGuard, RawMutex};
+use queue::{Command, Queue, QUEUE_DEPTH};
+use regs::{Register, AQA, CAP, CC, CSTS};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+
+mod queue;
+mod regs;
+
+pub const SUPPORT_PCI_CLASS: (u8, u8, u8) = (0x01, 0x08, 0x02);
+
+/// Page size of the controller and the DMA buffers.
+const PAGE_SIZE: usize = 0x1000;
+/// Offset of the doorbell registers.
+const DOORBELL_OFFSET: usize = 0x1000;
+/// Max I/O queue pairs requested from the controller.
+const MAX_IO_QUEUES: usize = 4;
+/// Entries of the PRP list, the list takes a page.
+const PRP_LIST_LEN: usize = PAGE_SIZE / 8;
+/// Max polls of the registers before timing out.
+const TIMEOUT_POLLS: usize = 0x100_0000;
+/// Time waiting for the completion of a command before timing out.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Admin command opcodes.
+const ADMIN_CREATE_SQ: u8 = 0x01;
+const ADMIN_CREATE_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+const ADMIN_SET_FEATURES: u8 = 0x09;
+
+/// NVM command opcodes.
+const NVM_FLUSH: u8 = 0x00;
+const NVM_WRITE: u8 = 0x01;
+const NVM_READ: u8 = 0x02;
+const NVM_DATASET_MANAGEMENT: u8 = 0x09;
+
+/// Controller or namespace structure returned by the identify command.
+const CNS_NAMESPACE: u32 = 0x00;
+const CNS_CONTROLLER: u32 = 0x01;
+const CNS_ACTIVE_NAMESPACES: u32 = 0x02;
+
+/// Feature id of the number of queues.
+const FEATURE_NUM_QUEUES: u32 = 0x07;
+/// The queue is physically contiguous.
+const QUEUE_PC: u32 = 1 << 0;
+/// Interrupts of the completion queue are enabled.
+const CQ_IEN: u32 = 1 << 1;
+/// Optional NVM command support, dataset management.
+const ONCS_DSM: u16 = 1 << 2;
+/// Deallocate attribute of the dataset management command.
+const DSM_AD: u32 = 1 << 2;
+
+/// The index of the next controller, used in the device names.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// NVMe error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeError {
+    /// The controller or the command doesn't finish in time.
+    Timeout,
+    /// The controller reports a fatal status.
+    Fatal,
+    /// The controller doesn't support the NVM command set or the page size.
+    Unsupported,
+    /// The command fails with the status field.
+    Command(u16),
+}
+
+impl From<NvmeError> for BlkError {
+    fn from(err: NvmeError) -> Self {
+        match err {
+            NvmeError::Fatal => BlkError::NotReady,
+            NvmeError::Unsupported => BlkError::Unsupported,
+            NvmeError::Timeout | NvmeError::Command(_) => BlkError::Io,
+        }
+    }
+}
+
+pub struct Nvme<R, D: DAlloc, S: DSched> {
+    name: String,
+    regs: &'static Register,
+    irqs: Vec<u32>,
+    /// The irqs are raised by the pin based interrupt instead of the messages.
+    pin_based: bool,
+    admin: Mutex<R, Queue<D>>,
+    io_queues: Vec<Mutex<R, Queue<D>>>,
+    /// The I/O queue tried first by the next request.
+    next_queue: AtomicUsize,
+    /// Max pages of a transfer.
+    max_pages: usize,
+    /// Optional NVM commands supported.
+    oncs: u16,
+    /// The controller has a volatile write cache.
+    write_cache: bool,
+    phantom: PhantomData<S>,
+}
+
+unsafe impl<R, D: DAlloc, S: DSched> Sync for Nvme<R, D, S> {}
+unsafe impl<R, D: DAlloc, S: DSched> Send for Nvme<R, D, S> {}
+
+impl<R: RawMutex, D: DAlloc, S: DSched> Nvme<R, D, S> {
+    /// Create the driver from the virtual address of the BAR 0.
+    ///
+    /// The completion queues only raise interrupts if `irqs` isn't empty.
+    /// The pin based interrupt is level triggered, it's masked by the
+    /// handler until the waiting task consumes the completions.
+    pub fn new(addr: usize, irqs: Vec<u32>, pin_based: bool) -> Result<Self, NvmeError> {
+        let regs = unsafe { (addr as *const Register).as_ref().unwrap() };
+        let cap = regs.cap.extract();
+        // The NVM command set and 4KB pages are required.
+        if cap.read(CAP::CSS) & 1 == 0 || cap.read(CAP::MPSMIN) != 0 {
+            return Err(NvmeError::Unsupported);
+        }
+        let stride = 4 << cap.read(CAP::DSTRD);
+        let depth = cmp::min(QUEUE_DEPTH, cap.read(CAP::MQES) as usize + 1);
+
+        regs.cc.modify(CC::EN::CLEAR);
+        Self::wait_ready(regs, false)?;
+        let intmc = match pin_based && !irqs.is_empty() {
+            true => Some(&regs.intmc as *const _ as usize),
+            false => None,
+        };
+        let mut admin = Queue::<D>::new(0, depth, addr + DOORBELL_OFFSET, stride);
+        admin.intmc = intmc;
+        admin.irq = !irqs.is_empty();
+        regs.aqa
+            .write(AQA::ASQS.val(depth as u32 - 1) + AQA::ACQS.val(depth as u32 - 1));
+        regs.asq.set(admin.sq_paddr as u64);
+        regs.acq.set(admin.cq_paddr as u64);
+        regs.cc.write(
+            CC::EN::SET
+                + CC::CSS::Nvm
+                + CC::MPS.val(0)
+                + CC::AMS::RoundRobin
+                + CC::SHN::None
+                + CC::IOSQES.val(6)
+                + CC::IOCQES.val(4),
+        );
+        Self::wait_ready(regs, true)?;
+
+        let mut nvme = Self {
+            name: format!("nvme{}", NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            regs,
+            irqs,
+            pin_based,
+            admin: Mutex::new(admin),
+            io_queues: Vec::new(),
+            next_queue: AtomicUsize::new(0),
+            max_pages: PRP_LIST_LEN,
+            oncs: 0,
+            write_cache: false,
+            phantom: PhantomData,
+        };
+        nvme.identify_controller()?;
+        nvme.create_io_queues(addr + DOORBELL_OFFSET, stride, depth)?;
+        Ok(nvme)
+    }
+
+    /// Wait until the ready status of the controller is `ready`.
+    fn wait_ready(regs: &Register, ready: bool) -> Result<(), NvmeError> {
+        for _ in 0..TIMEOUT_POLLS {
+            let csts = regs.csts.extract();
+            if csts.is_set(CSTS::CFS) {
+                return Err(NvmeError::Fatal);
+            }
+            if csts.is_set(CSTS::RDY) == ready {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(NvmeError::Timeout)
+    }
+
+    /// Execute the admin command.
+    fn admin(&self, command: Command) -> Result<u32, NvmeError> {
+        self.admin.lock().execute::<S>(command)
+    }
+
+    /// Execute the identify command, returns the data structure.
+    fn identify(&self, cns: u32, nsid: u32) -> Result<Vec<u8>, NvmeError> {
+        let mut admin = self.admin.lock();
+        let bounce = admin.bounce;
+        admin.execute::<S>(Command {
+            opcode: ADMIN_IDENTIFY,
+            nsid,
+            prp1: bounce as u64,
+            cdw10: cns,
+            ..Default::default()
+        })?;
+        let data = D::phys_to_virt(bounce) as *const u8;
+        Ok(unsafe { core::slice::from_raw_parts(data, PAGE_SIZE) }.to_vec())
+    }
+
+    fn identify_controller(&mut self) -> Result<(), NvmeError> {
+        let data = self.identify(CNS_CONTROLLER, 0)?;
+        let serial = String::from_utf8_lossy(&data[4..24]);
+        let model = String::from_utf8_lossy(&data[24..64]);
+        // Max data transfer size in 2^n pages, 0 means no limit.
+        let mdts = data[77] as u32;
+        if mdts != 0 && 1 << mdts < self.max_pages {
+            self.max_pages = 1 << mdts;
+        }
+        self.oncs = u16::from_le_bytes([data[520], data[521]]);
+        self.write_cache = data[525] & 1 != 0;
+        info!(
+            "{}: {} serial {}, max transfer {} pages",
+            self.name,
+            model.trim(),
+            serial.trim(),
+            self.max_pages
+        );
+        Ok(())
+    }
+
+    /// Create the I/O queue pairs, all completion queues use the interrupt vector 0.
+    fn create_io_queues(
+        &mut self,
+        doorbells: usize,
+        stride: usize,
+        depth: usize,
+    ) -> Result<(), NvmeError> {
+        let wanted = MAX_IO_QUEUES as u32 - 1;
+        // The numbers of the submission and completion queues allocated, 0's based.
+        let allocated = self.admin(Command {
+            opcode: ADMIN_SET_FEATURES,
+            cdw10: FEATURE_NUM_QUEUES,
+            cdw11: (wanted << 16) | wanted,
+            ..Default::default()
+        })?;
+        let count = cmp::min(allocated & 0xffff, allocated >> 16) as usize + 1;
+        let ien = match self.irqs.is_empty() {
+            true => 0,
+            false => CQ_IEN,
+        };
+        for id in 1..=cmp::min(count, MAX_IO_QUEUES) as u16 {
+            let mut queue = Queue::<D>::new(id, depth, doorbells, stride);
+            queue.intmc = self.admin.lock().intmc;
+            queue.irq = !self.irqs.is_empty();
+            let size_id = ((depth as u32 - 1) << 16) | id as u32;
+            self.admin(Command {
+                opcode: ADMIN_CREATE_CQ,
+                prp1: queue.cq_paddr as u64,
+                cdw10: size_id,
+                cdw11: ien | QUEUE_PC,
+                ..Default::default()
+            })?;
+            self.admin(Command {
+                opcode: ADMIN_CREATE_SQ,
+                prp1: queue.sq_paddr as u64,
+                cdw10: size_id,
+                cdw11: ((id as u32) << 16) | QUEUE_PC,
+                ..Default::default()
+            })?;
+            self.io_queues.push(Mutex::new(queue));
+        }
+        info!("{}: {} I/O queues", self.name, self.io_queues.len());
+        Ok(())
+    }
+
+    /// Identify the active namespaces, every namespace is a block device.
+    pub fn namespaces(self: &Arc<Self>) -> Result<Vec<NvmeNamespace<R, D, S>>, NvmeError> {
+        let list = self.identify(CNS_ACTIVE_NAMESPACES, 0)?;
+        let namespaces = list
+            .chunks_exact(4)
+            .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+            .take_while(|nsid| *nsid != 0)
+            .filter_map(|nsid| {
+                let data = match self.identify(CNS_NAMESPACE, nsid) {
+                    Ok(data) => data,
+                    Err(err) => {
+                        warn!(
+                            "{}: can't identify namespace {}: {:?}",
+                            self.name, nsid, err
+                        );
+                        return None;
+                    }
+                };
+                let blocks = u64::from_le_bytes(data[0..8].try_into().unwrap()) as usize;
+                // The LBA format in use, its LBA data size is in log2.
+                let format = 128 + (data[26] & 0xf) as usize * 4;
+                let block_size = 1 << data[format + 2];
+                if !(0x200..=PAGE_SIZE).contains(&block_size) {
+                    warn!(
+                        "{}: namespace {} has unsupported block size {:#x}",
+                        self.name, nsid, block_size
+                    );
+                    return None;
+                }
+                let namespace = NvmeNamespace {
+                    name: format!("{}n{}", self.name, nsid),
+                    controller: self.clone(),
+                    nsid,
+                    blocks,
+                    block_size,
+                };
+                info!(
+                    "{}: {} blocks of {:#x} bytes",
+                    namespace.name, blocks, block_size
+                );
+                Some(namespace)
+            })
+            .collect();
+        Ok(namespaces)
+    }
+
+    /// Get a free I/O queue, or wait for the next one if all queues are busy.
+    fn io_queue(&self) -> MutexGuard<'_, R, Queue<D>> {
+        let start = self.next_queue.fetch_add(1, Ordering::Relaxed);
+        let count = self.io_queues.len();
+        (0..count)
+            .find_map(|i| self.io_queues[(start + i) % count].try_lock())
+            .unwrap_or_else(|| self.io_queues[start % count].lock())
+    }
+
+    /// Fill the PRP entries of the buffer, returns the PRP1 and PRP2 of the command.
+    ///
+    /// The buffer must be 4-byte aligned and take at most `max_pages` pages.
+    fn fill_prps(&self, queue: &Queue<D>, vaddr: usize, len: usize) -> (u64, u64) {
+        let prp1 = D::virt_to_phys(vaddr) as u64;
+        // The other entries point to the pages following the first one.
+        let pages = (vaddr / PAGE_SIZE * PAGE_SIZE + PAGE_SIZE..vaddr + len).step_by(PAGE_SIZE);
+        match pages.len() {
+            0 => (prp1, 0),
+            1 => (
+                prp1,
+                D::virt_to_phys(vaddr / PAGE_SIZE * PAGE_SIZE + PAGE_SIZE) as u64,
+            ),
+            _ => {
+                let list = D::phys_to_virt(queue.prp_list) as *mut u64;
+                for (i, page) in pages.enumerate() {
+                    unsafe { list.add(i).write_volatile(D::virt_to_phys(page) as u64) };
+                }
+                (prp1, queue.prp_list as u64)
+            }
+        }
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> Driver for Nvme<R, D, S> {
+    fn get_id(&self) -> &str {
+        &self.name
+    }
+
+    fn interrupts(&self) -> &[u32] {
+        &self.irqs
+    }
+
+    fn try_handle_interrupt(&self, irq: u32) -> bool {
+        if !self.irqs.contains(&irq) {
+            return false;
+        }
+        // The waiting tasks check their completion queues.
+        if self.pin_based {
+            self.regs.intms.set(1);
+        }
+        S::wake();
+        true
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::None
+    }
+}
+
+impl<R, D: DAlloc, S: DSched> Drop for Nvme<R, D, S> {
+    fn drop(&mut self) {
+        // Notify the controller to flush its cache before the queues are freed.
+        self.regs.cc.modify(CC::SHN::Normal);
+    }
+}
+
+/// A namespace of the NVMe controller.
+pub struct NvmeNamespace<R, D: DAlloc, S: DSched> {
+    name: String,
+    controller: Arc<Nvme<R, D, S>>,
+    nsid: u32,
+    blocks: usize,
+    block_size: usize,
+}
+
+impl<R: RawMutex, D: DAlloc, S: DSched> NvmeNamespace<R, D, S> {
+    /// Check the block range of the buffer.
+    fn check_range(&self, block_id: usize, len: usize) -> Result<(), BlkError> {
+        match len % self.block_size == 0 && block_id + len / self.block_size <= self.blocks {
+            true => Ok(()),
+            false => Err(BlkError::InvalidParam),
+        }
+    }
+
+    /// Read or write the blocks, the buffer is split into commands by the transfer size.
+    ///
+    /// Buffers not 4-byte aligned are bounced through a page.
+    fn transfer(
+        &self,
+        opcode: u8,
+        block_id: usize,
+        vaddr: usize,
+        len: usize,
+    ) -> Result<(), NvmeError> {
+        let controller = &self.controller;
+        let mut queue = controller.io_queue();
+        let mut offset = 0;
+        while offset < len {
+            let addr = vaddr + offset;
+            let aligned = addr % 4 == 0;
+            let max_len = match aligned {
+                true => controller.max_pages * PAGE_SIZE - addr % PAGE_SIZE,
+                false => PAGE_SIZE,
+            };
+            let size = cmp::min(len - offset, max_len) / self.block_size * self.block_size;
+            let bounce = D::phys_to_virt(queue.bounce) as *mut u8;
+            let (prp1, prp2) = match aligned {
+                true => controller.fill_prps(&queue, addr, size),
+                false => {
+                    if opcode == NVM_WRITE {
+                        unsafe { ptr::copy_nonoverlapping(addr as *const u8, bounce, size) };
+                    }
+                    (queue.bounce as u64, 0)
+                }
+            };
+            let lba = block_id + offset / self.block_size;
+            queue.execute::<S>(Command {
+                opcode,
+                nsid: self.nsid,
+                prp1,
+                prp2,
+                cdw10: lba as u32,
+                cdw11: (lba >> 32) as u32,
+                // Number of blocks, 0's based.
+                cdw12: (size / self.block_size - 1) as u32,
+                ..Default::default()
+            })?;
+            if !aligned && opcode == NVM_READ {
+                unsafe { ptr::copy_nonoverlapping(bounce, addr as *mut u8, size) };
+            }
+            offset += size;
+        }
+        Ok(())
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> Driver for NvmeNamespace<R, D, S> {
+    fn get_id(&self) -> &str {
+        &self.name
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::BLOCK(self.clone())
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> BlkDriver for NvmeNamespace<R, D, S> {
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlkError> {
+        self.check_range(block_id, buf.len())?;
+        self.transfer(NVM_READ, block_id, buf.as_mut_ptr() as usize, buf.len())?;
+        Ok(())
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), BlkError> {
+        self.check_range(block_id, buf.len())?;
+        self.transfer(NVM_WRITE, block_id, buf.as_ptr() as usize, buf.len())?;
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.blocks
+    }
+
+    fn flush(&self) -> Result<(), BlkError> {
+        if !self.controller.write_cache {
+            return Ok(());
+        }
+        self.controller.io_queue().execute::<S>(Command {
+            opcode: NVM_FLUSH,
+            nsid: self.nsid,
+            ..Default::default()
+        })?;
+        Ok(())
+    }
+
+    fn discard(&self, range: Range<usize>) -> Result<(), BlkError> {
+        if self.controller.oncs & ONCS_DSM == 0 {
+            return Err(BlkError::Unsupported);
+        }
+        if range.start > range.end || range.end > self.blocks {
+            return Err(BlkError::InvalidParam);
+        }
+        let mut queue = self.controller.io_queue();
+        let bounce = queue.bounce;
+        let entry = D::phys_to_virt(bounce) as *mut u32;
+        for start in range.clone().step_by(u32::MAX as usize) {
+            let blocks = cmp::min(range.end - start, u32::MAX as usize);
+            // A single range of the context attributes, the length and the start block.
+            unsafe {
+                entry.write_volatile(0);
+                entry.add(1).write_volatile(blocks as u32);
+                (entry.add(2) as *mut u64).write_volatile(start as u64);
+            }
+            queue.execute::<S>(Command {
+                opcode: NVM_DATASET_MANAGEMENT,
+                nsid: self.nsid,
+                prp1: bounce as u64,
+                // Number of ranges, 0's based.
+                cdw10: 0,
+                cdw11: DSM_AD,
+                ..Default::default()
+            })?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! Submission and completion queue pairs.
+
+use core::{
+    marker::PhantomData,
+    sync::atomic::{fence, Ordering},
+};
+
+use drivers_base::{DAlloc, DSched};
+
+use crate::{NvmeError, COMMAND_TIMEOUT, PAGE_SIZE};
+
+/// Max entries of a queue, the submission queue takes a page.
+pub(crate) const QUEUE_DEPTH: usize = PAGE_SIZE / core::mem::size_of::<Command>();
+
+/// A submission queue entry.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Command {
+    pub opcode: u8,
+    pub flags: u8,
+    pub cid: u16,
+    pub nsid: u32,
+    pub _reserved: u64,
+    pub mptr: u64,
+    pub prp1: u64,
+    pub prp2: u64,
+    pub cdw10: u32,
+    pub cdw11: u32,
+    pub cdw12: u32,
+    pub cdw13: u32,
+    pub cdw14: u32,
+    pub cdw15: u32,
+}
+
+/// A completion queue entry.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Completion {
+    /// Command specific result.
+    dw0: u32,
+    _dw1: u32,
+    sq_head: u16,
+    sq_id: u16,
+    cid: u16,
+    /// The phase tag in bit 0 and the status field.
+    status: u16,
+}
+
+/// A submission queue and its completion queue.
+///
+/// Commands are executed one by one, the caller waits for the completion
+/// of the command it submits.
+pub(crate) struct Queue<D: DAlloc> {
+    /// The queue id, 0 is the admin queue.
+    pub id: u16,
+    /// Entries of the queues.
+    pub depth: usize,
+    /// Physical address of the submission queue.
+    pub sq_paddr: usize,
+    /// Physical address of the completion queue.
+    pub cq_paddr: usize,
+    /// Physical address of the PRP list of the command.
+    pub prp_list: usize,
+    /// Physical address of the page bouncing the unaligned buffers.
+    pub bounce: usize,
+    sq_tail: usize,
+    cq_head: usize,
+    /// The phase tag of the new completion entries.
+    phase: bool,
+    /// Virtual address of the submission queue tail doorbell.
+    sq_doorbell: usize,
+    /// Virtual address of the completion queue head doorbell.
+    cq_doorbell: usize,
+    next_cid: u16,
+    /// Virtual address of the interrupt mask clear register if the pin
+    /// based interrupt is used, it's unmasked before waiting.
+    pub intmc: Option<usize>,
+    /// Whether the completion queue raises interrupts, it's polled by spinning otherwise.
+    pub irq: bool,
+    phantom: PhantomData<D>,
+}
+
+impl<D: DAlloc> Queue<D> {
+    /// Create the queue pair, `doorbells` is the virtual address of the
+    /// doorbell registers and `stride` is the size of every doorbell.
+    pub fn new(id: u16, depth: usize, doorbells: usize, stride: usize) -> Self {
+        let queue = Self {
+            id,
+            depth,
+            sq_paddr: D::alloc(1),
+            cq_paddr: D::alloc(1),
+            prp_list: D::alloc(1),
+            bounce: D::alloc(1),
+            sq_tail: 0,
+            cq_head: 0,
+            phase: true,
+            sq_doorbell: doorbells + (2 * id as usize) * stride,
+            cq_doorbell: doorbells + (2 * id as usize + 1) * stride,
+            next_cid: 0,
+            intmc: None,
+            irq: false,
+            phantom: PhantomData,
+        };
+        // The phase tags of the new entries are 0.
+        unsafe { (D::phys_to_virt(queue.cq_paddr) as *mut u8).write_bytes(0, PAGE_SIZE) };
+        queue
+    }
+
+    /// Submit the command and wait for its completion.
+    ///
+    /// Returns the command specific result. The task sleeps between polls
+    /// and it is woken up by the interrupt if the controller has irqs,
+    /// otherwise it spins.
+    pub fn execute<S: DSched>(&mut self, mut command: Command) -> Result<u32, NvmeError> {
+        command.cid = self.next_cid;
+        self.next_cid = self.next_cid.wrapping_add(1);
+        unsafe {
+            (D::phys_to_virt(self.sq_paddr) as *mut Command)
+                .add(self.sq_tail)
+                .write_volatile(command);
+        }
+        // The command and its buffers must be visible before ringing the doorbell.
+        fence(Ordering::SeqCst);
+        self.sq_tail = (self.sq_tail + 1) % self.depth;
+        unsafe { (self.sq_doorbell as *mut u32).write_volatile(self.sq_tail as u32) };
+
+        let deadline = S::now() + COMMAND_TIMEOUT;
+        while S::now() < deadline {
+            let completion = unsafe {
+                (D::phys_to_virt(self.cq_paddr) as *const Completion)
+                    .add(self.cq_head)
+                    .read_volatile()
+            };
+            if (completion.status & 1 != 0) != self.phase {
+                if let Some(intmc) = self.intmc {
+                    unsafe { (intmc as *mut u32).write_volatile(1) };
+                }
+                match self.irq {
+                    true => S::sleep(),
+                    false => core::hint::spin_loop(),
+                }
+                continue;
+            }
+            fence(Ordering::SeqCst);
+            self.cq_head = (self.cq_head + 1) % self.depth;
+            if self.cq_head == 0 {
+                self.phase = !self.phase;
+            }
+            unsafe { (self.cq_doorbell as *mut u32).write_volatile(self.cq_head as u32) };
+
+            if completion.cid != command.cid {
+                warn!(
+                    "nvme queue {}: completion of command {} but {} is waited",
+                    self.id, completion.cid, command.cid
+                );
+                continue;
+            }
+            return match completion.status >> 1 {
+                0 => Ok(completion.dw0),
+                status => Err(NvmeError::Command(status)),
+            };
+        }
+        Err(NvmeError::Timeout)
+    }
+}
+
+impl<D: DAlloc> Drop for Queue<D> {
+    fn drop(&mut self) {
+        D::dealloc(self.sq_paddr, 1);
+        D::dealloc(self.cq_paddr, 1);
+        D::dealloc(self.prp_list, 1);
+        D::dealloc(self.bounce, 1);
+    }
+}
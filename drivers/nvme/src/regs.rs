@@ -0,0 +1,82 @@
+//! Controller registers of NVMe.
+//!
+//! See the NVM Express Base Specification, Controller Registers.
+
+use tock_registers::{
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+register_structs! {
+    pub(crate) Register {
+        (0x00 => pub cap: ReadOnly<u64, CAP::Register>),
+        (0x08 => pub vs: ReadOnly<u32>),
+        (0x0c => pub intms: ReadWrite<u32>),
+        (0x10 => pub intmc: ReadWrite<u32>),
+        (0x14 => pub cc: ReadWrite<u32, CC::Register>),
+        (0x18 => _reserved0),
+        (0x1c => pub csts: ReadOnly<u32, CSTS::Register>),
+        (0x20 => _reserved1),
+        (0x24 => pub aqa: ReadWrite<u32, AQA::Register>),
+        (0x28 => pub asq: ReadWrite<u64>),
+        (0x30 => pub acq: ReadWrite<u64>),
+        (0x38 => @END),
+    }
+}
+
+register_bitfields! [
+    u64,
+    // Controller capabilities.
+    pub CAP [
+        // Max queue entries supported, 0's based.
+        MQES OFFSET(0) NUMBITS(16) [],
+        // Timeout of the ready status, in 500ms.
+        TO OFFSET(24) NUMBITS(8) [],
+        // Doorbell stride, 4 << DSTRD bytes.
+        DSTRD OFFSET(32) NUMBITS(4) [],
+        // Command sets supported, bit 0 is the NVM command set.
+        CSS OFFSET(37) NUMBITS(8) [],
+        // Min memory page size, 4KB << MPSMIN.
+        MPSMIN OFFSET(48) NUMBITS(4) [],
+        MPSMAX OFFSET(52) NUMBITS(4) [],
+    ],
+];
+
+register_bitfields! [
+    u32,
+    // Controller configuration.
+    pub CC [
+        EN OFFSET(0) NUMBITS(1) [],
+        // I/O command set selected.
+        CSS OFFSET(4) NUMBITS(3) [
+            Nvm = 0
+        ],
+        // Memory page size, 4KB << MPS.
+        MPS OFFSET(7) NUMBITS(4) [],
+        // Arbitration mechanism selected.
+        AMS OFFSET(11) NUMBITS(3) [
+            RoundRobin = 0
+        ],
+        // Shutdown notification.
+        SHN OFFSET(14) NUMBITS(2) [
+            None = 0,
+            Normal = 1,
+            Abrupt = 2
+        ],
+        // I/O submission and completion queue entry sizes in log2.
+        IOSQES OFFSET(16) NUMBITS(4) [],
+        IOCQES OFFSET(20) NUMBITS(4) [],
+    ],
+    // Controller status.
+    pub CSTS [
+        RDY OFFSET(0) NUMBITS(1) [],
+        // Controller fatal status.
+        CFS OFFSET(1) NUMBITS(1) [],
+        SHST OFFSET(2) NUMBITS(2) [],
+    ],
+    // Admin queue attributes, 0's based sizes.
+    pub AQA [
+        ASQS OFFSET(0) NUMBITS(12) [],
+        ACQS OFFSET(16) NUMBITS(12) [],
+    ],
+];
@@ -1,70 +1,170 @@
 #![no_std]
 
+extern crate alloc;
+
 use core::{
     cmp,
     marker::PhantomData,
-    mem::transmute,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{fence, AtomicUsize, Ordering},
+    time::Duration,
 };
 
-use drivers_base::DAlloc;
+use alloc::{sync::Arc, vec::Vec};
+use drivers_base::{BlkDriver, BlkError, DAlloc, DSched, DeviceType, Driver};
+use lock_api::{Mutex, RawMutex};
 use regs::{
     BlkCnt, Capability, Capability2,
     ClkCtl::{self, TOUT_CNT},
-    CommandType,
-    ErrInt::{self, XFER_CMPL},
-    PresentStatus, Register, XferCmd, ADMA2_DT, PWRLVL,
+    CommandType, ErrInt, PresentStatus, Register, XferCmd, ADMA2_DT, PWRLVL,
 };
 use tock_registers::{
+    fields::FieldValue,
     interfaces::{ReadWriteable, Readable, Writeable},
     registers::InMemoryRegister,
+    LocalRegisterCopy,
 };
 
 mod regs;
 
 pub const SUPPORT_PCI_DEVICE: &[(u32, u32)] = &[(0x1b36, 0x0007)];
 
-pub struct SDCard<D: DAlloc> {
+/// Size of a block of the sdcard.
+const BLOCK_SIZE: usize = 0x200;
+/// Max blocks transferred by a single command.
+const MAX_BLOCKS_PER_CMD: usize = 0x400;
+/// Clock frequency in the identification mode.
+const IDENT_CLK: u32 = 400_000;
+/// Clock frequency in the default speed mode.
+const DEFAULT_CLK: u32 = 25_000_000;
+/// Clock frequency in the high speed mode.
+const HS_CLK: u32 = 50_000_000;
+/// Argument of CMD8, 2.7-3.6V and the check pattern.
+const CMD8_ARG: u32 = 0x1aa;
+/// Max polls of the status registers before timing out.
+const TIMEOUT_POLLS: usize = 0x100_0000;
+/// Time waiting for the data interrupts before timing out.
+const DATA_TIMEOUT: Duration = Duration::from_secs(5);
+/// Max retries of ACMD41 while the card is powering up.
+const ACMD41_RETRIES: usize = 1000;
+/// Spins between the retries.
+const RETRY_DELAY: usize = 0x1000;
+/// Page size of the DMA buffers.
+const PAGE_SIZE: usize = 0x1000;
+/// Number of descriptors in the ADMA2 descriptor table, the table takes a page.
+const ADMA2_DT_LEN: usize = PAGE_SIZE / 8;
+/// Max length of an ADMA2 descriptor.
+const ADMA2_MAX_LEN: usize = 0x8000;
+/// Error interrupt signals.
+const ERR_INT_SIGNALS: u32 = 0x07ff_0000;
+
+/// SD card error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SDError {
+    /// No card is inserted.
+    NoCard,
+    /// The card or the host doesn't respond in time.
+    Timeout,
+    /// The command failed with the error status.
+    Command(u32),
+    /// The data transfer failed with the error status.
+    Data(u32),
+    /// The card doesn't support the voltage of the host.
+    UnsupportedVoltage,
+    /// The card isn't supported by the driver.
+    Unsupported,
+}
+
+impl From<SDError> for BlkError {
+    fn from(err: SDError) -> Self {
+        match err {
+            SDError::NoCard => BlkError::NotReady,
+            SDError::Unsupported | SDError::UnsupportedVoltage => BlkError::Unsupported,
+            SDError::Timeout | SDError::Command(_) | SDError::Data(_) => BlkError::Io,
+        }
+    }
+}
+
+/// Data transfer mode of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferMode {
+    /// The data is moved through the buffer data port by the cpu.
+    Pio,
+    /// Single operation DMA, the host stops at every page boundary
+    /// and the next page address is set by the driver.
+    Sdma,
+    /// Advanced DMA, the host walks the descriptor table of the buffer.
+    Adma2,
+}
+
+/// The direction and buffer of a data transfer.
+enum Buffer<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+pub struct SDCard<R, D: DAlloc, S: DSched> {
     regs: &'static Register,
     rsa: u32,
-    dma: bool,
+    /// The DMA mode used by the block transfers.
+    mode: TransferMode,
+    irqs: Vec<u32>,
+    /// SDHC/SDXC cards are addressed by blocks, SDSC cards are addressed by bytes.
+    high_capacity: bool,
+    /// Number of blocks of the card, decoded from the CSD register.
+    blocks: usize,
     adma2_dt: (AtomicUsize, AtomicUsize),
-    phantom: PhantomData<D>,
+    /// Only one data transfer can be in progress.
+    lock: Mutex<R, ()>,
+    phantom: PhantomData<(D, S)>,
 }
 
-impl<D: DAlloc> SDCard<D> {
-    pub fn new(addr: usize, dma: bool) -> Self {
+unsafe impl<R, D: DAlloc, S: DSched> Sync for SDCard<R, D, S> {}
+unsafe impl<R, D: DAlloc, S: DSched> Send for SDCard<R, D, S> {}
+
+impl<R: RawMutex, D: DAlloc, S: DSched> SDCard<R, D, S> {
+    /// Create the sdcard driver and initialize the inserted card.
+    ///
+    /// ADMA2 is preferred when `dma` is true, SDMA and PIO are used if the host
+    /// doesn't support it. Transfers are polled if `irqs` is empty.
+    pub fn new(addr: usize, dma: bool, irqs: Vec<u32>) -> Result<Self, SDError> {
+        let regs = unsafe {
+            (addr as *const Register)
+                .as_ref()
+                .expect("Can't create SDCard")
+        };
+        let mode = if !dma {
+            TransferMode::Pio
+        } else if regs.cap1.is_set(Capability::ADMA2_SUPPORT) {
+            TransferMode::Adma2
+        } else if regs.cap1.is_set(Capability::SDMA_SUPPORT) {
+            TransferMode::Sdma
+        } else {
+            TransferMode::Pio
+        };
+        log::info!("sdcard transfer mode: {:?}", mode);
         let mut sd = Self {
-            regs: unsafe {
-                (addr as *const Register)
-                    .as_ref()
-                    .expect("Can't create SDCard")
-            },
-            dma,
+            regs,
+            mode,
+            irqs,
             rsa: 0,
+            high_capacity: false,
+            blocks: 0,
             adma2_dt: Default::default(),
-            phantom: PhantomData::default(),
+            lock: Mutex::new(()),
+            phantom: PhantomData,
         };
-        sd.init_sd();
-        if dma {
+        if mode == TransferMode::Adma2 {
             let paddr = D::alloc(1);
             let vaddr = D::phys_to_virt(paddr);
             sd.adma2_dt.0.store(paddr, Ordering::SeqCst);
             sd.adma2_dt.1.store(vaddr, Ordering::SeqCst);
         }
-        if sd.check_sd() {
-            // TODO: ReSet Configuration
-            sd.reset_config();
-            sd.set_clk(4);
-
-            // Setting Power
-            // sd.regs
-            //     .pwr_bg
-            //     .write(PWRLVL::PWR_EN::SET + PWRLVL::VOL_SEL::V33);
-
-            sd.test_transfer();
+        if !sd.check_sd() {
+            return Err(SDError::NoCard);
         }
-        sd
+        sd.reset_config()?;
+        sd.init_sd()?;
+        Ok(sd)
     }
 
     /// Get the base address of the sdcard register block.
@@ -72,39 +172,55 @@ impl<D: DAlloc> SDCard<D> {
         self.regs as *const _ as usize
     }
 
-    /// Set the clock for the sdcard.
-    pub fn set_clk(&self, clk: u32) {
+    /// Set the frequency of the sdcard clock.
+    ///
+    /// The clock is divided from the base clock, the result is the
+    /// highest frequency not exceeding `freq`.
+    pub fn set_clk(&self, freq: u32) -> Result<(), SDError> {
+        let base = self.regs.cap1.read(Capability::BASE_CLK_FREQ) * 1_000_000;
+        // SDCLK = base / (2 * div), div 0 means the base clock itself.
+        let div = match base {
+            // The base clock isn't reported, use the max divider.
+            0 => 0xff,
+            _ if base <= freq => 0,
+            _ => cmp::min(base.div_ceil(2 * freq), 0xff),
+        };
+        log::debug!("sdcard clock: {} Hz, base: {} Hz, div: {}", freq, base, div);
+
+        self.regs.clk_ctl.modify(ClkCtl::SD_CLK_EN::CLEAR);
         self.regs
             .clk_ctl
-            .modify(ClkCtl::SD_CLK_EN::CLEAR + ClkCtl::FREQ_SEL.val(clk) + ClkCtl::INT_CLK_EN::SET);
-
-        loop {
-            if self.regs.clk_ctl.is_set(ClkCtl::INT_CLK_STABLE) {
-                break;
-            }
-        }
+            .modify(ClkCtl::FREQ_SEL.val(div) + ClkCtl::INT_CLK_EN::SET);
+        self.wait_for(|| self.regs.clk_ctl.is_set(ClkCtl::INT_CLK_STABLE))?;
         self.regs.clk_ctl.modify(ClkCtl::SD_CLK_EN::SET);
+        Ok(())
     }
 
-    /// Reset SDIO Configuration
-    #[inline]
-    pub fn reset_config(&self) {
-        log::info!("cmd status: {:#x}", self.regs.err_int.get());
-
-        // Cleat All Bits
-        self.regs.pwr_bg.write(PWRLVL::VOL_SEL::CLEAR);
-        self.regs
-            .clk_ctl
-            .modify_no_read(self.regs.clk_ctl.extract(), ClkCtl::SOFT_RST_ALL::CLEAR);
-
-        self.regs
-            .pwr_bg
-            .write(PWRLVL::PWR_EN::SET + PWRLVL::VOL_SEL::V18);
-
-        match self.dma {
-            true => self.regs.pwr_bg.modify(PWRLVL::DMA_SEL::ADMA2),
-            false => self.regs.pwr_bg.modify(PWRLVL::HS_EN::SET),
+    /// Reset the host controller, power up the card and set the identification clock.
+    pub fn reset_config(&self) -> Result<(), SDError> {
+        self.regs.clk_ctl.modify(ClkCtl::SOFT_RST_ALL::SET);
+        self.wait_for(|| !self.regs.clk_ctl.is_set(ClkCtl::SOFT_RST_ALL))?;
+
+        // Use the highest voltage supported by the host.
+        let voltage = if self.regs.cap1.is_set(Capability::V33_SUPPORT) {
+            PWRLVL::VOL_SEL::V33
+        } else if self.regs.cap1.is_set(Capability::V30_SUPPORT) {
+            PWRLVL::VOL_SEL::V30
+        } else {
+            return Err(SDError::UnsupportedVoltage);
+        };
+        self.regs.pwr_bg.write(voltage + PWRLVL::PWR_EN::SET);
+        match self.mode {
+            TransferMode::Adma2 => self.regs.pwr_bg.modify(PWRLVL::DMA_SEL::ADMA2),
+            TransferMode::Sdma => self.regs.pwr_bg.modify(PWRLVL::DMA_SEL::SDMA),
+            TransferMode::Pio => {}
         }
+
+        // Enable all status bits, the signals are enabled while waiting for them.
+        self.regs.int_en.set(0xffff_ffff);
+        self.regs.int_sig.set(0);
+        self.regs.clk_ctl.modify(TOUT_CNT.val(0xe));
+        self.set_clk(IDENT_CLK)
     }
 
     /// check the sdcard that was inserted
@@ -114,7 +230,12 @@ impl<D: DAlloc> SDCard<D> {
         self.regs.status.is_set(PresentStatus::PRESENT)
     }
 
-    pub fn init_sd(&mut self) {
+    /// Identify the card and put it in the transfer state.
+    ///
+    /// The card goes through idle (CMD0), voltage check (CMD8), ready (ACMD41),
+    /// identification (CMD2, CMD3) and standby (CMD9) states,
+    /// then it is selected (CMD7) and configured for data transfers.
+    pub fn init_sd(&mut self) -> Result<(), SDError> {
         log::debug!(
             "V18    Support: {:#x?}",
             self.regs.cap1.is_set(Capability::V18_SUPPORT)
@@ -158,198 +279,441 @@ impl<D: DAlloc> SDCard<D> {
             self.regs.cap2.read(Capability2::CLK_MULTIPLIER)
         );
 
-        self.cmd_transfer(CommandType::CMD(0), 0, 0);
-        self.cmd_transfer(CommandType::CMD(8), 0x1aa, 0);
-
-        // wait for initialization to end.
-        const XSDPS_ACMD41_HCS: u32 = 0x4000_0000;
-        const XSDPS_ACMD41_3V3: u32 = 0x0030_0000;
-        loop {
-            self.cmd_transfer(CommandType::CMD(55), 0, 0);
-            self.cmd_transfer(
-                CommandType::ACMD(41),
-                XSDPS_ACMD41_HCS | XSDPS_ACMD41_3V3,
-                0,
-            );
+        self.cmd_transfer(CommandType::CMD(0), 0, 0)?;
+
+        // Cards before the physical layer spec 2.00 don't respond to CMD8.
+        let v2 = match self.cmd_transfer(CommandType::CMD(8), CMD8_ARG, 0) {
+            Ok(resp) if resp[0] & 0xfff == CMD8_ARG => true,
+            Ok(resp) => {
+                log::warn!("sdcard CMD8 check pattern mismatch: {:#x}", resp[0]);
+                return Err(SDError::UnsupportedVoltage);
+            }
+            Err(SDError::Timeout) => false,
+            Err(err) => return Err(err),
+        };
 
-            if self.regs.resp[0].get() >> 31 == 1 {
+        // Wait for the card to finish the power up, SDHC/SDXC cards are
+        // only reported when the host supports them (HCS).
+        const ACMD41_HCS: u32 = 0x4000_0000;
+        const ACMD41_3V3: u32 = 0x0030_0000;
+        const OCR_BUSY: u32 = 0x8000_0000;
+        const OCR_CCS: u32 = 0x4000_0000;
+        let arg = match v2 {
+            true => ACMD41_HCS | ACMD41_3V3,
+            false => ACMD41_3V3,
+        };
+        let mut ocr = 0;
+        for _ in 0..ACMD41_RETRIES {
+            self.cmd_transfer(CommandType::CMD(55), 0, 0)?;
+            ocr = self.cmd_transfer(CommandType::ACMD(41), arg, 0)?[0];
+            if ocr & OCR_BUSY != 0 {
                 break;
             }
+            self.delay();
+        }
+        if ocr & OCR_BUSY == 0 {
+            log::warn!("sdcard power up timeout, ocr: {:#x}", ocr);
+            return Err(SDError::Timeout);
         }
+        self.high_capacity = ocr & OCR_CCS != 0;
+
         // Verify the sdcard inserted, and get CID information.
-        self.cmd_transfer(CommandType::CMD(2), 0, 0);
+        let cid = self.cmd_transfer(CommandType::CMD(2), 0, 0)?;
         log::info!(
             "OEM: {}{} DEVICE: {}{}{}{}{} MDT: {}/{}",
-            self.regs.resp[3].get().to_le_bytes()[2] as char,
-            self.regs.resp[3].get().to_le_bytes()[1] as char,
-            self.regs.resp[3].get().to_le_bytes()[0] as char,
-            self.regs.resp[2].get().to_le_bytes()[3] as char,
-            self.regs.resp[2].get().to_le_bytes()[2] as char,
-            self.regs.resp[2].get().to_le_bytes()[1] as char,
-            self.regs.resp[2].get().to_le_bytes()[0] as char,
-            (self.regs.resp[0].get() >> 12) & 0xff,
-            (self.regs.resp[0].get() >> 8) & 0xf,
+            cid[3].to_le_bytes()[2] as char,
+            cid[3].to_le_bytes()[1] as char,
+            cid[3].to_le_bytes()[0] as char,
+            cid[2].to_le_bytes()[3] as char,
+            cid[2].to_le_bytes()[2] as char,
+            cid[2].to_le_bytes()[1] as char,
+            cid[2].to_le_bytes()[0] as char,
+            (cid[0] >> 12) & 0xff,
+            (cid[0] >> 8) & 0xf,
         );
         // Broadcast rsa of the sdcard.
-        self.cmd_transfer(CommandType::CMD(3), 0, 0);
-        self.rsa = self.regs.resp[0].get();
+        self.rsa = self.cmd_transfer(CommandType::CMD(3), 0, 0)?[0] & 0xffff_0000;
+        self.read_csd()?;
+
+        // Select SD Card, if 0 cancel all selected
+        self.cmd_transfer(CommandType::CMD(7), self.rsa, 0)?;
+        // SDSC cards may use another block length.
+        if !self.high_capacity {
+            self.cmd_transfer(CommandType::CMD(16), BLOCK_SIZE as _, 0)?;
+        }
+
+        // Use the 4-bit data bus.
+        self.cmd_transfer(CommandType::CMD(55), self.rsa, 0)?;
+        self.cmd_transfer(CommandType::ACMD(6), 0x2, 0)?;
+        self.regs.pwr_bg.modify(PWRLVL::DAT_WIDTH::BIT4);
+
+        let freq = match v2 && self.regs.cap1.is_set(Capability::HS_SUPPORT) {
+            true => match self.switch_high_speed() {
+                Ok(true) => HS_CLK,
+                Ok(false) => DEFAULT_CLK,
+                Err(err) => {
+                    log::warn!("sdcard can't switch to high speed: {:?}", err);
+                    self.reset_lines();
+                    DEFAULT_CLK
+                }
+            },
+            false => DEFAULT_CLK,
+        };
+        self.set_clk(freq)?;
+        log::info!(
+            "sdcard: {}, {} MB, {} Hz",
+            match self.high_capacity {
+                true => "SDHC/SDXC",
+                false => "SDSC",
+            },
+            (self.blocks * BLOCK_SIZE) >> 20,
+            freq
+        );
+        Ok(())
     }
 
-    pub fn test_transfer(&self) {
-        // Read CSD Register
-        let res = self.cmd_transfer(CommandType::CMD(9), self.rsa, 0);
-        log::info!("version: {}", self.regs.resp[3].get() >> 30);
-        log::info!("version: {}", (self.regs.resp[1].get() >> 24) & 0x3);
+    /// Read the CSD register and get the number of blocks of the card.
+    pub fn read_csd(&mut self) -> Result<(), SDError> {
+        let res = self.cmd_transfer(CommandType::CMD(9), self.rsa, 0)?;
+        // The response registers don't include the CRC byte,
+        // so the CSD fields are shifted right by 8 bits.
         let resp = ((res[3] as u128) << 96)
             | ((res[2] as u128) << 64)
             | ((res[1] as u128) << 32)
             | res[0] as u128;
-        let c_size = (resp >> 62) & 0xfff;
-        let size_multi = (resp >> 47) & 0x7;
-        let sector_size = (resp >> 80) & 0xf;
-        log::info!(
-            "c_size: {c_size:#x}  multi: {size_multi}  sec_size: {sector_size}  nr: {:#x}",
-            (c_size + 1) * (1 << (2 + size_multi))
-        );
-        log::info!("sectore size: {:#x}", (1 << sector_size));
-        log::info!(
-            "sdcard size: {} MB",
-            (c_size + 1) << (size_multi + 2 + sector_size - 10 - 10)
-        );
-        // Select SD Card, if 0 cancel all selected
-        self.cmd_transfer(CommandType::CMD(7), self.rsa, 0);
-
-        // self.cmd_transfer(CommandType::CMD(55), rsa, 0);
-        // // Read SCR Register
-        // self.cmd_transfer(CommandType::ACMD(51), 0, 0);
+        self.blocks = match (resp >> 118) & 0x3 {
+            // CSD version 1.0, SDSC.
+            0 => {
+                let c_size = (resp >> 54) & 0xfff;
+                let size_multi = (resp >> 39) & 0x7;
+                let sector_size = (resp >> 72) & 0xf;
+                log::debug!("c_size: {c_size:#x}  multi: {size_multi}  sec_size: {sector_size}");
+                ((c_size + 1) << (size_multi + 2 + sector_size)) as usize / BLOCK_SIZE
+            }
+            // CSD version 2.0, SDHC/SDXC, the capacity is (c_size + 1) * 512KB.
+            1 => {
+                let c_size = (resp >> 40) & 0x3f_ffff;
+                log::debug!("c_size: {c_size:#x}");
+                (c_size as usize + 1) * 1024
+            }
+            version => {
+                log::warn!("unsupported sdcard CSD version: {}", version);
+                return Err(SDError::Unsupported);
+            }
+        };
+        Ok(())
+    }
 
-        // self.cmd_transfer(CommandType::CMD(55), rsa, 0);
-        // self.cmd_transfer(CommandType::ACMD(6), 0, 0);
+    /// Switch the card to the high speed mode by CMD6.
+    ///
+    /// Returns false if the card doesn't support the high speed mode.
+    fn switch_high_speed(&self) -> Result<bool, SDError> {
+        // Switch function group 1 (access mode) to function 1 (high speed).
+        const CMD6_SWITCH_HS: u32 = 0x80ff_fff1;
+        let mut status = [0u8; 64];
+        self.send_cmd(
+            CommandType::CMD(6),
+            CMD6_SWITCH_HS,
+            1,
+            status.len(),
+            TransferMode::Pio,
+        )?;
+        self.wait_int(ErrInt::BUF_RR::SET)?;
+        for word in status.chunks_exact_mut(4) {
+            word.copy_from_slice(&self.regs.bf_data.get().to_le_bytes());
+        }
+        self.wait_int(ErrInt::XFER_CMPL::SET)?;
 
-        // Test Read
-        let mut buffer = [0u8; 0x1000];
-        self.read_block(0, &mut buffer);
-        for i in 0..10 {
-            log::info!("data: {:#x}", buffer[i]);
+        // The switch status is big endian, bits 379:376 are the result of group 1.
+        if status[16] & 0xf != 1 {
+            return Ok(false);
         }
-        for i in 0..10 {
-            log::info!("data: {:#x}", buffer[0x200 + i]);
+        self.regs.pwr_bg.modify(PWRLVL::HS_EN::SET);
+        Ok(true)
+    }
+
+    /// Relax the cpu for a while between retries.
+    fn delay(&self) {
+        for _ in 0..RETRY_DELAY {
+            core::hint::spin_loop();
         }
+    }
 
-        log::info!(
-            "pwr: {:#x?}",
-            self.regs
-                .pwr_bg
-                .read_as_enum::<PWRLVL::VOL_SEL::Value>(PWRLVL::VOL_SEL)
-        );
-        loop {}
+    /// Poll the condition until it is true or timed out.
+    fn wait_for(&self, cond: impl Fn() -> bool) -> Result<(), SDError> {
+        for _ in 0..TIMEOUT_POLLS {
+            if cond() {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(SDError::Timeout)
     }
 
-    /// Read Block From SD Card, IO Mode Transfer, Not DMA.
+    /// Read blocks from the sdcard.
     ///
     /// Block size is 0x200, 512 Bytes. Make sure that size of buffer aligned with 0x200.
     /// Read size relying on the buffer.
-    pub fn read_block(&self, blk_off: u32, buffer: &mut [u8]) {
-        assert!(buffer.len() % 0x200 == 0);
-        let blk_cnt = buffer.len() / 0x200;
-
-        // If Read Mode is DMA.
-        // TODO: Check ADMA Support and using SDMA When ADMA is not available
-        if self.dma {
-            let adma_dt = self.adma2_dt();
-            let mut idx = 0;
-            let mut buffer_vaddr = buffer.as_ptr() as usize;
-            let mut last = buffer.len();
-            loop {
-                adma_dt[idx].write(
-                    ADMA2_DT::VALID::SET
-                        + ADMA2_DT::INT::SET
-                        + ADMA2_DT::ACT::Tran
-                        + ADMA2_DT::ADDR.val(D::virt_to_phys(buffer_vaddr) as _),
-                );
-                let len = match buffer_vaddr % 0x100 != 0 {
-                    true => cmp::min(0x1000 - (buffer_vaddr % 0x100), last),
-                    false => cmp::min(0x1000, last),
-                };
-                adma_dt[idx].modify(ADMA2_DT::LEN.val(len as _));
-                buffer_vaddr += len;
-                last -= len;
-                idx += 1;
-                if last == 0 {
-                    break;
+    pub fn read_block(&self, blk_off: u32, buffer: &mut [u8]) -> Result<(), BlkError> {
+        self.transfer(blk_off, Buffer::Read(buffer))
+    }
+
+    /// Write blocks to the sdcard.
+    ///
+    /// Block size is 0x200, 512 Bytes. Make sure that size of buffer aligned with 0x200.
+    /// Write size relying on the buffer.
+    pub fn write_block(&self, blk_off: u32, buffer: &[u8]) -> Result<(), BlkError> {
+        self.transfer(blk_off, Buffer::Write(buffer))
+    }
+
+    /// Transfer blocks with a single block command (CMD17/CMD24) or
+    /// a multiple block command (CMD18/CMD25) stopped by CMD12.
+    fn transfer(&self, blk_off: u32, mut buffer: Buffer) -> Result<(), BlkError> {
+        let (vaddr, len) = match &buffer {
+            Buffer::Read(buf) => (buf.as_ptr() as usize, buf.len()),
+            Buffer::Write(buf) => (buf.as_ptr() as usize, buf.len()),
+        };
+        if len == 0 || len % BLOCK_SIZE != 0 {
+            return Err(BlkError::InvalidParam);
+        }
+        let blk_cnt = len / BLOCK_SIZE;
+        let arg = match self.high_capacity {
+            true => blk_off,
+            false => blk_off * BLOCK_SIZE as u32,
+        };
+
+        let _guard = self.lock.lock();
+        let mode = self.request_mode(vaddr, len);
+        match mode {
+            TransferMode::Adma2 => self.setup_adma(vaddr, len),
+            TransferMode::Sdma => self.regs.addr.set(D::virt_to_phys(vaddr) as _),
+            TransferMode::Pio => {}
+        }
+        let cmd = match (&buffer, blk_cnt) {
+            (Buffer::Read(_), 1) => CommandType::CMD(17),
+            (Buffer::Read(_), _) => CommandType::CMD(18),
+            (Buffer::Write(_), 1) => CommandType::CMD(24),
+            (Buffer::Write(_), _) => CommandType::CMD(25),
+        };
+        let res = self
+            .send_cmd(cmd, arg, blk_cnt as u32, BLOCK_SIZE, mode)
+            .and_then(|_| self.transfer_data(&mut buffer, vaddr, blk_cnt, mode));
+        if let Err(err) = res {
+            log::warn!("sdcard transfer at block {} failed: {:?}", blk_off, err);
+            self.reset_lines();
+        }
+        // The card stays in the data state after multiple block transfers.
+        if blk_cnt > 1 {
+            self.cmd_transfer(CommandType::CMD(12), 0, 0)?;
+        }
+        Ok(res?)
+    }
+
+    /// Get the transfer mode of the buffer.
+    ///
+    /// The DMA engines only address 32-bit physical memory, and ADMA2
+    /// requires 4-byte aligned addresses, PIO is used otherwise.
+    fn request_mode(&self, vaddr: usize, len: usize) -> TransferMode {
+        let addressable = (vaddr..vaddr + len)
+            .step_by(PAGE_SIZE)
+            .chain([vaddr + len - 1])
+            .all(|x| D::virt_to_phys(x) <= u32::MAX as usize);
+        match self.mode {
+            TransferMode::Adma2 if addressable && vaddr % 4 == 0 => TransferMode::Adma2,
+            TransferMode::Sdma if addressable => TransferMode::Sdma,
+            _ => TransferMode::Pio,
+        }
+    }
+
+    /// Transfer the data of the command, the data is moved by the host in DMA mode.
+    fn transfer_data(
+        &self,
+        buffer: &mut Buffer,
+        vaddr: usize,
+        blk_cnt: usize,
+        mode: TransferMode,
+    ) -> Result<(), SDError> {
+        match mode {
+            TransferMode::Pio => {
+                for idx in 0..blk_cnt {
+                    let block = idx * BLOCK_SIZE..(idx + 1) * BLOCK_SIZE;
+                    match buffer {
+                        Buffer::Read(buf) => {
+                            self.wait_int(ErrInt::BUF_RR::SET)?;
+                            for word in buf[block].chunks_exact_mut(4) {
+                                word.copy_from_slice(&self.regs.bf_data.get().to_le_bytes());
+                            }
+                        }
+                        Buffer::Write(buf) => {
+                            self.wait_int(ErrInt::BUF_WR::SET)?;
+                            for word in buf[block].chunks_exact(4) {
+                                self.regs
+                                    .bf_data
+                                    .set(u32::from_le_bytes(word.try_into().unwrap()));
+                            }
+                        }
+                    }
                 }
             }
-            adma_dt[buffer.len() / 0x1000].modify(ADMA2_DT::END::SET);
-            self.regs
-                .adma_addr
-                .set(self.adma2_dt.0.load(Ordering::SeqCst) as _);
-        }
-
-        // Send Write CMD, IO Mode, Not DMA Mode
-        self.cmd_transfer(CommandType::CMD(18), blk_off, blk_cnt as u32);
-
-        // Read Transfer Data.
-        for idx in 0..blk_cnt {
-            assert!(!self.regs.err_int.is_set(ErrInt::ERR_INT));
-            while !self
-                .regs
-                .err_int
-                .any_matching_bits_set(ErrInt::BUF_RR::SET + ErrInt::XFER_CMPL::SET)
-            {}
-
-            self.regs
-                .err_int
-                .write(ErrInt::BUF_RR::SET + ErrInt::XFER_CMPL::SET);
-
-            if self.dma {
-                return;
+            TransferMode::Sdma => {
+                // The host stops at every physical page boundary,
+                // the pages of the buffer may not be physically contiguous.
+                let mut next = (vaddr & !(PAGE_SIZE - 1)) + PAGE_SIZE;
+                loop {
+                    let status = self.wait_int(ErrInt::XFER_CMPL::SET + ErrInt::DMA_INT::SET)?;
+                    if status.is_set(ErrInt::XFER_CMPL) {
+                        break;
+                    }
+                    self.regs.addr.set(D::virt_to_phys(next) as _);
+                    next += PAGE_SIZE;
+                }
+                return Ok(());
             }
+            TransferMode::Adma2 => {}
+        }
+        // Writes complete after the card releases the busy signal.
+        self.wait_int(ErrInt::XFER_CMPL::SET).map(|_| ())
+    }
 
-            for off in 0..(buffer.len() / core::mem::size_of::<u32>()) {
-                unsafe {
-                    transmute::<*mut u8, *mut u32>(buffer.as_mut_ptr())
-                        .add(idx * 0x200 + off)
-                        .write_volatile(self.regs.bf_data.get());
+    /// Fill the ADMA2 descriptor table with the buffer.
+    ///
+    /// The buffer is split at the page boundaries,
+    /// physically contiguous pages share a descriptor.
+    fn setup_adma(&self, vaddr: usize, len: usize) {
+        let adma_dt = self.adma2_dt();
+        let mut count: usize = 0;
+        let mut offset = 0;
+        while offset < len {
+            let addr = vaddr + offset;
+            let size = cmp::min(PAGE_SIZE - addr % PAGE_SIZE, len - offset);
+            let paddr = D::virt_to_phys(addr);
+            offset += size;
+
+            if let Some(last) = count.checked_sub(1).map(|x| &adma_dt[x]) {
+                let last_addr = last.read(ADMA2_DT::ADDR) as usize;
+                let last_len = last.read(ADMA2_DT::LEN) as usize;
+                if last_addr + last_len == paddr && last_len + size <= ADMA2_MAX_LEN {
+                    last.modify(ADMA2_DT::LEN.val((last_len + size) as _));
+                    continue;
                 }
             }
+            assert!(count < ADMA2_DT_LEN, "too many sdcard DMA segments");
+            adma_dt[count].write(
+                ADMA2_DT::VALID::SET
+                    + ADMA2_DT::ACT::Tran
+                    + ADMA2_DT::LEN.val(size as _)
+                    + ADMA2_DT::ADDR.val(paddr as _),
+            );
+            count += 1;
+        }
+        adma_dt[count - 1].modify(ADMA2_DT::END::SET);
+        // The descriptors must be visible to the host before the command.
+        fence(Ordering::SeqCst);
+        self.regs
+            .adma_addr
+            .set(self.adma2_dt.0.load(Ordering::SeqCst) as _);
+    }
 
-            if self.regs.err_int.is_set(XFER_CMPL) {
-                self.regs.err_int.write(XFER_CMPL::SET);
-                break;
+    /// Wait for the data interrupt status and clear it.
+    ///
+    /// Returns the status bits that are set. The task sleeps between polls
+    /// and it is woken up by the interrupt if the card has irqs, otherwise it
+    /// spins.
+    fn wait_int(
+        &self,
+        status: FieldValue<u32, ErrInt::Register>,
+    ) -> Result<LocalRegisterCopy<u32, ErrInt::Register>, SDError> {
+        let deadline = S::now() + DATA_TIMEOUT;
+        while S::now() < deadline {
+            if self.regs.err_int.is_set(ErrInt::ERR_INT) {
+                let timeout = self.regs.err_int.is_set(ErrInt::DAT_TOUT_ERR);
+                let err = self.regs.err_int.get();
+                self.regs.err_int.set(err);
+                return match timeout {
+                    true => Err(SDError::Timeout),
+                    false => Err(SDError::Data(err)),
+                };
+            }
+            let matched = self.regs.err_int.get() & status.value;
+            if matched != 0 {
+                self.regs.err_int.set(matched);
+                return Ok(LocalRegisterCopy::new(matched));
             }
+            match self.irqs.is_empty() {
+                true => core::hint::spin_loop(),
+                false => {
+                    self.regs.int_sig.set(status.value | ERR_INT_SIGNALS);
+                    S::sleep();
+                }
+            }
+        }
+        Err(SDError::Timeout)
+    }
+
+    /// Reset the command and data lines after an error.
+    fn reset_lines(&self) {
+        let rst = ClkCtl::SOFT_RST_CMD::SET + ClkCtl::SOFT_RST_DAT::SET;
+        self.regs.clk_ctl.modify(rst);
+        if self
+            .wait_for(|| !self.regs.clk_ctl.any_matching_bits_set(rst))
+            .is_err()
+        {
+            log::error!("sdcard can't reset the command and data lines");
         }
     }
 
     /// Transfer a command.
-    /// 
+    ///
     /// If you have additional operations, you should complete before the transfer
     /// cmd is what command to transfer
     /// args is the argument of the transfer
     /// blk_cnt is the transfer block_count. (Read, Write Block only)
-    pub fn cmd_transfer(&self, cmd: CommandType, arg: u32, blk_cnt: u32) -> [u32; 4] {
-        log::info!("send cmd: {:?}", cmd);
-        while self.regs.status.is_set(PresentStatus::INHIBIT_DAT) {}
+    pub fn cmd_transfer(
+        &self,
+        cmd: CommandType,
+        arg: u32,
+        blk_cnt: u32,
+    ) -> Result<[u32; 4], SDError> {
+        self.send_cmd(cmd, arg, blk_cnt, BLOCK_SIZE, TransferMode::Pio)
+    }
+
+    /// Transfer a command with the data block size and the transfer mode of the data.
+    fn send_cmd(
+        &self,
+        cmd: CommandType,
+        arg: u32,
+        blk_cnt: u32,
+        blk_size: usize,
+        mode: TransferMode,
+    ) -> Result<[u32; 4], SDError> {
+        log::trace!("send cmd: {:?}", cmd);
+        self.wait_for(|| {
+            !self.regs.status.any_matching_bits_set(
+                PresentStatus::INHIBIT::SET + PresentStatus::INHIBIT_DAT::SET,
+            )
+        })?;
 
         let mut flags = XferCmd::CMD_IDX.val(cmd.num() as u32);
 
         if blk_cnt > 0 {
             // set blk size and blk count
-            self.regs
-                .cnt
-                .modify(BlkCnt::BLK_CNT.val(blk_cnt) + BlkCnt::XFER_BLK_SIZE.val(0x200));
+            self.regs.cnt.write(
+                BlkCnt::BLK_CNT.val(blk_cnt)
+                    + BlkCnt::XFER_BLK_SIZE.val(blk_size as _)
+                    + BlkCnt::SDMA_BUF_BOUNDARY.val(0),
+            );
             flags += XferCmd::BLK_CNT_EN::SET;
         }
         if blk_cnt > 1 {
             flags += XferCmd::MULTI_BLK_EN::SET;
         }
 
-        log::info!("flags: {:#x}", flags.value);
-
         flags += match cmd {
-            CommandType::CMD(17) | CommandType::CMD(18) | CommandType::ACMD(51) => {
-                XferCmd::DATA_PRESENT::SET + XferCmd::DAT_XFER_READ::SET
-            }
-            CommandType::CMD(24) => XferCmd::DATA_PRESENT::SET,
+            CommandType::CMD(6)
+            | CommandType::CMD(17)
+            | CommandType::CMD(18)
+            | CommandType::ACMD(51) => XferCmd::DATA_PRESENT::SET + XferCmd::DAT_XFER_READ::SET,
+            CommandType::CMD(24) | CommandType::CMD(25) => XferCmd::DATA_PRESENT::SET,
             _ => XferCmd::DMA_EN::CLEAR,
         };
 
@@ -358,15 +722,26 @@ impl<D: DAlloc> SDCard<D> {
             CommandType::ACMD(6)
             | CommandType::ACMD(42)
             | CommandType::ACMD(51)
+            | CommandType::CMD(6)
+            | CommandType::CMD(13)
+            | CommandType::CMD(55)
             | CommandType::CMD(17)
             | CommandType::CMD(18)
             | CommandType::CMD(24)
+            | CommandType::CMD(25)
             | CommandType::CMD(8)
             | CommandType::CMD(16)
             | CommandType::CMD(7) => XferCmd::RESP_TYPE_SEL::L48 + XferCmd::CMD_CRC_CHK_EN::SET,
             CommandType::CMD(2) | CommandType::CMD(9) => {
                 XferCmd::RESP_TYPE_SEL::L136 + XferCmd::CMD_CRC_CHK_EN::SET
             }
+            // R1b, abort the multiple block transfer.
+            CommandType::CMD(12) => {
+                XferCmd::RESP_TYPE_SEL::L48_BUSY
+                    + XferCmd::CMD_CRC_CHK_EN::SET
+                    + XferCmd::CMD_IDX_CHK_EN::SET
+                    + XferCmd::CMD_TYPE::ABORT
+            }
             // R3
             CommandType::ACMD(41) | CommandType::CMD(58) => XferCmd::RESP_TYPE_SEL::L48,
             // R6
@@ -378,14 +753,10 @@ impl<D: DAlloc> SDCard<D> {
             _ => XferCmd::DMA_EN::CLEAR,
         };
 
-        if self.dma {
+        if mode != TransferMode::Pio {
             flags += XferCmd::DMA_EN::SET;
         }
 
-        // set blk cnt
-        // self.regs.cnt.modify(BLK_CNT.val(0));
-        // set timeout time
-        self.regs.clk_ctl.modify(TOUT_CNT.val(0x3));
         // Clear Err Int Register
         self.regs.err_int.set(0xF3FFFFFF);
 
@@ -393,7 +764,7 @@ impl<D: DAlloc> SDCard<D> {
         self.regs.cmd.write(flags);
 
         // Wait for command complete
-        self.wait_for_cmd();
+        self.wait_for_cmd()?;
 
         let res = [
             self.regs.resp[0].get(),
@@ -412,7 +783,7 @@ impl<D: DAlloc> SDCard<D> {
             res[3]
         );
 
-        res
+        Ok(res)
     }
 
     pub fn adma2_dt(&self) -> &'static mut [InMemoryRegister<u64, ADMA2_DT::Register>] {
@@ -420,18 +791,97 @@ impl<D: DAlloc> SDCard<D> {
             core::slice::from_raw_parts_mut(
                 self.adma2_dt.1.load(Ordering::SeqCst)
                     as *mut InMemoryRegister<u64, ADMA2_DT::Register>,
-                0x200,
+                ADMA2_DT_LEN,
             )
         }
     }
 
-    pub fn wait_for_cmd(&self) {
-        loop {
-            assert!(!self.regs.err_int.is_set(ErrInt::ERR_INT));
+    /// Wait for the command complete.
+    ///
+    /// The command line is reset if the command failed.
+    pub fn wait_for_cmd(&self) -> Result<(), SDError> {
+        for _ in 0..TIMEOUT_POLLS {
+            if self.regs.err_int.is_set(ErrInt::ERR_INT) {
+                let timeout = self.regs.err_int.is_set(ErrInt::CMD_TOUT_ERR);
+                let err = self.regs.err_int.get();
+                self.regs.err_int.set(err);
+                self.regs.clk_ctl.modify(ClkCtl::SOFT_RST_CMD::SET);
+                self.wait_for(|| !self.regs.clk_ctl.is_set(ClkCtl::SOFT_RST_CMD))?;
+                return match timeout {
+                    true => Err(SDError::Timeout),
+                    false => Err(SDError::Command(err)),
+                };
+            }
             if self.regs.err_int.is_set(ErrInt::CMD_CMPL) {
                 self.regs.err_int.write(ErrInt::CMD_CMPL::SET);
-                break;
+                return Ok(());
             }
         }
+        Err(SDError::Timeout)
+    }
+}
+
+impl<R, D: DAlloc, S: DSched> Drop for SDCard<R, D, S> {
+    fn drop(&mut self) {
+        // The descriptor table is allocated in ADMA2 mode only.
+        let paddr = self.adma2_dt.0.load(Ordering::SeqCst);
+        if paddr != 0 {
+            D::dealloc(paddr, 1);
+        }
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> Driver for SDCard<R, D, S> {
+    fn interrupts(&self) -> &[u32] {
+        &self.irqs
+    }
+
+    fn try_handle_interrupt(&self, irq: u32) -> bool {
+        if !self.irqs.contains(&irq) {
+            return false;
+        }
+        // The irq line may be shared.
+        if self.regs.err_int.get() & self.regs.int_sig.get() == 0 {
+            return false;
+        }
+        // The status is cleared by the waiting task,
+        // mask the signals until it waits again.
+        self.regs.int_sig.set(0);
+        S::wake();
+        true
+    }
+
+    fn get_id(&self) -> &str {
+        "sdcard"
+    }
+
+    fn get_device(self: Arc<Self>) -> DeviceType {
+        DeviceType::BLOCK(self.clone())
+    }
+}
+
+impl<R: RawMutex + 'static, D: DAlloc, S: DSched> BlkDriver for SDCard<R, D, S> {
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlkError> {
+        if block_id + buf.len() / BLOCK_SIZE > self.blocks {
+            return Err(BlkError::InvalidParam);
+        }
+        for (i, chunk) in buf.chunks_mut(MAX_BLOCKS_PER_CMD * BLOCK_SIZE).enumerate() {
+            self.read_block((block_id + i * MAX_BLOCKS_PER_CMD) as _, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), BlkError> {
+        if block_id + buf.len() / BLOCK_SIZE > self.blocks {
+            return Err(BlkError::InvalidParam);
+        }
+        for (i, chunk) in buf.chunks(MAX_BLOCKS_PER_CMD * BLOCK_SIZE).enumerate() {
+            self.write_block((block_id + i * MAX_BLOCKS_PER_CMD) as _, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.blocks
     }
 }
@@ -14,7 +14,9 @@ register_structs! {
         (0x28 => pub pwr_bg: ReadWrite<u32, PWRLVL::Register>),
         (0x2c => pub clk_ctl: ReadWrite<u32, ClkCtl::Register>),
         (0x30 => pub err_int: ReadWrite<u32, ErrInt::Register>),
-        (0x34 => _reserved),
+        (0x34 => pub int_en: ReadWrite<u32, ErrInt::Register>),
+        (0x38 => pub int_sig: ReadWrite<u32, ErrInt::Register>),
+        (0x3c => _reserved),
         (0x40 => pub cap1: ReadOnly<u32, Capability::Register>),
         (0x44 => pub cap2: ReadOnly<u32, Capability2::Register>),
         (0x48 => _reserved2),
@@ -27,6 +29,8 @@ register_bitfields! [
     u32,
     pub BlkCnt [
         XFER_BLK_SIZE OFFSET(0) NUMBITS(12),
+        // SDMA stops at the boundary, 4KB << n.
+        SDMA_BUF_BOUNDARY OFFSET(12) NUMBITS(3),
         BLK_CNT OFFSET(16) NUMBITS(16),
     ],
     pub XferCmd [
@@ -88,6 +92,10 @@ register_bitfields! [
         CMD_SIG OFFSET(24) NUMBITS(1) [],
     ],
     pub PWRLVL [
+        DAT_WIDTH OFFSET(1) NUMBITS(1) [
+            BIT1 = 0,
+            BIT4 = 1
+        ],
         HS_EN OFFSET(2) NUMBITS(1) [],
         DMA_SEL OFFSET(3) NUMBITS(2) [
             SDMA = 0,
@@ -120,12 +128,22 @@ register_bitfields! [
     pub ErrInt [
         CMD_CMPL 0,
         XFER_CMPL 1,
+        DMA_INT 3,
         BUF_WR 4,
         BUF_RR 5,
         ERR_INT 15,
+        CMD_TOUT_ERR 16,
+        CMD_CRC_ERR 17,
+        CMD_END_BIT_ERR 18,
+        CMD_IDX_ERR 19,
+        DAT_TOUT_ERR 20,
+        DAT_CRC_ERR 21,
+        DAT_END_BIT_ERR 22,
+        ADMA_ERR 25,
     ],
     pub Capability [
         TOUT_CLK_FREQ OFFSET(0) NUMBITS(1) [],
+        BASE_CLK_FREQ OFFSET(8) NUMBITS(8) [],
         MAX_BLK_LEN OFFSET(16) NUMBITS(2) [
             B512 = 0,
             B1024 = 1,
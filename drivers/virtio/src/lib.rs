@@ -6,22 +6,30 @@ extern crate alloc;
 #[macro_use]
 extern crate log;
 
+mod msix;
 pub mod virtio_blk;
 pub mod virtio_impl;
 pub mod virtio_input;
 pub mod virtio_net;
+mod virtqueue;
 
 use core::ptr::NonNull;
 
 use alloc::{sync::Arc, vec::Vec};
-use drivers_base::{DAlloc, Driver};
+use drivers_base::{DAlloc, DSched, Driver};
 use lock_api::RawMutex;
+use msix::MsixTransport;
 use virtio_drivers::transport::{
     mmio::{MmioTransport, VirtIOHeader},
+    pci::{
+        bus::{Cam, DeviceFunction, PciRoot},
+        PciTransport,
+    },
     DeviceType, Transport,
 };
+use virtio_impl::HalImpl;
 
-pub fn probe<R: RawMutex + 'static, D: DAlloc>(
+pub fn probe<R: RawMutex + 'static, D: DAlloc, S: DSched>(
     addr: usize,
     irqs: Vec<u32>,
 ) -> Option<Arc<dyn Driver>> {
@@ -40,16 +48,67 @@ pub fn probe<R: RawMutex + 'static, D: DAlloc>(
             addr,
             irqs
         );
-        match transport.device_type() {
-            DeviceType::Block => virtio_blk::init::<MmioTransport, R, D>(transport, irqs),
-            DeviceType::Input => virtio_input::init::<MmioTransport, R, D>(transport, irqs),
-            DeviceType::Network => virtio_net::init::<MmioTransport, R, D>(transport, irqs),
-            device_type => {
-                warn!("Unrecognized virtio device: {:?}", device_type);
-                None
-            }
-        }
+        init_device::<MmioTransport, R, D, S>(transport, irqs)
     } else {
         None
     }
 }
+
+/// Probe the virtio PCI device at the ECAM config space.
+///
+/// The BARs of the device should be assigned and the memory space should be enabled.
+/// `common_cfg` is the virtual address of the common configuration if the
+/// device signals MSI-X, the interrupts are routed to the vector 0.
+pub fn probe_pci<R: RawMutex + 'static, D: DAlloc, S: DSched>(
+    mmconfig_base: usize,
+    bus: u8,
+    device: u8,
+    function: u8,
+    irqs: Vec<u32>,
+    common_cfg: Option<usize>,
+) -> Option<Arc<dyn Driver>> {
+    let mut root = unsafe { PciRoot::new(mmconfig_base as *mut u8, Cam::Ecam) };
+    let device_function = DeviceFunction {
+        bus,
+        device,
+        function,
+    };
+    match PciTransport::new::<HalImpl<D>>(&mut root, device_function) {
+        Ok(transport) => {
+            info!(
+                "Detected virtio PCI device with
+                device type {:?}
+                at {}
+                interrupt: {:?}",
+                transport.device_type(),
+                device_function,
+                irqs
+            );
+            let transport = MsixTransport::new(transport, common_cfg);
+            init_device::<MsixTransport, R, D, S>(transport, irqs)
+        }
+        Err(err) => {
+            warn!(
+                "can't create virtio PCI transport at {}: {:?}",
+                device_function, err
+            );
+            None
+        }
+    }
+}
+
+/// Initialize the virtio device driver by the device type of the transport.
+fn init_device<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched>(
+    transport: T,
+    irqs: Vec<u32>,
+) -> Option<Arc<dyn Driver>> {
+    match transport.device_type() {
+        DeviceType::Block => virtio_blk::init::<T, R, D, S>(transport, irqs),
+        DeviceType::Input => virtio_input::init::<T, R, D, S>(transport, irqs),
+        DeviceType::Network => virtio_net::init::<T, R, D, S>(transport, irqs),
+        device_type => {
+            warn!("Unrecognized virtio device: {:?}", device_type);
+            None
+        }
+    }
+}
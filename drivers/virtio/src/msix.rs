@@ -0,0 +1,107 @@
+//! The virtio PCI transport routing the interrupts to the MSI-X vector 0.
+//!
+//! The transport of virtio-drivers doesn't set the MSI-X vectors, and the
+//! device sends no messages without them. The vectors must be set while the
+//! queues are set up, before the driver sets DRIVER_OK.
+
+use core::ptr::NonNull;
+
+use virtio_drivers::{
+    transport::{pci::PciTransport, DeviceStatus, DeviceType, Transport},
+    PhysAddr, Result,
+};
+
+/// Offsets of the virtio common configuration.
+const MSIX_CONFIG: usize = 0x10;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_MSIX_VECTOR: usize = 0x1a;
+
+pub struct MsixTransport {
+    inner: PciTransport,
+    /// Virtual address of the common configuration, None if the device signals INTx.
+    common_cfg: Option<usize>,
+}
+
+impl MsixTransport {
+    pub fn new(inner: PciTransport, common_cfg: Option<usize>) -> Self {
+        Self { inner, common_cfg }
+    }
+
+    fn write(&self, offset: usize, value: u16) {
+        if let Some(common_cfg) = self.common_cfg {
+            unsafe { ((common_cfg + offset) as *mut u16).write_volatile(value) };
+        }
+    }
+}
+
+impl Transport for MsixTransport {
+    fn device_type(&self) -> DeviceType {
+        self.inner.device_type()
+    }
+
+    fn read_device_features(&mut self) -> u64 {
+        self.inner.read_device_features()
+    }
+
+    fn write_driver_features(&mut self, driver_features: u64) {
+        self.inner.write_driver_features(driver_features)
+    }
+
+    fn max_queue_size(&mut self, queue: u16) -> u32 {
+        self.inner.max_queue_size(queue)
+    }
+
+    fn notify(&mut self, queue: u16) {
+        self.inner.notify(queue)
+    }
+
+    fn get_status(&self) -> DeviceStatus {
+        self.inner.get_status()
+    }
+
+    fn set_status(&mut self, status: DeviceStatus) {
+        if status.contains(DeviceStatus::DRIVER_OK) {
+            self.write(MSIX_CONFIG, 0);
+        }
+        self.inner.set_status(status)
+    }
+
+    fn set_guest_page_size(&mut self, guest_page_size: u32) {
+        self.inner.set_guest_page_size(guest_page_size)
+    }
+
+    fn requires_legacy_layout(&self) -> bool {
+        self.inner.requires_legacy_layout()
+    }
+
+    fn queue_set(
+        &mut self,
+        queue: u16,
+        size: u32,
+        descriptors: PhysAddr,
+        driver_area: PhysAddr,
+        device_area: PhysAddr,
+    ) {
+        // The vector is set before the queue is enabled.
+        self.write(QUEUE_SELECT, queue);
+        self.write(QUEUE_MSIX_VECTOR, 0);
+        self.inner
+            .queue_set(queue, size, descriptors, driver_area, device_area);
+    }
+
+    fn queue_unset(&mut self, queue: u16) {
+        self.inner.queue_unset(queue)
+    }
+
+    fn queue_used(&mut self, queue: u16) -> bool {
+        self.inner.queue_used(queue)
+    }
+
+    fn ack_interrupt(&mut self) -> bool {
+        self.inner.ack_interrupt()
+    }
+
+    fn config_space<T: 'static>(&self) -> Result<NonNull<T>> {
+        self.inner.config_space()
+    }
+}
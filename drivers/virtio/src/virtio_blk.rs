@@ -1,25 +1,151 @@
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use drivers_base::{BlkDriver, DAlloc, DeviceType, Driver};
+use drivers_base::{BlkDriver, BlkError, DAlloc, DSched, DeviceType, Driver};
 use lock_api::{Mutex, RawMutex};
-use virtio_drivers::device::blk::VirtIOBlk;
+use virtio_drivers::device::blk::{BlkReq, BlkResp, VirtIOBlk};
 use virtio_drivers::transport::Transport;
+use virtio_drivers::Error;
 
 use super::virtio_impl::HalImpl;
 
-pub struct VirtIOBlock<T: Transport, R, D: DAlloc> {
+pub struct VirtIOBlock<T: Transport, R, D: DAlloc, S: DSched> {
     inner: Mutex<R, VirtIOBlk<HalImpl<D>, T>>,
+    /// Requests submitted and not completed yet, counted under the lock of `inner`.
+    in_flight: AtomicUsize,
     irqs: Vec<u32>,
+    sched: PhantomData<S>,
+}
+
+unsafe impl<T: Transport, R, D: DAlloc, S: DSched> Sync for VirtIOBlock<T, R, D, S> {}
+unsafe impl<T: Transport, R, D: DAlloc, S: DSched> Send for VirtIOBlock<T, R, D, S> {}
+
+/// The direction and buffer of a block request.
+enum BlkBuffer<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
 }
 
-unsafe impl<T: Transport, R, D: DAlloc> Sync for VirtIOBlock<T, R, D> {}
-unsafe impl<T: Transport, R, D: DAlloc> Send for VirtIOBlock<T, R, D> {}
+impl<T: Transport, R: RawMutex, D: DAlloc, S: DSched> VirtIOBlock<T, R, D, S> {
+    /// Submit a request to the device and sleep until it is completed.
+    ///
+    /// Multiple requests can be in flight, the device completes them in the
+    /// order of the used ring. Every task completes its own request when
+    /// its token reaches the head of the used ring.
+    fn request(&self, block_id: usize, mut buffer: BlkBuffer) -> Result<(), Error> {
+        // The request header and response must keep their address until completed.
+        let mut req = Box::<BlkReq>::default();
+        let mut resp = Box::<BlkResp>::default();
+
+        let token = loop {
+            let mut inner = self.inner.lock();
+            let res = unsafe {
+                match &mut buffer {
+                    BlkBuffer::Read(buf) => {
+                        inner.read_blocks_nb(block_id, &mut req, buf, &mut resp)
+                    }
+                    BlkBuffer::Write(buf) => {
+                        inner.write_blocks_nb(block_id, &mut req, buf, &mut resp)
+                    }
+                }
+            };
+            match res {
+                Ok(token) => {
+                    self.in_flight.fetch_add(1, Ordering::AcqRel);
+                    break token;
+                }
+                // Wait for other requests to release the descriptors.
+                Err(Error::QueueFull) => {
+                    drop(inner);
+                    self.wait();
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        loop {
+            let mut inner = self.inner.lock();
+            // Interrupts may not be delivered when the irq handler can't take the lock.
+            inner.ack_interrupt();
+            if inner.peek_used() == Some(token) {
+                let res = unsafe {
+                    match &mut buffer {
+                        BlkBuffer::Read(buf) => {
+                            inner.complete_read_blocks(token, &req, buf, &mut resp)
+                        }
+                        BlkBuffer::Write(buf) => {
+                            inner.complete_write_blocks(token, &req, buf, &mut resp)
+                        }
+                    }
+                };
+                self.in_flight.fetch_sub(1, Ordering::AcqRel);
+                drop(inner);
+                // The next request in the used ring may belong to another task.
+                S::wake();
+                return res;
+            }
+            drop(inner);
+            self.wait();
+        }
+    }
+
+    /// Sleep until the interrupt of the device, or spin if it has no irq.
+    fn wait(&self) {
+        match self.irqs.is_empty() {
+            true => core::hint::spin_loop(),
+            false => S::sleep(),
+        }
+    }
 
-impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc> Driver for VirtIOBlock<T, R, D> {
+    /// Flush the volatile write cache of the device.
+    ///
+    /// The flush waits for the used ring itself, so it's submitted once
+    /// the requests in flight are completed, and no request is submitted
+    /// while it holds the lock.
+    pub fn flush(&self) -> Result<(), Error> {
+        loop {
+            let mut inner = self.inner.lock();
+            if self.in_flight.load(Ordering::Acquire) == 0 {
+                return inner.flush();
+            }
+            drop(inner);
+            self.wait();
+        }
+    }
+
+    /// Read blocks from the device, the calling task sleeps until completed.
+    pub fn read(&self, block_id: usize, buf: &mut [u8]) -> Result<(), Error> {
+        self.request(block_id, BlkBuffer::Read(buf))
+    }
+
+    /// Write blocks to the device, the calling task sleeps until completed.
+    pub fn write(&self, block_id: usize, buf: &[u8]) -> Result<(), Error> {
+        self.request(block_id, BlkBuffer::Write(buf))
+    }
+}
+
+impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched> Driver
+    for VirtIOBlock<T, R, D, S>
+{
     fn interrupts(&self) -> &[u32] {
         &self.irqs
     }
 
+    fn try_handle_interrupt(&self, irq: u32) -> bool {
+        if !self.irqs.contains(&irq) {
+            return false;
+        }
+        // The lock holder acknowledges the interrupt itself.
+        if let Some(mut inner) = self.inner.try_lock() {
+            inner.ack_interrupt();
+        }
+        S::wake();
+        true
+    }
+
     fn get_id(&self) -> &str {
         "virtio-blk"
     }
@@ -29,37 +155,61 @@ impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc> Driver for VirtIO
     }
 }
 
-impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc> BlkDriver for VirtIOBlock<T, R, D> {
-    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
-        self.inner
-            .lock()
-            .read_blocks(block_id, buf)
-            .expect("can't read block by virtio block");
+impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched> BlkDriver
+    for VirtIOBlock<T, R, D, S>
+{
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlkError> {
+        self.read(block_id, buf).map_err(|err| {
+            warn!("can't read block {} by virtio block: {:?}", block_id, err);
+            as_blk_error(err)
+        })
     }
 
-    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
-        self.inner
-            .lock()
-            .write_blocks(block_id, buf)
-            .expect("can't write block by virtio block");
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), BlkError> {
+        self.write(block_id, buf).map_err(|err| {
+            warn!("can't write block {} by virtio block: {:?}", block_id, err);
+            as_blk_error(err)
+        })
     }
 
-    fn capacity(&self) -> usize {
-        self.inner.lock().capacity() as usize * 0x200
+    fn num_blocks(&self) -> usize {
+        self.inner.lock().capacity() as usize
+    }
+
+    fn flush(&self) -> Result<(), BlkError> {
+        // Succeeds directly if the device doesn't negotiate the flush feature.
+        VirtIOBlock::flush(self).map_err(as_blk_error)
+    }
+}
+
+/// Convert the virtio error to the block device error.
+fn as_blk_error(err: Error) -> BlkError {
+    match err {
+        Error::InvalidParam => BlkError::InvalidParam,
+        Error::Unsupported => BlkError::Unsupported,
+        Error::QueueFull | Error::NotReady | Error::AlreadyUsed => BlkError::NotReady,
+        _ => BlkError::Io,
     }
 }
 
-pub fn init<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc>(
+pub fn init<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched>(
     transport: T,
     irqs: Vec<u32>,
 ) -> Option<Arc<dyn Driver>> {
     info!("Initialize virtio-block device");
 
-    let blk_device = Arc::new(VirtIOBlock::<T, R, D> {
-        inner: Mutex::new(
-            VirtIOBlk::<HalImpl<D>, T>::new(transport).expect("failed to create blk driver"),
-        ),
+    let blk = match VirtIOBlk::<HalImpl<D>, T>::new(transport) {
+        Ok(blk) => blk,
+        Err(err) => {
+            warn!("failed to create blk driver: {:?}", err);
+            return None;
+        }
+    };
+    let blk_device = Arc::new(VirtIOBlock::<T, R, D, S> {
+        inner: Mutex::new(blk),
+        in_flight: AtomicUsize::new(0),
         irqs,
+        sched: PhantomData,
     });
 
     Some(blk_device)
@@ -1,21 +1,74 @@
+use core::marker::PhantomData;
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
-use drivers_base::{DAlloc, DeviceType, Driver, InputDriver};
+use drivers_base::{AbsInfo, DAlloc, DSched, DeviceType, Driver, InputDriver, InputEvent, InputId};
 use lock_api::{Mutex, RawMutex};
-use virtio_drivers::device::input::VirtIOInput as VirtIOInputWrapper;
+use virtio_drivers::device::input::{InputConfigSelect, VirtIOInput as VirtIOInputWrapper};
 use virtio_drivers::transport::Transport;
 
 use super::virtio_impl::HalImpl;
 
-pub struct VirtIOInput<T: Transport, R: RawMutex, D: DAlloc> {
-    _inner: Mutex<R, VirtIOInputWrapper<HalImpl<D>, T>>,
+/// Max pending events, the oldest events are dropped if the reader is slow.
+const EVENT_BUFFER_SIZE: usize = 256;
+/// Count of the event types.
+const EV_CNT: u8 = 0x20;
+/// The absolute axis event type.
+const EV_ABS: u8 = 0x03;
+/// Count of the absolute axes.
+const ABS_CNT: u8 = 0x40;
+
+pub struct VirtIOInput<T: Transport, R: RawMutex, D: DAlloc, S: DSched> {
+    inner: Mutex<R, VirtIOInputWrapper<HalImpl<D>, T>>,
+    /// Events taken from the device but not read.
+    events: Mutex<R, VecDeque<InputEvent>>,
+    name: String,
+    id: InputId,
+    /// Bitmaps of the supported codes indexed by the event type,
+    /// the event type 0 holds the bitmap of the supported types.
+    ev_bits: Vec<Vec<u8>>,
+    prop_bits: Vec<u8>,
+    abs_info: Vec<Option<AbsInfo>>,
     interrupts: Vec<u32>,
+    sched: PhantomData<S>,
 }
 
-unsafe impl<T: Transport, R: RawMutex, D: DAlloc> Sync for VirtIOInput<T, R, D> {}
-unsafe impl<T: Transport, R: RawMutex, D: DAlloc> Send for VirtIOInput<T, R, D> {}
+unsafe impl<T: Transport, R: RawMutex, D: DAlloc, S: DSched> Sync for VirtIOInput<T, R, D, S> {}
+unsafe impl<T: Transport, R: RawMutex, D: DAlloc, S: DSched> Send for VirtIOInput<T, R, D, S> {}
 
-impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc> Driver for VirtIOInput<T, R, D> {
+impl<T: Transport, R: RawMutex, D: DAlloc, S: DSched> VirtIOInput<T, R, D, S> {
+    /// Take the events reported by the device to the pending events.
+    ///
+    /// Returns false if the device is used by others.
+    fn drain(&self) -> bool {
+        let mut inner = match self.inner.try_lock() {
+            Some(inner) => inner,
+            None => return false,
+        };
+        inner.ack_interrupt();
+        let time = S::now();
+        let mut events = self.events.lock();
+        while let Some(event) = inner.pop_pending_event() {
+            if events.len() == EVENT_BUFFER_SIZE {
+                events.pop_front();
+            }
+            events.push_back(InputEvent {
+                time,
+                event_type: event.event_type,
+                code: event.code,
+                value: event.value as i32,
+            });
+        }
+        true
+    }
+}
+
+impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched> Driver
+    for VirtIOInput<T, R, D, S>
+{
     fn get_id(&self) -> &str {
         "virtio-input"
     }
@@ -24,39 +77,153 @@ impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc> Driver for VirtIO
         &self.interrupts
     }
 
+    fn try_handle_interrupt(&self, irq: u32) -> bool {
+        if !self.interrupts.contains(&irq) {
+            return false;
+        }
+        self.handle_irq();
+        true
+    }
+
     fn get_device(self: Arc<Self>) -> DeviceType {
         DeviceType::INPUT(self.clone())
     }
 }
 
-impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc> InputDriver
-    for VirtIOInput<T, R, D>
+impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched> InputDriver
+    for VirtIOInput<T, R, D, S>
 {
-    fn read_event(&self) -> u64 {
-        todo!()
+    fn read_event(&self) -> Option<InputEvent> {
+        // Devices without irqs are polled by the reader.
+        if self.interrupts.is_empty() {
+            self.drain();
+        }
+        self.events.lock().pop_front()
     }
 
     fn handle_irq(&self) {
-        todo!()
+        if self.drain() {
+            S::wake();
+        }
     }
 
     fn is_empty(&self) -> bool {
-        todo!()
+        if self.interrupts.is_empty() {
+            self.drain();
+        }
+        self.events.lock().is_empty()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
     }
+
+    fn input_id(&self) -> InputId {
+        self.id
+    }
+
+    fn event_bits(&self, event_type: u8) -> &[u8] {
+        self.ev_bits
+            .get(event_type as usize)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn prop_bits(&self) -> &[u8] {
+        &self.prop_bits
+    }
+
+    fn abs_info(&self, axis: u8) -> Option<AbsInfo> {
+        self.abs_info.get(axis as usize).copied().flatten()
+    }
+}
+
+/// Query the config of the device, returns the data of the selection.
+fn query<T: Transport, D: DAlloc>(
+    inner: &mut VirtIOInputWrapper<HalImpl<D>, T>,
+    select: InputConfigSelect,
+    subsel: u8,
+) -> Vec<u8> {
+    let mut buffer = [0u8; 128];
+    let size = inner.query_config_select(select, subsel, &mut buffer) as usize;
+    buffer[..size.min(buffer.len())].to_vec()
 }
 
-pub fn init<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc>(
+pub fn init<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched>(
     transport: T,
     irqs: Vec<u32>,
 ) -> Option<Arc<dyn Driver>> {
-    info!("Initialize virtio-iput device");
+    info!("Initialize virtio-input device, irqs: {:?}", irqs);
+
+    let mut inner = match VirtIOInputWrapper::<HalImpl<D>, T>::new(transport) {
+        Ok(inner) => inner,
+        Err(err) => {
+            warn!("failed to create virtio-input driver: {:?}", err);
+            return None;
+        }
+    };
+
+    let name = String::from_utf8(query(&mut inner, InputConfigSelect::IdName, 0))
+        .unwrap_or_else(|_| String::from("virtio-input"));
+    let read_u16 = |data: &[u8], idx: usize| {
+        data.get(idx * 2..idx * 2 + 2)
+            .map_or(0, |x| u16::from_le_bytes([x[0], x[1]]))
+    };
+    let devids = query(&mut inner, InputConfigSelect::IdDevids, 0);
+    let id = InputId {
+        bustype: read_u16(&devids, 0),
+        vendor: read_u16(&devids, 1),
+        product: read_u16(&devids, 2),
+        version: read_u16(&devids, 3),
+    };
+    let prop_bits = query(&mut inner, InputConfigSelect::PropBits, 0);
+
+    // EV_SYN is always supported, other types are supported if they have codes.
+    let mut ev_bits = vec![vec![0u8; EV_CNT as usize / 8]];
+    ev_bits[0][0] = 1;
+    for event_type in 1..EV_CNT {
+        let bits = query(&mut inner, InputConfigSelect::EvBits, event_type);
+        if !bits.is_empty() {
+            ev_bits[0][event_type as usize / 8] |= 1 << (event_type % 8);
+        }
+        ev_bits.push(bits);
+    }
+
+    let abs_bits = ev_bits[EV_ABS as usize].clone();
+    let abs_info = (0..ABS_CNT)
+        .map(|axis| {
+            let supported = abs_bits
+                .get(axis as usize / 8)
+                .is_some_and(|x| x & (1 << (axis % 8)) != 0);
+            if !supported {
+                return None;
+            }
+            let data = query(&mut inner, InputConfigSelect::AbsInfo, axis);
+            let read_i32 = |idx: usize| {
+                data.get(idx * 4..idx * 4 + 4)
+                    .map_or(0, |x| i32::from_le_bytes(x.try_into().unwrap()))
+            };
+            Some(AbsInfo {
+                min: read_i32(0),
+                max: read_i32(1),
+                fuzz: read_i32(2),
+                flat: read_i32(3),
+                resolution: read_i32(4),
+            })
+        })
+        .collect();
+    info!("virtio-input: {} {:x?}", name, id);
 
-    let input_device = Arc::new(VirtIOInput::<T, R, D> {
-        _inner: Mutex::new(
-            VirtIOInputWrapper::<HalImpl<D>, T>::new(transport)
-                .expect("failed to create blk driver"),
-        ),
+    let input_device = Arc::new(VirtIOInput::<T, R, D, S> {
+        inner: Mutex::new(inner),
+        events: Mutex::new(VecDeque::with_capacity(EVENT_BUFFER_SIZE)),
+        name,
+        id,
+        ev_bits,
+        prop_bits,
+        abs_info,
         interrupts: irqs,
+        sched: PhantomData,
     });
     Some(input_device)
 }
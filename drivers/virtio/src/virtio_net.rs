@@ -1,26 +1,192 @@
-use core::cmp;
+//! Virtio net driver.
+//!
+//! The driver negotiates the offloads itself, so the queues are driven
+//! directly through the transport. Received packets are handed out in
+//! their RX buffers, and the recycled buffers refill the RX queue.
+
+use core::{marker::PhantomData, mem::size_of, ptr::NonNull};
 
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use drivers_base::{DAlloc, DeviceType, Driver, NetDriver, NetError};
+use drivers_base::{
+    DAlloc, DSched, DeviceType, Driver, NetBuf, NetDriver, NetError, NetOffloads, TxOffload,
+};
 use lock_api::{Mutex, RawMutex};
-use virtio_drivers::device::net::{self, TxBuffer};
-use virtio_drivers::transport::Transport;
+use virtio_drivers::transport::{DeviceStatus, Transport};
+
+use super::virtqueue::VirtQueue;
+
+const PAGE_SIZE: usize = 0x1000;
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+const RX_QUEUE_SIZE: u16 = 64;
+const TX_QUEUE_SIZE: u16 = 16;
+/// Size of the RX buffers, the header and an ethernet frame.
+const RX_BUF_SIZE: usize = 2048;
+/// Max packet sent without TSO, an ethernet frame.
+const MAX_FRAME: usize = 1514;
+/// Max packet sent with TSO.
+const MAX_TSO_FRAME: usize = 0x10000;
+/// Alignment of the TX buffers.
+const TX_BUF_ALIGN: usize = 0x800;
+
+/// Feature bits of the virtio net device.
+const F_CSUM: u64 = 1 << 0;
+const F_GUEST_CSUM: u64 = 1 << 1;
+const F_MAC: u64 = 1 << 5;
+const F_HOST_TSO4: u64 = 1 << 11;
+const F_HOST_TSO6: u64 = 1 << 12;
+const F_MRG_RXBUF: u64 = 1 << 15;
+const F_STATUS: u64 = 1 << 16;
+const F_ANY_LAYOUT: u64 = 1 << 27;
+const F_VERSION_1: u64 = 1 << 32;
+const SUPPORTED_FEATURES: u64 = F_CSUM
+    | F_GUEST_CSUM
+    | F_MAC
+    | F_HOST_TSO4
+    | F_HOST_TSO6
+    | F_STATUS
+    | F_ANY_LAYOUT
+    | F_VERSION_1;
+
+/// Bits of the header flags.
+const HDR_F_NEEDS_CSUM: u8 = 1;
+const HDR_F_DATA_VALID: u8 = 2;
+/// Segmentation types of the header.
+const GSO_NONE: u8 = 0;
+const GSO_TCPV4: u8 = 1;
+const GSO_TCPV6: u8 = 4;
+/// The link status bit of the config.
+const STATUS_LINK_UP: u16 = 1;
+
+/// The header before every packet.
+///
+/// `num_buffers` only exists with VIRTIO_F_VERSION_1 or VIRTIO_NET_F_MRG_RXBUF.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct NetHdr {
+    flags: u8,
+    gso_type: u8,
+    hdr_len: u16,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+    num_buffers: u16,
+}
+
+#[repr(C)]
+struct NetConfig {
+    mac: [u8; 6],
+    status: u16,
+}
+
+struct RxQueue<D: DAlloc> {
+    queue: VirtQueue<D>,
+    /// The buffers owned by the device, indexed by the descriptor.
+    bufs: Vec<Option<NetBuf>>,
+    /// Recycled buffers.
+    pool: Vec<NetBuf>,
+}
+
+impl<D: DAlloc> RxQueue<D> {
+    /// Give a free buffer to the device.
+    fn refill(&mut self) {
+        let id = match self.queue.alloc_desc() {
+            Some(id) => id,
+            None => return,
+        };
+        let mut buf = self.pool.pop().unwrap_or_else(|| NetBuf::new(RX_BUF_SIZE));
+        let paddr = D::virt_to_phys(buf.buffer().as_ptr() as usize);
+        self.queue.submit(id, paddr, buf.capacity(), true);
+        self.bufs[id as usize] = Some(buf);
+    }
+}
 
-use super::virtio_impl::HalImpl;
+struct TxQueue<D: DAlloc> {
+    queue: VirtQueue<D>,
+    /// Physical address of the buffers, every descriptor owns one.
+    buf_paddr: usize,
+    buf_pages: usize,
+    buf_size: usize,
+}
 
-#[allow(dead_code)]
-pub struct VirtIONet<T: Transport, R, D: DAlloc> {
-    inner: Mutex<R, net::VirtIONet<HalImpl<D>, T, 32>>,
+pub struct VirtIONet<T: Transport, R: RawMutex, D: DAlloc, S: DSched> {
+    transport: Mutex<R, T>,
+    rx: Mutex<R, RxQueue<D>>,
+    tx: Mutex<R, TxQueue<D>>,
+    config: NonNull<NetConfig>,
+    features: u64,
+    /// Length of the header, it depends on the negotiated features.
+    hdr_len: usize,
+    mac: [u8; 6],
     irqs: Vec<u32>,
+    sched: PhantomData<S>,
 }
 
-unsafe impl<T: Transport, R, D: DAlloc> Sync for VirtIONet<T, R, D> {}
-unsafe impl<T: Transport, R, D: DAlloc> Send for VirtIONet<T, R, D> {}
+unsafe impl<T: Transport, R: RawMutex, D: DAlloc, S: DSched> Sync for VirtIONet<T, R, D, S> {}
+unsafe impl<T: Transport, R: RawMutex, D: DAlloc, S: DSched> Send for VirtIONet<T, R, D, S> {}
+
+/// Complete the checksum of the packet, it is computed from `start` to the end
+/// and stored at `start + offset`, the field holds the pseudo header checksum.
+fn complete_csum(packet: &mut [u8], start: usize, offset: usize) -> bool {
+    let field = start + offset;
+    if field + 2 > packet.len() {
+        return false;
+    }
+    let mut sum = packet[start..].chunks(2).fold(0u32, |sum, x| {
+        sum + u16::from_be_bytes([x[0], x.get(1).copied().unwrap_or(0)]) as u32
+    });
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    packet[field..field + 2].copy_from_slice(&(!sum as u16).to_be_bytes());
+    true
+}
 
-impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc> Driver for VirtIONet<T, R, D> {
+impl<T: Transport, R: RawMutex, D: DAlloc, S: DSched> VirtIONet<T, R, D, S> {
+    /// Copy the header and the packet to a TX buffer and notify the device.
+    fn transmit(&self, buf: &[u8], hdr: NetHdr) -> Result<(), NetError> {
+        let mut tx = self.tx.lock();
+        if self.hdr_len + buf.len() > tx.buf_size {
+            return Err(NetError::TooLarge);
+        }
+        // Free the buffers of the sent packets.
+        while tx.queue.pop_used().is_some() {}
+        let id = tx.queue.alloc_desc().ok_or(NetError::Busy)?;
+        let paddr = tx.buf_paddr + id as usize * tx.buf_size;
+        let vaddr = D::phys_to_virt(paddr) as *mut u8;
+        unsafe {
+            core::ptr::copy_nonoverlapping(&hdr as *const NetHdr as *const u8, vaddr, self.hdr_len);
+            core::ptr::copy_nonoverlapping(buf.as_ptr(), vaddr.add(self.hdr_len), buf.len());
+        }
+        tx.queue.submit(id, paddr, self.hdr_len + buf.len(), false);
+        drop(tx);
+        self.transport.lock().notify(TX_QUEUE);
+        Ok(())
+    }
+}
+
+impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched> Driver
+    for VirtIONet<T, R, D, S>
+{
     fn get_id(&self) -> &str {
-        "virtio-blk"
+        "virtio-net"
+    }
+
+    fn interrupts(&self) -> &[u32] {
+        &self.irqs
+    }
+
+    fn try_handle_interrupt(&self, irq: u32) -> bool {
+        if !self.irqs.contains(&irq) {
+            return false;
+        }
+        // The lock holder only notifies the device, the interrupt is acknowledged next time.
+        if let Some(mut transport) = self.transport.try_lock() {
+            transport.ack_interrupt();
+        }
+        S::wake();
+        true
     }
 
     fn get_device(self: Arc<Self>) -> DeviceType {
@@ -28,39 +194,203 @@ impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc> Driver for VirtIO
     }
 }
 
-impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc> NetDriver for VirtIONet<T, R, D> {
-    fn recv(&self, buf: &mut [u8]) -> Result<usize, NetError> {
-        let packet = self.inner.lock().receive().map_err(|_| NetError::NoData)?;
-        let rlen = cmp::min(buf.len(), packet.packet_len());
-        buf[..rlen].copy_from_slice(&packet.packet()[..rlen]);
-        self.inner
-            .lock()
-            .recycle_rx_buffer(packet)
-            .expect("can't receive data");
-        Ok(rlen)
+impl<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched> NetDriver
+    for VirtIONet<T, R, D, S>
+{
+    fn recv(&self) -> Result<NetBuf, NetError> {
+        let mut rx = self.rx.lock();
+        let (id, len) = rx.queue.pop_used().ok_or(NetError::NoData)?;
+        let mut buf = rx.bufs[id as usize].take().ok_or(NetError::Io)?;
+        rx.refill();
+        drop(rx);
+        self.transport.lock().notify(RX_QUEUE);
+
+        if len < self.hdr_len || len > buf.capacity() {
+            warn!("virtio-net drops a packet of {} bytes", len);
+            self.recycle(buf);
+            return Err(NetError::Io);
+        }
+        let hdr = unsafe { (buf.buffer().as_ptr() as *const NetHdr).read_unaligned() };
+        buf.set_packet(self.hdr_len, len - self.hdr_len);
+        // The packet is partially checksummed by the host, it is valid once completed.
+        buf.csum_valid = match hdr.flags {
+            x if x & HDR_F_NEEDS_CSUM != 0 => complete_csum(
+                buf.packet_mut(),
+                hdr.csum_start as usize,
+                hdr.csum_offset as usize,
+            ),
+            x => x & HDR_F_DATA_VALID != 0,
+        };
+        Ok(buf)
+    }
+
+    fn recycle(&self, mut buf: NetBuf) {
+        let mut rx = self.rx.lock();
+        if rx.pool.len() < rx.queue.size() as usize && buf.capacity() == RX_BUF_SIZE {
+            buf.csum_valid = false;
+            rx.pool.push(buf);
+        }
     }
 
     fn send(&self, buf: &[u8]) -> Result<(), NetError> {
-        self.inner
-            .lock()
-            .send(TxBuffer::from(buf))
-            .expect("can't send data");
-        Ok(())
+        if buf.len() > MAX_FRAME {
+            return Err(NetError::TooLarge);
+        }
+        self.transmit(buf, NetHdr::default())
+    }
+
+    fn send_offload(&self, buf: &[u8], offload: &TxOffload) -> Result<(), NetError> {
+        let offloads = self.offloads();
+        let mut hdr = NetHdr {
+            flags: HDR_F_NEEDS_CSUM,
+            gso_type: GSO_NONE,
+            csum_start: offload.csum_start,
+            csum_offset: offload.csum_offset,
+            ..Default::default()
+        };
+        match offload.tso {
+            _ if !offloads.tx_csum => return Err(NetError::Unsupported),
+            None if buf.len() > MAX_FRAME => return Err(NetError::TooLarge),
+            None => {}
+            Some(tso) if (tso.ipv6 && !offloads.tso6) || (!tso.ipv6 && !offloads.tso4) => {
+                return Err(NetError::Unsupported)
+            }
+            Some(tso) => {
+                hdr.gso_type = if tso.ipv6 { GSO_TCPV6 } else { GSO_TCPV4 };
+                hdr.hdr_len = tso.header_len;
+                hdr.gso_size = tso.mss;
+            }
+        }
+        self.transmit(buf, hdr)
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn link_up(&self) -> bool {
+        if self.features & F_STATUS == 0 {
+            return true;
+        }
+        let status = unsafe { core::ptr::addr_of!((*self.config.as_ptr()).status).read_volatile() };
+        status & STATUS_LINK_UP != 0
+    }
+
+    fn offloads(&self) -> NetOffloads {
+        NetOffloads {
+            tx_csum: self.features & F_CSUM != 0,
+            rx_csum: self.features & F_GUEST_CSUM != 0,
+            tso4: self.features & F_HOST_TSO4 != 0,
+            tso6: self.features & F_HOST_TSO6 != 0,
+        }
+    }
+}
+
+impl<T: Transport, R: RawMutex, D: DAlloc, S: DSched> Drop for VirtIONet<T, R, D, S> {
+    fn drop(&mut self) {
+        // Reset the device before the queues are freed.
+        let transport = self.transport.get_mut();
+        transport.set_status(DeviceStatus::empty());
+        transport.queue_unset(RX_QUEUE);
+        transport.queue_unset(TX_QUEUE);
+        let tx = self.tx.get_mut();
+        D::dealloc(tx.buf_paddr, tx.buf_pages);
     }
 }
 
-pub fn init<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc>(
-    transport: T,
+pub fn init<T: Transport + 'static, R: RawMutex + 'static, D: DAlloc, S: DSched>(
+    mut transport: T,
     irqs: Vec<u32>,
 ) -> Option<Arc<dyn Driver>> {
     info!("Initialize virtio-net device, irqs: {:?}", irqs);
 
-    let net_device = Arc::new(VirtIONet::<T, R, D> {
-        inner: Mutex::new(
-            net::VirtIONet::<HalImpl<D>, T, 32>::new(transport, 2048)
-                .expect("failed to create blk driver"),
-        ),
+    transport.set_status(DeviceStatus::empty());
+    transport.set_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER);
+    let mut features = transport.read_device_features() & SUPPORTED_FEATURES;
+    // TSO needs the checksum offload.
+    if features & F_CSUM == 0 {
+        features &= !(F_HOST_TSO4 | F_HOST_TSO6);
+    }
+    transport.write_driver_features(features);
+    transport
+        .set_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER | DeviceStatus::FEATURES_OK);
+    if !transport.get_status().contains(DeviceStatus::FEATURES_OK) {
+        warn!("virtio-net rejects the features {:#x}", features);
+        transport.set_status(DeviceStatus::FAILED);
+        return None;
+    }
+    transport.set_guest_page_size(PAGE_SIZE as u32);
+
+    let config = match transport.config_space::<NetConfig>() {
+        Ok(config) => config,
+        Err(err) => {
+            warn!("virtio-net has no config space: {:?}", err);
+            transport.set_status(DeviceStatus::FAILED);
+            return None;
+        }
+    };
+    let mac = match features & F_MAC {
+        0 => [0x52, 0x54, 0x00, 0x12, 0x34, 0x56],
+        _ => unsafe { core::ptr::addr_of!((*config.as_ptr()).mac).read_volatile() },
+    };
+    let hdr_len = match features & (F_VERSION_1 | F_MRG_RXBUF) {
+        0 => size_of::<NetHdr>() - size_of::<u16>(),
+        _ => size_of::<NetHdr>(),
+    };
+
+    let queues = VirtQueue::<D>::new(&mut transport, RX_QUEUE, RX_QUEUE_SIZE).and_then(|rx| {
+        Ok((
+            rx,
+            VirtQueue::<D>::new(&mut transport, TX_QUEUE, TX_QUEUE_SIZE)?,
+        ))
+    });
+    let (rx_queue, tx_queue) = match queues {
+        Ok(queues) => queues,
+        Err(err) => {
+            warn!("can't create the virtio-net queues: {:?}", err);
+            transport.set_status(DeviceStatus::FAILED);
+            return None;
+        }
+    };
+
+    let mut rx = RxQueue {
+        bufs: (0..rx_queue.size()).map(|_| None).collect(),
+        queue: rx_queue,
+        pool: Vec::new(),
+    };
+    for _ in 0..rx.queue.size() {
+        rx.refill();
+    }
+    let max_frame = match features & (F_HOST_TSO4 | F_HOST_TSO6) {
+        0 => MAX_FRAME,
+        _ => MAX_TSO_FRAME,
+    };
+    let buf_size = (hdr_len + max_frame).next_multiple_of(TX_BUF_ALIGN);
+    let buf_pages = (buf_size * tx_queue.size() as usize).div_ceil(PAGE_SIZE);
+    let tx = TxQueue {
+        queue: tx_queue,
+        buf_paddr: D::alloc(buf_pages),
+        buf_pages,
+        buf_size,
+    };
+
+    transport.finish_init();
+    transport.notify(RX_QUEUE);
+    info!(
+        "virtio-net mac {:02x?}, features {:#x}, header {} bytes",
+        mac, features, hdr_len
+    );
+
+    let net_device = Arc::new(VirtIONet::<T, R, D, S> {
+        transport: Mutex::new(transport),
+        rx: Mutex::new(rx),
+        tx: Mutex::new(tx),
+        config,
+        features,
+        hdr_len,
+        mac,
         irqs,
+        sched: PhantomData,
     });
     Some(net_device)
 }
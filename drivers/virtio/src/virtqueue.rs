@@ -0,0 +1,157 @@
+//! Split virtqueue of the drivers negotiating their own features.
+//!
+//! Every request is a single descriptor, the caller owns the buffer of the
+//! descriptor until it is popped from the used ring.
+
+use core::{
+    marker::PhantomData,
+    mem::size_of,
+    sync::atomic::{fence, Ordering},
+};
+
+use alloc::vec::Vec;
+use drivers_base::DAlloc;
+use virtio_drivers::{transport::Transport, Error};
+
+const PAGE_SIZE: usize = 0x1000;
+/// The device writes the buffer of the descriptor.
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+pub(crate) struct VirtQueue<D: DAlloc> {
+    size: u16,
+    /// Physical address and pages of the rings, the used ring is page aligned
+    /// like the legacy layout.
+    paddr: usize,
+    pages: usize,
+    avail_offset: usize,
+    used_offset: usize,
+    /// The next index of the available ring.
+    avail_idx: u16,
+    /// The next index of the used ring to pop.
+    last_used: u16,
+    /// Descriptors not used by any request.
+    free: Vec<u16>,
+    phantom: PhantomData<D>,
+}
+
+impl<D: DAlloc> VirtQueue<D> {
+    /// Create the queue with at most `size` entries and tell the device its address.
+    pub fn new<T: Transport>(transport: &mut T, index: u16, size: u16) -> Result<Self, Error> {
+        if transport.queue_used(index) {
+            return Err(Error::AlreadyUsed);
+        }
+        let size = size.min(transport.max_queue_size(index) as u16);
+        if size == 0 {
+            return Err(Error::InvalidParam);
+        }
+        let avail_offset = size_of::<Descriptor>() * size as usize;
+        // flags, idx, ring and used_event.
+        let avail_size = size_of::<u16>() * (3 + size as usize);
+        let used_offset = (avail_offset + avail_size).next_multiple_of(PAGE_SIZE);
+        // flags, idx, ring and avail_event.
+        let used_size = size_of::<u16>() * 3 + size_of::<UsedElem>() * size as usize;
+        let pages = (used_offset + used_size).div_ceil(PAGE_SIZE);
+
+        let paddr = D::alloc(pages);
+        unsafe { (D::phys_to_virt(paddr) as *mut u8).write_bytes(0, pages * PAGE_SIZE) };
+        transport.queue_set(
+            index,
+            size as u32,
+            paddr,
+            paddr + avail_offset,
+            paddr + used_offset,
+        );
+        Ok(Self {
+            size,
+            paddr,
+            pages,
+            avail_offset,
+            used_offset,
+            avail_idx: 0,
+            last_used: 0,
+            free: (0..size).rev().collect(),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Get the number of entries.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Take a free descriptor, None if all descriptors are used.
+    pub fn alloc_desc(&mut self) -> Option<u16> {
+        self.free.pop()
+    }
+
+    /// Give the buffer of the descriptor to the device.
+    ///
+    /// The device is notified by the caller through [Transport::notify].
+    pub fn submit(&mut self, id: u16, paddr: usize, len: usize, writable: bool) {
+        let base = D::phys_to_virt(self.paddr);
+        let desc = Descriptor {
+            addr: paddr as u64,
+            len: len as u32,
+            flags: if writable { DESC_F_WRITE } else { 0 },
+            next: 0,
+        };
+        unsafe {
+            (base as *mut Descriptor)
+                .add(id as usize)
+                .write_volatile(desc);
+            let avail = (base + self.avail_offset) as *mut u16;
+            avail
+                .add(2 + (self.avail_idx % self.size) as usize)
+                .write_volatile(id);
+            // The descriptor must be visible before the index.
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            avail.add(1).write_volatile(self.avail_idx);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    /// Pop a request completed by the device.
+    ///
+    /// Returns the descriptor and the length written by the device, the
+    /// descriptor is free after it.
+    pub fn pop_used(&mut self) -> Option<(u16, usize)> {
+        let used = D::phys_to_virt(self.paddr) + self.used_offset;
+        let used_idx = unsafe { (used as *const u16).add(1).read_volatile() };
+        if used_idx == self.last_used {
+            return None;
+        }
+        // Read the element after the device updates the index.
+        fence(Ordering::SeqCst);
+        let elem = unsafe {
+            ((used + size_of::<u16>() * 2) as *const UsedElem)
+                .add((self.last_used % self.size) as usize)
+                .read_volatile()
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+        self.free.push(elem.id as u16);
+        Some((elem.id as u16, elem.len as usize))
+    }
+}
+
+impl<D: DAlloc> Drop for VirtQueue<D> {
+    fn drop(&mut self) {
+        D::dealloc(self.paddr, self.pages);
+    }
+}
@@ -126,6 +126,7 @@ pub enum FileType {
     Device,
     Socket,
     Link,
+    Fifo,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -266,6 +267,11 @@ pub trait INodeInterface: Sync + Send {
         Err(Errno::EACCES)
     }
 
+    /// Create a special file (socket and so on) with name in the current directory
+    fn mknod(&self, _name: &str, _file_type: FileType) -> FsResult<Arc<dyn INodeInterface>> {
+        Err(Errno::EACCES)
+    }
+
     /// Rename a file with name
     fn remove(&self, _name: &str) -> FsResult<()> {
         Err(Errno::EACCES)
@@ -339,6 +345,56 @@ pub trait INodeInterface: Sync + Send {
     fn poll(&self, _events: PollEvent) -> FsResult<PollEvent> {
         Err(Errno::EACCES)
     }
+
+    /// Add the waiter woken up when the poll events of the file may change.
+    ///
+    /// Files whose poll events never change ignore it.
+    fn poll_wait(&self, _waiter: &Arc<dyn Waiter>) {}
+}
+
+/// A waiter of the events of the files, see [INodeInterface::poll_wait].
+pub trait Waiter: Sync + Send {
+    fn wake(&self);
+}
+
+/// The waiters of the events of a file.
+///
+/// The waiters are held weakly, a waiter stops waiting by being dropped.
+pub struct WaitQueue<R: RawMutex>(Mutex<R, Vec<Weak<dyn Waiter>>>);
+
+impl<R: RawMutex> WaitQueue<R> {
+    pub const fn new() -> Self {
+        Self(Mutex::new(Vec::new()))
+    }
+
+    /// Add the waiter, adding it again does nothing.
+    pub fn add(&self, waiter: &Arc<dyn Waiter>) {
+        let mut waiters = self.0.lock();
+        waiters.retain(|x| x.strong_count() > 0);
+        if !waiters
+            .iter()
+            .any(|x| x.as_ptr() as *const u8 == Arc::as_ptr(waiter) as *const u8)
+        {
+            waiters.push(Arc::downgrade(waiter));
+        }
+    }
+
+    /// Wake up all the waiters.
+    pub fn wake_all(&self) {
+        let waiters: Vec<Arc<dyn Waiter>> = {
+            let mut waiters = self.0.lock();
+            waiters.retain(|x| x.strong_count() > 0);
+            waiters.iter().filter_map(Weak::upgrade).collect()
+        };
+        // The lock is released, the waiters may wake up other queues.
+        waiters.iter().for_each(|x| x.wake());
+    }
+}
+
+impl<R: RawMutex> Default for WaitQueue<R> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Dentry Node represents
@@ -491,6 +547,12 @@ impl<R: RawMutex + 'static, W: RawRwLock, F: FSTrait> DentryFile<R, W, F> {
         })
     }
 
+    /// Get the inode of the file.
+    #[inline]
+    pub fn inode(&self) -> Arc<dyn INodeInterface> {
+        self.file.clone()
+    }
+
     /// Get the metadata of the file.
     #[inline]
     pub fn metadata(&self) -> FsResult<Metadata> {
@@ -521,6 +583,12 @@ impl<R: RawMutex + 'static, W: RawRwLock, F: FSTrait> DentryFile<R, W, F> {
         self.file.rmdir(name)
     }
 
+    /// Create a special file (socket and so on) with name
+    #[inline]
+    pub fn mknod(&self, name: &str, file_type: FileType) -> FsResult<Arc<dyn INodeInterface>> {
+        self.file.mknod(name, file_type)
+    }
+
     /// Rename a file with name
     #[inline]
     pub fn remove(&self, name: &str) -> FsResult<()> {
@@ -602,6 +670,12 @@ impl<R: RawMutex + 'static, W: RawRwLock, F: FSTrait> DentryFile<R, W, F> {
     pub fn poll(&self, events: PollEvent) -> FsResult<PollEvent> {
         self.file.poll(events)
     }
+
+    /// Add the waiter woken up when the poll events of the file may change.
+    #[inline]
+    pub fn poll_wait(&self, waiter: &Arc<dyn Waiter>) {
+        self.file.poll_wait(waiter)
+    }
 }
 
 /// FSPages Container, first arg is address, second arg is page count.
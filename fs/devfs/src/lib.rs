@@ -0,0 +1,147 @@
+//! Device file system.
+//!
+//! The kernel adds device files with their paths, the directories in the
+//! paths are created by the file system. Files can't be created by users.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    collections::{btree_map::Entry, BTreeMap},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use fs_base::{
+    DirEntry, Errno, FileSystem, FileType, FsResult, INodeInterface, Metadata, OpenFlags, Stat,
+    StatMode,
+};
+use lock_api::{Mutex, RawMutex};
+
+pub struct DevFs<R: RawMutex> {
+    root: Arc<DevDir<R>>,
+}
+
+impl<R: RawMutex + 'static> DevFs<R> {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            root: Arc::new(DevDir::new("")),
+        })
+    }
+
+    /// Add the device file to the path relative to the root, such as `input/event0`.
+    ///
+    /// The missing directories in the path are created.
+    pub fn add_device(&self, path: &str, device: Arc<dyn INodeInterface>) -> FsResult<()> {
+        let (dirs, name) = match path.rsplit_once('/') {
+            Some((dirs, name)) => (Some(dirs), name),
+            None => (None, path),
+        };
+        let mut dir = self.root.clone();
+        for dir_name in dirs.into_iter().flat_map(|x| x.split('/')) {
+            if dir_name.is_empty() {
+                continue;
+            }
+            let next = match dir.children.lock().entry(String::from(dir_name)) {
+                Entry::Occupied(entry) => match entry.get() {
+                    DevNode::Dir(next) => next.clone(),
+                    DevNode::Device(_) => return Err(Errno::ENOTDIR),
+                },
+                Entry::Vacant(entry) => {
+                    let next = Arc::new(DevDir::new(dir_name));
+                    entry.insert(DevNode::Dir(next.clone()));
+                    next
+                }
+            };
+            dir = next;
+        }
+        let mut children = dir.children.lock();
+        if children.contains_key(name) {
+            return Err(Errno::EEXIST);
+        }
+        children.insert(String::from(name), DevNode::Device(device));
+        Ok(())
+    }
+}
+
+impl<R: RawMutex + Sync + Send + 'static> FileSystem for DevFs<R> {
+    fn root_dir(&self) -> Arc<dyn INodeInterface> {
+        self.root.clone()
+    }
+
+    fn name(&self) -> &str {
+        "devfs"
+    }
+}
+
+/// A node in the device directory.
+enum DevNode<R: RawMutex> {
+    Dir(Arc<DevDir<R>>),
+    Device(Arc<dyn INodeInterface>),
+}
+
+pub struct DevDir<R: RawMutex> {
+    name: String,
+    children: Mutex<R, BTreeMap<String, DevNode<R>>>,
+}
+
+impl<R: RawMutex> DevDir<R> {
+    fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            children: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl<R: RawMutex + Send + Sync + 'static> INodeInterface for DevDir<R> {
+    fn open(&self, name: &str, _flags: OpenFlags) -> FsResult<Arc<dyn INodeInterface>> {
+        match self.children.lock().get(name).ok_or(Errno::ENOENT)? {
+            DevNode::Dir(dir) => Ok(dir.clone()),
+            DevNode::Device(device) => Ok(device.clone()),
+        }
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        Ok(self
+            .children
+            .lock()
+            .iter()
+            .map(|(name, node)| DirEntry {
+                filename: name.clone(),
+                len: 0,
+                file_type: match node {
+                    DevNode::Dir(_) => FileType::Directory,
+                    DevNode::Device(_) => FileType::Device,
+                },
+            })
+            .collect())
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            filename: &self.name,
+            inode: self as *const Self as usize,
+            file_type: FileType::Directory,
+            size: 0,
+            childrens: self.children.lock().len(),
+        })
+    }
+
+    fn stat(&self, stat: &mut Stat) -> FsResult<()> {
+        stat.ino = self as *const Self as u64;
+        stat.mode = StatMode::DIR | StatMode::from_bits_truncate(0o755);
+        stat.nlink = 1;
+        stat.uid = 0;
+        stat.gid = 0;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        stat.rdev = 0;
+        stat.mtime = Default::default();
+        stat.atime = Default::default();
+        stat.ctime = Default::default();
+        Ok(())
+    }
+}
@@ -57,10 +57,17 @@ pub struct RamLinkInner<R: RawMutex> {
     link_file: Mutex<R, String>,
 }
 
+/// Special file without data, the kernel finds its object by the inode.
+pub struct RamNodeInner {
+    name: String,
+    file_type: FileType,
+}
+
 pub enum FileContainer<R: RawMutex, F: FSTrait> {
     File(RamFileInner<R, F>),
     Dir(RamDirInner<R, F>),
     Link(RamLinkInner<R>),
+    Node(RamNodeInner),
 }
 
 impl<R: RawMutex + Send + Sync + 'static, F: FSTrait> FileContainer<R, F> {
@@ -70,6 +77,7 @@ impl<R: RawMutex + Send + Sync + 'static, F: FSTrait> FileContainer<R, F> {
             FileContainer::File(file) => &file.name,
             FileContainer::Dir(dir) => &dir.name,
             FileContainer::Link(link) => &link.name,
+            FileContainer::Node(node) => &node.name,
         }
     }
 }
@@ -132,6 +140,26 @@ impl<R: RawMutex + Send + Sync + 'static, F: FSTrait> INodeInterface for FileCon
         Ok(new_dir)
     }
 
+    fn mknod(&self, name: &str, file_type: FileType) -> FsResult<Arc<dyn INodeInterface>> {
+        let dir = match self {
+            FileContainer::Dir(dir) => dir,
+            _ => return Err(Errno::ENOTDIR),
+        };
+        if !matches!(file_type, FileType::Socket | FileType::Fifo) {
+            return Err(Errno::EINVAL);
+        }
+        let mut children = dir.children.lock();
+        if children.iter().any(|x| x.filename() == name) {
+            return Err(Errno::EEXIST);
+        }
+        let new_node = Arc::new(FileContainer::Node(RamNodeInner {
+            name: String::from(name),
+            file_type,
+        }));
+        children.push(new_node.clone());
+        Ok(new_node)
+    }
+
     fn rmdir(&self, name: &str) -> FsResult<()> {
         let dir = match self {
             FileContainer::Dir(dir) => dir,
@@ -179,6 +207,11 @@ impl<R: RawMutex + Send + Sync + 'static, F: FSTrait> INodeInterface for FileCon
                     len: 0,
                     file_type: FileType::Link,
                 },
+                FileContainer::Node(node) => DirEntry {
+                    filename: node.name.clone(),
+                    len: 0,
+                    file_type: node.file_type,
+                },
             })
             .collect())
     }
@@ -195,6 +228,7 @@ impl<R: RawMutex + Send + Sync + 'static, F: FSTrait> INodeInterface for FileCon
                 FileContainer::File(x) => x.name == name,
                 FileContainer::Dir(_) => false,
                 FileContainer::Link(x) => x.name == name,
+                FileContainer::Node(x) => x.name == name,
             })
             .count();
 
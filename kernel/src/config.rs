@@ -11,5 +11,8 @@ pub const DEFAULT_USER_STACK_SIZE: usize = 0x8000;
 /// Default stack top address for the user stack.
 pub const DEFAULT_USER_STACK_TOP: usize = 0x1_0000_0000;
 
+/// The page of the trampoline returning from the signal handlers, above the user stack.
+pub const SIG_TRAMPOLINE: usize = 0x1_0000_1000;
+
 /// Default alignment for the user stack and memory.
 pub const ALIGN_SIZE: usize = core::mem::size_of::<usize>();
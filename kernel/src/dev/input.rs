@@ -0,0 +1,185 @@
+//! Evdev files of the input devices, `/dev/input/eventN`.
+//!
+//! Reading the file returns the pending events in the Linux `input_event`
+//! layout, and the `EVIOCG*` ioctls report the identity and capabilities
+//! of the device.
+
+use alloc::{format, string::String, sync::Arc};
+use drivers_base::InputDriver;
+use fs_base::{
+    Errno, FileType, FsResult, INodeInterface, Metadata, PollEvent, Stat, StatMode, Waiter,
+};
+
+/// The size of the Linux `input_event`, the timeval and the type, code and value.
+const INPUT_EVENT_SIZE: usize = 24;
+/// The evdev protocol version reported by EVIOCGVERSION.
+const EV_VERSION: i32 = 0x010001;
+/// The major device number and the first minor of the evdev files.
+const INPUT_MAJOR: u64 = 13;
+const EVDEV_MINOR_BASE: u64 = 64;
+/// Count of the key codes, the largest state bitmap.
+const KEY_CNT: usize = 0x300;
+
+/// The ioctl type of evdev.
+const IOC_TYPE_EVDEV: usize = b'E' as usize;
+/// The ioctl direction of the commands reading from the kernel.
+const IOC_READ: usize = 2;
+
+/// Numbers of the evdev ioctls.
+const EVIOCGVERSION: usize = 0x01;
+const EVIOCGID: usize = 0x02;
+const EVIOCGNAME: usize = 0x06;
+const EVIOCGPHYS: usize = 0x07;
+const EVIOCGUNIQ: usize = 0x08;
+const EVIOCGPROP: usize = 0x09;
+/// Key, LED, sound and switch states.
+const EVIOCGKEY: usize = 0x18;
+const EVIOCGSW: usize = 0x1b;
+/// EVIOCGBIT(ev) is EVIOCGBIT + ev.
+const EVIOCGBIT: usize = 0x20;
+/// EVIOCGABS(abs) is EVIOCGABS + abs.
+const EVIOCGABS: usize = 0x40;
+const EVIOCGEFFECTS: usize = 0x84;
+const EVIOCGRAB: usize = 0x90;
+const EVIOCSCLOCKID: usize = 0xa0;
+
+pub struct EventFile {
+    name: String,
+    minor: u64,
+    device: Arc<dyn InputDriver>,
+}
+
+impl EventFile {
+    pub fn new(index: usize, device: Arc<dyn InputDriver>) -> Self {
+        Self {
+            name: format!("event{}", index),
+            minor: EVDEV_MINOR_BASE + index as u64,
+            device,
+        }
+    }
+}
+
+/// Copy the data to the user buffer of the ioctl, at most len bytes.
+///
+/// Returns the copied length.
+fn copy_to_user(arg: usize, len: usize, data: &[u8]) -> FsResult<usize> {
+    if arg == 0 {
+        return Err(Errno::EFAULT);
+    }
+    let len = len.min(data.len());
+    unsafe { core::slice::from_raw_parts_mut(arg as *mut u8, len) }.copy_from_slice(&data[..len]);
+    Ok(len)
+}
+
+impl INodeInterface for EventFile {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> FsResult<usize> {
+        if buffer.len() < INPUT_EVENT_SIZE {
+            return Err(Errno::EINVAL);
+        }
+        let mut rlen = 0;
+        for chunk in buffer.chunks_exact_mut(INPUT_EVENT_SIZE) {
+            let event = match self.device.read_event() {
+                Some(event) => event,
+                None => break,
+            };
+            chunk[0..8].copy_from_slice(&event.time.as_secs().to_ne_bytes());
+            chunk[8..16].copy_from_slice(&(event.time.subsec_micros() as u64).to_ne_bytes());
+            chunk[16..18].copy_from_slice(&event.event_type.to_ne_bytes());
+            chunk[18..20].copy_from_slice(&event.code.to_ne_bytes());
+            chunk[20..24].copy_from_slice(&event.value.to_ne_bytes());
+            rlen += INPUT_EVENT_SIZE;
+        }
+        match rlen {
+            0 => Err(Errno::EAGAIN),
+            _ => Ok(rlen),
+        }
+    }
+
+    fn poll(&self, events: PollEvent) -> FsResult<PollEvent> {
+        let mut res = PollEvent::NONE;
+        if events.contains(PollEvent::POLLIN) && !self.device.is_empty() {
+            res |= PollEvent::POLLIN;
+        }
+        Ok(res)
+    }
+
+    fn poll_wait(&self, waiter: &Arc<dyn Waiter>) {
+        crate::drivers::poll_wait(waiter);
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> FsResult<usize> {
+        let dir = command >> 30;
+        let size = (command >> 16) & 0x3fff;
+        let nr = command & 0xff;
+        if (command >> 8) & 0xff != IOC_TYPE_EVDEV {
+            return Err(Errno::ENOTTY);
+        }
+        match nr {
+            EVIOCGRAB | EVIOCSCLOCKID => Ok(0),
+            _ if dir != IOC_READ => Err(Errno::ENOTTY),
+            EVIOCGVERSION => copy_to_user(arg, size, &EV_VERSION.to_ne_bytes()).map(|_| 0),
+            EVIOCGID => {
+                let id = self.device.input_id();
+                let mut data = [0u8; 8];
+                [id.bustype, id.vendor, id.product, id.version]
+                    .iter()
+                    .enumerate()
+                    .for_each(|(i, x)| data[i * 2..i * 2 + 2].copy_from_slice(&x.to_ne_bytes()));
+                copy_to_user(arg, size, &data).map(|_| 0)
+            }
+            EVIOCGNAME => {
+                let mut name = String::from(self.device.name());
+                name.push('\0');
+                copy_to_user(arg, size, name.as_bytes())
+            }
+            EVIOCGPHYS | EVIOCGUNIQ => Err(Errno::ENOENT),
+            EVIOCGPROP => copy_to_user(arg, size, self.device.prop_bits()),
+            // The states are not tracked, all keys are released and switches are off.
+            EVIOCGKEY..=EVIOCGSW => copy_to_user(arg, size, &[0; KEY_CNT / 8]),
+            _ if (EVIOCGBIT..EVIOCGABS).contains(&nr) => {
+                copy_to_user(arg, size, self.device.event_bits((nr - EVIOCGBIT) as u8))
+            }
+            _ if (EVIOCGABS..EVIOCGABS + 0x40).contains(&nr) => {
+                let abs = self
+                    .device
+                    .abs_info((nr - EVIOCGABS) as u8)
+                    .ok_or(Errno::EINVAL)?;
+                // The current value is not tracked.
+                let mut data = [0u8; 24];
+                [0, abs.min, abs.max, abs.fuzz, abs.flat, abs.resolution]
+                    .iter()
+                    .enumerate()
+                    .for_each(|(i, x)| data[i * 4..i * 4 + 4].copy_from_slice(&x.to_ne_bytes()));
+                copy_to_user(arg, size, &data).map(|_| 0)
+            }
+            EVIOCGEFFECTS => copy_to_user(arg, size, &0i32.to_ne_bytes()).map(|_| 0),
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            filename: &self.name,
+            inode: self as *const Self as usize,
+            file_type: FileType::Device,
+            size: 0,
+            childrens: 0,
+        })
+    }
+
+    fn stat(&self, stat: &mut Stat) -> FsResult<()> {
+        stat.ino = self as *const Self as u64;
+        stat.mode = StatMode::CHAR | StatMode::from_bits_truncate(0o660);
+        stat.nlink = 1;
+        stat.uid = 0;
+        stat.gid = 0;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        stat.rdev = super::make_dev(INPUT_MAJOR, self.minor);
+        stat.mtime = Default::default();
+        stat.atime = Default::default();
+        stat.ctime = Default::default();
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+//! Device files under `/dev`.
+//!
+//! The device files are created for the probed drivers when the device
+//! file system is initialized.
+
+mod input;
+
+use alloc::{format, sync::Arc};
+use drivers_base::DeviceType;
+use fs_devfs::DevFs;
+use spin::Mutex;
+
+use crate::drivers;
+
+/// Encode the device number like the Linux `makedev`.
+pub fn make_dev(major: u64, minor: u64) -> u64 {
+    ((major & 0xfff) << 8)
+        | ((major & !0xfff) << 32)
+        | (minor & 0xff)
+        | ((minor & !0xff) << 12)
+}
+
+/// Create the device file system with the files of the probed drivers.
+pub fn init() -> Arc<DevFs<Mutex<()>>> {
+    let devfs = DevFs::new();
+    let mut input_count = 0;
+    for driver in drivers::get_drivers() {
+        if let DeviceType::INPUT(device) = driver.get_device() {
+            let path = format!("input/event{}", input_count);
+            log::info!("/dev/{}: {}", path, device.name());
+            devfs
+                .add_device(&path, Arc::new(input::EventFile::new(input_count, device)))
+                .expect("can't add the input device");
+            input_count += 1;
+        }
+    }
+    devfs
+}
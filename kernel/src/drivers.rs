@@ -0,0 +1,336 @@
+//! Device driver management.
+//!
+//! Probes the devices from the device tree, records the drivers and
+//! dispatches the external interrupts through the interrupt controller.
+
+use alloc::{sync::Arc, vec::Vec};
+use block::BlockCache;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use drivers_base::{DSched, DeviceType, Driver, IntDriver};
+use fdt::node::FdtNode;
+use fs_base::{WaitQueue, Waiter};
+use polyhal::{common::get_fdt, consts::VIRT_ADDR_START, time::Time};
+use spin::{Mutex, Once};
+
+use crate::PageAllocator;
+
+/// All drivers probed by the kernel.
+static DRIVERS: Mutex<Vec<Arc<dyn Driver>>> = Mutex::new(Vec::new());
+
+/// The interrupt controller of the current platform.
+static INT_DRIVER: Once<Arc<dyn IntDriver>> = Once::new();
+
+/// Waiters of the events of the devices and the sockets, woken up by [DriverSched::wake].
+static WAITERS: WaitQueue<Mutex<()>> = WaitQueue::new();
+
+/// Set by [DriverSched::wake] and cleared by the next sleep.
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+/// Set once a network device without an interrupt is registered, the sockets
+/// only get its frames by polling it.
+static POLLED_NET: AtomicBool = AtomicBool::new(false);
+
+/// Scheduling interface for the drivers.
+///
+/// There is no task scheduler yet, so sleeping parks the cpu until the next
+/// interrupt, the timer interrupt bounds the sleep. Waking up ends the next
+/// sleep and notifies the waiters added by [poll_wait].
+///
+/// Sleeping spins instead if no interrupt can end the wait, the drivers
+/// without irqs spin themselves.
+pub struct DriverSched;
+
+impl DSched for DriverSched {
+    fn sleep() {
+        match INT_DRIVER.get().is_none() || POLLED_NET.load(Ordering::Acquire) {
+            true => core::hint::spin_loop(),
+            false => wait_for_interrupt(),
+        }
+    }
+
+    fn wake() {
+        WOKEN.store(true, Ordering::Release);
+        WAITERS.wake_all();
+    }
+
+    fn now() -> Duration {
+        Duration::from_nanos(Time::now().to_nsec() as u64)
+    }
+}
+
+/// Park the cpu until an interrupt is handled, unless it has been woken up.
+///
+/// The wake-up is checked with the interrupts disabled and the cpu waits
+/// with them disabled, then it takes the interrupt. A wake-up from an
+/// interrupt handler after the check isn't missed.
+fn wait_for_interrupt() {
+    let woken = || WOKEN.swap(false, Ordering::AcqRel);
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        let sstatus: usize;
+        asm!("csrrci {}, sstatus, 2", out(reg) sstatus);
+        if !woken() {
+            asm!("wfi", "csrsi sstatus, 2", "csrci sstatus, 2");
+        }
+        if sstatus & 2 != 0 {
+            asm!("csrsi sstatus, 2");
+        }
+    }
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        let rflags: usize;
+        asm!("pushfq", "pop {}", "cli", out(reg) rflags);
+        if !woken() {
+            // The interrupts are taken after the instruction following `sti`.
+            asm!("sti", "hlt", "cli");
+        }
+        if rflags & (1 << 9) != 0 {
+            asm!("sti");
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        let daif: usize;
+        asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif);
+        if !woken() {
+            asm!("wfi", "msr daifclr, #2", "isb", "msr daifset, #2");
+        }
+        if daif & (1 << 7) == 0 {
+            asm!("msr daifclr, #2");
+        }
+    }
+    #[cfg(target_arch = "loongarch64")]
+    unsafe {
+        let crmd: usize;
+        // The old value is written back to the first register of `csrxchg`.
+        asm!("csrrd {}, 0x0", "csrxchg $zero, {}, 0x0", out(reg) crmd, in(reg) 4usize);
+        if !woken() {
+            asm!(
+                "idle 0",
+                "csrxchg {ie}, {mask}, 0x0",
+                "csrxchg $zero, {mask}, 0x0",
+                ie = inout(reg) 4usize => _,
+                mask = in(reg) 4usize,
+            );
+        }
+        if crmd & 4 != 0 {
+            asm!("csrxchg {}, {}, 0x0", inout(reg) 4usize => _, in(reg) 4usize);
+        }
+    }
+}
+
+/// Add the waiter woken up when a driver or a socket reports an event.
+pub fn poll_wait(waiter: &Arc<dyn Waiter>) {
+    WAITERS.add(waiter);
+}
+
+/// Record the driver and register its interrupts to the interrupt controller.
+pub fn register(driver: Arc<dyn Driver>) {
+    log::debug!("{:?}", driver);
+    // The loopback device delivers its frames while sending.
+    if driver.interrupts().is_empty()
+        && driver.get_id() != "loopback"
+        && matches!(driver.clone().get_device(), DeviceType::NET(_))
+    {
+        POLLED_NET.store(true, Ordering::Release);
+    }
+    if let Some(int_driver) = INT_DRIVER.get() {
+        driver
+            .interrupts()
+            .iter()
+            .for_each(|irq| int_driver.register_irq(*irq, driver.clone()));
+    }
+    DRIVERS.lock().push(driver);
+}
+
+/// Get all the drivers probed by the kernel.
+pub fn get_drivers() -> Vec<Arc<dyn Driver>> {
+    DRIVERS.lock().clone()
+}
+
+/// Get the interrupt controller of the current platform.
+pub fn int_driver() -> Option<&'static Arc<dyn IntDriver>> {
+    INT_DRIVER.get()
+}
+
+/// Handle the external interrupt.
+pub fn handle_irq() {
+    match INT_DRIVER.get() {
+        Some(int_driver) => int_driver.handle_irq(),
+        None => log::warn!("external interrupt without an interrupt controller"),
+    }
+}
+
+/// Get the irq numbers of the device tree node.
+///
+/// The interrupt specifier is decoded with the `#interrupt-cells` of the interrupt parent.
+fn node_irqs(node: &FdtNode) -> Vec<u32> {
+    let cells = node
+        .interrupt_parent()
+        .and_then(|parent| parent.interrupt_cells())
+        .unwrap_or(1);
+    node.property("interrupts")
+        .map(|prop| {
+            prop.value
+                .chunks_exact(cells * 4)
+                .map(specifier_irq)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Get the irq of the interrupt specifier of the interrupt controller.
+pub fn specifier_irq(specifier: &[u8]) -> u32 {
+    let read_cell =
+        |idx: usize| u32::from_be_bytes(specifier[idx * 4..idx * 4 + 4].try_into().unwrap());
+    match specifier.len() / 4 {
+        // ARM GIC (type, number, flags), SPIs start from 32 and PPIs start from 16.
+        3 => match read_cell(0) {
+            0 => read_cell(1) + 32,
+            _ => read_cell(1) + 16,
+        },
+        _ => read_cell(0),
+    }
+}
+
+/// Use the driver as the interrupt controller of the current platform.
+fn set_int_driver<T: IntDriver + 'static>(int_driver: Arc<T>) {
+    log::info!("Interrupt controller: {}", int_driver.get_id());
+    INT_DRIVER.call_once(|| int_driver.clone() as Arc<dyn IntDriver>);
+    DRIVERS.lock().push(int_driver);
+}
+
+/// Probe the interrupt controller of the current platform.
+fn init_int_driver(hart_id: usize) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use drivers_intc::apic::{Apic, IOAPIC_ADDR, LAPIC_ADDR};
+        set_int_driver(Arc::new(Apic::<Mutex<()>>::new(
+            IOAPIC_ADDR | VIRT_ADDR_START,
+            LAPIC_ADDR | VIRT_ADDR_START,
+        )));
+    }
+
+    let fdt = match get_fdt() {
+        Some(fdt) => fdt,
+        None => return,
+    };
+    for node in fdt.all_nodes() {
+        let compatible = match node.compatible() {
+            Some(compatible) => compatible,
+            None => continue,
+        };
+        let mut regs = node
+            .reg()
+            .into_iter()
+            .flatten()
+            .map(|reg| reg.starting_address as usize | VIRT_ADDR_START);
+        if compatible
+            .all()
+            .any(|x| x == "riscv,plic0" || x == "sifive,plic-1.0.0")
+        {
+            // QEMU virt machine uses context hart_id * 2 + 1 for the supervisor mode.
+            let plic =
+                drivers_intc::plic::Plic::<Mutex<()>>::new(regs.next().unwrap(), hart_id * 2 + 1);
+            set_int_driver(Arc::new(plic));
+            break;
+        } else if compatible
+            .all()
+            .any(|x| x == "arm,cortex-a15-gic" || x == "arm,gic-400")
+        {
+            let gicd = regs.next().unwrap();
+            let gicc = regs.next().unwrap();
+            let mut gic = drivers_intc::gicv2::GicV2::<Mutex<()>>::new(gicd, gicc);
+            // The GICv2m frame for MSIs is a child of the GIC node.
+            let v2m = node.children().find(|x| {
+                x.compatible()
+                    .is_some_and(|c| c.all().any(|c| c == "arm,gic-v2m-frame"))
+            });
+            if let Some(frame) = v2m.and_then(|x| x.reg()?.next()) {
+                let frame = frame.starting_address as usize;
+                gic.set_v2m(frame | VIRT_ADDR_START, frame as u64);
+            }
+            set_int_driver(Arc::new(gic));
+            break;
+        }
+        #[cfg(target_arch = "aarch64")]
+        if compatible.all().any(|x| x == "arm,gic-v3") {
+            // The first redistributor frame belongs to the boot cpu.
+            let gicd = regs.next().unwrap();
+            let gicr = regs.next().unwrap();
+            set_int_driver(Arc::new(drivers_intc::gicv3::GicV3::<Mutex<()>>::new(
+                gicd, gicr,
+            )));
+            break;
+        }
+    }
+}
+
+/// Initialize the interrupt controller and probe the devices in the device tree.
+pub fn init(hart_id: usize) {
+    init_int_driver(hart_id);
+    register(drivers_loopback::Loopback::<Mutex<()>>::new());
+
+    let fdt = match get_fdt() {
+        Some(fdt) => fdt,
+        None => return,
+    };
+    for node in fdt.all_nodes() {
+        let is_virtio = node
+            .compatible()
+            .map(|x| x.all().any(|n| n == "virtio,mmio"))
+            .unwrap_or(false);
+        if !is_virtio {
+            continue;
+        }
+        let addr = node.reg().unwrap().next().unwrap().starting_address;
+        if let Some(dri) = drivers_virtio::probe::<Mutex<()>, PageAllocator, DriverSched>(
+            addr as usize | VIRT_ADDR_START,
+            node_irqs(&node),
+        ) {
+            register(dri);
+        }
+    }
+}
+
+/// Max cached blocks of a partition.
+const PARTITION_CACHE_BLOCKS: usize = 256;
+
+/// Parse the partition tables of the probed block devices,
+/// and register every partition behind a write-back cache.
+pub fn probe_partitions() {
+    for driver in get_drivers() {
+        let device = match driver.get_device() {
+            DeviceType::BLOCK(device) => device,
+            _ => continue,
+        };
+        let partitions = match block::parse_partitions(&device) {
+            Ok(partitions) => partitions,
+            Err(err) => {
+                log::warn!("can't parse partitions of {}: {:?}", device.get_id(), err);
+                continue;
+            }
+        };
+        for partition in partitions {
+            register(BlockCache::<Mutex<()>>::new(
+                partition,
+                PARTITION_CACHE_BLOCKS,
+            ));
+        }
+    }
+}
+
+/// Write the dirty blocks of the caches back to the devices.
+pub fn sync_blocks() {
+    for driver in get_drivers() {
+        if let DeviceType::BLOCK(device) = driver.get_device() {
+            if let Err(err) = device.flush() {
+                log::warn!("can't flush {}: {:?}", device.get_id(), err);
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Epoll instances, the files watching the events of other files.
+//!
+//! Every watched file has an entry which waits on the file by
+//! [INodeInterface::poll_wait]. A level-triggered entry reports the events
+//! whenever the file is ready, an edge-triggered entry only after the file
+//! woke it up since the events were last reported.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+use fs_base::{Errno, FsResult, INodeInterface, PollEvent, WaitQueue, Waiter};
+use spin::Mutex;
+
+use crate::task::fd::FileItem;
+
+pub const EPOLL_CTL_ADD: usize = 1;
+pub const EPOLL_CTL_DEL: usize = 2;
+pub const EPOLL_CTL_MOD: usize = 3;
+
+pub const EPOLLERR: u32 = 0x008;
+pub const EPOLLHUP: u32 = 0x010;
+pub const EPOLLEXCLUSIVE: u32 = 1 << 28;
+pub const EPOLLWAKEUP: u32 = 1 << 29;
+pub const EPOLLONESHOT: u32 = 1 << 30;
+pub const EPOLLET: u32 = 1 << 31;
+
+/// The flags of the entry which aren't events.
+const EPOLL_FLAGS: u32 = EPOLLEXCLUSIVE | EPOLLWAKEUP | EPOLLONESHOT | EPOLLET;
+
+/// Max levels of the epoll instances watching each other.
+const MAX_DEPTH: usize = 5;
+
+/// The `struct epoll_event`, it's packed on x86_64.
+#[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[derive(Debug, Clone, Copy)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+/// Get the poll events of the file, the files which can't be polled are always ready.
+///
+/// The errors and the hang-ups are reported without being requested.
+pub fn poll_file(file: &FileItem, events: PollEvent) -> PollEvent {
+    let events = events | PollEvent::POLLERR | PollEvent::POLLHUP;
+    file.inode.poll(events.clone()).unwrap_or(
+        PollEvent::POLLIN | PollEvent::POLLOUT | PollEvent::POLLRDNORM | PollEvent::POLLWRNORM,
+    ) & events
+}
+
+struct Entry {
+    file: Weak<FileItem>,
+    event: Mutex<EpollEvent>,
+    /// The file woke up the entry since the events were last reported.
+    woken: AtomicBool,
+    epoll: Weak<EventPoll>,
+}
+
+impl Entry {
+    /// Get the events to report, returns None if the entry isn't ready.
+    ///
+    /// `consume` clears the edge and disables a one-shot entry.
+    fn ready(&self, consume: bool) -> Option<EpollEvent> {
+        let file = self.file.upgrade()?;
+        let mut event = self.event.lock();
+        if event.events & !EPOLL_FLAGS == 0 {
+            return None;
+        }
+        let edge = event.events & EPOLLET != 0;
+        if edge && !self.woken.load(Ordering::Acquire) {
+            return None;
+        }
+        if edge && consume {
+            self.woken.store(false, Ordering::Release);
+        }
+        let requested = PollEvent::from_bits_truncate(event.events as u16);
+        let events = poll_file(&file, requested).bits() as u32;
+        if events == 0 {
+            return None;
+        }
+        if consume && event.events & EPOLLONESHOT != 0 {
+            event.events &= EPOLL_FLAGS;
+        }
+        Some(EpollEvent {
+            events,
+            data: event.data,
+        })
+    }
+}
+
+impl Waiter for Entry {
+    fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(epoll) = self.epoll.upgrade() {
+            epoll.waiters.wake_all();
+        }
+    }
+}
+
+/// An epoll instance.
+pub struct EventPoll {
+    /// The entries of the watched files by their descriptors.
+    entries: Mutex<BTreeMap<usize, Arc<Entry>>>,
+    /// The waiters of the epoll file itself.
+    waiters: WaitQueue<Mutex<()>>,
+    this: Weak<EventPoll>,
+}
+
+impl EventPoll {
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            entries: Mutex::new(BTreeMap::new()),
+            waiters: WaitQueue::new(),
+            this: this.clone(),
+        })
+    }
+
+    /// Check the instance and the nested instances it watches, `root` is at the depth 1.
+    ///
+    /// Returns ELOOP if `root` is watched again or the instances nest too deep.
+    fn check_nested(&self, root: &EventPoll, depth: usize) -> FsResult<()> {
+        if core::ptr::eq(self, root) || depth > MAX_DEPTH {
+            return Err(Errno::ELOOP);
+        }
+        let nested: Vec<Arc<EventPoll>> = self
+            .entries
+            .lock()
+            .values()
+            .filter_map(|x| x.file.upgrade()?.epoll.clone())
+            .collect();
+        nested
+            .iter()
+            .try_for_each(|x| x.check_nested(root, depth + 1))
+    }
+
+    /// Add, modify or delete the entry of the file `fd`.
+    pub fn ctl(
+        &self,
+        op: usize,
+        fd: usize,
+        file: &Arc<FileItem>,
+        event: Option<EpollEvent>,
+    ) -> FsResult<()> {
+        // An instance watching itself through the nested ones would loop forever.
+        if let (EPOLL_CTL_ADD, Some(epoll)) = (op, &file.epoll) {
+            epoll.check_nested(self, 2)?;
+        }
+        let mut entries = self.entries.lock();
+        // The entries of the closed files are dropped.
+        entries.retain(|_, x| x.file.strong_count() > 0);
+        let same_file = |x: &Arc<Entry>| x.file.as_ptr() == Arc::as_ptr(file);
+        // The errors and the hang-ups are always reported.
+        let event = event.map(|mut x| {
+            x.events |= EPOLLERR | EPOLLHUP;
+            x
+        });
+        match op {
+            EPOLL_CTL_ADD => {
+                let event = event.ok_or(Errno::EFAULT)?;
+                if entries.get(&fd).is_some_and(same_file) {
+                    return Err(Errno::EEXIST);
+                }
+                let entry = Arc::new(Entry {
+                    file: Arc::downgrade(file),
+                    event: Mutex::new(event),
+                    woken: AtomicBool::new(true),
+                    epoll: self.this.clone(),
+                });
+                file.inode.poll_wait(&(entry.clone() as Arc<dyn Waiter>));
+                entries.insert(fd, entry);
+            }
+            EPOLL_CTL_MOD => {
+                let event = event.ok_or(Errno::EFAULT)?;
+                let entry = entries
+                    .get(&fd)
+                    .filter(|x| same_file(x))
+                    .ok_or(Errno::ENOENT)?;
+                if event.events & EPOLLEXCLUSIVE != 0 {
+                    return Err(Errno::EINVAL);
+                }
+                *entry.event.lock() = event;
+                entry.woken.store(true, Ordering::Release);
+            }
+            EPOLL_CTL_DEL => {
+                entries
+                    .get(&fd)
+                    .filter(|x| same_file(x))
+                    .ok_or(Errno::ENOENT)?;
+                entries.remove(&fd);
+            }
+            _ => return Err(Errno::EINVAL),
+        }
+        drop(entries);
+        // The waiters check the new entry.
+        self.waiters.wake_all();
+        Ok(())
+    }
+
+    /// Take at most `max` events of the ready entries.
+    pub fn wait(&self, max: usize) -> Vec<EpollEvent> {
+        let mut entries = self.entries.lock();
+        entries.retain(|_, x| x.file.strong_count() > 0);
+        entries
+            .values()
+            .filter_map(|x| x.ready(true))
+            .take(max)
+            .collect()
+    }
+}
+
+impl INodeInterface for EventPoll {
+    fn poll(&self, events: PollEvent) -> FsResult<PollEvent> {
+        let ready = self
+            .entries
+            .lock()
+            .values()
+            .any(|x| x.ready(false).is_some());
+        match ready && events.contains(PollEvent::POLLIN) {
+            true => Ok(PollEvent::POLLIN),
+            false => Ok(PollEvent::NONE),
+        }
+    }
+
+    fn poll_wait(&self, waiter: &Arc<dyn Waiter>) {
+        self.waiters.add(waiter);
+    }
+}
@@ -0,0 +1,20 @@
+//! Files of the kernel which don't belong to a file system.
+
+pub mod epoll;
+pub mod pipe;
+
+use alloc::sync::Arc;
+use fs_base::{Errno, FileType, FsResult, INodeInterface, OpenFlags};
+
+/// Create the special file of the path, the paths are relative to the root.
+pub fn mknod(path: &str, file_type: FileType) -> FsResult<Arc<dyn INodeInterface>> {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() {
+        return Err(Errno::EINVAL);
+    }
+    let root = crate::FILE_TREE.root();
+    match dir {
+        "" => root.mknod(name, file_type),
+        _ => root.open(dir, OpenFlags::DIRECTORY)?.mknod(name, file_type),
+    }
+}
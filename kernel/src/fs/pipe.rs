@@ -0,0 +1,336 @@
+//! Pipes and named FIFOs.
+//!
+//! A pipe is a bounded ring buffer shared by its read and write ends. The
+//! readers wait while the buffer is empty and the writers while it's full.
+//! Reading returns the end of file once all the write ends are closed,
+//! writing fails with EPIPE once all the read ends are closed.
+//!
+//! A FIFO is a node in the file tree, all its opened ends share one pipe
+//! until they are all closed.
+
+use core::{
+    cmp::min,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+};
+use drivers_base::DSched;
+use fs_base::{
+    Errno, FileType, FsResult, INodeInterface, Metadata, OpenFlags, PollEvent, Stat, StatMode,
+    WaitQueue, Waiter,
+};
+use spin::Mutex;
+
+use crate::{
+    drivers::DriverSched,
+    task::{signal, task::Task},
+};
+
+/// Bytes of the buffer of a pipe.
+pub const PIPE_SIZE: usize = 0x10000;
+/// Writes of at most `PIPE_BUF` bytes aren't interleaved with other writes.
+pub const PIPE_BUF: usize = 0x1000;
+
+const FIONREAD: usize = 0x541b;
+const FIONBIO: usize = 0x5421;
+
+struct Pipe {
+    buffer: Mutex<VecDeque<u8>>,
+    readers: AtomicUsize,
+    writers: AtomicUsize,
+    waiters: WaitQueue<Mutex<()>>,
+}
+
+impl Pipe {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            buffer: Mutex::new(VecDeque::new()),
+            readers: AtomicUsize::new(0),
+            writers: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+        })
+    }
+}
+
+/// An opened end of a pipe, the FIFOs opened for reading and writing have both ends.
+pub struct PipeEnd {
+    pipe: Arc<Pipe>,
+    readable: bool,
+    writable: bool,
+    nonblocking: AtomicBool,
+}
+
+impl PipeEnd {
+    fn new(pipe: Arc<Pipe>, readable: bool, writable: bool) -> Arc<Self> {
+        if readable {
+            pipe.readers.fetch_add(1, Ordering::AcqRel);
+        }
+        if writable {
+            pipe.writers.fetch_add(1, Ordering::AcqRel);
+        }
+        pipe.waiters.wake_all();
+        Arc::new(Self {
+            pipe,
+            readable,
+            writable,
+            nonblocking: AtomicBool::new(false),
+        })
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Release);
+    }
+
+    fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Acquire)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> FsResult<usize> {
+        if !self.readable {
+            return Err(Errno::EBADF);
+        }
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut data = self.pipe.buffer.lock();
+            if !data.is_empty() {
+                let len = min(buffer.len(), data.len());
+                buffer
+                    .iter_mut()
+                    .zip(data.drain(..len))
+                    .for_each(|(dst, src)| *dst = src);
+                drop(data);
+                self.pipe.waiters.wake_all();
+                return Ok(len);
+            }
+            drop(data);
+            if self.pipe.writers.load(Ordering::Acquire) == 0 {
+                return Ok(0);
+            }
+            if self.is_nonblocking() {
+                return Err(Errno::EAGAIN);
+            }
+            if signal::interrupted() {
+                return Err(Errno::EINTR);
+            }
+            DriverSched::sleep();
+        }
+    }
+
+    fn write(&self, buffer: &[u8]) -> FsResult<usize> {
+        if !self.writable {
+            return Err(Errno::EBADF);
+        }
+        let mut written = 0;
+        while written < buffer.len() {
+            if self.pipe.readers.load(Ordering::Acquire) == 0 {
+                return match written {
+                    0 => Err(Errno::EPIPE),
+                    _ => Ok(written),
+                };
+            }
+            let mut data = self.pipe.buffer.lock();
+            let room = PIPE_SIZE - data.len();
+            let remaining = buffer.len() - written;
+            // A small write waits for the room of the whole buffer.
+            if room > 0 && (remaining > PIPE_BUF || room >= remaining) {
+                let len = min(room, remaining);
+                data.extend(&buffer[written..written + len]);
+                drop(data);
+                written += len;
+                self.pipe.waiters.wake_all();
+                continue;
+            }
+            drop(data);
+            let err = match (self.is_nonblocking(), signal::interrupted()) {
+                (true, _) => Errno::EAGAIN,
+                (false, true) => Errno::EINTR,
+                (false, false) => {
+                    DriverSched::sleep();
+                    continue;
+                }
+            };
+            return match written {
+                0 => Err(err),
+                _ => Ok(written),
+            };
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        if self.readable {
+            self.pipe.readers.fetch_sub(1, Ordering::AcqRel);
+        }
+        if self.writable {
+            self.pipe.writers.fetch_sub(1, Ordering::AcqRel);
+        }
+        self.pipe.waiters.wake_all();
+    }
+}
+
+impl INodeInterface for PipeEnd {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> FsResult<usize> {
+        self.read(buffer)
+    }
+
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> FsResult<usize> {
+        self.write(buffer)
+    }
+
+    fn poll(&self, events: PollEvent) -> FsResult<PollEvent> {
+        let len = self.pipe.buffer.lock().len();
+        let mut res = PollEvent::NONE;
+        if self.readable {
+            if events.contains(PollEvent::POLLIN) && len > 0 {
+                res |= PollEvent::POLLIN;
+            }
+            if self.pipe.writers.load(Ordering::Acquire) == 0 {
+                res |= PollEvent::POLLHUP;
+            }
+        }
+        if self.writable {
+            if events.contains(PollEvent::POLLOUT) && PIPE_SIZE - len >= PIPE_BUF {
+                res |= PollEvent::POLLOUT;
+            }
+            if self.pipe.readers.load(Ordering::Acquire) == 0 {
+                res |= PollEvent::POLLERR;
+            }
+        }
+        Ok(res)
+    }
+
+    fn poll_wait(&self, waiter: &Arc<dyn Waiter>) {
+        self.pipe.waiters.add(waiter);
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> FsResult<usize> {
+        if arg == 0 {
+            return Err(Errno::EFAULT);
+        }
+        match command {
+            FIONBIO => {
+                let nonblocking = unsafe { (arg as *const i32).read_unaligned() } != 0;
+                self.set_nonblocking(nonblocking);
+                Ok(0)
+            }
+            FIONREAD => {
+                let len = self.pipe.buffer.lock().len();
+                unsafe { (arg as *mut i32).write_unaligned(len as i32) };
+                Ok(0)
+            }
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            filename: "",
+            inode: Arc::as_ptr(&self.pipe) as usize,
+            file_type: FileType::Fifo,
+            size: 0,
+            childrens: 0,
+        })
+    }
+
+    fn stat(&self, stat: &mut Stat) -> FsResult<()> {
+        stat.ino = Arc::as_ptr(&self.pipe) as u64;
+        stat.mode = StatMode::FIFO | StatMode::from_bits_truncate(0o600);
+        stat.nlink = 1;
+        stat.uid = 0;
+        stat.gid = 0;
+        stat.size = 0;
+        stat.blksize = PIPE_BUF as _;
+        stat.blocks = 0;
+        stat.rdev = 0;
+        stat.mtime = Default::default();
+        stat.atime = Default::default();
+        stat.ctime = Default::default();
+        Ok(())
+    }
+}
+
+/// Create a pipe, returns the read end and the write end.
+pub fn pipe() -> (Arc<PipeEnd>, Arc<PipeEnd>) {
+    let pipe = Pipe::new();
+    (
+        PipeEnd::new(pipe.clone(), true, false),
+        PipeEnd::new(pipe, false, true),
+    )
+}
+
+struct Fifo {
+    /// Keep the address of the node from being reused while it's in the table.
+    node: Weak<dyn INodeInterface>,
+    /// The pipe shared by the opened ends.
+    pipe: Weak<Pipe>,
+}
+
+/// The FIFOs by the addresses of their nodes.
+static FIFOS: Mutex<BTreeMap<usize, Fifo>> = Mutex::new(BTreeMap::new());
+
+fn node_key(node: &Arc<dyn INodeInterface>) -> usize {
+    Arc::as_ptr(node) as *const () as usize
+}
+
+/// Create the FIFO of the path.
+pub fn mkfifo(path: &str) -> FsResult<()> {
+    let node = super::mknod(path, FileType::Fifo)?;
+    let mut fifos = FIFOS.lock();
+    fifos.retain(|_, x| x.node.strong_count() > 0);
+    fifos.insert(
+        node_key(&node),
+        Fifo {
+            node: Arc::downgrade(&node),
+            pipe: Weak::new(),
+        },
+    );
+    Ok(())
+}
+
+/// Open the end of the FIFO, returns None if the node isn't a FIFO.
+///
+/// Opening only one end waits for the other end to be opened, unless it's
+/// nonblocking or a signal interrupts it. A nonblocking write end fails with
+/// ENXIO without readers.
+pub fn open_fifo(
+    task: &Task,
+    node: &Arc<dyn INodeInterface>,
+    flags: OpenFlags,
+) -> FsResult<Option<Arc<PipeEnd>>> {
+    let pipe = match FIFOS.lock().get_mut(&node_key(node)) {
+        Some(fifo) => fifo.pipe.upgrade().unwrap_or_else(|| {
+            let pipe = Pipe::new();
+            fifo.pipe = Arc::downgrade(&pipe);
+            pipe
+        }),
+        None => return Ok(None),
+    };
+    let access = flags.bits() & OpenFlags::ACCMODE.bits();
+    let readable = access != OpenFlags::WRONLY.bits();
+    let writable = access != OpenFlags::RDONLY.bits();
+    let nonblocking = flags.contains(OpenFlags::NONBLOCK);
+    if nonblocking && !readable && pipe.readers.load(Ordering::Acquire) == 0 {
+        return Err(Errno::ENXIO);
+    }
+    let end = PipeEnd::new(pipe, readable, writable);
+    if !nonblocking && readable != writable {
+        let peers = match readable {
+            true => &end.pipe.writers,
+            false => &end.pipe.readers,
+        };
+        while peers.load(Ordering::Acquire) == 0 {
+            if task.signal.lock().has_deliverable() {
+                return Err(Errno::EINTR);
+            }
+            DriverSched::sleep();
+        }
+    }
+    Ok(Some(end))
+}
@@ -6,7 +6,7 @@ extern crate alloc;
 
 use core::ffi::CStr;
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::boxed::Box;
 use fs_base::{FSPage, FSTrait, FileTree, FileType, OpenFlags};
 use mem::frames::{self, alloc_pages_raw, dealloc_pages_raw};
 use polyhal::{
@@ -21,8 +21,12 @@ use polyhal::{
 use spin::{Mutex, RwLock};
 
 mod config;
+mod dev;
+mod drivers;
+mod fs;
 mod lang_items;
 mod mem;
+mod net;
 mod pci;
 mod syscall;
 mod task;
@@ -78,7 +82,11 @@ fn trap_handler(ctx: &mut TrapFrame, trap_type: TrapType) {
         IllegalInstruction(_) => {
             log::info!("illegal instruction");
         }
-        Timer => {}
+        Timer => net::poll(),
+        SupervisorExternal => {
+            drivers::handle_irq();
+            net::poll();
+        }
         _ => {
             log::warn!("unsuspended trap type: {:?}", trap_type);
         }
@@ -123,20 +131,10 @@ fn main(hart_id: usize) {
     println!(r"    \___\_\ \__,_| \__,_| \__,_| \____/ |_____/ ");
     println!();
 
-    // Probe the device from the device tree.
+    // Probe the interrupt controller and devices from the device tree.
+    drivers::init(hart_id);
+
     if let Some(fdt) = get_fdt() {
-        for node in fdt.all_nodes() {
-            node.compatible().inspect(|x| {
-                x.all().find(|n| *n == "virtio,mmio").inspect(|_| {
-                    let addr = node.reg().unwrap().next().unwrap().starting_address;
-                    let dri = drivers_virtio::probe::<Mutex<()>, PageAllocator>(
-                        addr as usize | VIRT_ADDR_START,
-                        Vec::new(),
-                    );
-                    dri.inspect(|dri| log::debug!("{:?}", dri));
-                });
-            });
-        }
         fdt.chosen()
             .bootargs()
             .inspect(|x| log::info!("BootArgs: {}", x));
@@ -158,6 +156,8 @@ fn main(hart_id: usize) {
     }
 
     pci::init();
+    drivers::probe_partitions();
+    net::init(get_fdt().and_then(|fdt| fdt.chosen().bootargs()));
 
     /* Test File System begin */
     FILE_TREE.init_by(FileTree::new());
@@ -244,6 +244,10 @@ fn main(hart_id: usize) {
     }
     /* Test File System end */
 
+    FILE_TREE.root().mkdir("dev").expect("can't create /dev");
+    FILE_TREE.mount("/dev", dev::init()).expect("can't mount /dev");
+    net::pcap::init(get_fdt().and_then(|fdt| fdt.chosen().bootargs()));
+
     // Test map elf
     #[cfg(target_arch = "riscv64")]
     let file_data = include_bytes_align_as!(u128, "../../resources/testcase-riscv64/bin/busybox");
@@ -257,4 +261,5 @@ fn main(hart_id: usize) {
         &["/busybox", "echo", "123"],
     ));
     task.into_user();
+    drivers::sync_blocks();
 }
@@ -0,0 +1,212 @@
+//! Interface ioctls of the sockets, used by `ifconfig` and `route`.
+//!
+//! Only the default route can be changed, the routes of the subnets follow
+//! the addresses of the interfaces.
+
+use alloc::vec::Vec;
+use fs_base::{Errno, FsResult};
+use net::{ethernet, Interface, Ipv4Addr, Ipv4Cidr};
+
+use super::{socket::AF_INET, stack};
+
+const SIOCADDRT: usize = 0x890b;
+const SIOCDELRT: usize = 0x890c;
+const SIOCGIFCONF: usize = 0x8912;
+const SIOCGIFFLAGS: usize = 0x8913;
+const SIOCSIFFLAGS: usize = 0x8914;
+const SIOCGIFADDR: usize = 0x8915;
+const SIOCSIFADDR: usize = 0x8916;
+const SIOCGIFBRDADDR: usize = 0x8919;
+const SIOCGIFNETMASK: usize = 0x891b;
+const SIOCSIFNETMASK: usize = 0x891c;
+const SIOCGIFMTU: usize = 0x8921;
+const SIOCGIFHWADDR: usize = 0x8927;
+const SIOCGIFINDEX: usize = 0x8933;
+
+const IFF_UP: i16 = 0x1;
+const IFF_BROADCAST: i16 = 0x2;
+const IFF_LOOPBACK: i16 = 0x8;
+const IFF_RUNNING: i16 = 0x40;
+
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_LOOPBACK: u16 = 772;
+
+const RTF_GATEWAY: u16 = 0x2;
+
+const IFNAMSIZ: usize = 16;
+/// Size of `struct ifreq`, the name and the union of the values.
+const IFREQ_LEN: usize = 40;
+/// Size of `struct rtentry`.
+const RTENTRY_LEN: usize = 120;
+
+/// Handle the interface ioctl, returns ENOTTY for the other commands.
+pub fn ioctl(command: usize, arg: usize) -> FsResult<usize> {
+    match command {
+        SIOCGIFCONF => ifconf(arg),
+        SIOCADDRT | SIOCDELRT => {
+            let rtentry = unsafe { core::slice::from_raw_parts(arg as *const u8, RTENTRY_LEN) };
+            route(command == SIOCADDRT, rtentry)
+        }
+        SIOCGIFFLAGS | SIOCSIFFLAGS | SIOCGIFADDR | SIOCSIFADDR | SIOCGIFBRDADDR
+        | SIOCGIFNETMASK | SIOCSIFNETMASK | SIOCGIFMTU | SIOCGIFHWADDR | SIOCGIFINDEX => {
+            let ifreq = unsafe { core::slice::from_raw_parts_mut(arg as *mut u8, IFREQ_LEN) };
+            let (name, value) = ifreq.split_at_mut(IFNAMSIZ);
+            let name = name.split(|x| *x == 0).next().unwrap_or(&[]);
+            let name = core::str::from_utf8(name).map_err(|_| Errno::ENODEV)?;
+            stack().with_interfaces(|ifaces| {
+                let index = ifaces
+                    .iter()
+                    .position(|x| x.name == name)
+                    .ok_or(Errno::ENODEV)?;
+                ifreq_ioctl(command, index, &mut ifaces[index], value)
+            })
+        }
+        _ => Err(Errno::ENOTTY),
+    }
+}
+
+/// Read the address of the `struct sockaddr_in`.
+fn read_addr(sockaddr: &[u8]) -> FsResult<Ipv4Addr> {
+    match u16::from_ne_bytes([sockaddr[0], sockaddr[1]]) as usize {
+        AF_INET => Ok(Ipv4Addr::from_bytes(&sockaddr[4..8])),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+fn write_addr(sockaddr: &mut [u8], addr: Ipv4Addr) {
+    sockaddr[..16].fill(0);
+    sockaddr[..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+    sockaddr[4..8].copy_from_slice(&addr.0);
+}
+
+/// Get the `ARPHRD_*` type of the interface.
+pub fn hardware_type(iface: &Interface) -> u16 {
+    match iface.is_loopback() {
+        true => ARPHRD_LOOPBACK,
+        false => ARPHRD_ETHER,
+    }
+}
+
+fn ifreq_ioctl(
+    command: usize,
+    index: usize,
+    iface: &mut Interface,
+    value: &mut [u8],
+) -> FsResult<usize> {
+    match command {
+        SIOCGIFFLAGS => {
+            let mut flags = IFF_UP | IFF_RUNNING;
+            flags |= match iface.is_loopback() {
+                true => IFF_LOOPBACK,
+                false => IFF_BROADCAST,
+            };
+            value[..2].copy_from_slice(&flags.to_ne_bytes());
+        }
+        // The interfaces are always up.
+        SIOCSIFFLAGS => {}
+        SIOCGIFADDR => write_addr(value, iface.addr().ok_or(Errno::EADDRNOTAVAIL)?),
+        SIOCSIFADDR => {
+            let addr = read_addr(value)?;
+            let ipv4 = match iface.ipv4 {
+                _ if addr.is_unspecified() => None,
+                Some(cidr) => Some(Ipv4Cidr::new(addr, cidr.prefix_len)),
+                None => Some(Ipv4Cidr::classful(addr)),
+            };
+            log::info!("{}: set address {:?}", iface.name, ipv4);
+            iface.set_ipv4(ipv4);
+        }
+        SIOCGIFBRDADDR => {
+            let cidr = iface.ipv4.ok_or(Errno::EADDRNOTAVAIL)?;
+            write_addr(value, cidr.broadcast());
+        }
+        SIOCGIFNETMASK => {
+            let cidr = iface.ipv4.ok_or(Errno::EADDRNOTAVAIL)?;
+            write_addr(value, cidr.netmask());
+        }
+        SIOCSIFNETMASK => {
+            let addr = iface.addr().ok_or(Errno::EADDRNOTAVAIL)?;
+            let cidr = Ipv4Cidr::from_netmask(addr, read_addr(value)?).ok_or(Errno::EINVAL)?;
+            iface.set_ipv4(Some(cidr));
+        }
+        SIOCGIFMTU => value[..4].copy_from_slice(&(ethernet::MTU as i32).to_ne_bytes()),
+        SIOCGIFHWADDR => {
+            value[..16].fill(0);
+            value[..2].copy_from_slice(&hardware_type(iface).to_ne_bytes());
+            value[2..8].copy_from_slice(&iface.mac.0);
+        }
+        SIOCGIFINDEX => value[..4].copy_from_slice(&(index as i32 + 1).to_ne_bytes()),
+        _ => return Err(Errno::ENOTTY),
+    }
+    Ok(0)
+}
+
+/// Fill the `struct ifconf` with the interfaces which have addresses.
+fn ifconf(arg: usize) -> FsResult<usize> {
+    let len = unsafe { (arg as *const i32).read_unaligned() };
+    let buf = unsafe { ((arg + 8) as *const usize).read_unaligned() };
+    let ifaces = stack().with_interfaces(|ifaces| {
+        ifaces
+            .iter()
+            .filter_map(|x| Some((x.name.clone(), x.addr()?)))
+            .collect::<Vec<_>>()
+    });
+    // A null buffer asks the length of all the entries.
+    let count = match buf {
+        0 => ifaces.len(),
+        _ => ifaces.len().min(len.max(0) as usize / IFREQ_LEN),
+    };
+    if buf != 0 {
+        let ifreqs = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, count * IFREQ_LEN) };
+        for ((name, addr), ifreq) in ifaces.iter().zip(ifreqs.chunks_exact_mut(IFREQ_LEN)) {
+            ifreq.fill(0);
+            let name_len = name.len().min(IFNAMSIZ - 1);
+            ifreq[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+            write_addr(&mut ifreq[IFNAMSIZ..], *addr);
+        }
+    }
+    unsafe { (arg as *mut i32).write_unaligned((count * IFREQ_LEN) as i32) };
+    Ok(0)
+}
+
+/// Add or delete the default route of the `struct rtentry`.
+fn route(add: bool, rtentry: &[u8]) -> FsResult<usize> {
+    // The families of the unused addresses aren't set, they are not checked.
+    let dst = Ipv4Addr::from_bytes(&rtentry[12..16]);
+    let gateway = Ipv4Addr::from_bytes(&rtentry[28..32]);
+    let genmask = Ipv4Addr::from_bytes(&rtentry[44..48]);
+    let flags = u16::from_ne_bytes([rtentry[56], rtentry[57]]);
+    if !dst.is_unspecified() || !genmask.is_unspecified() {
+        return Err(Errno::EOPNOTSUPP);
+    }
+    stack().with_interfaces(|ifaces| {
+        if !add {
+            let mut found = false;
+            for iface in ifaces.iter_mut() {
+                if iface.gateway.is_some()
+                    && (gateway.is_unspecified() || iface.gateway == Some(gateway))
+                {
+                    iface.gateway = None;
+                    found = true;
+                }
+            }
+            return match found {
+                true => Ok(0),
+                false => Err(Errno::ESRCH),
+            };
+        }
+        if flags & RTF_GATEWAY == 0 {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        let index = ifaces
+            .iter()
+            .position(|x| x.ipv4.is_some_and(|x| x.contains(gateway)))
+            .ok_or(Errno::ENETUNREACH)?;
+        // There is one default route.
+        for iface in ifaces.iter_mut() {
+            iface.gateway = None;
+        }
+        log::info!("{}: set gateway {}", ifaces[index].name, gateway);
+        ifaces[index].gateway = Some(gateway);
+        Ok(0)
+    })
+}
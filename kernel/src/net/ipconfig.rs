@@ -0,0 +1,103 @@
+//! Interface configuration from the kernel command line.
+//!
+//! Accepts the `ip=` option of Linux, `ip=dhcp`, `ip=off` or
+//! `ip=<client>:<server>:<gateway>:<netmask>:<hostname>:<device>:<autoconf>:<dns0>:<dns1>`.
+//! The server and the hostname are ignored.
+
+use core::time::Duration;
+
+use alloc::{string::String, vec::Vec};
+use net::{Interface, Ipv4Addr, Ipv4Cidr};
+
+#[derive(Debug)]
+pub enum Mode {
+    /// Leave the interface unconfigured.
+    Off,
+    Dhcp,
+    Static(Ipv4Cidr),
+}
+
+#[derive(Debug)]
+pub struct IpConfig {
+    /// The interface to configure, the first ethernet interface if it's None.
+    pub device: Option<String>,
+    pub mode: Mode,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+}
+
+impl IpConfig {
+    /// Parse the `ip=` option of the command line, returns None without it.
+    pub fn parse(cmdline: &str) -> Option<Self> {
+        let value = cmdline
+            .split_whitespace()
+            .rev()
+            .find_map(|x| x.strip_prefix("ip="))?;
+        let mut config = Self {
+            device: None,
+            mode: Mode::Off,
+            gateway: None,
+            dns: Vec::new(),
+        };
+        let fields: Vec<&str> = value.split(':').collect();
+        if fields.len() == 1 {
+            config.mode = Self::autoconf(value).unwrap_or_else(|| {
+                log::warn!("ipconfig: unknown option ip={}", value);
+                Mode::Off
+            });
+            return Some(config);
+        }
+        let field = |index: usize| fields.get(index).copied().filter(|x| !x.is_empty());
+        let addr = |index: usize| field(index).and_then(Ipv4Addr::parse);
+        config.device = field(5).map(String::from);
+        config.gateway = addr(2);
+        config.dns = [addr(7), addr(8)].into_iter().flatten().collect();
+        let autoconf = field(6).map_or(Some(Mode::Off), Self::autoconf);
+        config.mode = match (addr(0), autoconf) {
+            (Some(client), Some(Mode::Off)) => {
+                let netmask = addr(3);
+                Mode::Static(
+                    netmask
+                        .and_then(|x| Ipv4Cidr::from_netmask(client, x))
+                        .unwrap_or_else(|| Ipv4Cidr::classful(client)),
+                )
+            }
+            // Linux configures the interface automatically without the address.
+            (None, Some(Mode::Off)) if field(6).is_none() => Mode::Dhcp,
+            (_, Some(mode)) => mode,
+            (_, None) => {
+                log::warn!("ipconfig: unknown option ip={}", value);
+                Mode::Off
+            }
+        };
+        Some(config)
+    }
+
+    fn autoconf(value: &str) -> Option<Mode> {
+        match value {
+            "off" | "none" => Some(Mode::Off),
+            "on" | "any" | "dhcp" => Some(Mode::Dhcp),
+            _ => None,
+        }
+    }
+
+    /// Check whether the interface is the one to configure.
+    pub fn matches(&self, iface: &Interface, first: bool) -> bool {
+        match &self.device {
+            Some(device) => *device == iface.name,
+            None => first,
+        }
+    }
+
+    pub fn apply(&self, iface: &mut Interface, now: Duration) {
+        match self.mode {
+            Mode::Off => iface.set_ipv4(None),
+            Mode::Dhcp => iface.start_dhcp(now),
+            Mode::Static(ipv4) => {
+                iface.set_ipv4(Some(ipv4));
+                iface.gateway = self.gateway;
+                iface.dns = self.dns.clone();
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Network stack of the kernel.
+//!
+//! Every probed network device becomes an interface of the stack. The stack
+//! is polled after the external interrupts and on the timer.
+//!
+//! The first ethernet interface has the static address of the QEMU user
+//! network unless the `ip=` option of the command line configures it.
+//! The ethernet interfaces get their IPv6 addresses by SLAAC.
+
+pub mod ioctl;
+pub mod ipconfig;
+pub mod pcap;
+pub mod socket;
+pub mod unix;
+
+use alloc::{format, sync::Arc};
+use drivers_base::{DSched, DeviceType};
+use net::{Interface, Ipv4Addr, Ipv4Cidr, Ipv6Addr, Ipv6Cidr, NetStack};
+use spin::{Mutex, Once};
+
+use crate::drivers::{self, DriverSched};
+
+use self::ipconfig::IpConfig;
+
+pub type KernelNetStack = NetStack<Mutex<()>, DriverSched>;
+
+static NET: Once<Arc<KernelNetStack>> = Once::new();
+
+/// Address of the first ethernet interface, the QEMU user network.
+const DEFAULT_ADDR: Ipv4Cidr = Ipv4Cidr::new(Ipv4Addr::new(10, 0, 2, 15), 24);
+const DEFAULT_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+const LOOPBACK_ADDR: Ipv4Cidr = Ipv4Cidr::new(Ipv4Addr::new(127, 0, 0, 1), 8);
+const LOOPBACK_ADDR6: Ipv6Cidr = Ipv6Cidr::new(Ipv6Addr::LOOPBACK, 128);
+
+/// Create the network stack with the probed network devices.
+///
+/// The interfaces are configured by the `ip=` option of the command line.
+pub fn init(cmdline: Option<&str>) {
+    let config = cmdline.and_then(IpConfig::parse);
+    let stack = KernelNetStack::new();
+    let mut count = 0;
+    for driver in drivers::get_drivers() {
+        let device = match driver.get_device() {
+            DeviceType::NET(device) => device,
+            _ => continue,
+        };
+        if device.is_loopback() {
+            let mut iface = Interface::new("lo", device);
+            iface.ipv4 = Some(LOOPBACK_ADDR);
+            iface.ipv6.push(LOOPBACK_ADDR6);
+            stack.add_interface(iface);
+            continue;
+        }
+        let mut iface = Interface::new(&format!("eth{}", count), device);
+        match &config {
+            Some(config) if config.matches(&iface, count == 0) => {
+                config.apply(&mut iface, DriverSched::now())
+            }
+            Some(_) => {}
+            None if count == 0 => {
+                iface.ipv4 = Some(DEFAULT_ADDR);
+                iface.gateway = Some(DEFAULT_GATEWAY);
+            }
+            None => {}
+        }
+        stack.add_interface(iface);
+        count += 1;
+    }
+    // There is no entropy source yet, the clock and the MAC addresses seed the key.
+    let macs = stack.with_interfaces(|ifaces| {
+        ifaces.iter().fold(0, |acc: u64, x| {
+            let mut mac = [0; 8];
+            mac[..6].copy_from_slice(&x.mac.0);
+            acc.rotate_left(16) ^ u64::from_le_bytes(mac)
+        })
+    });
+    let now = DriverSched::now().as_nanos() as u64;
+    stack.set_isn_key([now, macs ^ now.rotate_left(32)]);
+    NET.call_once(|| stack);
+}
+
+/// Get the network stack.
+pub fn stack() -> &'static Arc<KernelNetStack> {
+    NET.get().expect("the network stack isn't initialized")
+}
+
+/// Handle the received packets and the timers of the network stack.
+pub fn poll() {
+    if let Some(stack) = NET.get() {
+        stack.poll();
+    }
+}
@@ -0,0 +1,62 @@
+//! Capture of all the frames into a pcap file.
+//!
+//! The `pcap=<path>` option of the command line creates the file in the
+//! [FileTree](fs_base::FileTree) and appends every received and sent frame
+//! of the interfaces to it.
+
+use core::time::Duration;
+
+use alloc::sync::Arc;
+use fs_base::{INodeInterface, OpenFlags};
+use net::{pcap, FrameTap};
+use spin::Mutex;
+
+struct PcapFile {
+    file: Arc<dyn INodeInterface>,
+    /// The end of the file, where the next record is written.
+    offset: Mutex<usize>,
+}
+
+impl FrameTap for PcapFile {
+    fn tap(&self, _iface: usize, frame: &[u8], now: Duration) {
+        let mut offset = self.offset.lock();
+        match self.file.writeat(*offset, &pcap::record(now, frame)) {
+            Ok(len) => *offset += len,
+            Err(err) => log::warn!("pcap: can't write the frame: {:?}", err),
+        }
+    }
+}
+
+/// Start the capture if the command line has the `pcap=` option.
+///
+/// Called once the file systems are mounted.
+pub fn init(cmdline: Option<&str>) {
+    let path = match cmdline.and_then(|x| {
+        x.split_whitespace()
+            .rev()
+            .find_map(|x| x.strip_prefix("pcap="))
+    }) {
+        Some(path) => path,
+        None => return,
+    };
+    let file = match crate::FILE_TREE
+        .root()
+        .open(path, OpenFlags::CREAT | OpenFlags::RDWR)
+    {
+        Ok(file) => file.inode(),
+        Err(err) => {
+            log::warn!("pcap: can't open {}: {:?}", path, err);
+            return;
+        }
+    };
+    let header = pcap::header();
+    if let Err(err) = file.truncate(0).and_then(|_| file.writeat(0, &header)) {
+        log::warn!("pcap: can't write {}: {:?}", path, err);
+        return;
+    }
+    log::info!("pcap: capture the frames into {}", path);
+    super::stack().set_tap(Some(Arc::new(PcapFile {
+        file,
+        offset: Mutex::new(header.len()),
+    })));
+}
@@ -0,0 +1,681 @@
+//! Socket files.
+//!
+//! A socket is an [INodeInterface] in the fd table, reading and writing
+//! the file receives and sends the data. The socket syscalls use the
+//! [Socket] of the file directly.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::time::Duration;
+use fs_base::{
+    Errno, FileType, FsResult, INodeInterface, Metadata, PollEvent, Stat, StatMode, Waiter,
+};
+use net::{
+    IpAddr, Ipv6Addr, LinkAddr, PacketSocket, SockError, SocketAddr, TcpSocket, TcpState, UdpSocket,
+};
+use spin::Mutex;
+
+use super::unix::{Ucred, UnixAddr, UnixSocket};
+use crate::{drivers::DriverSched, task::fd::FileItem};
+
+pub const AF_UNIX: usize = 1;
+pub const AF_INET: usize = 2;
+pub const AF_INET6: usize = 10;
+pub const AF_PACKET: usize = 17;
+
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+pub const SOCK_RAW: usize = 3;
+pub const SOCK_SEQPACKET: usize = 5;
+
+pub const SOL_SOCKET: usize = 1;
+pub const IPPROTO_TCP: usize = 6;
+pub const IPPROTO_UDP: usize = 17;
+pub const IPPROTO_IPV6: usize = 41;
+pub const SOL_PACKET: usize = 263;
+
+const SO_REUSEADDR: usize = 2;
+const SO_TYPE: usize = 3;
+const SO_ERROR: usize = 4;
+const SO_BROADCAST: usize = 6;
+const SO_SNDBUF: usize = 7;
+const SO_RCVBUF: usize = 8;
+const SO_KEEPALIVE: usize = 9;
+const SO_LINGER: usize = 13;
+const SO_REUSEPORT: usize = 15;
+const SO_PASSCRED: usize = 16;
+const SO_PEERCRED: usize = 17;
+const SO_RCVTIMEO: usize = 20;
+const SO_SNDTIMEO: usize = 21;
+const SO_ACCEPTCONN: usize = 30;
+const SO_PROTOCOL: usize = 38;
+const SO_DOMAIN: usize = 39;
+const TCP_NODELAY: usize = 1;
+const IPV6_V6ONLY: usize = 26;
+const PACKET_ADD_MEMBERSHIP: usize = 1;
+const PACKET_DROP_MEMBERSHIP: usize = 2;
+
+/// The buffer size reported by SO_SNDBUF and SO_RCVBUF.
+const BUFFER_SIZE: i32 = 0xffff;
+
+const FIONREAD: usize = 0x541b;
+const FIONBIO: usize = 0x5421;
+
+/// How to shut down the socket.
+pub const SHUT_RD: usize = 0;
+pub const SHUT_WR: usize = 1;
+pub const SHUT_RDWR: usize = 2;
+
+type KernelTcpSocket = TcpSocket<Mutex<()>, DriverSched>;
+type KernelUdpSocket = UdpSocket<Mutex<()>, DriverSched>;
+type KernelPacketSocket = PacketSocket<Mutex<()>, DriverSched>;
+
+/// Convert the error of the network stack to the errno.
+pub fn errno(err: SockError) -> Errno {
+    match err {
+        SockError::WouldBlock => Errno::EAGAIN,
+        SockError::InProgress => Errno::EINPROGRESS,
+        SockError::AddrInUse => Errno::EADDRINUSE,
+        SockError::AddrNotAvail => Errno::EADDRNOTAVAIL,
+        SockError::ConnRefused => Errno::ECONNREFUSED,
+        SockError::ConnReset => Errno::ECONNRESET,
+        SockError::NotConnected => Errno::ENOTCONN,
+        SockError::IsConnected => Errno::EISCONN,
+        SockError::Shutdown => Errno::EPIPE,
+        SockError::InvalidState | SockError::InvalidParam => Errno::EINVAL,
+        SockError::Unreachable => Errno::ENETUNREACH,
+        SockError::TimedOut => Errno::ETIMEDOUT,
+        SockError::NoBuffer => Errno::EMSGSIZE,
+    }
+}
+
+/// The address of a socket.
+#[derive(Debug, Clone)]
+pub enum SockAddr {
+    Inet(SocketAddr),
+    Unix(UnixAddr),
+    Link(LinkAddr),
+}
+
+/// A received message.
+#[derive(Default)]
+pub struct RecvMsg {
+    pub len: usize,
+    /// The length of the whole datagram.
+    pub full_len: usize,
+    pub src: Option<SockAddr>,
+    /// The files passed by SCM_RIGHTS.
+    pub rights: Vec<Arc<FileItem>>,
+}
+
+/// Get the AF_INET or AF_INET6 address, the IPv4-mapped addresses are
+/// converted to IPv4.
+fn inet(addr: &SockAddr) -> FsResult<SocketAddr> {
+    let SockAddr::Inet(addr) = addr else {
+        return Err(Errno::EINVAL);
+    };
+    match addr.addr {
+        IpAddr::V6(v6) => Ok(v6
+            .to_ipv4_mapped()
+            .map_or(*addr, |v4| SocketAddr::new(v4, addr.port))),
+        IpAddr::V4(_) => Ok(*addr),
+    }
+}
+
+fn unix(addr: &SockAddr) -> FsResult<&UnixAddr> {
+    match addr {
+        SockAddr::Unix(addr) => Ok(addr),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+fn link(addr: &SockAddr) -> FsResult<LinkAddr> {
+    match addr {
+        SockAddr::Link(addr) => Ok(*addr),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// Read the `struct timeval` of SO_RCVTIMEO or SO_SNDTIMEO, zero waits forever.
+fn read_timeval(value: &[u8]) -> FsResult<Option<Duration>> {
+    if value.len() < 16 {
+        return Err(Errno::EINVAL);
+    }
+    let sec = i64::from_ne_bytes(value[..8].try_into().unwrap());
+    let usec = i64::from_ne_bytes(value[8..16].try_into().unwrap());
+    if !(0..1_000_000).contains(&usec) {
+        return Err(Errno::EDOM);
+    }
+    // A negative timeout is taken as the shortest one.
+    let timeout = match sec < 0 {
+        true => Duration::from_micros(1),
+        false => Duration::from_secs(sec as u64) + Duration::from_micros(usec as u64),
+    };
+    Ok((!timeout.is_zero()).then_some(timeout))
+}
+
+/// Check the type and the protocol of an AF_UNIX socket.
+fn check_unix_type(sock_type: usize, protocol: usize) -> FsResult<()> {
+    match (sock_type, protocol) {
+        (SOCK_STREAM | SOCK_DGRAM | SOCK_SEQPACKET, 0) => Ok(()),
+        (SOCK_STREAM | SOCK_DGRAM | SOCK_SEQPACKET, _) => Err(Errno::EPROTONOSUPPORT),
+        _ => Err(Errno::ESOCKTNOSUPPORT),
+    }
+}
+
+enum SocketKind {
+    Tcp(KernelTcpSocket),
+    Udp(KernelUdpSocket),
+    Unix(Arc<UnixSocket>),
+    Packet(KernelPacketSocket),
+}
+
+pub struct Socket {
+    kind: SocketKind,
+    /// The address family, AF_INET6 sockets are dual-stack.
+    domain: usize,
+    /// Options set by setsockopt, SO_REUSEADDR and the timeouts are enforced.
+    options: Mutex<BTreeMap<(usize, usize), Vec<u8>>>,
+    /// The sending side is shut down.
+    write_shutdown: Mutex<bool>,
+}
+
+impl Socket {
+    /// Create an AF_INET or AF_INET6 socket of the type.
+    pub fn new(domain: usize, sock_type: usize, protocol: usize) -> FsResult<Self> {
+        let stack = super::stack();
+        let ipv6 = domain == AF_INET6;
+        let kind = match (sock_type, protocol) {
+            (SOCK_STREAM, 0 | IPPROTO_TCP) => SocketKind::Tcp(TcpSocket::new(stack, ipv6)),
+            (SOCK_DGRAM, 0 | IPPROTO_UDP) => SocketKind::Udp(UdpSocket::new(stack, ipv6)),
+            (SOCK_STREAM | SOCK_DGRAM, _) => return Err(Errno::EPROTONOSUPPORT),
+            _ => return Err(Errno::ESOCKTNOSUPPORT),
+        };
+        Ok(Self::from_kind(kind, domain))
+    }
+
+    /// Create an AF_PACKET socket of the type, the protocol is in the network byte order.
+    pub fn new_packet(sock_type: usize, protocol: usize) -> FsResult<Self> {
+        let protocol = u16::from_be(protocol as u16);
+        let socket = match sock_type {
+            SOCK_RAW | SOCK_DGRAM => {
+                PacketSocket::new(super::stack(), sock_type == SOCK_RAW, protocol)
+            }
+            _ => return Err(Errno::ESOCKTNOSUPPORT),
+        };
+        Ok(Self::from_kind(SocketKind::Packet(socket), AF_PACKET))
+    }
+
+    /// Create an AF_UNIX socket of the type, `cred` is the creating process.
+    pub fn new_unix(sock_type: usize, protocol: usize, cred: Ucred) -> FsResult<Self> {
+        check_unix_type(sock_type, protocol)?;
+        Ok(Self::from_kind(
+            SocketKind::Unix(UnixSocket::new(sock_type, cred)),
+            AF_UNIX,
+        ))
+    }
+
+    /// Create a pair of connected AF_UNIX sockets.
+    pub fn pair(sock_type: usize, protocol: usize, cred: Ucred) -> FsResult<(Self, Self)> {
+        check_unix_type(sock_type, protocol)?;
+        let (a, b) = UnixSocket::pair(sock_type, cred);
+        Ok((
+            Self::from_kind(SocketKind::Unix(a), AF_UNIX),
+            Self::from_kind(SocketKind::Unix(b), AF_UNIX),
+        ))
+    }
+
+    fn from_kind(kind: SocketKind, domain: usize) -> Self {
+        Self {
+            kind,
+            domain,
+            options: Mutex::new(BTreeMap::new()),
+            write_shutdown: Mutex::new(false),
+        }
+    }
+
+    /// Get the AF_UNIX socket.
+    pub fn unix(&self) -> Option<&Arc<UnixSocket>> {
+        match &self.kind {
+            SocketKind::Unix(socket) => Some(socket),
+            _ => None,
+        }
+    }
+
+    /// Get the address family of the socket.
+    pub fn domain(&self) -> usize {
+        self.domain
+    }
+
+    /// Convert the address of the stack to the socket's family, AF_INET6
+    /// sockets see the IPv4 addresses as IPv4-mapped addresses.
+    fn sockaddr(&self, addr: SocketAddr) -> SockAddr {
+        match addr.addr {
+            IpAddr::V4(v4) if self.domain == AF_INET6 => {
+                let v6 = match v4.is_unspecified() {
+                    true => Ipv6Addr::UNSPECIFIED,
+                    false => Ipv6Addr::from_ipv4_mapped(v4),
+                };
+                SockAddr::Inet(SocketAddr::new(v6, addr.port))
+            }
+            _ => SockAddr::Inet(addr),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        match &self.kind {
+            SocketKind::Tcp(socket) => socket.set_nonblocking(nonblocking),
+            SocketKind::Udp(socket) => socket.set_nonblocking(nonblocking),
+            SocketKind::Unix(socket) => socket.set_nonblocking(nonblocking),
+            SocketKind::Packet(socket) => socket.set_nonblocking(nonblocking),
+        }
+    }
+
+    pub fn bind(&self, addr: &SockAddr) -> FsResult<()> {
+        match &self.kind {
+            SocketKind::Tcp(socket) => {
+                let reuse_addr = self.int_option(SOL_SOCKET, SO_REUSEADDR) != 0;
+                socket.bind(inet(addr)?, reuse_addr).map_err(errno)
+            }
+            SocketKind::Udp(socket) => socket.bind(inet(addr)?).map_err(errno),
+            SocketKind::Unix(socket) => socket.bind(unix(addr)?),
+            SocketKind::Packet(socket) => {
+                let addr = link(addr)?;
+                socket.bind(addr.protocol, addr.iface).map_err(errno)
+            }
+        }
+    }
+
+    pub fn listen(&self, backlog: usize) -> FsResult<()> {
+        match &self.kind {
+            SocketKind::Tcp(socket) => socket.listen(backlog).map_err(errno),
+            SocketKind::Udp(_) | SocketKind::Packet(_) => Err(Errno::EOPNOTSUPP),
+            SocketKind::Unix(socket) => socket.listen(backlog),
+        }
+    }
+
+    /// Take a connection of the listening socket, returns it and its peer.
+    pub fn accept(&self) -> FsResult<(Self, SockAddr)> {
+        match &self.kind {
+            SocketKind::Tcp(socket) => {
+                let socket = socket.accept().map_err(errno)?;
+                let peer = self.sockaddr(socket.peer_addr().unwrap_or_default());
+                Ok((Self::from_kind(SocketKind::Tcp(socket), self.domain), peer))
+            }
+            SocketKind::Udp(_) | SocketKind::Packet(_) => Err(Errno::EOPNOTSUPP),
+            SocketKind::Unix(socket) => {
+                let (socket, peer) = socket.accept()?;
+                Ok((
+                    Self::from_kind(SocketKind::Unix(socket), AF_UNIX),
+                    SockAddr::Unix(peer),
+                ))
+            }
+        }
+    }
+
+    pub fn connect(&self, addr: &SockAddr) -> FsResult<()> {
+        match &self.kind {
+            SocketKind::Tcp(socket) => socket.connect(inet(addr)?).map_err(errno),
+            SocketKind::Udp(socket) => socket.connect(inet(addr)?).map_err(errno),
+            SocketKind::Unix(socket) => socket.connect(unix(addr)?),
+            SocketKind::Packet(_) => Err(Errno::EOPNOTSUPP),
+        }
+    }
+
+    /// Send the data, `addr` is the destination of the datagram.
+    ///
+    /// `dontwait` makes the call fail with EAGAIN instead of blocking.
+    pub fn send_to(&self, buf: &[u8], addr: Option<&SockAddr>, dontwait: bool) -> FsResult<usize> {
+        self.send_msg(buf, addr, Vec::new(), dontwait)
+    }
+
+    /// Send the data with the files, only AF_UNIX sockets pass the files.
+    pub fn send_msg(
+        &self,
+        buf: &[u8],
+        addr: Option<&SockAddr>,
+        rights: Vec<Arc<FileItem>>,
+        dontwait: bool,
+    ) -> FsResult<usize> {
+        if *self.write_shutdown.lock() {
+            return Err(Errno::EPIPE);
+        }
+        if !rights.is_empty() && !matches!(self.kind, SocketKind::Unix(_)) {
+            return Err(Errno::EINVAL);
+        }
+        match &self.kind {
+            SocketKind::Tcp(socket) => {
+                if dontwait && !socket.writable() {
+                    return Err(Errno::EAGAIN);
+                }
+                socket.send(buf).map_err(errno)
+            }
+            SocketKind::Udp(socket) => {
+                let addr = addr.map(inet).transpose()?;
+                socket.send_to(buf, addr).map_err(errno)
+            }
+            SocketKind::Unix(socket) => {
+                let addr = addr.map(unix).transpose()?;
+                socket.send(buf, addr, rights, dontwait || socket.is_nonblocking())
+            }
+            SocketKind::Packet(socket) => {
+                let addr = addr.map(link).transpose()?;
+                socket.send_to(buf, addr).map_err(errno)
+            }
+        }
+    }
+
+    /// Receive the data, returns the received length, the length of the
+    /// whole datagram and the source.
+    pub fn recv_from(
+        &self,
+        buf: &mut [u8],
+        dontwait: bool,
+    ) -> FsResult<(usize, usize, Option<SockAddr>)> {
+        let msg = self.recv_msg(buf, dontwait)?;
+        Ok((msg.len, msg.full_len, msg.src))
+    }
+
+    /// Receive the data with the passed files.
+    pub fn recv_msg(&self, buf: &mut [u8], dontwait: bool) -> FsResult<RecvMsg> {
+        let readable = match &self.kind {
+            SocketKind::Tcp(socket) => socket.readable(),
+            SocketKind::Udp(socket) => socket.readable(),
+            SocketKind::Packet(socket) => socket.readable(),
+            SocketKind::Unix(socket) => {
+                return socket.recv(buf, dontwait || socket.is_nonblocking())
+            }
+        };
+        if dontwait && !readable {
+            super::poll();
+            return Err(Errno::EAGAIN);
+        }
+        match &self.kind {
+            SocketKind::Tcp(socket) => {
+                let len = socket.recv(buf).map_err(errno)?;
+                Ok(RecvMsg {
+                    len,
+                    full_len: len,
+                    src: socket.peer_addr().map(|x| self.sockaddr(x)),
+                    rights: Vec::new(),
+                })
+            }
+            SocketKind::Udp(socket) => {
+                let (len, src) = socket.recv_from(buf).map_err(errno)?;
+                Ok(RecvMsg {
+                    len: len.min(buf.len()),
+                    full_len: len,
+                    src: Some(self.sockaddr(src)),
+                    rights: Vec::new(),
+                })
+            }
+            SocketKind::Packet(socket) => {
+                let (len, src) = socket.recv_from(buf).map_err(errno)?;
+                Ok(RecvMsg {
+                    len: len.min(buf.len()),
+                    full_len: len,
+                    src: Some(SockAddr::Link(src)),
+                    rights: Vec::new(),
+                })
+            }
+            SocketKind::Unix(_) => unreachable!(),
+        }
+    }
+
+    pub fn shutdown(&self, how: usize) -> FsResult<()> {
+        if how > SHUT_RDWR {
+            return Err(Errno::EINVAL);
+        }
+        if let SocketKind::Unix(socket) = &self.kind {
+            socket.shutdown(how != SHUT_WR, how != SHUT_RD)?;
+            *self.write_shutdown.lock() |= how != SHUT_RD;
+            return Ok(());
+        }
+        if matches!(self.kind, SocketKind::Packet(_)) {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        if how == SHUT_RD {
+            return Ok(());
+        }
+        *self.write_shutdown.lock() = true;
+        match &self.kind {
+            SocketKind::Tcp(socket) => socket.shutdown().map_err(errno),
+            SocketKind::Udp(socket) => match socket.peer_addr() {
+                Some(_) => Ok(()),
+                None => Err(Errno::ENOTCONN),
+            },
+            SocketKind::Unix(_) | SocketKind::Packet(_) => unreachable!(),
+        }
+    }
+
+    pub fn local_addr(&self) -> SockAddr {
+        match &self.kind {
+            SocketKind::Tcp(socket) => self.sockaddr(socket.local_addr().unwrap_or_default()),
+            SocketKind::Udp(socket) => self.sockaddr(socket.local_addr().unwrap_or_default()),
+            SocketKind::Unix(socket) => SockAddr::Unix(socket.local_addr()),
+            SocketKind::Packet(socket) => SockAddr::Link(socket.local_addr().unwrap_or_default()),
+        }
+    }
+
+    pub fn peer_addr(&self) -> FsResult<SockAddr> {
+        match &self.kind {
+            SocketKind::Tcp(socket) => socket.peer_addr().map(|x| self.sockaddr(x)),
+            SocketKind::Udp(socket) => socket.peer_addr().map(|x| self.sockaddr(x)),
+            SocketKind::Unix(socket) => return socket.peer_addr().map(SockAddr::Unix),
+            SocketKind::Packet(_) => return Err(Errno::EOPNOTSUPP),
+        }
+        .ok_or(Errno::ENOTCONN)
+    }
+
+    /// Get the recorded integer option, 0 if it isn't set.
+    fn int_option(&self, level: usize, name: usize) -> i32 {
+        self.options
+            .lock()
+            .get(&(level, name))
+            .and_then(|x| Some(i32::from_ne_bytes(x.get(..4)?.try_into().unwrap())))
+            .unwrap_or(0)
+    }
+
+    pub fn setsockopt(&self, level: usize, name: usize, value: &[u8]) -> FsResult<()> {
+        match (level, name) {
+            (SOL_SOCKET, SO_RCVTIMEO) => {
+                let timeout = read_timeval(value)?;
+                match &self.kind {
+                    SocketKind::Tcp(socket) => socket.set_recv_timeout(timeout),
+                    SocketKind::Udp(socket) => socket.set_recv_timeout(timeout),
+                    SocketKind::Unix(socket) => socket.set_recv_timeout(timeout),
+                    SocketKind::Packet(socket) => socket.set_recv_timeout(timeout),
+                }
+            }
+            // Sending UDP datagrams and frames never blocks.
+            (SOL_SOCKET, SO_SNDTIMEO) => {
+                let timeout = read_timeval(value)?;
+                match &self.kind {
+                    SocketKind::Tcp(socket) => socket.set_send_timeout(timeout),
+                    SocketKind::Unix(socket) => socket.set_send_timeout(timeout),
+                    SocketKind::Udp(_) | SocketKind::Packet(_) => {}
+                }
+            }
+            (SOL_SOCKET, SO_REUSEADDR) if value.len() < 4 => return Err(Errno::EINVAL),
+            (
+                SOL_SOCKET,
+                SO_REUSEADDR | SO_REUSEPORT | SO_BROADCAST | SO_KEEPALIVE | SO_SNDBUF | SO_RCVBUF
+                | SO_LINGER,
+            ) => {}
+            // There is no Nagle's algorithm, the segments are always sent immediately.
+            (IPPROTO_TCP, TCP_NODELAY) if matches!(self.kind, SocketKind::Tcp(_)) => {}
+            // The credentials messages aren't sent, the peer uses SO_PEERCRED.
+            (SOL_SOCKET, SO_PASSCRED) if matches!(self.kind, SocketKind::Unix(_)) => {}
+            // The AF_INET6 sockets always accept the IPv4 connections too.
+            (IPPROTO_IPV6, IPV6_V6ONLY) if self.domain == AF_INET6 => {}
+            // The packet sockets see the frames before they are filtered by the destination.
+            (SOL_PACKET, PACKET_ADD_MEMBERSHIP | PACKET_DROP_MEMBERSHIP)
+                if matches!(self.kind, SocketKind::Packet(_)) => {}
+            _ => return Err(Errno::ENOPROTOOPT),
+        }
+        self.options.lock().insert((level, name), value.to_vec());
+        Ok(())
+    }
+
+    pub fn getsockopt(&self, level: usize, name: usize) -> FsResult<Vec<u8>> {
+        let int = |value: i32| Ok(value.to_ne_bytes().to_vec());
+        match (level, name) {
+            (SOL_SOCKET, SO_TYPE) => match &self.kind {
+                SocketKind::Tcp(_) => int(SOCK_STREAM as _),
+                SocketKind::Udp(_) => int(SOCK_DGRAM as _),
+                SocketKind::Unix(socket) => int(socket.sock_type() as _),
+                SocketKind::Packet(socket) => match socket.is_raw() {
+                    true => int(SOCK_RAW as _),
+                    false => int(SOCK_DGRAM as _),
+                },
+            },
+            (SOL_SOCKET, SO_PROTOCOL) => match &self.kind {
+                SocketKind::Tcp(_) => int(IPPROTO_TCP as _),
+                SocketKind::Udp(_) => int(IPPROTO_UDP as _),
+                SocketKind::Unix(_) => int(0),
+                SocketKind::Packet(socket) => {
+                    int(socket.local_addr().map_or(0, |x| x.protocol.to_be()) as _)
+                }
+            },
+            (SOL_SOCKET, SO_DOMAIN) => int(self.domain() as _),
+            (SOL_SOCKET, SO_ERROR) => match &self.kind {
+                SocketKind::Tcp(socket) => {
+                    int(socket.take_error().map_or(0, |err| errno(err).into_raw()))
+                }
+                SocketKind::Udp(_) | SocketKind::Unix(_) | SocketKind::Packet(_) => int(0),
+            },
+            (SOL_SOCKET, SO_ACCEPTCONN) => match &self.kind {
+                SocketKind::Tcp(socket) => int((socket.state() == TcpState::Listen) as _),
+                SocketKind::Udp(_) | SocketKind::Packet(_) => int(0),
+                SocketKind::Unix(socket) => int(socket.is_listening() as _),
+            },
+            (SOL_SOCKET, SO_PEERCRED) => match &self.kind {
+                SocketKind::Unix(socket) => {
+                    let cred = socket.peer_cred().ok_or(Errno::ENOTCONN)?;
+                    let mut value = Vec::with_capacity(core::mem::size_of::<Ucred>());
+                    value.extend_from_slice(&cred.pid.to_ne_bytes());
+                    value.extend_from_slice(&cred.uid.to_ne_bytes());
+                    value.extend_from_slice(&cred.gid.to_ne_bytes());
+                    Ok(value)
+                }
+                _ => Err(Errno::ENOPROTOOPT),
+            },
+            _ => {
+                if let Some(value) = self.options.lock().get(&(level, name)) {
+                    return Ok(value.clone());
+                }
+                match (level, name) {
+                    (SOL_SOCKET, SO_SNDBUF | SO_RCVBUF) => int(BUFFER_SIZE),
+                    (SOL_SOCKET, SO_LINGER) => Ok(vec![0; 8]),
+                    (SOL_SOCKET, SO_RCVTIMEO | SO_SNDTIMEO) => Ok(vec![0; 16]),
+                    (
+                        SOL_SOCKET,
+                        SO_REUSEADDR | SO_REUSEPORT | SO_BROADCAST | SO_KEEPALIVE | SO_PASSCRED,
+                    ) => int(0),
+                    (IPPROTO_TCP, TCP_NODELAY) if matches!(self.kind, SocketKind::Tcp(_)) => int(0),
+                    (IPPROTO_IPV6, IPV6_V6ONLY) if self.domain == AF_INET6 => int(0),
+                    _ => Err(Errno::ENOPROTOOPT),
+                }
+            }
+        }
+    }
+}
+
+impl INodeInterface for Socket {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> FsResult<usize> {
+        self.recv_from(buffer, false).map(|(len, ..)| len)
+    }
+
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> FsResult<usize> {
+        self.send_to(buffer, None, false)
+    }
+
+    fn poll(&self, events: PollEvent) -> FsResult<PollEvent> {
+        super::poll();
+        let mut res = PollEvent::NONE;
+        let (readable, writable) = match &self.kind {
+            SocketKind::Tcp(socket) => (socket.readable(), socket.writable()),
+            SocketKind::Udp(socket) => (socket.readable(), true),
+            SocketKind::Unix(socket) => (socket.readable(), socket.writable()),
+            SocketKind::Packet(socket) => (socket.readable(), true),
+        };
+        if events.contains(PollEvent::POLLIN) && readable {
+            res |= PollEvent::POLLIN;
+        }
+        if events.contains(PollEvent::POLLOUT) && writable {
+            res |= PollEvent::POLLOUT;
+        }
+        // The hang-up and the error are reported without being requested.
+        let (hung_up, error) = match &self.kind {
+            SocketKind::Tcp(socket) => (
+                matches!(
+                    socket.state(),
+                    TcpState::Closed | TcpState::Closing | TcpState::LastAck | TcpState::TimeWait
+                ),
+                socket.has_error(),
+            ),
+            SocketKind::Unix(socket) => (socket.hung_up(), false),
+            SocketKind::Udp(_) | SocketKind::Packet(_) => (false, false),
+        };
+        if hung_up {
+            res |= PollEvent::POLLHUP;
+        }
+        if error {
+            res |= PollEvent::POLLERR;
+        }
+        Ok(res)
+    }
+
+    fn poll_wait(&self, waiter: &Arc<dyn Waiter>) {
+        crate::drivers::poll_wait(waiter);
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> FsResult<usize> {
+        if arg == 0 {
+            return Err(Errno::EFAULT);
+        }
+        match command {
+            FIONBIO => {
+                let nonblocking = unsafe { (arg as *const i32).read_unaligned() } != 0;
+                self.set_nonblocking(nonblocking);
+                Ok(0)
+            }
+            FIONREAD => {
+                let len = match &self.kind {
+                    SocketKind::Tcp(socket) => socket.recv_queued(),
+                    SocketKind::Udp(socket) => socket.recv_queued(),
+                    SocketKind::Unix(socket) => socket.recv_queued(),
+                    SocketKind::Packet(socket) => socket.recv_queued(),
+                };
+                unsafe { (arg as *mut i32).write_unaligned(len as i32) };
+                Ok(0)
+            }
+            _ => super::ioctl::ioctl(command, arg),
+        }
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            filename: "",
+            inode: self as *const Self as usize,
+            file_type: FileType::Socket,
+            size: 0,
+            childrens: 0,
+        })
+    }
+
+    fn stat(&self, stat: &mut Stat) -> FsResult<()> {
+        stat.ino = self as *const Self as u64;
+        stat.mode = StatMode::SOCKET | StatMode::from_bits_truncate(0o777);
+        stat.nlink = 1;
+        stat.uid = 0;
+        stat.gid = 0;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        stat.rdev = 0;
+        stat.mtime = Default::default();
+        stat.atime = Default::default();
+        stat.ctime = Default::default();
+        Ok(())
+    }
+}
@@ -0,0 +1,584 @@
+//! Unix domain sockets.
+//!
+//! A socket bound to a path creates a socket inode in the file tree, the
+//! connecting sockets find it by the inode. Abstract sockets are found by
+//! their names. Sending copies the data into the receive queue of the peer.
+
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use drivers_base::DSched;
+use fs_base::{Errno, FileType, FsResult, INodeInterface, OpenFlags};
+use spin::Mutex;
+
+use super::socket::{RecvMsg, SockAddr, SOCK_DGRAM, SOCK_STREAM};
+use crate::{
+    drivers::DriverSched,
+    task::{fd::FileItem, signal},
+};
+
+/// Max bytes waiting in the receive queue of a socket.
+const BUFFER_SIZE: usize = 0x40000;
+
+/// Max files passed in a message.
+pub const MAX_RIGHTS: usize = 253;
+
+/// Max levels of the sockets passed in the queues of the passed sockets.
+const MAX_NESTING: usize = 4;
+
+/// The address of a unix socket.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum UnixAddr {
+    #[default]
+    Unnamed,
+    Path(String),
+    /// A name in the abstract namespace, it isn't a file.
+    Abstract(Vec<u8>),
+}
+
+/// The `ucred` of the process creating the socket.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Ucred {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum BindKey {
+    /// The address of the socket inode.
+    Inode(usize),
+    Abstract(Vec<u8>),
+}
+
+/// The bound sockets.
+static BOUND: Mutex<BTreeMap<BindKey, Weak<UnixSocket>>> = Mutex::new(BTreeMap::new());
+
+fn inode_key(inode: &Arc<dyn INodeInterface>) -> BindKey {
+    BindKey::Inode(Arc::as_ptr(inode) as *const () as usize)
+}
+
+/// Create the socket inode of the path.
+fn create_inode(path: &str) -> FsResult<Arc<dyn INodeInterface>> {
+    crate::fs::mknod(path, FileType::Socket).map_err(|err| match err == Errno::EEXIST {
+        true => Errno::EADDRINUSE,
+        false => err,
+    })
+}
+
+/// Find the socket bound to the address.
+fn lookup(addr: &UnixAddr) -> FsResult<Arc<UnixSocket>> {
+    let key = match addr {
+        UnixAddr::Unnamed => return Err(Errno::EINVAL),
+        UnixAddr::Path(path) => {
+            let file = crate::FILE_TREE.root().open(path, OpenFlags::RDONLY)?;
+            inode_key(&file.inode())
+        }
+        UnixAddr::Abstract(name) => BindKey::Abstract(name.clone()),
+    };
+    BOUND
+        .lock()
+        .get(&key)
+        .and_then(Weak::upgrade)
+        .ok_or(Errno::ECONNREFUSED)
+}
+
+/// The data of a send call with its source and the passed files.
+struct Message {
+    data: Vec<u8>,
+    src: UnixAddr,
+    rights: Vec<Arc<FileItem>>,
+}
+
+enum State {
+    Unconnected,
+    Listening {
+        backlog: usize,
+        /// The server sides of the connections waiting to be accepted.
+        pending: VecDeque<Arc<UnixSocket>>,
+    },
+    /// Connected to the peer, or the default destination of a datagram socket.
+    Connected(Weak<UnixSocket>),
+}
+
+struct Inner {
+    state: State,
+    local: UnixAddr,
+    /// Keep the bound inode alive so its address isn't reused.
+    inode: Option<Arc<dyn INodeInterface>>,
+    rx: VecDeque<Message>,
+    rx_bytes: usize,
+    /// No more data is received, the reads return the end of file.
+    read_shutdown: bool,
+    peer_cred: Option<Ucred>,
+}
+
+pub struct UnixSocket {
+    sock_type: usize,
+    cred: Ucred,
+    nonblocking: AtomicBool,
+    /// The timeouts of the blocking calls in nanoseconds, 0 waits forever.
+    recv_timeout: AtomicU64,
+    send_timeout: AtomicU64,
+    inner: Mutex<Inner>,
+}
+
+impl UnixSocket {
+    pub fn new(sock_type: usize, cred: Ucred) -> Arc<Self> {
+        Arc::new(Self {
+            sock_type,
+            cred,
+            nonblocking: AtomicBool::new(false),
+            recv_timeout: AtomicU64::new(0),
+            send_timeout: AtomicU64::new(0),
+            inner: Mutex::new(Inner {
+                state: State::Unconnected,
+                local: UnixAddr::Unnamed,
+                inode: None,
+                rx: VecDeque::new(),
+                rx_bytes: 0,
+                read_shutdown: false,
+                peer_cred: None,
+            }),
+        })
+    }
+
+    /// Create a pair of connected sockets.
+    pub fn pair(sock_type: usize, cred: Ucred) -> (Arc<Self>, Arc<Self>) {
+        let (a, b) = (Self::new(sock_type, cred), Self::new(sock_type, cred));
+        for (socket, peer) in [(&a, &b), (&b, &a)] {
+            let mut inner = socket.inner.lock();
+            inner.state = State::Connected(Arc::downgrade(peer));
+            inner.peer_cred = Some(cred);
+        }
+        (a, b)
+    }
+
+    pub fn sock_type(&self) -> usize {
+        self.sock_type
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    /// Set the timeout of the blocking receive and accept, None waits forever.
+    pub fn set_recv_timeout(&self, timeout: Option<Duration>) {
+        let nanos = timeout.map_or(0, |x| (x.as_nanos() as u64).max(1));
+        self.recv_timeout.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Set the timeout of the blocking send, None waits forever.
+    pub fn set_send_timeout(&self, timeout: Option<Duration>) {
+        let nanos = timeout.map_or(0, |x| (x.as_nanos() as u64).max(1));
+        self.send_timeout.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Run the operation, wait while it returns EAGAIN unless `nonblocking`.
+    ///
+    /// The wait ends with EAGAIN once the `deadline` passes, or with EINTR
+    /// once a signal interrupts it.
+    fn block_on<T>(
+        &self,
+        nonblocking: bool,
+        deadline: Option<Duration>,
+        mut f: impl FnMut() -> FsResult<T>,
+    ) -> FsResult<T> {
+        loop {
+            match f() {
+                Err(err)
+                    if err == Errno::EAGAIN
+                        && !nonblocking
+                        && !deadline.is_some_and(|x| DriverSched::now() >= x) =>
+                {
+                    if signal::interrupted() {
+                        return Err(Errno::EINTR);
+                    }
+                    DriverSched::sleep()
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Get the deadline of a blocking call by the timeout.
+    fn deadline(timeout: &AtomicU64) -> Option<Duration> {
+        match timeout.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => DriverSched::now().checked_add(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// Check whether the socket is `target` or its queues hold `target` by the passed files.
+    ///
+    /// Returns ETOOMANYREFS if the passed sockets nest too deep.
+    fn holds(&self, target: &UnixSocket, depth: usize) -> FsResult<bool> {
+        if core::ptr::eq(self, target) {
+            return Ok(true);
+        }
+        // The lock is released before checking the queued sockets.
+        let sockets: Vec<Arc<UnixSocket>> = {
+            let inner = self.inner.lock();
+            let pending = match &inner.state {
+                State::Listening { pending, .. } => pending.iter().cloned().collect(),
+                _ => Vec::new(),
+            };
+            let passed = inner
+                .rx
+                .iter()
+                .flat_map(|x| x.rights.iter())
+                .filter_map(|x| x.socket.as_ref()?.unix().cloned());
+            pending.into_iter().chain(passed).collect()
+        };
+        if !sockets.is_empty() && depth >= MAX_NESTING {
+            return Err(Errno::ETOOMANYREFS);
+        }
+        for socket in sockets {
+            if socket.holds(target, depth + 1)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Get the connected peer.
+    fn peer(&self) -> Option<Weak<UnixSocket>> {
+        match &self.inner.lock().state {
+            State::Connected(peer) => Some(peer.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn bind(self: &Arc<Self>, addr: &UnixAddr) -> FsResult<()> {
+        let mut inner = self.inner.lock();
+        if inner.local != UnixAddr::Unnamed {
+            return Err(Errno::EINVAL);
+        }
+        let (key, inode) = match addr {
+            UnixAddr::Unnamed => return Err(Errno::EINVAL),
+            UnixAddr::Path(path) => {
+                let inode = create_inode(path)?;
+                (inode_key(&inode), Some(inode))
+            }
+            UnixAddr::Abstract(name) => (BindKey::Abstract(name.clone()), None),
+        };
+        let mut bound = BOUND.lock();
+        if bound.get(&key).is_some_and(|x| x.strong_count() > 0) {
+            return Err(Errno::EADDRINUSE);
+        }
+        bound.insert(key, Arc::downgrade(self));
+        inner.local = addr.clone();
+        inner.inode = inode;
+        Ok(())
+    }
+
+    pub fn listen(&self, backlog: usize) -> FsResult<()> {
+        if self.sock_type == SOCK_DGRAM {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        let mut inner = self.inner.lock();
+        if inner.local == UnixAddr::Unnamed {
+            return Err(Errno::EINVAL);
+        }
+        match &mut inner.state {
+            State::Unconnected => {
+                inner.state = State::Listening {
+                    backlog: backlog.max(1),
+                    pending: VecDeque::new(),
+                }
+            }
+            State::Listening { backlog: old, .. } => *old = backlog.max(1),
+            State::Connected(_) => return Err(Errno::EINVAL),
+        }
+        Ok(())
+    }
+
+    /// Connect to the listening socket, or set the default destination of
+    /// the datagram socket.
+    pub fn connect(self: &Arc<Self>, addr: &UnixAddr) -> FsResult<()> {
+        let target = lookup(addr)?;
+        if target.sock_type != self.sock_type {
+            return Err(Errno::EPROTOTYPE);
+        }
+        if self.sock_type == SOCK_DGRAM {
+            self.inner.lock().state = State::Connected(Arc::downgrade(&target));
+            return Ok(());
+        }
+        match self.inner.lock().state {
+            State::Unconnected => {}
+            State::Listening { .. } => return Err(Errno::EINVAL),
+            State::Connected(_) => return Err(Errno::EISCONN),
+        }
+        let server = Self::new(self.sock_type, target.cred);
+        let deadline = Self::deadline(&self.send_timeout);
+        self.block_on(self.is_nonblocking(), deadline, || {
+            let mut listener = target.inner.lock();
+            let local = listener.local.clone();
+            match &mut listener.state {
+                State::Listening { backlog, pending } if pending.len() < *backlog => {
+                    let mut inner = server.inner.lock();
+                    inner.local = local;
+                    inner.state = State::Connected(Arc::downgrade(self));
+                    inner.peer_cred = Some(self.cred);
+                    drop(inner);
+                    pending.push_back(server.clone());
+                    Ok(())
+                }
+                State::Listening { .. } => Err(Errno::EAGAIN),
+                _ => Err(Errno::ECONNREFUSED),
+            }
+        })?;
+        let mut inner = self.inner.lock();
+        inner.state = State::Connected(Arc::downgrade(&server));
+        inner.peer_cred = Some(target.cred);
+        DriverSched::wake();
+        Ok(())
+    }
+
+    /// Take a connection of the listening socket, returns it and its peer.
+    pub fn accept(&self) -> FsResult<(Arc<Self>, UnixAddr)> {
+        let deadline = Self::deadline(&self.recv_timeout);
+        let socket = self.block_on(self.is_nonblocking(), deadline, || {
+            match &mut self.inner.lock().state {
+                State::Listening { pending, .. } => pending.pop_front().ok_or(Errno::EAGAIN),
+                _ => Err(Errno::EINVAL),
+            }
+        })?;
+        let peer = socket.peer_addr().unwrap_or_default();
+        Ok((socket, peer))
+    }
+
+    /// Send the data to `addr` or the peer, the files are passed with the data.
+    ///
+    /// A blocking stream socket waits until all the data is queued.
+    pub fn send(
+        &self,
+        buf: &[u8],
+        addr: Option<&UnixAddr>,
+        rights: Vec<Arc<FileItem>>,
+        nonblocking: bool,
+    ) -> FsResult<usize> {
+        let peer = match (self.sock_type, addr, self.peer()) {
+            (SOCK_DGRAM, Some(addr), _) => {
+                let peer = lookup(addr)?;
+                if peer.sock_type != SOCK_DGRAM {
+                    return Err(Errno::EPROTOTYPE);
+                }
+                peer
+            }
+            (SOCK_DGRAM, None, Some(peer)) => peer.upgrade().ok_or(Errno::ECONNREFUSED)?,
+            (_, Some(_), Some(_)) => return Err(Errno::EISCONN),
+            (_, _, Some(peer)) => peer.upgrade().ok_or(Errno::EPIPE)?,
+            (_, _, None) => return Err(Errno::ENOTCONN),
+        };
+        if self.sock_type != SOCK_STREAM && buf.len() > BUFFER_SIZE {
+            return Err(Errno::EMSGSIZE);
+        }
+        // A socket in the queue holding itself would never be freed.
+        for right in rights.iter() {
+            if let Some(socket) = right.socket.as_ref().and_then(|x| x.unix()) {
+                if socket.holds(&peer, 0)? {
+                    return Err(Errno::ETOOMANYREFS);
+                }
+            }
+        }
+        let src = self.inner.lock().local.clone();
+        let mut rights = Some(rights);
+        let deadline = Self::deadline(&self.send_timeout);
+        let mut sent = 0;
+        loop {
+            let result = self.block_on(nonblocking, deadline, || {
+                let mut inner = peer.inner.lock();
+                if inner.read_shutdown {
+                    return Err(Errno::EPIPE);
+                }
+                let free = BUFFER_SIZE - inner.rx_bytes;
+                let len = match self.sock_type {
+                    SOCK_STREAM => free.min(buf.len() - sent),
+                    _ if buf.len() <= free => buf.len(),
+                    _ => return Err(Errno::EAGAIN),
+                };
+                if len == 0 && sent < buf.len() {
+                    return Err(Errno::EAGAIN);
+                }
+                inner.rx_bytes += len;
+                inner.rx.push_back(Message {
+                    data: buf[sent..sent + len].to_vec(),
+                    src: src.clone(),
+                    rights: rights.take().unwrap_or_default(),
+                });
+                Ok(len)
+            });
+            match result {
+                Ok(len) => sent += len,
+                Err(_) if sent > 0 => break,
+                Err(err) => return Err(err),
+            }
+            DriverSched::wake();
+            if nonblocking || sent >= buf.len() {
+                break;
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Receive the data, a stream socket doesn't merge the data across the
+    /// passed files.
+    pub fn recv(&self, buf: &mut [u8], nonblocking: bool) -> FsResult<RecvMsg> {
+        let deadline = Self::deadline(&self.recv_timeout);
+        let result = self.block_on(nonblocking, deadline, || {
+            let mut guard = self.inner.lock();
+            let inner = &mut *guard;
+            if inner.rx.is_empty() {
+                let eof = inner.read_shutdown
+                    || match &inner.state {
+                        State::Connected(peer) => {
+                            self.sock_type != SOCK_DGRAM && peer.strong_count() == 0
+                        }
+                        _ => false,
+                    };
+                return match (eof, &inner.state) {
+                    (true, _) => Ok(RecvMsg::default()),
+                    (false, State::Connected(_)) => Err(Errno::EAGAIN),
+                    _ if self.sock_type == SOCK_DGRAM => Err(Errno::EAGAIN),
+                    _ => Err(Errno::ENOTCONN),
+                };
+            }
+            if self.sock_type != SOCK_STREAM {
+                let message = inner.rx.pop_front().unwrap();
+                inner.rx_bytes -= message.data.len();
+                let len = buf.len().min(message.data.len());
+                buf[..len].copy_from_slice(&message.data[..len]);
+                return Ok(RecvMsg {
+                    len,
+                    full_len: message.data.len(),
+                    src: Some(SockAddr::Unix(message.src)),
+                    rights: message.rights,
+                });
+            }
+            let mut received = RecvMsg::default();
+            while received.len < buf.len() {
+                let message = match inner.rx.front_mut() {
+                    Some(message) => message,
+                    None => break,
+                };
+                if received.len > 0 && !message.rights.is_empty() {
+                    break;
+                }
+                if received.len == 0 {
+                    received.src = Some(SockAddr::Unix(message.src.clone()));
+                    received.rights = core::mem::take(&mut message.rights);
+                }
+                let count = (buf.len() - received.len).min(message.data.len());
+                buf[received.len..received.len + count].copy_from_slice(&message.data[..count]);
+                message.data.drain(..count);
+                if message.data.is_empty() {
+                    inner.rx.pop_front();
+                }
+                inner.rx_bytes -= count;
+                received.len += count;
+            }
+            received.full_len = received.len;
+            Ok(received)
+        })?;
+        DriverSched::wake();
+        Ok(result)
+    }
+
+    /// Shut down the receiving side and the sending side.
+    pub fn shutdown(&self, read: bool, write: bool) -> FsResult<()> {
+        let peer = match self.peer() {
+            Some(peer) => peer.upgrade(),
+            None if self.sock_type == SOCK_DGRAM => None,
+            None => return Err(Errno::ENOTCONN),
+        };
+        if read {
+            self.inner.lock().read_shutdown = true;
+        }
+        // The peer of a connection gets the end of file.
+        if let (true, Some(peer), false) = (write, peer, self.sock_type == SOCK_DGRAM) {
+            peer.inner.lock().read_shutdown = true;
+        }
+        DriverSched::wake();
+        Ok(())
+    }
+
+    pub fn local_addr(&self) -> UnixAddr {
+        self.inner.lock().local.clone()
+    }
+
+    pub fn peer_addr(&self) -> FsResult<UnixAddr> {
+        let peer = self
+            .peer()
+            .and_then(|x| x.upgrade())
+            .ok_or(Errno::ENOTCONN)?;
+        let addr = peer.inner.lock().local.clone();
+        Ok(addr)
+    }
+
+    /// Get the credentials of the peer when the connection is made.
+    pub fn peer_cred(&self) -> Option<Ucred> {
+        self.inner.lock().peer_cred
+    }
+
+    pub fn is_listening(&self) -> bool {
+        matches!(self.inner.lock().state, State::Listening { .. })
+    }
+
+    pub fn readable(&self) -> bool {
+        let inner = self.inner.lock();
+        match &inner.state {
+            _ if !inner.rx.is_empty() || inner.read_shutdown => true,
+            State::Listening { pending, .. } => !pending.is_empty(),
+            State::Connected(peer) => self.sock_type != SOCK_DGRAM && peer.strong_count() == 0,
+            State::Unconnected => false,
+        }
+    }
+
+    /// The connection is closed by the peer, or shut down in both directions.
+    pub fn hung_up(&self) -> bool {
+        let peer = match (&self.inner.lock().state, self.sock_type) {
+            (_, SOCK_DGRAM) => return false,
+            (State::Connected(peer), _) => peer.upgrade(),
+            _ => return false,
+        };
+        match peer {
+            Some(peer) => self.inner.lock().read_shutdown && peer.inner.lock().read_shutdown,
+            None => true,
+        }
+    }
+
+    pub fn writable(&self) -> bool {
+        match self.peer().map(|x| x.upgrade()) {
+            Some(Some(peer)) => {
+                let inner = peer.inner.lock();
+                inner.read_shutdown || inner.rx_bytes < BUFFER_SIZE
+            }
+            // Sending to a closed peer fails immediately.
+            Some(None) => true,
+            None => self.sock_type == SOCK_DGRAM,
+        }
+    }
+
+    /// Get the length of the received data, or the next message.
+    pub fn recv_queued(&self) -> usize {
+        let inner = self.inner.lock();
+        match self.sock_type {
+            SOCK_STREAM => inner.rx_bytes,
+            _ => inner.rx.front().map_or(0, |x| x.data.len()),
+        }
+    }
+}
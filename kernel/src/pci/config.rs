@@ -0,0 +1,152 @@
+//! PCI configuration space access through ECAM.
+
+use core::fmt::Display;
+
+/// Offsets of the configuration space header.
+pub const VENDOR_ID: usize = 0x00;
+pub const DEVICE_ID: usize = 0x02;
+pub const COMMAND: usize = 0x04;
+pub const STATUS: usize = 0x06;
+pub const PROG_IF: usize = 0x09;
+pub const SUBCLASS: usize = 0x0a;
+pub const CLASS: usize = 0x0b;
+pub const HEADER_TYPE: usize = 0x0e;
+pub const BAR0: usize = 0x10;
+pub const CAPABILITIES: usize = 0x34;
+pub const INTERRUPT_PIN: usize = 0x3d;
+
+/// Offsets of the PCI-to-PCI bridge header.
+pub const PRIMARY_BUS: usize = 0x18;
+pub const SECONDARY_BUS: usize = 0x19;
+pub const SUBORDINATE_BUS: usize = 0x1a;
+pub const IO_BASE: usize = 0x1c;
+pub const IO_LIMIT: usize = 0x1d;
+pub const MEMORY_BASE: usize = 0x20;
+pub const MEMORY_LIMIT: usize = 0x22;
+pub const PREF_BASE: usize = 0x24;
+pub const PREF_LIMIT: usize = 0x26;
+pub const PREF_BASE_UPPER: usize = 0x28;
+pub const PREF_LIMIT_UPPER: usize = 0x2c;
+pub const IO_BASE_UPPER: usize = 0x30;
+pub const IO_LIMIT_UPPER: usize = 0x32;
+
+/// Bits of the command register.
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+/// The status bit of the capability list.
+pub const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// Capability ids.
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR: u8 = 0x09;
+pub const CAP_MSIX: u8 = 0x11;
+
+/// Header types.
+pub const HEADER_TYPE_MASK: u8 = 0x7f;
+pub const HEADER_TYPE_BRIDGE: u8 = 0x01;
+pub const HEADER_MULTI_FUNCTION: u8 = 0x80;
+
+/// The address of a PCI function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// The ECAM configuration space of the host bridge.
+pub struct ConfigSpace {
+    /// Virtual address of the configuration space of the first bus.
+    base: usize,
+    /// The first bus number of the host bridge.
+    bus_start: u8,
+}
+
+impl ConfigSpace {
+    /// Create the configuration space accessor.
+    ///
+    /// `base` is the virtual address mapped to the ECAM region of `bus_start`.
+    pub const fn new(base: usize, bus_start: u8) -> Self {
+        Self { base, bus_start }
+    }
+
+    /// Get the virtual address of the configuration space of the bus 0,
+    /// used by the drivers which access the configuration space themselves.
+    pub fn base(&self) -> usize {
+        self.base - ((self.bus_start as usize) << 20)
+    }
+
+    #[inline]
+    fn addr(&self, address: PciAddress, offset: usize) -> usize {
+        self.base
+            + (((address.bus - self.bus_start) as usize) << 20)
+            + ((address.device as usize) << 15)
+            + ((address.function as usize) << 12)
+            + offset
+    }
+
+    pub fn read8(&self, address: PciAddress, offset: usize) -> u8 {
+        unsafe { (self.addr(address, offset) as *const u8).read_volatile() }
+    }
+
+    pub fn read16(&self, address: PciAddress, offset: usize) -> u16 {
+        unsafe { (self.addr(address, offset) as *const u16).read_volatile() }
+    }
+
+    pub fn read32(&self, address: PciAddress, offset: usize) -> u32 {
+        unsafe { (self.addr(address, offset) as *const u32).read_volatile() }
+    }
+
+    pub fn write8(&self, address: PciAddress, offset: usize, value: u8) {
+        unsafe { (self.addr(address, offset) as *mut u8).write_volatile(value) }
+    }
+
+    pub fn write16(&self, address: PciAddress, offset: usize, value: u16) {
+        unsafe { (self.addr(address, offset) as *mut u16).write_volatile(value) }
+    }
+
+    pub fn write32(&self, address: PciAddress, offset: usize, value: u32) {
+        unsafe { (self.addr(address, offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Set the bits of the command register.
+    pub fn enable(&self, address: PciAddress, command: u16) {
+        let old = self.read16(address, COMMAND);
+        self.write16(address, COMMAND, old | command);
+    }
+
+    /// Iterate the capability list of the function, yields the id and offset of every capability.
+    pub fn capabilities(&self, address: PciAddress) -> impl Iterator<Item = (u8, usize)> + '_ {
+        let mut next = match self.read16(address, STATUS) & STATUS_CAPABILITIES {
+            0 => 0,
+            _ => self.read8(address, CAPABILITIES) as usize & !0x3,
+        };
+        // A capability is at least 4 bytes, so a longer list is broken.
+        let mut remaining = 48;
+        core::iter::from_fn(move || {
+            if next == 0 || remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            let offset = next;
+            next = self.read8(address, offset + 1) as usize & !0x3;
+            Some((self.read8(address, offset), offset))
+        })
+    }
+
+    /// Find the first capability of the id, returns its offset.
+    pub fn find_capability(&self, address: PciAddress, id: u8) -> Option<usize> {
+        self.capabilities(address)
+            .find(|(cap, _)| *cap == id)
+            .map(|(_, offset)| offset)
+    }
+}
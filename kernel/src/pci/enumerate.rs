@@ -0,0 +1,306 @@
+//! PCI bus enumeration and BAR assignment.
+
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+use super::{
+    config::*,
+    resource::{Resources, WindowKind},
+    Bar, PciDevice,
+};
+
+/// Bridge memory windows are aligned to 1MB.
+const BRIDGE_MEM_ALIGN: u64 = 0x10_0000;
+/// Bridge I/O windows are aligned to 4KB.
+const BRIDGE_IO_ALIGN: u64 = 0x1000;
+
+/// Walks the buses of the host bridge.
+///
+/// If the resources are given, the bus numbers, BARs and bridge windows are
+/// assigned from them. Otherwise the assignment of the firmware is kept.
+pub struct Enumerator<'a> {
+    config: &'a ConfigSpace,
+    resources: Option<Resources>,
+    bus_range: RangeInclusive<u8>,
+    /// The next bus number assigned to a bridge.
+    next_bus: u8,
+    devices: Vec<PciDevice>,
+}
+
+impl<'a> Enumerator<'a> {
+    pub fn new(
+        config: &'a ConfigSpace,
+        resources: Option<Resources>,
+        bus_range: RangeInclusive<u8>,
+    ) -> Self {
+        Self {
+            config,
+            resources,
+            next_bus: *bus_range.start() + 1,
+            bus_range,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Enumerate all buses below the host bridge and return the devices.
+    pub fn enumerate(mut self) -> Vec<PciDevice> {
+        self.scan_bus(*self.bus_range.start());
+        self.devices
+    }
+
+    /// Scan the bus, returns the last bus number below it.
+    fn scan_bus(&mut self, bus: u8) -> u8 {
+        let mut subordinate = bus;
+        for device in 0..32 {
+            for function in 0..8 {
+                let address = PciAddress {
+                    bus,
+                    device,
+                    function,
+                };
+                if self.config.read16(address, VENDOR_ID) == 0xffff {
+                    // Function 0 must exist on every device.
+                    match function {
+                        0 => break,
+                        _ => continue,
+                    }
+                }
+                let header_type = self.config.read8(address, HEADER_TYPE);
+                let last = self.scan_function(address, header_type & HEADER_TYPE_MASK);
+                subordinate = subordinate.max(last);
+                if function == 0 && header_type & HEADER_MULTI_FUNCTION == 0 {
+                    break;
+                }
+            }
+        }
+        subordinate
+    }
+
+    /// Record the function and assign its resources, bridges are scanned recursively.
+    fn scan_function(&mut self, address: PciAddress, header_type: u8) -> u8 {
+        let bar_count = match header_type {
+            0 => 6,
+            HEADER_TYPE_BRIDGE => 2,
+            _ => 0,
+        };
+        let mut bars = [None; 6];
+        let mut index = 0;
+        while index < bar_count {
+            let (bar, takes_two) = self.probe_bar(address, index);
+            bars[index] = bar;
+            index += if takes_two { 2 } else { 1 };
+        }
+        let device = PciDevice {
+            address,
+            vendor_id: self.config.read16(address, VENDOR_ID),
+            device_id: self.config.read16(address, DEVICE_ID),
+            class: self.config.read8(address, CLASS),
+            subclass: self.config.read8(address, SUBCLASS),
+            prog_if: self.config.read8(address, PROG_IF),
+            header_type,
+            bars,
+        };
+        log::info!("PCI {}", device);
+        self.devices.push(device);
+
+        match header_type {
+            HEADER_TYPE_BRIDGE => self.scan_bridge(address),
+            _ => address.bus,
+        }
+    }
+
+    /// Size the BAR and assign it if needed.
+    ///
+    /// Returns the BAR and whether it takes two entries,
+    /// the BAR is None if it isn't implemented or has no address.
+    fn probe_bar(&mut self, address: PciAddress, index: usize) -> (Option<Bar>, bool) {
+        let offset = BAR0 + index * 4;
+        // Stop decoding while the BAR is sized.
+        let command = self.config.read16(address, COMMAND);
+        self.config
+            .write16(address, COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+
+        let orig = self.config.read32(address, offset);
+        self.config.write32(address, offset, 0xffff_ffff);
+        let mask = self.config.read32(address, offset);
+        self.config.write32(address, offset, orig);
+
+        let res = if mask == 0 {
+            (None, false)
+        } else if orig & 0x1 == 1 {
+            // I/O BARs may only decode 16 bits.
+            let mask = match mask >> 16 {
+                0 => mask | 0xffff_0000,
+                _ => mask,
+            };
+            let size = (!(mask & !0x3)).wrapping_add(1) as u64;
+            let addr = self.assign_bar(address, index, WindowKind::Io, size, false);
+            let addr = addr.unwrap_or((orig & !0x3) as u64);
+            let bar = Bar::Io {
+                addr: addr as usize,
+                size: size as usize,
+            };
+            ((addr != 0).then_some(bar), false)
+        } else {
+            let is64 = (orig >> 1) & 0x3 == 0x2;
+            let prefetchable = orig & 0x8 != 0;
+            let (orig, mask) = match is64 {
+                true => {
+                    let orig_hi = self.config.read32(address, offset + 4);
+                    self.config.write32(address, offset + 4, 0xffff_ffff);
+                    let mask_hi = self.config.read32(address, offset + 4);
+                    self.config.write32(address, offset + 4, orig_hi);
+                    (
+                        (orig_hi as u64) << 32 | orig as u64,
+                        (mask_hi as u64) << 32 | mask as u64,
+                    )
+                }
+                false => (orig as u64, mask as u64 | 0xffff_ffff_0000_0000),
+            };
+            let size = (!(mask & !0xf)).wrapping_add(1);
+            let kind = match is64 && prefetchable {
+                true => WindowKind::Mem64,
+                false => WindowKind::Mem32,
+            };
+            let addr = self.assign_bar(address, index, kind, size, is64);
+            let addr = addr.unwrap_or(orig & !0xf);
+            let bar = Bar::Memory {
+                addr: addr as usize,
+                size: size as usize,
+                prefetchable,
+                is64,
+            };
+            ((addr != 0).then_some(bar), is64)
+        };
+        self.config.write16(address, COMMAND, command);
+        res
+    }
+
+    /// Assign the BAR from the windows, returns the cpu address.
+    ///
+    /// Returns None if the assignment of the firmware is kept or there is no space.
+    fn assign_bar(
+        &mut self,
+        address: PciAddress,
+        index: usize,
+        kind: WindowKind,
+        size: u64,
+        is64: bool,
+    ) -> Option<u64> {
+        let resources = self.resources.as_mut()?;
+        let (pci_addr, cpu_addr) = match resources.alloc(kind, size) {
+            Some(addr) => addr,
+            None => {
+                log::warn!(
+                    "PCI {}: no space for BAR {} size {:#x}",
+                    address,
+                    index,
+                    size
+                );
+                return None;
+            }
+        };
+        let offset = BAR0 + index * 4;
+        self.config.write32(address, offset, pci_addr as u32);
+        if is64 {
+            self.config
+                .write32(address, offset + 4, (pci_addr >> 32) as u32);
+        }
+        log::info!(
+            "PCI {}: BAR {} {:?} at {:#x}, size {:#x}",
+            address,
+            index,
+            kind,
+            cpu_addr,
+            size
+        );
+        Some(cpu_addr)
+    }
+
+    /// Assign the bus numbers and windows of the bridge and scan its secondary bus.
+    fn scan_bridge(&mut self, address: PciAddress) -> u8 {
+        let config = self.config;
+        if self.resources.is_none() {
+            // Keep the bus numbers assigned by the firmware.
+            let secondary = config.read8(address, SECONDARY_BUS);
+            if secondary <= address.bus || !self.bus_range.contains(&secondary) {
+                return address.bus;
+            }
+            self.scan_bus(secondary);
+            return config.read8(address, SUBORDINATE_BUS);
+        }
+
+        if self.next_bus > *self.bus_range.end() || self.next_bus == 0 {
+            log::warn!("PCI {}: no bus number for the bridge", address);
+            return address.bus;
+        }
+        let secondary = self.next_bus;
+        self.next_bus = self.next_bus.wrapping_add(1);
+        config.write8(address, PRIMARY_BUS, address.bus);
+        config.write8(address, SECONDARY_BUS, secondary);
+        // Open all buses until the subordinate bus is known.
+        config.write8(address, SUBORDINATE_BUS, *self.bus_range.end());
+
+        let starts = self.align_windows();
+        let subordinate = self.scan_bus(secondary);
+        let ends = self.align_windows();
+        config.write8(address, SUBORDINATE_BUS, subordinate);
+
+        // The inclusive range of the windows used by the devices below the bridge.
+        let [io, mem, pref] = [0, 1, 2].map(|i| match (starts[i], ends[i]) {
+            (Some(start), Some(end)) if end > start => Some((start, end - 1)),
+            _ => None,
+        });
+        // The windows are closed by setting the base above the limit.
+        match io {
+            Some((start, end)) => {
+                config.write8(address, IO_BASE, ((start >> 8) & 0xf0) as u8);
+                config.write8(address, IO_LIMIT, ((end >> 8) & 0xf0) as u8);
+                config.write16(address, IO_BASE_UPPER, (start >> 16) as u16);
+                config.write16(address, IO_LIMIT_UPPER, (end >> 16) as u16);
+            }
+            _ => {
+                config.write8(address, IO_BASE, 0xf0);
+                config.write8(address, IO_LIMIT, 0);
+            }
+        }
+        match mem {
+            Some((start, end)) => {
+                config.write16(address, MEMORY_BASE, ((start >> 16) & 0xfff0) as u16);
+                config.write16(address, MEMORY_LIMIT, ((end >> 16) & 0xfff0) as u16);
+            }
+            _ => {
+                config.write16(address, MEMORY_BASE, 0xfff0);
+                config.write16(address, MEMORY_LIMIT, 0);
+            }
+        }
+        match pref {
+            Some((start, end)) => {
+                config.write16(address, PREF_BASE, ((start >> 16) & 0xfff0) as u16);
+                config.write16(address, PREF_LIMIT, ((end >> 16) & 0xfff0) as u16);
+                config.write32(address, PREF_BASE_UPPER, (start >> 32) as u32);
+                config.write32(address, PREF_LIMIT_UPPER, (end >> 32) as u32);
+            }
+            _ => {
+                config.write16(address, PREF_BASE, 0xfff0);
+                config.write16(address, PREF_LIMIT, 0);
+                config.write32(address, PREF_BASE_UPPER, 0);
+                config.write32(address, PREF_LIMIT_UPPER, 0);
+            }
+        }
+        config.enable(address, COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER);
+        subordinate
+    }
+
+    /// Align the I/O, memory and prefetchable windows to the bridge granularity,
+    /// returns the next free PCI addresses of them.
+    fn align_windows(&mut self) -> [Option<u64>; 3] {
+        let resources = self.resources.as_mut().unwrap();
+        [
+            (WindowKind::Io, BRIDGE_IO_ALIGN),
+            (WindowKind::Mem32, BRIDGE_MEM_ALIGN),
+            (WindowKind::Mem64, BRIDGE_MEM_ALIGN),
+        ]
+        .map(|(kind, align)| resources.window(kind).map(|x| x.align(align)))
+    }
+}
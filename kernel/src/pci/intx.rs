@@ -0,0 +1,155 @@
+//! Legacy INTx interrupts of the PCI devices.
+//!
+//! The devices without MSIs raise their INTx pins, the pins on the root bus
+//! are routed to the interrupt controller by the `interrupt-map` of the host
+//! bridge. The pins of the devices behind bridges are swizzled to the pins
+//! of the bridges on the root bus.
+
+use alloc::vec::Vec;
+use fdt::{node::FdtNode, Fdt};
+use log::{info, warn};
+
+use super::{
+    config::{ConfigSpace, PciAddress, HEADER_TYPE_BRIDGE, INTERRUPT_PIN, SECONDARY_BUS},
+    PciDevice,
+};
+use crate::drivers;
+
+/// Cells of the unit address and the interrupt specifier of the PCI child.
+const CHILD_ADDRESS_CELLS: usize = 3;
+const CHILD_INTERRUPT_CELLS: usize = 1;
+
+/// An entry of the `interrupt-map`.
+#[derive(Debug, Clone, Copy)]
+struct MapEntry {
+    /// The masked unit address and pin of the child.
+    child: [u32; CHILD_ADDRESS_CELLS + CHILD_INTERRUPT_CELLS],
+    /// The irq of the interrupt controller.
+    irq: u32,
+}
+
+/// The `interrupt-map` of the host bridge.
+#[derive(Debug, Clone)]
+pub struct InterruptMap {
+    /// The root bus, the pins of other buses are swizzled.
+    root_bus: u8,
+    mask: [u32; CHILD_ADDRESS_CELLS + CHILD_INTERRUPT_CELLS],
+    entries: Vec<MapEntry>,
+}
+
+impl InterruptMap {
+    /// Parse the `interrupt-map` and `interrupt-map-mask` of the host bridge node.
+    ///
+    /// Returns None if the node doesn't have a map or its layout isn't supported.
+    pub fn from_node(fdt: &Fdt, node: &FdtNode, root_bus: u8) -> Option<Self> {
+        let read_cell = |data: &[u8], idx: usize| {
+            u32::from_be_bytes(data[idx * 4..idx * 4 + 4].try_into().unwrap())
+        };
+        let child_cells = CHILD_ADDRESS_CELLS + CHILD_INTERRUPT_CELLS;
+        if node.cell_sizes().address_cells != CHILD_ADDRESS_CELLS
+            || node.interrupt_cells() != Some(CHILD_INTERRUPT_CELLS)
+        {
+            warn!("PCI: unsupported interrupt cells of the host bridge");
+            return None;
+        }
+        let map = node.property("interrupt-map")?.value;
+        let mut mask = [u32::MAX; CHILD_ADDRESS_CELLS + CHILD_INTERRUPT_CELLS];
+        if let Some(prop) = node.property("interrupt-map-mask") {
+            if prop.value.len() != child_cells * 4 {
+                warn!("PCI: invalid interrupt-map-mask");
+                return None;
+            }
+            (0..child_cells).for_each(|i| mask[i] = read_cell(prop.value, i));
+        }
+
+        // Every entry has the child, the phandle of the parent, the unit
+        // address and the interrupt specifier of the parent.
+        let mut entries = Vec::new();
+        let mut cells = map
+            .chunks_exact(4)
+            .map(|x| u32::from_be_bytes(x.try_into().unwrap()));
+        while cells.len() != 0 {
+            let mut child = [0; CHILD_ADDRESS_CELLS + CHILD_INTERRUPT_CELLS];
+            for cell in child.iter_mut() {
+                *cell = cells.next()?;
+            }
+            let parent = fdt.find_phandle(cells.next()?)?;
+            let address_cells = parent
+                .property("#address-cells")
+                .and_then(|x| x.as_usize())
+                .unwrap_or(0);
+            let interrupt_cells = parent.interrupt_cells()?;
+            let specifier: Vec<u8> = (&mut cells)
+                .skip(address_cells)
+                .take(interrupt_cells)
+                .flat_map(u32::to_be_bytes)
+                .collect();
+            if specifier.len() != interrupt_cells * 4 {
+                warn!("PCI: truncated interrupt-map");
+                return None;
+            }
+            (0..child_cells).for_each(|i| child[i] &= mask[i]);
+            entries.push(MapEntry {
+                child,
+                irq: drivers::specifier_irq(&specifier),
+            });
+        }
+        Some(Self {
+            root_bus,
+            mask,
+            entries,
+        })
+    }
+
+    /// Find the irq of the pin of the device on the root bus, the pins are 1 to 4.
+    fn lookup(&self, address: PciAddress, pin: u8) -> Option<u32> {
+        let phys_hi = (address.bus as u32) << 16
+            | (address.device as u32) << 11
+            | (address.function as u32) << 8;
+        let mut child = [phys_hi, 0, 0, pin as u32];
+        (0..child.len()).for_each(|i| child[i] &= self.mask[i]);
+        self.entries
+            .iter()
+            .find(|x| x.child == child)
+            .map(|x| x.irq)
+    }
+}
+
+/// Route the INTx pin of the device, returns the irq.
+///
+/// `devices` has the bridges between the device and the root bus.
+pub fn route(
+    map: &InterruptMap,
+    config: &ConfigSpace,
+    devices: &[PciDevice],
+    device: &PciDevice,
+) -> Option<u32> {
+    let mut pin = match config.read8(device.address, INTERRUPT_PIN) {
+        pin @ 1..=4 => pin,
+        _ => return None,
+    };
+    // Swizzle the pin through the bridges up to the root bus.
+    let mut address = device.address;
+    while address.bus != map.root_bus {
+        pin = (pin - 1 + address.device) % 4 + 1;
+        address = devices
+            .iter()
+            .find(|x| {
+                x.header_type == HEADER_TYPE_BRIDGE
+                    && config.read8(x.address, SECONDARY_BUS) == address.bus
+            })?
+            .address;
+    }
+    let irq = match map.lookup(address, pin) {
+        Some(irq) => irq,
+        None => {
+            warn!(
+                "PCI {}: INTx pin {} isn't in the interrupt-map",
+                device.address, pin
+            );
+            return None;
+        }
+    };
+    info!("PCI {}: irq {} by INTx", device.address, irq);
+    Some(irq)
+}
@@ -0,0 +1,327 @@
+//! PCI bus support.
+//!
+//! Enumerates the buses below the host bridge, assigns the BARs and bridge
+//! windows from the `ranges` of the host bridge, records the devices and
+//! probes the drivers of them. The interrupts of the drivers are signalled
+//! by MSI-X or MSI messages, or the INTx pins if the interrupt controller
+//! can't allocate MSIs.
+
+mod config;
+mod enumerate;
+mod intx;
+mod msi;
+mod resource;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{fmt::Display, ops::RangeInclusive};
+use drivers_ahci::Ahci;
+use drivers_e1000::E1000;
+use drivers_nvme::Nvme;
+use drivers_sdcard::SDCard;
+use log::info;
+use polyhal::{common::get_fdt, consts::VIRT_ADDR_START};
+use spin::{Mutex, Once};
+
+pub use config::{ConfigSpace, PciAddress};
+use config::{
+    CAP_MSIX, CAP_VENDOR, COMMAND_BUS_MASTER, COMMAND_IO, COMMAND_MEMORY, HEADER_TYPE_BRIDGE,
+};
+use enumerate::Enumerator;
+use intx::InterruptMap;
+use resource::Resources;
+
+use crate::{
+    drivers::{self, DriverSched},
+    PageAllocator,
+};
+
+/// ECAM address of the QEMU q35 machine, x86_64 doesn't have a device tree.
+#[cfg(target_arch = "x86_64")]
+const Q35_ECAM_ADDR: usize = 0xb000_0000;
+
+/// The virtio vendor capability of the common configuration.
+const VIRTIO_CAP_COMMON_CFG: u8 = 1;
+
+/// A BAR of the PCI device.
+#[derive(Debug, Clone, Copy)]
+pub enum Bar {
+    /// Memory BAR at the cpu physical address.
+    Memory {
+        addr: usize,
+        size: usize,
+        prefetchable: bool,
+        is64: bool,
+    },
+    /// I/O BAR, the cpu physical address of the I/O window, or the port on x86_64.
+    Io { addr: usize, size: usize },
+}
+
+/// A PCI function found in the enumeration.
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub header_type: u8,
+    pub bars: [Option<Bar>; 6],
+}
+
+impl Display for PciDevice {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} {:04x}:{:04x} class {:02x}.{:02x}.{:02x}",
+            self.address, self.vendor_id, self.device_id, self.class, self.subclass, self.prog_if
+        )?;
+        if self.header_type == HEADER_TYPE_BRIDGE {
+            write!(f, " bridge")?;
+        }
+        for (index, bar) in self.bars.iter().enumerate() {
+            match bar {
+                Some(Bar::Memory {
+                    addr,
+                    size,
+                    prefetchable,
+                    is64,
+                }) => write!(
+                    f,
+                    "\n  BAR {}: memory {:#x} size {:#x}{}{}",
+                    index,
+                    addr,
+                    size,
+                    if *is64 { " 64-bit" } else { "" },
+                    if *prefetchable { " prefetchable" } else { "" }
+                )?,
+                Some(Bar::Io { addr, size }) => {
+                    write!(f, "\n  BAR {}: io {:#x} size {:#x}", index, addr, size)?
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PciDevice {
+    /// Get the cpu physical address of the memory BAR.
+    pub fn memory_bar(&self, index: usize) -> Option<usize> {
+        match self.bars[index] {
+            Some(Bar::Memory { addr, .. }) => Some(addr),
+            _ => None,
+        }
+    }
+}
+
+/// The configuration space of the host bridge.
+static CONFIG: Once<ConfigSpace> = Once::new();
+
+/// All devices found in the enumeration.
+static DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+
+/// The INTx routing of the host bridge, x86_64 doesn't have a device tree
+/// and its interrupt controller always allocates MSIs.
+static INTERRUPT_MAP: Once<InterruptMap> = Once::new();
+
+/// Initialize PCI Configuration.
+pub fn init() {
+    let (config, resources, bus_range) = match probe_host_bridge() {
+        Some(host) => host,
+        None => return,
+    };
+    let config = CONFIG.call_once(|| config);
+    let devices = Enumerator::new(config, resources, bus_range).enumerate();
+    *DEVICES.lock() = devices.clone();
+
+    for device in devices {
+        probe_driver(config, &device);
+    }
+}
+
+/// Get the configuration space, the windows and the bus range of the host bridge.
+///
+/// The windows are None if the BARs are assigned by the firmware.
+fn probe_host_bridge() -> Option<(ConfigSpace, Option<Resources>, RangeInclusive<u8>)> {
+    let fdt = match get_fdt() {
+        Some(fdt) => fdt,
+        #[cfg(target_arch = "x86_64")]
+        None => {
+            return Some((
+                ConfigSpace::new(Q35_ECAM_ADDR | VIRT_ADDR_START, 0),
+                None,
+                0..=255,
+            ))
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        None => return None,
+    };
+    let node = fdt.all_nodes().find(|x| {
+        x.compatible()
+            .map(|c| c.all().any(|c| c == "pci-host-ecam-generic"))
+            .unwrap_or(x.name.starts_with("pci"))
+    })?;
+    let pci_addr = node.reg()?.next()?.starting_address as usize;
+    info!("PCI Address: {:#x}", pci_addr);
+
+    let read_cell = |data: &[u8], idx: usize| {
+        u32::from_be_bytes(data[idx * 4..idx * 4 + 4].try_into().unwrap())
+    };
+    let bus_range = node
+        .property("bus-range")
+        .map(|prop| read_cell(prop.value, 0) as u8..=read_cell(prop.value, 1) as u8)
+        .unwrap_or(0..=255);
+    // The host bridge is under the root node or the soc node, both use the root cells.
+    let parent_cells = fdt.root().cell_sizes().address_cells;
+    let resources = node
+        .property("ranges")
+        .map(|prop| Resources::from_ranges(prop.value, parent_cells));
+    if let Some(map) = InterruptMap::from_node(&fdt, &node, *bus_range.start()) {
+        INTERRUPT_MAP.call_once(|| map);
+    }
+    Some((
+        ConfigSpace::new(pci_addr | VIRT_ADDR_START, *bus_range.start()),
+        resources,
+        bus_range,
+    ))
+}
+
+/// Probe the driver of the device.
+fn probe_driver(config: &ConfigSpace, device: &PciDevice) {
+    let address = device.address;
+    match (device.vendor_id, device.device_id) {
+        // Detected E1000 Net Card
+        (0x8086, 0x100e) => {
+            config.enable(address, COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER);
+            let addr = match device.memory_bar(0) {
+                Some(addr) => addr,
+                None => return,
+            };
+            let irqs = device_irqs(config, device);
+            match E1000::<Mutex<()>, PageAllocator, DriverSched>::new(addr | VIRT_ADDR_START, irqs)
+            {
+                Ok(e1000) => drivers::register(Arc::new(e1000)),
+                Err(err) => log::warn!("can't initialize the e1000: {:?}", err),
+            }
+        }
+        // Virtio devices
+        (0x1af4, 0x1000..=0x107f) => {
+            // Enable the device to use its BARs.
+            config.enable(address, COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER);
+            let irqs = device_irqs(config, device);
+            let common_cfg = match msi::enabled(config, address)
+                && config.find_capability(address, CAP_MSIX).is_some()
+            {
+                true => virtio_common_cfg(config, device),
+                false => None,
+            };
+            if let Some(driver) = drivers_virtio::probe_pci::<Mutex<()>, PageAllocator, DriverSched>(
+                config.base(),
+                address.bus,
+                address.device,
+                address.function,
+                irqs,
+                common_cfg,
+            ) {
+                drivers::register(driver);
+            }
+        }
+        // SDHCI
+        (0x1b36, 0x0007) => {
+            config.enable(address, COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER);
+            let addr = match device.memory_bar(0) {
+                Some(addr) => addr,
+                None => return,
+            };
+            let irqs = device_irqs(config, device);
+            match SDCard::<Mutex<()>, PageAllocator, DriverSched>::new(
+                addr | VIRT_ADDR_START,
+                true,
+                irqs,
+            ) {
+                Ok(sdcard) => drivers::register(Arc::new(sdcard)),
+                Err(err) => log::warn!("can't initialize the sdcard: {:?}", err),
+            }
+        }
+        // NVMe controllers are matched by the class code.
+        _ if (device.class, device.subclass, device.prog_if) == drivers_nvme::SUPPORT_PCI_CLASS => {
+            config.enable(address, COMMAND_MEMORY | COMMAND_BUS_MASTER);
+            let addr = match device.memory_bar(0) {
+                Some(addr) => addr,
+                None => return,
+            };
+            let irqs = device_irqs(config, device);
+            let pin_based = !msi::enabled(config, address);
+            let nvme = match Nvme::<Mutex<()>, PageAllocator, DriverSched>::new(
+                addr | VIRT_ADDR_START,
+                irqs,
+                pin_based,
+            ) {
+                Ok(nvme) => Arc::new(nvme),
+                Err(err) => {
+                    log::warn!("can't initialize the nvme controller: {:?}", err);
+                    return;
+                }
+            };
+            match nvme.namespaces() {
+                Ok(namespaces) => namespaces
+                    .into_iter()
+                    .for_each(|x| drivers::register(Arc::new(x))),
+                Err(err) => log::warn!("can't identify the nvme namespaces: {:?}", err),
+            }
+            drivers::register(nvme);
+        }
+        // AHCI controllers are matched by the class code, the registers are in the BAR 5.
+        _ if (device.class, device.subclass, device.prog_if) == drivers_ahci::SUPPORT_PCI_CLASS => {
+            config.enable(address, COMMAND_MEMORY | COMMAND_BUS_MASTER);
+            let addr = match device.memory_bar(5) {
+                Some(addr) => addr,
+                None => return,
+            };
+            let irqs = device_irqs(config, device);
+            let ahci = match Ahci::<Mutex<()>, PageAllocator, DriverSched>::new(
+                addr | VIRT_ADDR_START,
+                irqs,
+            ) {
+                Ok(ahci) => Arc::new(ahci),
+                Err(err) => {
+                    log::warn!("can't initialize the ahci controller: {:?}", err);
+                    return;
+                }
+            };
+            ahci.disks()
+                .into_iter()
+                .for_each(|x| drivers::register(Arc::new(x)));
+            drivers::register(ahci);
+        }
+        _ => {}
+    }
+}
+
+/// Get the irqs of the device, the MSI or the routed INTx pin.
+fn device_irqs(config: &ConfigSpace, device: &PciDevice) -> Vec<u32> {
+    msi::enable(config, device)
+        .or_else(|| intx::route(INTERRUPT_MAP.get()?, config, &DEVICES.lock(), device))
+        .into_iter()
+        .collect()
+}
+
+/// Get the virtual address of the common configuration of the virtio device.
+///
+/// The transport routes the interrupts to the MSI-X vector 0 through it.
+fn virtio_common_cfg(config: &ConfigSpace, device: &PciDevice) -> Option<usize> {
+    let address = device.address;
+    // The vendor capability has the type, the BAR index and the offset in the BAR.
+    config.capabilities(address).find_map(|(id, cap)| {
+        if id != CAP_VENDOR || config.read8(address, cap + 3) != VIRTIO_CAP_COMMON_CFG {
+            return None;
+        }
+        let bar = config.read8(address, cap + 4) as usize;
+        let offset = config.read32(address, cap + 8) as usize;
+        if bar >= device.bars.len() {
+            return None;
+        }
+        Some((device.memory_bar(bar)? + offset) | VIRT_ADDR_START)
+    })
+}
@@ -0,0 +1,166 @@
+//! Message signalled interrupts of the PCI devices.
+//!
+//! The irq and its message are allocated from the interrupt controller,
+//! MSI-X is preferred over MSI. The legacy INTx is disabled once the
+//! messages are enabled.
+
+use drivers_base::MsiMessage;
+use log::{info, warn};
+use polyhal::consts::VIRT_ADDR_START;
+
+use super::{config::*, PciDevice};
+use crate::drivers;
+
+/// Offsets of the MSI capability.
+const MSI_CONTROL: usize = 0x02;
+const MSI_ADDR_LO: usize = 0x04;
+const MSI_ADDR_HI: usize = 0x08;
+/// Offset of the data without and with the 64-bit address.
+const MSI_DATA_32: usize = 0x08;
+const MSI_DATA_64: usize = 0x0c;
+
+/// Bits of the MSI message control.
+const MSI_CONTROL_ENABLE: u16 = 1 << 0;
+/// Multiple message enable, the number of the allocated vectors in log2.
+const MSI_CONTROL_MME: u16 = 0x7 << 4;
+const MSI_CONTROL_64BIT: u16 = 1 << 7;
+
+/// Offsets of the MSI-X capability.
+const MSIX_CONTROL: usize = 0x02;
+const MSIX_TABLE: usize = 0x04;
+
+/// Bits of the MSI-X message control.
+const MSIX_CONTROL_TABLE_SIZE: u16 = 0x7ff;
+const MSIX_CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CONTROL_ENABLE: u16 = 1 << 15;
+
+/// Size of the MSI-X table entry, the address, data and vector control.
+const MSIX_ENTRY_SIZE: usize = 0x10;
+const MSIX_VECTOR_MASKED: u32 = 1;
+
+/// Enable the message signalled interrupt of the device.
+///
+/// The device must decode its memory BARs, the MSI-X table is in one of them.
+/// Returns the irq raised by the vector 0, None if the device or the
+/// interrupt controller doesn't support MSIs.
+pub fn enable(config: &ConfigSpace, device: &PciDevice) -> Option<u32> {
+    let address = device.address;
+    let msix = config
+        .find_capability(address, CAP_MSIX)
+        .and_then(|cap| Some((cap, msix_table(config, device, cap)?)));
+    let msi = config.find_capability(address, CAP_MSI);
+    if msix.is_none() && msi.is_none() {
+        return None;
+    }
+
+    let msi_irq = drivers::int_driver().and_then(|x| Some((x, x.alloc_msi()?)));
+    let (int_driver, (irq, message)) = match msi_irq {
+        Some(msi_irq) => msi_irq,
+        None => {
+            warn!(
+                "PCI {}: the interrupt controller can't allocate MSIs",
+                address
+            );
+            return None;
+        }
+    };
+    match (msix, msi) {
+        (Some((cap, table)), _) => enable_msix(config, address, cap, table, message),
+        (None, Some(cap)) => {
+            if enable_msi(config, address, cap, message).is_none() {
+                int_driver.free_msi(irq);
+                return None;
+            }
+        }
+        (None, None) => unreachable!(),
+    }
+    config.enable(address, COMMAND_INTX_DISABLE);
+    info!(
+        "PCI {}: irq {} by {}",
+        address,
+        irq,
+        if msix.is_some() { "MSI-X" } else { "MSI" }
+    );
+    Some(irq)
+}
+
+/// Whether the messages of the device are enabled, its INTx is disabled then.
+pub fn enabled(config: &ConfigSpace, address: PciAddress) -> bool {
+    config.read16(address, COMMAND) & COMMAND_INTX_DISABLE != 0
+}
+
+/// Get the virtual address of the MSI-X table.
+fn msix_table(config: &ConfigSpace, device: &PciDevice, cap: usize) -> Option<usize> {
+    // The BAR index is in the low 3 bits of the table offset.
+    let table = config.read32(device.address, cap + MSIX_TABLE) as usize;
+    let bar = table & 0x7;
+    if bar >= device.bars.len() {
+        return None;
+    }
+    Some((device.memory_bar(bar)? + (table & !0x7)) | VIRT_ADDR_START)
+}
+
+/// Program the message to the vector 0 of the MSI-X table, other vectors are masked.
+fn enable_msix(
+    config: &ConfigSpace,
+    address: PciAddress,
+    cap: usize,
+    table: usize,
+    message: MsiMessage,
+) {
+    let control = config.read16(address, cap + MSIX_CONTROL);
+    // Mask the function while the table is programmed.
+    config.write16(
+        address,
+        cap + MSIX_CONTROL,
+        control | MSIX_CONTROL_ENABLE | MSIX_CONTROL_FUNCTION_MASK,
+    );
+    let table_size = (control & MSIX_CONTROL_TABLE_SIZE) as usize + 1;
+    for index in 0..table_size {
+        let entry = (table + index * MSIX_ENTRY_SIZE) as *mut u32;
+        let vector_control = match index {
+            0 => 0,
+            _ => MSIX_VECTOR_MASKED,
+        };
+        unsafe {
+            entry.write_volatile(message.addr as u32);
+            entry.add(1).write_volatile((message.addr >> 32) as u32);
+            entry.add(2).write_volatile(message.data);
+            entry.add(3).write_volatile(vector_control);
+        }
+    }
+    config.write16(
+        address,
+        cap + MSIX_CONTROL,
+        (control | MSIX_CONTROL_ENABLE) & !MSIX_CONTROL_FUNCTION_MASK,
+    );
+}
+
+/// Program the message to the MSI capability with a single vector.
+fn enable_msi(
+    config: &ConfigSpace,
+    address: PciAddress,
+    cap: usize,
+    message: MsiMessage,
+) -> Option<()> {
+    let control = config.read16(address, cap + MSI_CONTROL);
+    let data = match control & MSI_CONTROL_64BIT {
+        0 if message.addr >> 32 != 0 => {
+            warn!("PCI {}: MSI address {:#x} above 4G", address, message.addr);
+            return None;
+        }
+        0 => MSI_DATA_32,
+        _ => {
+            config.write32(address, cap + MSI_ADDR_HI, (message.addr >> 32) as u32);
+            MSI_DATA_64
+        }
+    };
+    config.write32(address, cap + MSI_ADDR_LO, message.addr as u32);
+    config.write16(address, cap + data, message.data as u16);
+    config.write16(
+        address,
+        cap + MSI_CONTROL,
+        (control & !MSI_CONTROL_MME) | MSI_CONTROL_ENABLE,
+    );
+    Some(())
+}
@@ -0,0 +1,125 @@
+//! Address windows of the PCI host bridge.
+
+use alloc::vec::Vec;
+
+/// The address space of a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowKind {
+    Io,
+    /// 32-bit memory space, used by non-prefetchable BARs.
+    Mem32,
+    /// 64-bit memory space, used by 64-bit prefetchable BARs.
+    Mem64,
+}
+
+/// An address window forwarded by the host bridge.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub kind: WindowKind,
+    /// The start address on the PCI bus.
+    pub pci_addr: u64,
+    /// The start address in the cpu physical address space.
+    pub cpu_addr: u64,
+    pub size: u64,
+    /// The next free PCI address.
+    next: u64,
+}
+
+impl Window {
+    pub fn new(kind: WindowKind, pci_addr: u64, cpu_addr: u64, size: u64) -> Self {
+        // Address 0 means an unassigned BAR.
+        let next = match pci_addr {
+            0 => 0x1000,
+            _ => pci_addr,
+        };
+        Self {
+            kind,
+            pci_addr,
+            cpu_addr,
+            size,
+            next,
+        }
+    }
+
+    /// Allocate a naturally aligned region, returns the PCI address.
+    pub fn alloc(&mut self, size: u64) -> Option<u64> {
+        let addr = self.next.next_multiple_of(size);
+        if addr + size > self.pci_addr + self.size {
+            return None;
+        }
+        self.next = addr + size;
+        Some(addr)
+    }
+
+    /// Align the next free address, used for the bridge windows.
+    pub fn align(&mut self, align: u64) -> u64 {
+        self.next = self.next.next_multiple_of(align);
+        self.next
+    }
+
+    /// Translate the PCI address to the cpu physical address.
+    pub fn translate(&self, pci_addr: u64) -> u64 {
+        pci_addr - self.pci_addr + self.cpu_addr
+    }
+}
+
+/// The address windows of the host bridge.
+pub struct Resources {
+    windows: Vec<Window>,
+}
+
+impl Resources {
+    /// Parse the `ranges` property of the host bridge.
+    ///
+    /// Every entry is the 3-cell PCI address, the cpu address of
+    /// `parent_cells` cells and the 2-cell size.
+    pub fn from_ranges(ranges: &[u8], parent_cells: usize) -> Self {
+        let read_cells = |data: &[u8]| {
+            data.chunks_exact(4).fold(0u64, |acc, x| {
+                acc << 32 | u32::from_be_bytes(x.try_into().unwrap()) as u64
+            })
+        };
+        let entry_size = (3 + parent_cells + 2) * 4;
+        let windows = ranges
+            .chunks_exact(entry_size)
+            .filter_map(|entry| {
+                let flags = read_cells(&entry[0..4]) as u32;
+                let pci_addr = read_cells(&entry[4..12]);
+                let cpu_addr = read_cells(&entry[12..12 + parent_cells * 4]);
+                let size = read_cells(&entry[12 + parent_cells * 4..]);
+                // Space code in bits 24-25 of phys.hi.
+                let kind = match (flags >> 24) & 0x3 {
+                    1 => WindowKind::Io,
+                    2 => WindowKind::Mem32,
+                    3 => WindowKind::Mem64,
+                    _ => return None,
+                };
+                log::info!(
+                    "PCI window {:?}: pci {:#x} cpu {:#x} size {:#x}",
+                    kind,
+                    pci_addr,
+                    cpu_addr,
+                    size
+                );
+                Some(Window::new(kind, pci_addr, cpu_addr, size))
+            })
+            .collect();
+        Self { windows }
+    }
+
+    /// Get the window of the kind.
+    pub fn window(&mut self, kind: WindowKind) -> Option<&mut Window> {
+        self.windows.iter_mut().find(|x| x.kind == kind)
+    }
+
+    /// Allocate a region in the window of the kind, 64-bit regions
+    /// fall back to the 32-bit window. Returns the PCI address and the cpu address.
+    pub fn alloc(&mut self, kind: WindowKind, size: u64) -> Option<(u64, u64)> {
+        let window = match (kind, self.windows.iter().any(|x| x.kind == kind)) {
+            (WindowKind::Mem64, false) => self.window(WindowKind::Mem32)?,
+            _ => self.window(kind)?,
+        };
+        let pci_addr = window.alloc(size)?;
+        Some((pci_addr, window.translate(pci_addr)))
+    }
+}
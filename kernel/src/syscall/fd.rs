@@ -0,0 +1,168 @@
+//! Syscalls of the file descriptors.
+
+use fs_base::{FileType, OpenFlags, StatMode};
+use polyhal::debug_console::DebugConsole;
+use syscalls::Errno;
+
+use super::{signal, user_buf, user_buf_mut, user_path, write_user, SysResult};
+use crate::{
+    fs::pipe,
+    task::{fd::FileItem, task::Task},
+};
+
+/// The `dirfd` of the `*at` syscalls for the current directory.
+const AT_FDCWD: i32 = -100;
+
+const F_DUPFD: usize = 0;
+const F_DUPFD_CLOEXEC: usize = 1030;
+
+/// Read the path of the `*at` syscalls.
+///
+/// There is no working directory, the relative paths are relative to the root.
+fn at_path<'a>(dirfd: usize, path: usize) -> Result<&'a str, Errno> {
+    let path = user_path(path)?;
+    match dirfd as i32 == AT_FDCWD || path.starts_with('/') {
+        true => Ok(path),
+        // The opened directories don't record their paths.
+        false => Err(Errno::ENOTDIR),
+    }
+}
+
+pub fn sys_read(task: &Task, fd: usize, buf: usize, len: usize) -> SysResult {
+    let item = task.fd_table.lock().get(fd)?;
+    item.read(user_buf_mut(buf, len)?)
+}
+
+pub fn sys_write(task: &Task, fd: usize, buf: usize, len: usize) -> SysResult {
+    let buf = user_buf(buf, len)?;
+    let item = task.fd_table.lock().get(fd);
+    match item {
+        Ok(item) => signal::sigpipe(task, item.write(buf)),
+        // The standard output and error are the console.
+        Err(_) if fd == 1 || fd == 2 => {
+            buf.iter().copied().for_each(DebugConsole::putchar);
+            Ok(len)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub fn sys_close(task: &Task, fd: usize) -> SysResult {
+    task.fd_table.lock().close(fd).map(|_| 0)
+}
+
+pub fn sys_dup(task: &Task, fd: usize) -> SysResult {
+    let mut fd_table = task.fd_table.lock();
+    let item = fd_table.get(fd)?;
+    fd_table.alloc_shared(item)
+}
+
+/// Duplicate the descriptor to `new_fd`, the file of `new_fd` is closed.
+///
+/// There is no exec, so O_CLOEXEC is accepted and ignored.
+pub fn sys_dup3(task: &Task, old_fd: usize, new_fd: usize, flags: usize) -> SysResult {
+    if flags & !OpenFlags::CLOEXEC.bits() != 0 || old_fd == new_fd {
+        return Err(Errno::EINVAL);
+    }
+    let mut fd_table = task.fd_table.lock();
+    let item = fd_table.get(old_fd)?;
+    fd_table.set(new_fd, item).map(|_| new_fd)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_dup2(task: &Task, old_fd: usize, new_fd: usize) -> SysResult {
+    match old_fd == new_fd {
+        true => task.fd_table.lock().get(old_fd).map(|_| new_fd),
+        false => sys_dup3(task, old_fd, new_fd, 0),
+    }
+}
+
+/// Only the duplicating commands are supported.
+pub fn sys_fcntl(task: &Task, fd: usize, command: usize, arg: usize) -> SysResult {
+    match command {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            let mut fd_table = task.fd_table.lock();
+            let item = fd_table.get(fd)?;
+            fd_table.alloc_shared_from(item, arg)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+pub fn sys_ioctl(task: &Task, fd: usize, command: usize, arg: usize) -> SysResult {
+    let item = task.fd_table.lock().get(fd);
+    match item {
+        Ok(item) => item.inode.ioctl(command, arg),
+        Err(_) if fd < 3 => Err(Errno::ENOTTY),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn sys_pipe2(task: &Task, fds: usize, flags: usize) -> SysResult {
+    // The packet mode of O_DIRECT isn't supported.
+    if flags & !(OpenFlags::NONBLOCK | OpenFlags::CLOEXEC).bits() != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let flags = OpenFlags::from_bits_truncate(flags);
+    let (rx, tx) = pipe::pipe();
+    let mut fd_table = task.fd_table.lock();
+    let read_fd = fd_table.alloc(FileItem::new_pipe(rx, OpenFlags::RDONLY | flags))?;
+    let write_fd = match fd_table.alloc(FileItem::new_pipe(tx, OpenFlags::WRONLY | flags)) {
+        Ok(fd) => fd,
+        Err(err) => {
+            fd_table.close(read_fd)?;
+            return Err(err);
+        }
+    };
+    drop(fd_table);
+    write_user(fds, [read_fd as i32, write_fd as i32]).map(|_| 0)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_pipe(task: &Task, fds: usize) -> SysResult {
+    sys_pipe2(task, fds, 0)
+}
+
+pub fn sys_openat(task: &Task, dirfd: usize, path: usize, flags: usize, _mode: usize) -> SysResult {
+    let path = at_path(dirfd, path)?;
+    let flags = OpenFlags::from_bits_truncate(flags);
+    let root = crate::FILE_TREE.root();
+    let file = match root.open(path, flags) {
+        // Creating opens the existing file unless it's exclusive.
+        Err(err) if err == Errno::EEXIST && !flags.contains(OpenFlags::EXCL) => {
+            root.open(path, flags.difference(OpenFlags::CREAT))?
+        }
+        result => result?,
+    };
+    let inode = file.inode();
+    let item = match pipe::open_fifo(task, &inode, flags)? {
+        Some(end) => FileItem::new_pipe(end, flags),
+        None => FileItem::new(inode, flags),
+    };
+    task.fd_table.lock().alloc(item)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_open(task: &Task, path: usize, flags: usize, mode: usize) -> SysResult {
+    sys_openat(task, AT_FDCWD as usize, path, flags, mode)
+}
+
+pub fn sys_mknodat(_task: &Task, dirfd: usize, path: usize, mode: usize, _dev: usize) -> SysResult {
+    let path = at_path(dirfd, path)?;
+    match mode as u32 & StatMode::TYPE_MASK.bits() {
+        x if x == 0 || x == StatMode::FILE.bits() => crate::FILE_TREE
+            .root()
+            .open(path, OpenFlags::CREAT | OpenFlags::EXCL)
+            .map(|_| 0),
+        x if x == StatMode::FIFO.bits() => pipe::mkfifo(path).map(|_| 0),
+        x if x == StatMode::SOCKET.bits() => crate::fs::mknod(path, FileType::Socket).map(|_| 0),
+        // The device files only exist in the devfs.
+        x if x == StatMode::CHAR.bits() || x == StatMode::BLOCK.bits() => Err(Errno::EPERM),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_mknod(task: &Task, path: usize, mode: usize, dev: usize) -> SysResult {
+    sys_mknodat(task, AT_FDCWD as usize, path, mode, dev)
+}
@@ -1 +1,169 @@
+//! System call handlers.
+//!
+//! A handler returns [SysResult], the error is returned to the user as the
+//! negative errno. The user memory is mapped in the current page table, so
+//! the handlers access the user pointers directly.
 
+mod fd;
+mod net;
+mod poll;
+mod signal;
+
+use syscalls::{Errno, Sysno};
+
+use crate::task::task::Task;
+
+pub type SysResult = Result<usize, Errno>;
+
+/// Convert the result to the return value of the syscall.
+pub fn into_ret(result: SysResult) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(err) => -(err.into_raw() as isize) as usize,
+    }
+}
+
+/// Handle the syscall, returns None if the syscall isn't implemented.
+pub fn dispatch(task: &Task, sysid: Sysno, args: [usize; 6]) -> Option<SysResult> {
+    let result = match sysid {
+        Sysno::read => fd::sys_read(task, args[0], args[1], args[2]),
+        Sysno::write => fd::sys_write(task, args[0], args[1], args[2]),
+        Sysno::close => fd::sys_close(task, args[0]),
+        Sysno::dup => fd::sys_dup(task, args[0]),
+        Sysno::dup3 => fd::sys_dup3(task, args[0], args[1], args[2]),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::dup2 => fd::sys_dup2(task, args[0], args[1]),
+        Sysno::fcntl => fd::sys_fcntl(task, args[0], args[1], args[2]),
+        Sysno::ioctl => fd::sys_ioctl(task, args[0], args[1], args[2]),
+        Sysno::pipe2 => fd::sys_pipe2(task, args[0], args[1]),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::pipe => fd::sys_pipe(task, args[0]),
+        Sysno::openat => fd::sys_openat(task, args[0], args[1], args[2], args[3]),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::open => fd::sys_open(task, args[0], args[1], args[2]),
+        Sysno::mknodat => fd::sys_mknodat(task, args[0], args[1], args[2], args[3]),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::mknod => fd::sys_mknod(task, args[0], args[1], args[2]),
+        Sysno::socket => net::sys_socket(task, args[0], args[1], args[2]),
+        Sysno::socketpair => net::sys_socketpair(task, args[0], args[1], args[2], args[3]),
+        Sysno::bind => net::sys_bind(task, args[0], args[1], args[2]),
+        Sysno::listen => net::sys_listen(task, args[0], args[1]),
+        Sysno::accept => net::sys_accept4(task, args[0], args[1], args[2], 0),
+        Sysno::accept4 => net::sys_accept4(task, args[0], args[1], args[2], args[3]),
+        Sysno::connect => net::sys_connect(task, args[0], args[1], args[2]),
+        Sysno::sendto => {
+            net::sys_sendto(task, args[0], args[1], args[2], args[3], args[4], args[5])
+        }
+        Sysno::recvfrom => {
+            net::sys_recvfrom(task, args[0], args[1], args[2], args[3], args[4], args[5])
+        }
+        Sysno::sendmsg => net::sys_sendmsg(task, args[0], args[1], args[2]),
+        Sysno::recvmsg => net::sys_recvmsg(task, args[0], args[1], args[2]),
+        Sysno::shutdown => net::sys_shutdown(task, args[0], args[1]),
+        Sysno::getsockname => net::sys_getsockname(task, args[0], args[1], args[2]),
+        Sysno::getpeername => net::sys_getpeername(task, args[0], args[1], args[2]),
+        Sysno::setsockopt => net::sys_setsockopt(task, args[0], args[1], args[2], args[3], args[4]),
+        Sysno::getsockopt => net::sys_getsockopt(task, args[0], args[1], args[2], args[3], args[4]),
+        Sysno::ppoll => poll::sys_ppoll(task, args[0], args[1], args[2], args[3], args[4]),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::poll => poll::sys_poll(task, args[0], args[1], args[2]),
+        Sysno::pselect6 => {
+            poll::sys_pselect6(task, args[0], args[1], args[2], args[3], args[4], args[5])
+        }
+        #[cfg(target_arch = "x86_64")]
+        Sysno::select => poll::sys_select(task, args[0], args[1], args[2], args[3], args[4]),
+        Sysno::epoll_create1 => poll::sys_epoll_create1(task, args[0]),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::epoll_create => poll::sys_epoll_create(task, args[0]),
+        Sysno::epoll_ctl => poll::sys_epoll_ctl(task, args[0], args[1], args[2], args[3]),
+        Sysno::epoll_pwait => {
+            poll::sys_epoll_pwait(task, args[0], args[1], args[2], args[3], args[4], args[5])
+        }
+        #[cfg(target_arch = "x86_64")]
+        Sysno::epoll_wait => poll::sys_epoll_wait(task, args[0], args[1], args[2], args[3]),
+        Sysno::rt_sigaction => signal::sys_rt_sigaction(task, args[0], args[1], args[2], args[3]),
+        Sysno::rt_sigprocmask => {
+            signal::sys_rt_sigprocmask(task, args[0], args[1], args[2], args[3])
+        }
+        Sysno::rt_sigreturn => signal::sys_rt_sigreturn(task),
+        Sysno::kill => signal::sys_kill(task, args[0], args[1]),
+        Sysno::tkill => signal::sys_tkill(task, args[0], args[1]),
+        Sysno::tgkill => signal::sys_tgkill(task, args[0], args[1], args[2]),
+        Sysno::sigaltstack => signal::sys_sigaltstack(task, args[0], args[1]),
+        Sysno::rt_sigsuspend => signal::sys_rt_sigsuspend(task, args[0], args[1]),
+        Sysno::rt_sigtimedwait => {
+            signal::sys_rt_sigtimedwait(task, args[0], args[1], args[2], args[3])
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Whether the syscall interrupted by a signal is restarted, the waits for the events aren't.
+pub fn restartable(sysid: Sysno) -> bool {
+    match sysid {
+        Sysno::ppoll
+        | Sysno::pselect6
+        | Sysno::epoll_pwait
+        | Sysno::rt_sigsuspend
+        | Sysno::rt_sigtimedwait
+        | Sysno::rt_sigreturn => false,
+        #[cfg(target_arch = "x86_64")]
+        Sysno::poll | Sysno::select | Sysno::epoll_wait => false,
+        _ => true,
+    }
+}
+
+/// Get the user buffer.
+fn user_buf<'a>(ptr: usize, len: usize) -> Result<&'a [u8], Errno> {
+    match (ptr, len) {
+        (_, 0) => Ok(&[]),
+        (0, _) => Err(Errno::EFAULT),
+        _ => Ok(unsafe { core::slice::from_raw_parts(ptr as *const u8, len) }),
+    }
+}
+
+/// Get the mutable user buffer.
+fn user_buf_mut<'a>(ptr: usize, len: usize) -> Result<&'a mut [u8], Errno> {
+    match (ptr, len) {
+        (_, 0) => Ok(&mut []),
+        (0, _) => Err(Errno::EFAULT),
+        _ => Ok(unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, len) }),
+    }
+}
+
+/// Max bytes of a path.
+const PATH_MAX: usize = 4096;
+
+/// Read the nul-terminated path from the user pointer.
+fn user_path<'a>(ptr: usize) -> Result<&'a str, Errno> {
+    if ptr == 0 {
+        return Err(Errno::EFAULT);
+    }
+    let len = (0..PATH_MAX)
+        .find(|x| unsafe { *((ptr + x) as *const u8) } == 0)
+        .ok_or(Errno::ENAMETOOLONG)?;
+    match len {
+        0 => Err(Errno::ENOENT),
+        _ => core::str::from_utf8(user_buf(ptr, len)?).map_err(|_| Errno::EINVAL),
+    }
+}
+
+/// Read a value from the user pointer.
+fn read_user<T: Copy>(ptr: usize) -> Result<T, Errno> {
+    match ptr {
+        0 => Err(Errno::EFAULT),
+        _ => Ok(unsafe { (ptr as *const T).read_unaligned() }),
+    }
+}
+
+/// Write the value to the user pointer.
+fn write_user<T>(ptr: usize, value: T) -> Result<(), Errno> {
+    match ptr {
+        0 => Err(Errno::EFAULT),
+        _ => {
+            unsafe { (ptr as *mut T).write_unaligned(value) };
+            Ok(())
+        }
+    }
+}
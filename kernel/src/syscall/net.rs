@@ -0,0 +1,506 @@
+//! Socket syscalls.
+//!
+//! AF_INET sockets use `sockaddr_in`, AF_INET6 sockets use `sockaddr_in6`,
+//! AF_UNIX sockets use `sockaddr_un`, AF_PACKET sockets use `sockaddr_ll`.
+//! AF_UNIX sockets pass the files by SCM_RIGHTS control messages.
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::mem::size_of;
+use fs_base::OpenFlags;
+use net::{IpAddr, Ipv4Addr, Ipv6Addr, LinkAddr, MacAddr, SocketAddr};
+use syscalls::Errno;
+
+use super::{read_user, signal, user_buf, user_buf_mut, write_user, SysResult};
+use crate::{
+    net::{
+        ioctl::hardware_type,
+        socket::{SockAddr, Socket, AF_INET, AF_INET6, AF_PACKET, AF_UNIX, SOL_SOCKET},
+        unix::{Ucred, UnixAddr, MAX_RIGHTS},
+    },
+    task::{fd::FileItem, task::Task},
+};
+
+/// The size of `sockaddr_in`.
+const SOCKADDR_IN_LEN: usize = 16;
+/// The size of `sockaddr_in6`.
+const SOCKADDR_IN6_LEN: usize = 28;
+/// The size of `sockaddr_ll`.
+const SOCKADDR_LL_LEN: usize = 20;
+/// The size of `sockaddr_un`.
+const SOCKADDR_UN_LEN: usize = 110;
+
+/// The type is in the low bits of the type argument, the flags are in the others.
+const SOCK_TYPE_MASK: usize = 0xf;
+
+const MSG_PEEK: usize = 0x2;
+const MSG_CTRUNC: usize = 0x8;
+const MSG_TRUNC: usize = 0x20;
+const MSG_DONTWAIT: usize = 0x40;
+const MSG_NOSIGNAL: usize = 0x4000;
+
+const SCM_RIGHTS: i32 = 1;
+
+/// The Linux `msghdr`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MsgHdr {
+    name: usize,
+    name_len: u32,
+    iov: usize,
+    iov_len: usize,
+    control: usize,
+    control_len: usize,
+    flags: i32,
+}
+
+/// The Linux `iovec`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+/// The Linux `cmsghdr`, the data follows it.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CmsgHdr {
+    len: usize,
+    level: i32,
+    cmsg_type: i32,
+}
+
+/// Align the length of the control message.
+fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+/// Read the socket address of the domain from the user.
+fn read_sockaddr(domain: usize, addr: usize, len: usize) -> Result<SockAddr, Errno> {
+    if len < 2 {
+        return Err(Errno::EINVAL);
+    }
+    let data = user_buf(addr, len.min(SOCKADDR_UN_LEN))?;
+    if u16::from_ne_bytes([data[0], data[1]]) as usize != domain {
+        return Err(Errno::EAFNOSUPPORT);
+    }
+    match domain {
+        AF_INET if len < SOCKADDR_IN_LEN => Err(Errno::EINVAL),
+        AF_INET => Ok(SockAddr::Inet(SocketAddr::new(
+            Ipv4Addr::from_bytes(&data[4..8]),
+            u16::from_be_bytes([data[2], data[3]]),
+        ))),
+        // The flow information and the scope are ignored.
+        AF_INET6 if len < SOCKADDR_IN6_LEN => Err(Errno::EINVAL),
+        AF_INET6 => Ok(SockAddr::Inet(SocketAddr::new(
+            Ipv6Addr::from_bytes(&data[8..24]),
+            u16::from_be_bytes([data[2], data[3]]),
+        ))),
+        AF_PACKET if len < SOCKADDR_LL_LEN => Err(Errno::EINVAL),
+        AF_PACKET => {
+            // The interface index starts from 1, zero is any interface.
+            let ifindex = i32::from_ne_bytes(data[4..8].try_into().unwrap());
+            Ok(SockAddr::Link(LinkAddr {
+                protocol: u16::from_be_bytes([data[2], data[3]]),
+                iface: (ifindex > 0).then_some(ifindex.wrapping_sub(1) as usize),
+                mac: MacAddr(data[12..18].try_into().unwrap()),
+                ..Default::default()
+            }))
+        }
+        _ if len > SOCKADDR_UN_LEN => Err(Errno::EINVAL),
+        _ => {
+            let path = &data[2..];
+            let addr = match path.first() {
+                None => UnixAddr::Unnamed,
+                Some(0) => UnixAddr::Abstract(path[1..].to_vec()),
+                Some(_) => {
+                    let len = path.iter().position(|x| *x == 0).unwrap_or(path.len());
+                    let path = core::str::from_utf8(&path[..len]).map_err(|_| Errno::EINVAL)?;
+                    UnixAddr::Path(String::from(path))
+                }
+            };
+            Ok(SockAddr::Unix(addr))
+        }
+    }
+}
+
+/// Write the socket address to the user buffer of `*addr_len` bytes.
+///
+/// The address is truncated to the buffer, `*addr_len` is set to the full length.
+fn write_sockaddr(addr: usize, addr_len: usize, sockaddr: &SockAddr) -> Result<(), Errno> {
+    if addr == 0 {
+        return Ok(());
+    }
+    let data = match sockaddr {
+        SockAddr::Inet(sockaddr) => match sockaddr.addr {
+            IpAddr::V4(addr) => {
+                let mut data = vec![0u8; SOCKADDR_IN_LEN];
+                data[0..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+                data[2..4].copy_from_slice(&sockaddr.port.to_be_bytes());
+                data[4..8].copy_from_slice(&addr.0);
+                data
+            }
+            IpAddr::V6(addr) => {
+                let mut data = vec![0u8; SOCKADDR_IN6_LEN];
+                data[0..2].copy_from_slice(&(AF_INET6 as u16).to_ne_bytes());
+                data[2..4].copy_from_slice(&sockaddr.port.to_be_bytes());
+                data[8..24].copy_from_slice(&addr.0);
+                data
+            }
+        },
+        SockAddr::Unix(sockaddr) => {
+            let mut data = (AF_UNIX as u16).to_ne_bytes().to_vec();
+            match sockaddr {
+                UnixAddr::Unnamed => {}
+                UnixAddr::Path(path) => {
+                    data.extend_from_slice(path.as_bytes());
+                    data.push(0);
+                }
+                UnixAddr::Abstract(name) => {
+                    data.push(0);
+                    data.extend_from_slice(name);
+                }
+            }
+            data
+        }
+        SockAddr::Link(sockaddr) => {
+            let hatype = sockaddr.iface.and_then(|index| {
+                crate::net::stack().with_interfaces(|ifaces| ifaces.get(index).map(hardware_type))
+            });
+            let ifindex = sockaddr.iface.map_or(0, |x| x as i32 + 1);
+            let mut data = vec![0u8; SOCKADDR_LL_LEN];
+            data[0..2].copy_from_slice(&(AF_PACKET as u16).to_ne_bytes());
+            data[2..4].copy_from_slice(&sockaddr.protocol.to_be_bytes());
+            data[4..8].copy_from_slice(&ifindex.to_ne_bytes());
+            data[8..10].copy_from_slice(&hatype.unwrap_or(0).to_ne_bytes());
+            data[10] = sockaddr.pkttype as u8;
+            data[11] = sockaddr.mac.0.len() as u8;
+            data[12..18].copy_from_slice(&sockaddr.mac.0);
+            data
+        }
+    };
+    let len = read_user::<u32>(addr_len)? as usize;
+    let copy_len = len.min(data.len());
+    user_buf_mut(addr, copy_len)?.copy_from_slice(&data[..copy_len]);
+    write_user(addr_len, data.len() as u32)
+}
+
+/// Get the socket of the file descriptor.
+fn socket_of(task: &Task, fd: usize) -> Result<Arc<Socket>, Errno> {
+    task.fd_table
+        .lock()
+        .get(fd)?
+        .socket
+        .clone()
+        .ok_or(Errno::ENOTSOCK)
+}
+
+/// Get the file flags of the SOCK_NONBLOCK and SOCK_CLOEXEC flags.
+fn sock_flags(flags: usize) -> OpenFlags {
+    OpenFlags::RDWR
+        | OpenFlags::from_bits_truncate(flags) & (OpenFlags::NONBLOCK | OpenFlags::CLOEXEC)
+}
+
+/// The credentials of the task, every task runs as root.
+fn cred_of(task: &Task) -> Ucred {
+    Ucred {
+        pid: task.pid as i32,
+        uid: 0,
+        gid: 0,
+    }
+}
+
+pub fn sys_socket(task: &Task, domain: usize, sock_type: usize, protocol: usize) -> SysResult {
+    let socket = match domain {
+        AF_INET | AF_INET6 => Socket::new(domain, sock_type & SOCK_TYPE_MASK, protocol)?,
+        AF_PACKET => Socket::new_packet(sock_type & SOCK_TYPE_MASK, protocol)?,
+        AF_UNIX => Socket::new_unix(sock_type & SOCK_TYPE_MASK, protocol, cred_of(task))?,
+        _ => return Err(Errno::EAFNOSUPPORT),
+    };
+    let item = FileItem::new_socket(Arc::new(socket), sock_flags(sock_type));
+    task.fd_table.lock().alloc(item)
+}
+
+pub fn sys_socketpair(
+    task: &Task,
+    domain: usize,
+    sock_type: usize,
+    protocol: usize,
+    sv: usize,
+) -> SysResult {
+    if domain != AF_UNIX {
+        return Err(Errno::EOPNOTSUPP);
+    }
+    let (a, b) = Socket::pair(sock_type & SOCK_TYPE_MASK, protocol, cred_of(task))?;
+    let mut fd_table = task.fd_table.lock();
+    let fd_a = fd_table.alloc(FileItem::new_socket(Arc::new(a), sock_flags(sock_type)))?;
+    let fd_b = match fd_table.alloc(FileItem::new_socket(Arc::new(b), sock_flags(sock_type))) {
+        Ok(fd) => fd,
+        Err(err) => {
+            fd_table.close(fd_a)?;
+            return Err(err);
+        }
+    };
+    drop(fd_table);
+    write_user(sv, [fd_a as i32, fd_b as i32]).map(|_| 0)
+}
+
+pub fn sys_bind(task: &Task, fd: usize, addr: usize, addr_len: usize) -> SysResult {
+    let socket = socket_of(task, fd)?;
+    let addr = read_sockaddr(socket.domain(), addr, addr_len)?;
+    socket.bind(&addr).map(|_| 0)
+}
+
+pub fn sys_listen(task: &Task, fd: usize, backlog: usize) -> SysResult {
+    socket_of(task, fd)?.listen(backlog).map(|_| 0)
+}
+
+pub fn sys_accept4(
+    task: &Task,
+    fd: usize,
+    addr: usize,
+    addr_len: usize,
+    flags: usize,
+) -> SysResult {
+    let (socket, peer) = socket_of(task, fd)?.accept()?;
+    write_sockaddr(addr, addr_len, &peer)?;
+    let item = FileItem::new_socket(Arc::new(socket), sock_flags(flags));
+    task.fd_table.lock().alloc(item)
+}
+
+pub fn sys_connect(task: &Task, fd: usize, addr: usize, addr_len: usize) -> SysResult {
+    let socket = socket_of(task, fd)?;
+    let addr = read_sockaddr(socket.domain(), addr, addr_len)?;
+    socket.connect(&addr).map(|_| 0)
+}
+
+/// Raise SIGPIPE on a broken connection unless MSG_NOSIGNAL is given.
+fn sent(task: &Task, flags: usize, result: SysResult) -> SysResult {
+    match flags & MSG_NOSIGNAL {
+        0 => signal::sigpipe(task, result),
+        _ => result,
+    }
+}
+
+pub fn sys_sendto(
+    task: &Task,
+    fd: usize,
+    buf: usize,
+    len: usize,
+    flags: usize,
+    addr: usize,
+    addr_len: usize,
+) -> SysResult {
+    let socket = socket_of(task, fd)?;
+    let addr = match addr {
+        0 => None,
+        _ => Some(read_sockaddr(socket.domain(), addr, addr_len)?),
+    };
+    let result = socket.send_to(
+        user_buf(buf, len)?,
+        addr.as_ref(),
+        flags & MSG_DONTWAIT != 0,
+    );
+    sent(task, flags, result)
+}
+
+pub fn sys_recvfrom(
+    task: &Task,
+    fd: usize,
+    buf: usize,
+    len: usize,
+    flags: usize,
+    addr: usize,
+    addr_len: usize,
+) -> SysResult {
+    if flags & MSG_PEEK != 0 {
+        return Err(Errno::EOPNOTSUPP);
+    }
+    let socket = socket_of(task, fd)?;
+    let (len, full_len, src) =
+        socket.recv_from(user_buf_mut(buf, len)?, flags & MSG_DONTWAIT != 0)?;
+    if let Some(src) = src {
+        write_sockaddr(addr, addr_len, &src)?;
+    }
+    match flags & MSG_TRUNC {
+        0 => Ok(len),
+        _ => Ok(full_len),
+    }
+}
+
+/// Read the iovecs of the message.
+fn read_iovecs(msg: &MsgHdr) -> Result<Vec<IoVec>, Errno> {
+    (0..msg.iov_len)
+        .map(|i| read_user::<IoVec>(msg.iov + i * size_of::<IoVec>()))
+        .collect()
+}
+
+/// Get the files passed by the SCM_RIGHTS control messages.
+fn read_rights(task: &Task, msg: &MsgHdr) -> Result<Vec<Arc<FileItem>>, Errno> {
+    let mut rights = Vec::new();
+    let mut offset = 0;
+    while offset + size_of::<CmsgHdr>() <= msg.control_len {
+        let cmsg = read_user::<CmsgHdr>(msg.control + offset)?;
+        if cmsg.len < size_of::<CmsgHdr>() || offset + cmsg.len > msg.control_len {
+            return Err(Errno::EINVAL);
+        }
+        if cmsg.level as usize != SOL_SOCKET || cmsg.cmsg_type != SCM_RIGHTS {
+            return Err(Errno::EINVAL);
+        }
+        let data = user_buf(
+            msg.control + offset + size_of::<CmsgHdr>(),
+            cmsg.len - size_of::<CmsgHdr>(),
+        )?;
+        let fd_table = task.fd_table.lock();
+        for fd in data.chunks_exact(size_of::<i32>()) {
+            let fd = i32::from_ne_bytes(fd.try_into().unwrap());
+            rights.push(fd_table.get(fd as usize)?);
+        }
+        offset += cmsg_align(cmsg.len);
+    }
+    match rights.len() > MAX_RIGHTS {
+        true => Err(Errno::EINVAL),
+        false => Ok(rights),
+    }
+}
+
+/// Put the passed files in the fd table and write their descriptors to the
+/// control buffer, the files exceeding the buffer are closed.
+///
+/// Returns the length of the control messages and whether they are truncated.
+fn write_rights(
+    task: &Task,
+    msg: &MsgHdr,
+    rights: Vec<Arc<FileItem>>,
+) -> Result<(usize, bool), Errno> {
+    if rights.is_empty() {
+        return Ok((0, false));
+    }
+    let room = msg.control_len.saturating_sub(size_of::<CmsgHdr>()) / size_of::<i32>();
+    let total = rights.len();
+    let mut fds = Vec::new();
+    let mut fd_table = task.fd_table.lock();
+    for item in rights.into_iter().take(room) {
+        match fd_table.alloc_shared(item) {
+            Ok(fd) => fds.push(fd as i32),
+            Err(_) => break,
+        }
+    }
+    drop(fd_table);
+    if fds.is_empty() {
+        return Ok((0, true));
+    }
+    let len = size_of::<CmsgHdr>() + fds.len() * size_of::<i32>();
+    let cmsg = CmsgHdr {
+        len,
+        level: SOL_SOCKET as i32,
+        cmsg_type: SCM_RIGHTS,
+    };
+    write_user(msg.control, cmsg)?;
+    let data = user_buf_mut(
+        msg.control + size_of::<CmsgHdr>(),
+        len - size_of::<CmsgHdr>(),
+    )?;
+    for (buf, fd) in data.chunks_exact_mut(size_of::<i32>()).zip(&fds) {
+        buf.copy_from_slice(&fd.to_ne_bytes());
+    }
+    Ok((cmsg_align(len).min(msg.control_len), fds.len() < total))
+}
+
+pub fn sys_sendmsg(task: &Task, fd: usize, msg: usize, flags: usize) -> SysResult {
+    let socket = socket_of(task, fd)?;
+    let msg = read_user::<MsgHdr>(msg)?;
+    let addr = match msg.name {
+        0 => None,
+        _ => Some(read_sockaddr(
+            socket.domain(),
+            msg.name,
+            msg.name_len as usize,
+        )?),
+    };
+    let rights = read_rights(task, &msg)?;
+    let mut data = Vec::new();
+    for iov in read_iovecs(&msg)? {
+        data.extend_from_slice(user_buf(iov.base, iov.len)?);
+    }
+    let result = socket.send_msg(&data, addr.as_ref(), rights, flags & MSG_DONTWAIT != 0);
+    sent(task, flags, result)
+}
+
+pub fn sys_recvmsg(task: &Task, fd: usize, msg_ptr: usize, flags: usize) -> SysResult {
+    if flags & MSG_PEEK != 0 {
+        return Err(Errno::EOPNOTSUPP);
+    }
+    let socket = socket_of(task, fd)?;
+    let mut msg = read_user::<MsgHdr>(msg_ptr)?;
+    let iovecs = read_iovecs(&msg)?;
+    let mut data = vec![0u8; iovecs.iter().map(|x| x.len).sum()];
+    let received = socket.recv_msg(&mut data, flags & MSG_DONTWAIT != 0)?;
+    let len = received.len;
+    let mut copied = 0;
+    for iov in iovecs {
+        let count = iov.len.min(len - copied);
+        user_buf_mut(iov.base, count)?.copy_from_slice(&data[copied..copied + count]);
+        copied += count;
+    }
+    if let (Some(src), true) = (&received.src, msg.name != 0) {
+        let mut name_len = msg.name_len;
+        write_sockaddr(msg.name, &mut name_len as *mut u32 as usize, src)?;
+        msg.name_len = name_len;
+    }
+    let (control_len, ctrunc) = write_rights(task, &msg, received.rights)?;
+    msg.control_len = control_len;
+    msg.flags = 0;
+    if received.full_len > len {
+        msg.flags |= MSG_TRUNC as i32;
+    }
+    if ctrunc {
+        msg.flags |= MSG_CTRUNC as i32;
+    }
+    write_user(msg_ptr, msg)?;
+    match flags & MSG_TRUNC {
+        0 => Ok(len),
+        _ => Ok(received.full_len),
+    }
+}
+
+pub fn sys_shutdown(task: &Task, fd: usize, how: usize) -> SysResult {
+    socket_of(task, fd)?.shutdown(how).map(|_| 0)
+}
+
+pub fn sys_getsockname(task: &Task, fd: usize, addr: usize, addr_len: usize) -> SysResult {
+    let local = socket_of(task, fd)?.local_addr();
+    write_sockaddr(addr, addr_len, &local).map(|_| 0)
+}
+
+pub fn sys_getpeername(task: &Task, fd: usize, addr: usize, addr_len: usize) -> SysResult {
+    let peer = socket_of(task, fd)?.peer_addr()?;
+    write_sockaddr(addr, addr_len, &peer).map(|_| 0)
+}
+pub fn sys_setsockopt(
+    task: &Task,
+    fd: usize,
+    level: usize,
+    name: usize,
+    value: usize,
+    len: usize,
+) -> SysResult {
+    let socket = socket_of(task, fd)?;
+    socket
+        .setsockopt(level, name, user_buf(value, len)?)
+        .map(|_| 0)
+}
+
+pub fn sys_getsockopt(
+    task: &Task,
+    fd: usize,
+    level: usize,
+    name: usize,
+    value: usize,
+    len_ptr: usize,
+) -> SysResult {
+    let data = socket_of(task, fd)?.getsockopt(level, name)?;
+    let len = (read_user::<u32>(len_ptr)? as usize).min(data.len());
+    user_buf_mut(value, len)?.copy_from_slice(&data[..len]);
+    write_user(len_ptr, len as u32).map(|_| 0)
+}
@@ -0,0 +1,362 @@
+//! Syscalls waiting for the events of the files, poll, select and epoll.
+//!
+//! The waiting task adds its waiter to the files and polls them again
+//! whenever a file wakes it up. A deliverable signal ends the wait with
+//! EINTR, the signal masks of `ppoll`, `pselect6` and `epoll_pwait` replace
+//! the mask of the task while waiting.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use drivers_base::DSched;
+use fs_base::{INodeInterface, OpenFlags, PollEvent, TimeSpec, Waiter};
+use syscalls::Errno;
+
+use super::{read_user, signal, user_buf_mut, write_user, SysResult};
+use crate::{
+    drivers::DriverSched,
+    fs::epoll::{poll_file, EpollEvent, EventPoll, EPOLL_CTL_DEL},
+    task::{
+        fd::{FileItem, FD_LIMIT},
+        task::Task,
+    },
+};
+
+/// The `struct pollfd`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+/// The waiter of a waiting syscall, a file sets the flag when its events may change.
+#[derive(Default)]
+struct PollWaiter(AtomicBool);
+
+impl Waiter for PollWaiter {
+    fn wake(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Call `ready` until it returns a nonzero count or the deadline passes.
+///
+/// `ready` adds the waiter to the files, it's called again after a file wakes up the waiter.
+/// Returns EINTR once a signal of the task is deliverable.
+fn wait_ready(
+    task: &Task,
+    deadline: Option<Duration>,
+    mut ready: impl FnMut(&Arc<dyn Waiter>) -> SysResult,
+) -> SysResult {
+    let flag = Arc::new(PollWaiter::default());
+    let waiter: Arc<dyn Waiter> = flag.clone();
+    loop {
+        flag.0.store(false, Ordering::Release);
+        let count = ready(&waiter)?;
+        if count > 0 {
+            return Ok(count);
+        }
+        while !flag.0.load(Ordering::Acquire) {
+            if deadline.is_some_and(|x| DriverSched::now() >= x) {
+                return Ok(0);
+            }
+            if task.signal.lock().has_deliverable() {
+                return Err(Errno::EINTR);
+            }
+            // The sockets become ready by polling the network stack.
+            crate::net::poll();
+            DriverSched::sleep();
+        }
+    }
+}
+
+/// Get the deadline of the timeout, None waits forever.
+pub(super) fn deadline(timeout: Option<Duration>) -> Option<Duration> {
+    timeout.and_then(|x| DriverSched::now().checked_add(x))
+}
+
+/// Read the `struct timespec` timeout, a null pointer waits forever.
+pub(super) fn read_timespec(ptr: usize) -> Result<Option<Duration>, Errno> {
+    if ptr == 0 {
+        return Ok(None);
+    }
+    let timespec = read_user::<TimeSpec>(ptr)?;
+    if timespec.sec as i64 >= 0 && timespec.nsec < 1_000_000_000 {
+        Ok(Some(Duration::new(timespec.sec, timespec.nsec as u32)))
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
+
+/// Convert the timeout in milliseconds, a negative timeout waits forever.
+fn millis(timeout: usize) -> Option<Duration> {
+    match timeout as i32 {
+        timeout if timeout < 0 => None,
+        timeout => Some(Duration::from_millis(timeout as u64)),
+    }
+}
+
+/// Get the opened files of the descriptors.
+fn files_of(task: &Task, fds: impl Iterator<Item = usize>) -> Vec<Option<Arc<FileItem>>> {
+    let fd_table = task.fd_table.lock();
+    fds.map(|fd| fd_table.get(fd).ok()).collect()
+}
+
+/// Poll the file of the descriptor and add the waiter to it.
+///
+/// Returns None if the descriptor isn't opened.
+fn poll_fd(
+    file: Option<&Arc<FileItem>>,
+    fd: usize,
+    events: PollEvent,
+    waiter: &Arc<dyn Waiter>,
+) -> Option<PollEvent> {
+    match file {
+        Some(file) => {
+            file.inode.poll_wait(waiter);
+            Some(poll_file(file, events))
+        }
+        // The standard output and error are the console, they are always writable.
+        None if fd == 1 || fd == 2 => Some(events & (PollEvent::POLLOUT | PollEvent::POLLWRNORM)),
+        None => None,
+    }
+}
+
+fn do_poll(task: &Task, fds: usize, nfds: usize, timeout: Option<Duration>) -> SysResult {
+    if nfds > FD_LIMIT {
+        return Err(Errno::EINVAL);
+    }
+    let ptr = |index: usize| fds + index * size_of::<PollFd>();
+    let mut pollfds = (0..nfds)
+        .map(|index| read_user::<PollFd>(ptr(index)))
+        .collect::<Result<Vec<_>, _>>()?;
+    // The negative descriptors are ignored.
+    let files = files_of(task, pollfds.iter().map(|x| x.fd.max(0) as usize));
+    let count = wait_ready(task, deadline(timeout), |waiter| {
+        let mut count = 0;
+        for (pollfd, file) in pollfds.iter_mut().zip(files.iter()) {
+            let events = PollEvent::from_bits_truncate(pollfd.events as u16);
+            let revents = match pollfd.fd {
+                fd if fd < 0 => PollEvent::NONE,
+                fd => poll_fd(file.as_ref(), fd as usize, events, waiter)
+                    .unwrap_or(PollEvent::POLLNVAL),
+            };
+            pollfd.revents = revents.bits() as i16;
+            count += !revents.is_empty() as usize;
+        }
+        Ok(count)
+    })?;
+    for (index, pollfd) in pollfds.into_iter().enumerate() {
+        write_user(ptr(index), pollfd)?;
+    }
+    Ok(count)
+}
+
+/// Replace the signal mask while waiting, a null mask keeps the current one.
+fn wait_mask(task: &Task, mask: usize, sigset_size: usize) -> Result<(), Errno> {
+    match mask {
+        0 => Ok(()),
+        _ => signal::set_wait_mask(task, mask, sigset_size),
+    }
+}
+
+pub fn sys_ppoll(
+    task: &Task,
+    fds: usize,
+    nfds: usize,
+    timeout: usize,
+    mask: usize,
+    sigset_size: usize,
+) -> SysResult {
+    let timeout = read_timespec(timeout)?;
+    wait_mask(task, mask, sigset_size)?;
+    do_poll(task, fds, nfds, timeout)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_poll(task: &Task, fds: usize, nfds: usize, timeout: usize) -> SysResult {
+    do_poll(task, fds, nfds, millis(timeout))
+}
+
+/// Bits of an `fd_set`.
+const FD_SET_BITS: usize = usize::BITS as usize;
+
+/// The events of the readable, writable and exceptional sets of select.
+fn select_events() -> [PollEvent; 3] {
+    [
+        PollEvent::POLLIN | PollEvent::POLLRDNORM | PollEvent::POLLHUP | PollEvent::POLLERR,
+        PollEvent::POLLOUT | PollEvent::POLLWRNORM | PollEvent::POLLERR,
+        PollEvent::POLLPRI,
+    ]
+}
+
+fn do_select(task: &Task, nfds: usize, sets: [usize; 3], timeout: Option<Duration>) -> SysResult {
+    if nfds > FD_LIMIT {
+        return Err(Errno::EINVAL);
+    }
+    let words = nfds.div_ceil(FD_SET_BITS);
+    let read_set = |ptr: usize| match ptr {
+        0 => Ok(vec![0; words]),
+        _ => (0..words)
+            .map(|index| read_user::<usize>(ptr + index * size_of::<usize>()))
+            .collect::<Result<Vec<_>, _>>(),
+    };
+    let input = [read_set(sets[0])?, read_set(sets[1])?, read_set(sets[2])?];
+    let is_set = |set: &[usize], fd: usize| set[fd / FD_SET_BITS] & (1 << (fd % FD_SET_BITS)) != 0;
+    let fds: Vec<usize> = (0..nfds)
+        .filter(|fd| input.iter().any(|set| is_set(set, *fd)))
+        .collect();
+    let files = files_of(task, fds.iter().copied());
+    let events = select_events();
+    let mut output = [vec![0; words], vec![0; words], vec![0; words]];
+    let count = wait_ready(task, deadline(timeout), |waiter| {
+        let mut count = 0;
+        output.iter_mut().for_each(|set| set.fill(0));
+        for (fd, file) in fds.iter().zip(files.iter()) {
+            let requested = (0..3)
+                .filter(|x| is_set(&input[*x], *fd))
+                .fold(PollEvent::NONE, |acc, x| acc | events[x].clone());
+            let revents = poll_fd(file.as_ref(), *fd, requested, waiter).ok_or(Errno::EBADF)?;
+            for (index, set) in output.iter_mut().enumerate() {
+                if is_set(&input[index], *fd) && revents.intersects(events[index].clone()) {
+                    set[fd / FD_SET_BITS] |= 1 << (fd % FD_SET_BITS);
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    })?;
+    for (ptr, set) in sets.into_iter().zip(output.iter()) {
+        for (index, word) in set.iter().enumerate().filter(|_| ptr != 0) {
+            write_user(ptr + index * size_of::<usize>(), *word)?;
+        }
+    }
+    Ok(count)
+}
+
+pub fn sys_pselect6(
+    task: &Task,
+    nfds: usize,
+    readfds: usize,
+    writefds: usize,
+    exceptfds: usize,
+    timeout: usize,
+    sigmask: usize,
+) -> SysResult {
+    let timeout = read_timespec(timeout)?;
+    // The mask is passed by the pointer and the size of the signal set.
+    if sigmask != 0 {
+        let [mask, sigset_size] = read_user::<[usize; 2]>(sigmask)?;
+        wait_mask(task, mask, sigset_size)?;
+    }
+    do_select(task, nfds, [readfds, writefds, exceptfds], timeout)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_select(
+    task: &Task,
+    nfds: usize,
+    readfds: usize,
+    writefds: usize,
+    exceptfds: usize,
+    timeout: usize,
+) -> SysResult {
+    // The timeout is a `struct timeval`.
+    let timeout = match timeout {
+        0 => None,
+        ptr => {
+            let [sec, usec] = read_user::<[i64; 2]>(ptr)?;
+            if sec < 0 || !(0..1_000_000).contains(&usec) {
+                return Err(Errno::EINVAL);
+            }
+            Some(Duration::new(sec as u64, usec as u32 * 1000))
+        }
+    };
+    do_select(task, nfds, [readfds, writefds, exceptfds], timeout)
+}
+
+pub fn sys_epoll_create1(task: &Task, flags: usize) -> SysResult {
+    let flags = OpenFlags::from_bits_truncate(flags);
+    if flags.bits() & !OpenFlags::CLOEXEC.bits() != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let item = FileItem::new_epoll(EventPoll::new(), OpenFlags::RDWR | flags);
+    task.fd_table.lock().alloc(item)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_epoll_create(task: &Task, size: usize) -> SysResult {
+    match size as i32 {
+        size if size <= 0 => Err(Errno::EINVAL),
+        _ => sys_epoll_create1(task, 0),
+    }
+}
+
+/// Get the epoll instance of the descriptor.
+fn epoll_of(task: &Task, epfd: usize) -> Result<Arc<EventPoll>, Errno> {
+    task.fd_table
+        .lock()
+        .get(epfd)?
+        .epoll
+        .clone()
+        .ok_or(Errno::EINVAL)
+}
+
+pub fn sys_epoll_ctl(task: &Task, epfd: usize, op: usize, fd: usize, event: usize) -> SysResult {
+    let epoll = epoll_of(task, epfd)?;
+    let file = task.fd_table.lock().get(fd)?;
+    if epfd == fd {
+        return Err(Errno::EINVAL);
+    }
+    // The files without the poll events can't be watched.
+    if file.inode.poll(PollEvent::NONE).is_err() {
+        return Err(Errno::EPERM);
+    }
+    let event = match op {
+        EPOLL_CTL_DEL => None,
+        _ => Some(read_user::<EpollEvent>(event)?),
+    };
+    epoll.ctl(op, fd, &file, event).map(|_| 0)
+}
+
+pub fn sys_epoll_pwait(
+    task: &Task,
+    epfd: usize,
+    events: usize,
+    max_events: usize,
+    timeout: usize,
+    mask: usize,
+    sigset_size: usize,
+) -> SysResult {
+    let max_events = match max_events as i32 {
+        max if max <= 0 => return Err(Errno::EINVAL),
+        max => max as usize,
+    };
+    user_buf_mut(events, max_events * size_of::<EpollEvent>())?;
+    let epoll = epoll_of(task, epfd)?;
+    wait_mask(task, mask, sigset_size)?;
+    wait_ready(task, deadline(millis(timeout)), |waiter| {
+        epoll.poll_wait(waiter);
+        let ready = epoll.wait(max_events);
+        for (index, event) in ready.iter().enumerate() {
+            write_user(events + index * size_of::<EpollEvent>(), *event)?;
+        }
+        Ok(ready.len())
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_epoll_wait(
+    task: &Task,
+    epfd: usize,
+    events: usize,
+    max_events: usize,
+    timeout: usize,
+) -> SysResult {
+    sys_epoll_pwait(task, epfd, events, max_events, timeout, 0, 0)
+}
@@ -0,0 +1,239 @@
+//! Syscalls of the signals.
+//!
+//! There is no process table, the current task is the only process which
+//! can be signalled.
+
+use core::mem::size_of;
+
+use drivers_base::DSched;
+use polyhal::trapframe::TrapFrameArgs;
+use syscalls::Errno;
+
+use super::{
+    poll::{deadline, read_timespec},
+    read_user, write_user, SysResult,
+};
+use crate::{
+    drivers::DriverSched,
+    task::{
+        signal::{
+            self, sig_bit, SigAction, SigAltStack, SigInfo, SigSet, MINSIGSTKSZ, NSIG, SIGPIPE,
+            SIGSEGV, SI_TKILL, SI_USER, SS_DISABLE, SS_ONSTACK, UNBLOCKABLE,
+        },
+        task::Task,
+    },
+};
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// Check the size of the signal set given by the user.
+fn check_sigset_size(size: usize) -> Result<(), Errno> {
+    match size == size_of::<SigSet>() {
+        true => Ok(()),
+        false => Err(Errno::EINVAL),
+    }
+}
+
+/// Check the signal number, 0 only checks the permission of sending.
+fn check_signo(signo: usize) -> Result<(), Errno> {
+    match signo <= NSIG {
+        true => Ok(()),
+        false => Err(Errno::EINVAL),
+    }
+}
+
+/// Send SIGPIPE to the task if the write failed with EPIPE.
+pub fn sigpipe(task: &Task, result: SysResult) -> SysResult {
+    if result == Err(Errno::EPIPE) {
+        task.signal
+            .lock()
+            .send(SigInfo::new(SIGPIPE, SI_USER, task.pid));
+    }
+    result
+}
+
+pub fn sys_rt_sigaction(
+    task: &Task,
+    signo: usize,
+    act: usize,
+    old_act: usize,
+    sigset_size: usize,
+) -> SysResult {
+    check_sigset_size(sigset_size)?;
+    if signo == 0 || signo > NSIG {
+        return Err(Errno::EINVAL);
+    }
+    let action = match act {
+        0 => None,
+        _ if UNBLOCKABLE & sig_bit(signo) != 0 => return Err(Errno::EINVAL),
+        _ => Some(read_user::<SigAction>(act)?),
+    };
+    let mut signal = task.signal.lock();
+    if old_act != 0 {
+        write_user(old_act, signal.action(signo))?;
+    }
+    if let Some(action) = action {
+        signal.set_action(signo, action);
+    }
+    Ok(0)
+}
+
+pub fn sys_rt_sigprocmask(
+    task: &Task,
+    how: usize,
+    set: usize,
+    old_set: usize,
+    sigset_size: usize,
+) -> SysResult {
+    check_sigset_size(sigset_size)?;
+    let mut signal = task.signal.lock();
+    if old_set != 0 {
+        write_user(old_set, signal.mask)?;
+    }
+    if set != 0 {
+        let set = read_user::<SigSet>(set)?;
+        let mask = match how {
+            SIG_BLOCK => signal.mask | set,
+            SIG_UNBLOCK => signal.mask & !set,
+            SIG_SETMASK => set,
+            _ => return Err(Errno::EINVAL),
+        };
+        signal.set_mask(mask);
+    }
+    Ok(0)
+}
+
+pub fn sys_rt_sigreturn(task: &Task) -> SysResult {
+    match signal::sigreturn(task) {
+        Some(ret) => Ok(ret),
+        // The stack pointer isn't at a signal frame.
+        None => {
+            task.signal
+                .lock()
+                .send(SigInfo::new(SIGSEGV, SI_USER, task.pid));
+            Err(Errno::EFAULT)
+        }
+    }
+}
+
+/// Send the signal to the process `pid`, the groups are the processes themselves.
+fn send(task: &Task, pid: isize, signo: usize, code: i32) -> SysResult {
+    check_signo(signo)?;
+    if pid.unsigned_abs() != task.pid && pid != 0 {
+        return Err(Errno::ESRCH);
+    }
+    if signo != 0 {
+        task.signal.lock().send(SigInfo::new(signo, code, task.pid));
+    }
+    Ok(0)
+}
+
+pub fn sys_kill(task: &Task, pid: usize, signo: usize) -> SysResult {
+    send(task, pid as i32 as isize, signo, SI_USER)
+}
+
+pub fn sys_tkill(task: &Task, tid: usize, signo: usize) -> SysResult {
+    match tid as i32 {
+        tid if tid <= 0 => Err(Errno::EINVAL),
+        tid => send(task, tid as isize, signo, SI_TKILL),
+    }
+}
+
+pub fn sys_tgkill(task: &Task, tgid: usize, tid: usize, signo: usize) -> SysResult {
+    match (tgid as i32, tid as i32) {
+        (tgid, tid) if tgid <= 0 || tid <= 0 => Err(Errno::EINVAL),
+        // The task is the only thread of its process.
+        (tgid, tid) if tgid != tid => Err(Errno::ESRCH),
+        (_, tid) => send(task, tid as isize, signo, SI_TKILL),
+    }
+}
+
+pub fn sys_sigaltstack(task: &Task, stack: usize, old_stack: usize) -> SysResult {
+    let sp = task.get_tf_mut_force()[TrapFrameArgs::SP];
+    let mut signal = task.signal.lock();
+    let on_altstack = signal.on_altstack(sp);
+    let new = match stack {
+        0 => None,
+        _ => Some(read_user::<SigAltStack>(stack)?),
+    };
+    if old_stack != 0 {
+        let mut old = signal.altstack;
+        if on_altstack {
+            old.flags = SS_ONSTACK;
+        }
+        write_user(old_stack, old)?;
+    }
+    if let Some(new) = new {
+        if on_altstack {
+            return Err(Errno::EPERM);
+        }
+        signal.altstack = match new.flags {
+            SS_DISABLE => SigAltStack {
+                sp: 0,
+                flags: SS_DISABLE,
+                size: 0,
+            },
+            0 if new.size < MINSIGSTKSZ => return Err(Errno::ENOMEM),
+            0 => new,
+            _ => return Err(Errno::EINVAL),
+        };
+    }
+    Ok(0)
+}
+
+/// Replace the signal mask while the syscall waits, the old mask is saved.
+///
+/// The old mask is restored once the handler of the interrupting signal
+/// returns, or when the syscall returns without entering a handler.
+pub(super) fn set_wait_mask(task: &Task, mask: usize, sigset_size: usize) -> Result<(), Errno> {
+    check_sigset_size(sigset_size)?;
+    let mask = read_user::<SigSet>(mask)?;
+    let mut signal = task.signal.lock();
+    signal.saved_mask = Some(signal.mask);
+    signal.set_mask(mask);
+    Ok(())
+}
+
+pub fn sys_rt_sigsuspend(task: &Task, mask: usize, sigset_size: usize) -> SysResult {
+    set_wait_mask(task, mask, sigset_size)?;
+    while !task.signal.lock().has_deliverable() {
+        crate::net::poll();
+        DriverSched::sleep();
+    }
+    // The mask is restored once the handler returns.
+    Err(Errno::EINTR)
+}
+
+pub fn sys_rt_sigtimedwait(
+    task: &Task,
+    set: usize,
+    info: usize,
+    timeout: usize,
+    sigset_size: usize,
+) -> SysResult {
+    check_sigset_size(sigset_size)?;
+    let set = read_user::<SigSet>(set)? & !UNBLOCKABLE;
+    let deadline = deadline(read_timespec(timeout)?);
+    loop {
+        let mut signal = task.signal.lock();
+        if let Some(taken) = signal.take(set) {
+            drop(signal);
+            if info != 0 {
+                write_user(info, taken)?;
+            }
+            return Ok(taken.signo as usize);
+        }
+        // A signal out of the set interrupts the wait.
+        if signal.has_deliverable() {
+            return Err(Errno::EINTR);
+        }
+        drop(signal);
+        if deadline.is_some_and(|x| DriverSched::now() >= x) {
+            return Err(Errno::EAGAIN);
+        }
+        crate::net::poll();
+        DriverSched::sleep();
+    }
+}
@@ -0,0 +1,141 @@
+//! File descriptor table of the task.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use fs_base::{Errno, FsResult, INodeInterface, OpenFlags};
+use spin::Mutex;
+
+use crate::{
+    fs::{epoll::EventPoll, pipe::PipeEnd},
+    net::socket::Socket,
+};
+
+/// Max file descriptors of a task.
+pub const FD_LIMIT: usize = 1024;
+/// The standard streams are the console, they are not in the table.
+const STD_STREAMS: usize = 3;
+
+/// An opened file.
+pub struct FileItem {
+    pub inode: Arc<dyn INodeInterface>,
+    /// The socket of the file if the file is a socket.
+    pub socket: Option<Arc<Socket>>,
+    /// The epoll instance of the file if the file is an epoll file.
+    pub epoll: Option<Arc<EventPoll>>,
+    offset: Mutex<usize>,
+    pub flags: Mutex<OpenFlags>,
+}
+
+impl FileItem {
+    pub fn new(inode: Arc<dyn INodeInterface>, flags: OpenFlags) -> Self {
+        Self {
+            inode,
+            socket: None,
+            epoll: None,
+            offset: Mutex::new(0),
+            flags: Mutex::new(flags),
+        }
+    }
+
+    pub fn new_socket(socket: Arc<Socket>, flags: OpenFlags) -> Self {
+        socket.set_nonblocking(flags.contains(OpenFlags::NONBLOCK));
+        Self {
+            inode: socket.clone(),
+            socket: Some(socket),
+            epoll: None,
+            offset: Mutex::new(0),
+            flags: Mutex::new(flags),
+        }
+    }
+
+    pub fn new_epoll(epoll: Arc<EventPoll>, flags: OpenFlags) -> Self {
+        Self {
+            inode: epoll.clone(),
+            socket: None,
+            epoll: Some(epoll),
+            offset: Mutex::new(0),
+            flags: Mutex::new(flags),
+        }
+    }
+
+    pub fn new_pipe(end: Arc<PipeEnd>, flags: OpenFlags) -> Self {
+        end.set_nonblocking(flags.contains(OpenFlags::NONBLOCK));
+        Self::new(end, flags)
+    }
+
+    /// Read from the current offset and advance it.
+    pub fn read(&self, buffer: &mut [u8]) -> FsResult<usize> {
+        let mut offset = self.offset.lock();
+        let len = self.inode.readat(*offset, buffer)?;
+        *offset += len;
+        Ok(len)
+    }
+
+    /// Write at the current offset and advance it.
+    pub fn write(&self, buffer: &[u8]) -> FsResult<usize> {
+        let mut offset = self.offset.lock();
+        let len = self.inode.writeat(*offset, buffer)?;
+        *offset += len;
+        Ok(len)
+    }
+}
+
+pub struct FdTable(Vec<Option<Arc<FileItem>>>);
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self(vec![None; STD_STREAMS])
+    }
+}
+
+impl FdTable {
+    /// Put the file in the lowest free descriptor.
+    pub fn alloc(&mut self, item: FileItem) -> FsResult<usize> {
+        self.alloc_shared(Arc::new(item))
+    }
+
+    /// Put the opened file shared with other descriptors in the lowest free descriptor.
+    pub fn alloc_shared(&mut self, item: Arc<FileItem>) -> FsResult<usize> {
+        self.alloc_shared_from(item, STD_STREAMS)
+    }
+
+    /// Put the shared file in the lowest free descriptor not less than `min`.
+    pub fn alloc_shared_from(&mut self, item: Arc<FileItem>, min: usize) -> FsResult<usize> {
+        if min >= FD_LIMIT {
+            return Err(Errno::EINVAL);
+        }
+        let min = min.max(STD_STREAMS);
+        let fd = match self.0.iter().skip(min).position(Option::is_none) {
+            Some(index) => min + index,
+            None if self.0.len().max(min) < FD_LIMIT => self.0.len().max(min),
+            None => return Err(Errno::EMFILE),
+        };
+        self.set(fd, item)?;
+        Ok(fd)
+    }
+
+    /// Put the shared file in the descriptor, the file already in it is closed.
+    pub fn set(&mut self, fd: usize, item: Arc<FileItem>) -> FsResult<()> {
+        if fd >= FD_LIMIT {
+            return Err(Errno::EBADF);
+        }
+        if self.0.len() <= fd {
+            self.0.resize(fd + 1, None);
+        }
+        self.0[fd] = Some(item);
+        Ok(())
+    }
+
+    pub fn get(&self, fd: usize) -> FsResult<Arc<FileItem>> {
+        self.0.get(fd).cloned().flatten().ok_or(Errno::EBADF)
+    }
+
+    pub fn close(&mut self, fd: usize) -> FsResult<()> {
+        match self.0.get_mut(fd) {
+            Some(item @ Some(_)) => {
+                *item = None;
+                Ok(())
+            }
+            _ => Err(Errno::EBADF),
+        }
+    }
+}
@@ -1,3 +1,5 @@
+pub mod fd;
 pub mod memset;
 pub mod schedular;
+pub mod signal;
 pub mod task;
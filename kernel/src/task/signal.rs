@@ -0,0 +1,526 @@
+//! POSIX signals of the task.
+//!
+//! A sent signal is pending until the task goes back to the user and the
+//! signal isn't blocked. A signal with a handler builds the signal frame on
+//! the user stack, the handler returns to the trampoline calling
+//! `rt_sigreturn`. The registers are restored from the context saved in the
+//! kernel, only the signal mask of the frame is taken back from the user.
+
+use core::{
+    mem::size_of,
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use alloc::{collections::VecDeque, vec::Vec};
+use drivers_base::DSched;
+use polyhal::trapframe::{TrapFrame, TrapFrameArgs};
+
+use super::task::Task;
+use crate::{config::SIG_TRAMPOLINE, drivers::DriverSched};
+
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGSEGV: usize = 11;
+pub const SIGPIPE: usize = 13;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGWINCH: usize = 28;
+pub const SIGSYS: usize = 31;
+/// The first real-time signal, the real-time signals are queued.
+pub const SIGRTMIN: usize = 32;
+/// The number of signals.
+pub const NSIG: usize = 64;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// The `sa_restorer` is set, only the sigaction of x86_64 and aarch64 has it.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub const SA_RESTORER: usize = 0x0400_0000;
+pub const SA_ONSTACK: usize = 0x0800_0000;
+pub const SA_RESTART: usize = 0x1000_0000;
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// The signal is sent by `kill`.
+pub const SI_USER: i32 = 0;
+/// The signal is sent by `tkill` or `tgkill`.
+pub const SI_TKILL: i32 = -6;
+
+pub const SS_ONSTACK: i32 = 1;
+pub const SS_DISABLE: i32 = 2;
+/// Min bytes of an alternate signal stack.
+pub const MINSIGSTKSZ: usize = 2048;
+
+/// Max contexts saved for the handlers, the handlers leaving by `longjmp`
+/// never return their contexts so the oldest ones are dropped.
+const MAX_SAVED: usize = 64;
+
+/// The set of signals, the bit `n - 1` is the signal `n`.
+pub type SigSet = u64;
+
+pub const fn sig_bit(signo: usize) -> SigSet {
+    1 << (signo - 1)
+}
+
+/// The signals which can't be caught, blocked or ignored.
+pub const UNBLOCKABLE: SigSet = sig_bit(SIGKILL) | sig_bit(SIGSTOP);
+
+/// The `struct sigaction` of the kernel.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub restorer: usize,
+    pub mask: SigSet,
+}
+
+impl SigAction {
+    /// The return address of the handler.
+    fn restorer(&self) -> usize {
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        if self.flags & SA_RESTORER != 0 && self.restorer != 0 {
+            return self.restorer;
+        }
+        SIG_TRAMPOLINE
+    }
+}
+
+/// The `siginfo_t`, only the fields of the signals sent by the processes are filled.
+#[repr(C, align(8))]
+#[derive(Debug, Clone, Copy)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    pub code: i32,
+    _pad: i32,
+    /// The sending process.
+    pub pid: i32,
+    pub uid: u32,
+    _fields: [u8; 104],
+}
+
+impl SigInfo {
+    pub fn new(signo: usize, code: i32, pid: usize) -> Self {
+        Self {
+            signo: signo as _,
+            errno: 0,
+            code,
+            _pad: 0,
+            pid: pid as _,
+            uid: 0,
+            _fields: [0; 104],
+        }
+    }
+}
+
+/// The `stack_t` of the alternate signal stack.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigAltStack {
+    pub sp: usize,
+    pub flags: i32,
+    pub size: usize,
+}
+
+/// The `ucontext_t` of the signal frame, the machine context is the trap frame.
+#[repr(C)]
+#[derive(Clone)]
+#[cfg(target_arch = "x86_64")]
+struct UContext {
+    flags: usize,
+    link: usize,
+    stack: SigAltStack,
+    mcontext: TrapFrame,
+    mask: SigSet,
+}
+
+/// The `ucontext_t` of the signal frame, the machine context is the trap frame.
+#[repr(C)]
+#[derive(Clone)]
+#[cfg(not(target_arch = "x86_64"))]
+struct UContext {
+    flags: usize,
+    link: usize,
+    stack: SigAltStack,
+    mask: SigSet,
+    /// The `sigset_t` of the libc has 1024 bits.
+    _pad: [u8; 120],
+    mcontext: TrapFrame,
+}
+
+/// The frame built on the user stack for the handler.
+#[repr(C)]
+#[derive(Clone)]
+struct SignalFrame {
+    /// The return address popped by the handler on x86_64.
+    restorer: usize,
+    info: SigInfo,
+    context: UContext,
+}
+
+/// The bytes below the stack pointer which the user code may use.
+#[cfg(target_arch = "x86_64")]
+const RED_ZONE: usize = 128;
+#[cfg(not(target_arch = "x86_64"))]
+const RED_ZONE: usize = 0;
+
+/// The offset of the stack pointer from the frame when `rt_sigreturn` is called.
+#[cfg(target_arch = "x86_64")]
+const RETURN_SP_OFFSET: usize = size_of::<usize>();
+#[cfg(not(target_arch = "x86_64"))]
+const RETURN_SP_OFFSET: usize = 0;
+
+/// Bytes of the syscall instruction, a restarted syscall goes back over it.
+#[cfg(target_arch = "x86_64")]
+const SYSCALL_LEN: usize = 2;
+#[cfg(not(target_arch = "x86_64"))]
+const SYSCALL_LEN: usize = 4;
+
+/// The code of the trampoline page, it calls `rt_sigreturn`.
+#[cfg(target_arch = "x86_64")]
+pub const TRAMPOLINE: &[u8] = &[
+    0xb8, 0x0f, 0x00, 0x00, 0x00, // mov eax, 15
+    0x0f, 0x05, // syscall
+];
+#[cfg(target_arch = "riscv64")]
+pub const TRAMPOLINE: &[u8] = &[
+    0x93, 0x08, 0xb0, 0x08, // li a7, 139
+    0x73, 0x00, 0x00, 0x00, // ecall
+];
+#[cfg(target_arch = "aarch64")]
+pub const TRAMPOLINE: &[u8] = &[
+    0x68, 0x11, 0x80, 0xd2, // mov x8, #139
+    0x01, 0x00, 0x00, 0xd4, // svc #0
+];
+#[cfg(target_arch = "loongarch64")]
+pub const TRAMPOLINE: &[u8] = &[
+    0x0b, 0x2c, 0x82, 0x03, // li.w $a7, 139
+    0x00, 0x00, 0x2b, 0x00, // syscall 0
+];
+
+enum DefaultAction {
+    Terminate,
+    /// Terminate with a core file, no core files are written.
+    Core,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+fn default_action(signo: usize) -> DefaultAction {
+    match signo {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ
+        | SIGSYS => DefaultAction::Core,
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+fn is_stop(signo: usize) -> bool {
+    matches!(default_action(signo), DefaultAction::Stop)
+}
+
+/// The syscall interrupted by a signal, it's restarted unless a handler without
+/// SA_RESTART is entered.
+pub struct Interrupted {
+    pub sysno: usize,
+    pub arg0: usize,
+}
+
+/// The context of the interrupted code, restored by `rt_sigreturn`.
+struct SavedContext {
+    frame: usize,
+    trap_frame: TrapFrame,
+}
+
+pub struct SignalState {
+    actions: [SigAction; NSIG],
+    /// The blocked signals.
+    pub mask: SigSet,
+    pending: VecDeque<SigInfo>,
+    pub altstack: SigAltStack,
+    /// The mask restored once the handler interrupting `rt_sigsuspend` returns.
+    pub saved_mask: Option<SigSet>,
+    saved: Vec<SavedContext>,
+    /// Stopped by a stop signal until SIGCONT.
+    stopped: bool,
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self {
+            actions: [SigAction::default(); NSIG],
+            mask: 0,
+            pending: VecDeque::new(),
+            altstack: SigAltStack {
+                sp: 0,
+                flags: SS_DISABLE,
+                size: 0,
+            },
+            saved_mask: None,
+            saved: Vec::new(),
+            stopped: false,
+        }
+    }
+}
+
+impl SignalState {
+    fn is_ignored(&self, signo: usize) -> bool {
+        match self.actions[signo - 1].handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(default_action(signo), DefaultAction::Ignore),
+            _ => false,
+        }
+    }
+
+    /// Make the signal pending, the ignored signals are discarded unless they are blocked.
+    pub fn send(&mut self, info: SigInfo) {
+        let signo = info.signo as usize;
+        if signo == SIGCONT {
+            self.stopped = false;
+            self.pending.retain(|x| !is_stop(x.signo as usize));
+        } else if is_stop(signo) {
+            self.pending.retain(|x| x.signo as usize != SIGCONT);
+        }
+        if self.mask & sig_bit(signo) == 0 && self.is_ignored(signo) {
+            return;
+        }
+        // The standard signals are pending at most once.
+        if signo < SIGRTMIN && self.pending.iter().any(|x| x.signo == info.signo) {
+            return;
+        }
+        self.pending.push_back(info);
+    }
+
+    pub fn pending_set(&self) -> SigSet {
+        self.pending
+            .iter()
+            .fold(0, |acc, x| acc | sig_bit(x.signo as usize))
+    }
+
+    /// Whether a pending signal isn't blocked.
+    pub fn has_deliverable(&self) -> bool {
+        self.pending_set() & !self.mask != 0
+    }
+
+    /// Take the pending signal of the set, the lowest signal first.
+    pub fn take(&mut self, set: SigSet) -> Option<SigInfo> {
+        let index = self
+            .pending
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| set & sig_bit(x.signo as usize) != 0)
+            .min_by_key(|(_, x)| x.signo)
+            .map(|(index, _)| index)?;
+        self.pending.remove(index)
+    }
+
+    /// Take the pending signal which isn't blocked.
+    fn take_deliverable(&mut self) -> Option<SigInfo> {
+        self.take(!self.mask)
+    }
+
+    pub fn action(&self, signo: usize) -> SigAction {
+        self.actions[signo - 1]
+    }
+
+    /// Set the action of the signal, ignoring the signal discards its pending ones.
+    pub fn set_action(&mut self, signo: usize, action: SigAction) {
+        self.actions[signo - 1] = action;
+        if self.is_ignored(signo) {
+            self.pending.retain(|x| x.signo as usize != signo);
+        }
+    }
+
+    pub fn set_mask(&mut self, mask: SigSet) {
+        self.mask = mask & !UNBLOCKABLE;
+    }
+
+    /// Whether the stack pointer is in the alternate signal stack.
+    pub fn on_altstack(&self, sp: usize) -> bool {
+        self.altstack.flags & SS_DISABLE == 0
+            && (self.altstack.sp..self.altstack.sp + self.altstack.size).contains(&sp)
+    }
+
+    /// Take the saved context of the frame, the contexts of the handlers
+    /// which left without `rt_sigreturn` are dropped.
+    fn take_saved(&mut self, frame: usize) -> Option<TrapFrame> {
+        let index = self.saved.iter().rposition(|x| x.frame == frame)?;
+        self.saved.drain(index..).next().map(|x| x.trap_frame)
+    }
+}
+
+/// The task running in the kernel, the files check its signals while blocking.
+static RUNNING: AtomicPtr<Task> = AtomicPtr::new(null_mut());
+
+/// Set the task running in the kernel, None once it has left.
+pub fn set_running(task: Option<&Task>) {
+    let task = task.map_or(null_mut(), |x| x as *const Task as *mut Task);
+    RUNNING.store(task, Ordering::Release);
+}
+
+/// Whether a signal interrupts the blocking call of the running task.
+///
+/// The reads and writes of the files don't get the task, they check it here.
+pub fn interrupted() -> bool {
+    let task = RUNNING.load(Ordering::Acquire);
+    // The task is cleared before it's dropped.
+    !task.is_null() && unsafe { &*task }.signal.lock().has_deliverable()
+}
+
+/// Restore the context saved by the handler of the frame, returns the
+/// result of the restored syscall.
+///
+/// Returns None if the stack pointer isn't at a frame of a handler.
+pub fn sigreturn(task: &Task) -> Option<usize> {
+    let tf = task.get_tf_mut_force();
+    let frame = tf[TrapFrameArgs::SP].wrapping_sub(RETURN_SP_OFFSET);
+    let mut signal = task.signal.lock();
+    let trap_frame = signal.take_saved(frame)?;
+    let mask = unsafe { core::ptr::addr_of!((*(frame as *const SignalFrame)).context.mask).read() };
+    signal.set_mask(mask);
+    *tf = trap_frame;
+    Some(tf[TrapFrameArgs::RET])
+}
+
+impl Task {
+    /// Handle the pending signals before going back to the user.
+    ///
+    /// Returns the signal terminating the task.
+    pub fn handle_signals(&self, interrupted: Option<Interrupted>) -> Option<usize> {
+        let mut signal = self.signal.lock();
+        while let Some(info) = signal.take_deliverable() {
+            let signo = info.signo as usize;
+            let action = signal.action(signo);
+            match action.handler {
+                SIG_IGN => continue,
+                SIG_DFL => match default_action(signo) {
+                    DefaultAction::Ignore | DefaultAction::Continue => continue,
+                    DefaultAction::Terminate | DefaultAction::Core => return Some(signo),
+                    DefaultAction::Stop => {
+                        signal.stopped = true;
+                        while signal.stopped && signal.pending_set() & sig_bit(SIGKILL) == 0 {
+                            drop(signal);
+                            crate::net::poll();
+                            DriverSched::sleep();
+                            signal = self.signal.lock();
+                        }
+                        continue;
+                    }
+                },
+                _ => {
+                    let restart = action.flags & SA_RESTART != 0;
+                    if let (Some(interrupted), true) = (&interrupted, restart) {
+                        self.restart_syscall(interrupted);
+                    }
+                    self.enter_handler(&mut signal, info, action);
+                    return None;
+                }
+            }
+        }
+        // No handler was entered, the syscall goes on and the waits get their masks back.
+        if let Some(interrupted) = &interrupted {
+            self.restart_syscall(interrupted);
+        }
+        if let Some(mask) = signal.saved_mask.take() {
+            signal.mask = mask;
+        }
+        None
+    }
+
+    /// Go back to the syscall instruction with the arguments of the syscall.
+    fn restart_syscall(&self, interrupted: &Interrupted) {
+        let tf = self.get_tf_mut_force();
+        tf[TrapFrameArgs::SEPC] -= SYSCALL_LEN;
+        tf[TrapFrameArgs::ARG0] = interrupted.arg0;
+        tf[TrapFrameArgs::SYSCALL] = interrupted.sysno;
+    }
+
+    /// Build the signal frame and enter the handler.
+    ///
+    /// The handler gets the siginfo and the ucontext with or without SA_SIGINFO.
+    fn enter_handler(&self, signal: &mut SignalState, info: SigInfo, action: SigAction) {
+        let signo = info.signo as usize;
+        let tf = self.get_tf_mut_force();
+        let sp = tf[TrapFrameArgs::SP];
+        let top = match action.flags & SA_ONSTACK != 0
+            && signal.altstack.flags & SS_DISABLE == 0
+            && !signal.on_altstack(sp)
+        {
+            true => signal.altstack.sp + signal.altstack.size,
+            false => sp - RED_ZONE,
+        };
+        // The stack pointer is aligned to 16 bytes before the return address is pushed.
+        let frame = ((top - size_of::<SignalFrame>()) & !0xf) - RETURN_SP_OFFSET;
+        let mask = signal.saved_mask.take().unwrap_or(signal.mask);
+        let restorer = action.restorer();
+        // The trap frame is only Copy on some arches.
+        #[cfg(target_arch = "x86_64")]
+        let context = UContext {
+            flags: 0,
+            link: 0,
+            stack: signal.altstack,
+            mcontext: TrapFrame::clone(tf),
+            mask,
+        };
+        #[cfg(not(target_arch = "x86_64"))]
+        let context = UContext {
+            flags: 0,
+            link: 0,
+            stack: signal.altstack,
+            mask,
+            _pad: [0; 120],
+            mcontext: TrapFrame::clone(tf),
+        };
+        unsafe {
+            (frame as *mut SignalFrame).write(SignalFrame {
+                restorer,
+                info,
+                context,
+            })
+        };
+        if signal.saved.len() == MAX_SAVED {
+            signal.saved.remove(0);
+        }
+        signal.saved.push(SavedContext {
+            frame,
+            trap_frame: TrapFrame::clone(tf),
+        });
+        let mut blocked = action.mask;
+        if action.flags & SA_NODEFER == 0 {
+            blocked |= sig_bit(signo);
+        }
+        signal.set_mask(signal.mask | blocked);
+        if action.flags & SA_RESETHAND != 0 {
+            signal.set_action(signo, SigAction::default());
+        }
+        let frame_ptr = frame as *const SignalFrame;
+        tf[TrapFrameArgs::SP] = frame;
+        tf[TrapFrameArgs::SEPC] = action.handler;
+        tf[TrapFrameArgs::ARG0] = signo;
+        tf[TrapFrameArgs::ARG1] = unsafe { core::ptr::addr_of!((*frame_ptr).info) } as usize;
+        tf[TrapFrameArgs::ARG2] = unsafe { core::ptr::addr_of!((*frame_ptr).context) } as usize;
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            tf[TrapFrameArgs::RA] = restorer;
+        }
+    }
+}
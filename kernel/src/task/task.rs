@@ -1,19 +1,30 @@
-use core::{cell::UnsafeCell, cmp::min};
+use core::{
+    cell::UnsafeCell,
+    cmp::min,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use alloc::{collections::btree_map::BTreeMap, vec::Vec};
 use polyhal::{
-    debug_console::DebugConsole,
     pagetable::PAGE_SIZE,
     trap::{run_user_task, EscapeReason},
     trapframe::{TrapFrame, TrapFrameArgs},
     PageTableWrapper, VirtPage,
 };
+use spin::Mutex;
 use syscalls::{Errno, Sysno};
 use xmas_elf::{program::Type, ElfFile};
 
-use crate::config::{ALIGN_SIZE, DEFAULT_USER_STACK_SIZE, DEFAULT_USER_STACK_TOP};
+use crate::{
+    config::{ALIGN_SIZE, DEFAULT_USER_STACK_SIZE, DEFAULT_USER_STACK_TOP, SIG_TRAMPOLINE},
+    syscall,
+};
 
-use super::memset::MemSet;
+use super::{
+    fd::FdTable,
+    memset::MemSet,
+    signal::{self, Interrupted, SignalState},
+};
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 #[allow(non_camel_case_types, dead_code)]
@@ -68,8 +79,13 @@ pub enum AuxV {
     EXECFN = 31,
 }
 
+/// The id of the next task.
+static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+
 /// Monolithic Task
 pub struct Task {
+    /// The process id of the task.
+    pub pid: usize,
     /// This field records the current state of the user  task.
     pub trap_frame: UnsafeCell<TrapFrame>,
     /// This field records the page table of the user task.
@@ -77,6 +93,10 @@ pub struct Task {
     pub page_table: PageTableWrapper,
     /// Records the used Physical pages.
     pub memset: MemSet,
+    /// The opened files of the task.
+    pub fd_table: Mutex<FdTable>,
+    /// The signal handlers, the blocked and the pending signals.
+    pub signal: Mutex<SignalState>,
 }
 
 impl Task {
@@ -84,9 +104,12 @@ impl Task {
     pub fn from_elf(elf_data: &[u8], args: &[&str]) -> Self {
         let page_table = PageTableWrapper::alloc();
         let mut task = Task {
+            pid: NEXT_PID.fetch_add(1, Ordering::Relaxed),
             page_table,
             trap_frame: UnsafeCell::new(TrapFrame::new()),
             memset: MemSet::new(),
+            fd_table: Mutex::new(FdTable::default()),
+            signal: Mutex::new(SignalState::default()),
         };
 
         let file = ElfFile::new(elf_data).expect("This is not a valid elf file");
@@ -132,6 +155,12 @@ impl Task {
             );
         }
 
+        // Map the trampoline of the signal handlers.
+        task.memset
+            .map_page(task.page_table.0, VirtPage::from_addr(SIG_TRAMPOLINE))
+            .get_buffer()[..signal::TRAMPOLINE.len()]
+            .copy_from_slice(signal::TRAMPOLINE);
+
         // FIXME: This is just for debugging, remove it when debug is finished.
         for i in 0..10 {
             task.memset.map_page(
@@ -226,25 +255,25 @@ impl Task {
     pub fn into_user(&self) {
         self.page_table.change();
         let tf = self.get_tf_mut_force();
+        signal::set_running(Some(self));
         loop {
             let reason = run_user_task(tf);
+            let mut interrupted = None;
             if reason == EscapeReason::SysCall {
                 tf.syscall_ok();
                 let sysid = match Sysno::new(tf[TrapFrameArgs::SYSCALL]) {
                     Some(sysid) => sysid,
                     _ => {
                         tf[TrapFrameArgs::RET] = (-Errno::EINVAL.into_raw()) as _;
-                        return;
+                        break;
                     }
                 };
+                // The result overwrites the first argument on some architectures.
+                let arg0 = tf.args()[0];
                 tf[TrapFrameArgs::RET] = match sysid {
                     Sysno::set_tid_address => 1,
                     Sysno::getuid => 0,
-                    Sysno::ioctl => -1 as isize as usize,
-                    Sysno::dup3 => 4,
-                    Sysno::getpid => 1,
-                    Sysno::rt_sigprocmask => 0,
-                    Sysno::rt_sigaction => 0,
+                    Sysno::getpid => self.pid,
                     Sysno::getppid => 1,
                     Sysno::uname => 0,
                     Sysno::getcwd => 0,
@@ -258,26 +287,28 @@ impl Task {
                             tf.args()[0]
                         }
                     }
-                    Sysno::write => {
-                        if tf.args()[0] == 2 || tf.args()[0] == 1 {
-                            unsafe {
-                                core::slice::from_raw_parts_mut(
-                                    tf.args()[1] as *mut u8,
-                                    tf.args()[2],
-                                )
-                                .iter()
-                                .map(u8::clone)
-                                .for_each(DebugConsole::putchar);
-                            }
-                        }
-                        tf.args()[2]
-                    }
-                    _ => todo!("Syscall {sysid:?} is not implemented"),
+                    _ => match syscall::dispatch(self, sysid, tf.args()) {
+                        Some(result) => syscall::into_ret(result),
+                        None => todo!("Syscall {sysid:?} is not implemented"),
+                    },
                 };
+                if tf[TrapFrameArgs::RET] == -Errno::EINTR.into_raw() as usize
+                    && syscall::restartable(sysid)
+                {
+                    interrupted = Some(Interrupted {
+                        sysno: sysid.id() as _,
+                        arg0,
+                    });
+                }
                 // log::debug!("3");
                 // tf.syscall_ok();
                 // log::debug!("sys trapframe: {:#x?}", tf);
             }
+            if let Some(signo) = self.handle_signals(interrupted) {
+                log::info!("task {} is terminated by the signal {}", self.pid, signo);
+                break;
+            }
         }
+        signal::set_running(None);
     }
 }
@@ -0,0 +1,434 @@
+//! Addresses of the link and the network layer.
+
+use core::fmt::{Debug, Display, Formatter, Result};
+
+/// Ethernet MAC address.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: Self = Self([0xff; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+
+    /// Get the MAC address of the IPv6 multicast address.
+    pub fn ipv6_multicast(addr: Ipv6Addr) -> Self {
+        let a = addr.0;
+        Self([0x33, 0x33, a[12], a[13], a[14], a[15]])
+    }
+}
+
+impl Display for MacAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let m = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            m[0], m[1], m[2], m[3], m[4], m[5]
+        )
+    }
+}
+
+impl Debug for MacAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// IPv4 address in the network byte order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Self = Self([0; 4]);
+    pub const BROADCAST: Self = Self([0xff; 4]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    pub fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub fn from_u32(addr: u32) -> Self {
+        Self(addr.to_be_bytes())
+    }
+
+    /// Parse the dotted decimal address.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut addr = [0u8; 4];
+        let mut parts = s.split('.');
+        for byte in addr.iter_mut() {
+            *byte = parts.next()?.parse().ok()?;
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Self(addr)),
+        }
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xf0 == 0xe0
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+}
+
+impl Display for Ipv4Addr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+impl Debug for Ipv4Addr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// IPv4 address with the prefix length of its subnet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    pub addr: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    pub const fn new(addr: Ipv4Addr, prefix_len: u8) -> Self {
+        Self { addr, prefix_len }
+    }
+
+    /// Create the subnet of the netmask, returns None if the netmask isn't contiguous.
+    pub fn from_netmask(addr: Ipv4Addr, netmask: Ipv4Addr) -> Option<Self> {
+        let mask = netmask.to_u32();
+        let prefix_len = mask.leading_ones();
+        match mask.checked_shl(prefix_len).unwrap_or(0) {
+            0 => Some(Self::new(addr, prefix_len as u8)),
+            _ => None,
+        }
+    }
+
+    /// Create the subnet of the address class, the default of the addresses without netmasks.
+    pub fn classful(addr: Ipv4Addr) -> Self {
+        let prefix_len = match addr.0[0] {
+            0..=127 => 8,
+            128..=191 => 16,
+            _ => 24,
+        };
+        Self::new(addr, prefix_len)
+    }
+
+    pub fn netmask(&self) -> Ipv4Addr {
+        match self.prefix_len {
+            0 => Ipv4Addr::UNSPECIFIED,
+            len => Ipv4Addr::from_u32(u32::MAX << (32 - len.min(32) as u32)),
+        }
+    }
+
+    /// Check whether the address is in the subnet.
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = self.netmask().to_u32();
+        addr.to_u32() & mask == self.addr.to_u32() & mask
+    }
+
+    /// Get the broadcast address of the subnet.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.addr.to_u32() | !self.netmask().to_u32())
+    }
+}
+
+/// IPv6 address in the network byte order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ipv6Addr(pub [u8; 16]);
+
+impl Ipv6Addr {
+    pub const UNSPECIFIED: Self = Self([0; 16]);
+    pub const LOOPBACK: Self = Self::new([0, 0, 0, 0, 0, 0, 0, 1]);
+    /// The link-local all-nodes multicast address.
+    pub const ALL_NODES: Self = Self::new([0xff02, 0, 0, 0, 0, 0, 0, 1]);
+    /// The link-local all-routers multicast address.
+    pub const ALL_ROUTERS: Self = Self::new([0xff02, 0, 0, 0, 0, 0, 0, 2]);
+
+    pub const fn new(segments: [u16; 8]) -> Self {
+        let mut addr = [0u8; 16];
+        let mut i = 0;
+        while i < 8 {
+            let bytes = segments[i].to_be_bytes();
+            addr[i * 2] = bytes[0];
+            addr[i * 2 + 1] = bytes[1];
+            i += 1;
+        }
+        Self(addr)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut addr = [0u8; 16];
+        addr.copy_from_slice(&bytes[..16]);
+        Self(addr)
+    }
+
+    pub fn segments(&self) -> [u16; 8] {
+        let mut segments = [0u16; 8];
+        for (segment, bytes) in segments.iter_mut().zip(self.0.chunks_exact(2)) {
+            *segment = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+        segments
+    }
+
+    /// Create the address of the prefix and the interface identifier of the MAC address.
+    pub fn from_eui64(prefix: Ipv6Addr, mac: MacAddr) -> Self {
+        let m = mac.0;
+        let mut addr = prefix.0;
+        addr[8..].copy_from_slice(&[m[0] ^ 2, m[1], m[2], 0xff, 0xfe, m[3], m[4], m[5]]);
+        Self(addr)
+    }
+
+    /// Get the link-local address of the MAC address.
+    pub fn link_local(mac: MacAddr) -> Self {
+        Self::from_eui64(Self::new([0xfe80, 0, 0, 0, 0, 0, 0, 0]), mac)
+    }
+
+    /// Get the solicited-node multicast address, the neighbour solicitations are sent to it.
+    pub fn solicited_node(&self) -> Self {
+        let mut addr = Self::new([0xff02, 0, 0, 0, 0, 1, 0xff00, 0]).0;
+        addr[13..].copy_from_slice(&self.0[13..]);
+        Self(addr)
+    }
+
+    /// Get the IPv4 address of the IPv4-mapped address `::ffff:a.b.c.d`.
+    pub fn to_ipv4_mapped(&self) -> Option<Ipv4Addr> {
+        match self.0[..12] == [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff] {
+            true => Some(Ipv4Addr::from_bytes(&self.0[12..])),
+            false => None,
+        }
+    }
+
+    pub fn from_ipv4_mapped(addr: Ipv4Addr) -> Self {
+        let mut bytes = [0u8; 16];
+        bytes[10..12].fill(0xff);
+        bytes[12..].copy_from_slice(&addr.0);
+        Self(bytes)
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        *self == Self::LOOPBACK
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] == 0xff
+    }
+
+    /// Check whether the address is in `fe80::/10`.
+    pub fn is_link_local(&self) -> bool {
+        self.0[0] == 0xfe && self.0[1] & 0xc0 == 0x80
+    }
+}
+
+impl Display for Ipv6Addr {
+    /// Format the address in the canonical form of RFC 5952.
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if let Some(addr) = self.to_ipv4_mapped() {
+            return write!(f, "::ffff:{}", addr);
+        }
+        let segments = self.segments();
+        // The longest run of the zero segments is compressed, if it's longer than one.
+        let (mut start, mut len) = (0, 0);
+        let mut i = 0;
+        while i < 8 {
+            let run = segments[i..].iter().take_while(|x| **x == 0).count();
+            if run > len {
+                (start, len) = (i, run);
+            }
+            i += run.max(1);
+        }
+        let write_all = |f: &mut Formatter<'_>, segments: &[u16]| -> Result {
+            for (i, segment) in segments.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ":")?;
+                }
+                write!(f, "{:x}", segment)?;
+            }
+            Ok(())
+        };
+        if len < 2 {
+            return write_all(f, &segments);
+        }
+        write_all(f, &segments[..start])?;
+        write!(f, "::")?;
+        write_all(f, &segments[start + len..])
+    }
+}
+
+impl Debug for Ipv6Addr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// IPv6 address with the prefix length of its subnet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Cidr {
+    pub addr: Ipv6Addr,
+    pub prefix_len: u8,
+}
+
+impl Ipv6Cidr {
+    pub const fn new(addr: Ipv6Addr, prefix_len: u8) -> Self {
+        Self { addr, prefix_len }
+    }
+
+    /// Check whether the address is in the subnet.
+    pub fn contains(&self, addr: Ipv6Addr) -> bool {
+        let mask = match self.prefix_len {
+            0 => 0,
+            len => u128::MAX << (128 - len.min(128) as u32),
+        };
+        u128::from_be_bytes(addr.0) & mask == u128::from_be_bytes(self.addr.0) & mask
+    }
+}
+
+/// IPv4 or IPv6 address.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IpAddr {
+    V4(Ipv4Addr),
+    V6(Ipv6Addr),
+}
+
+impl IpAddr {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            IpAddr::V4(addr) => &addr.0,
+            IpAddr::V6(addr) => &addr.0,
+        }
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        match self {
+            IpAddr::V4(addr) => addr.is_unspecified(),
+            IpAddr::V6(addr) => addr.is_unspecified(),
+        }
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        match self {
+            IpAddr::V4(addr) => addr.is_loopback(),
+            IpAddr::V6(addr) => addr.is_loopback(),
+        }
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        match self {
+            IpAddr::V4(addr) => addr.is_multicast(),
+            IpAddr::V6(addr) => addr.is_multicast(),
+        }
+    }
+
+    /// Check whether the address is the IPv4 limited broadcast address.
+    pub fn is_broadcast(&self) -> bool {
+        matches!(self, IpAddr::V4(addr) if addr.is_broadcast())
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        matches!(self, IpAddr::V4(_))
+    }
+
+    /// Check whether a socket bound to the address receives the packets to `dst`.
+    ///
+    /// The IPv6 wildcard address receives IPv4 packets too, sockets are dual-stack.
+    pub fn accepts(&self, dst: IpAddr) -> bool {
+        match self {
+            _ if *self == dst => true,
+            IpAddr::V4(addr) => addr.is_unspecified() && dst.is_ipv4(),
+            IpAddr::V6(addr) => addr.is_unspecified(),
+        }
+    }
+}
+
+impl Default for IpAddr {
+    fn default() -> Self {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    }
+}
+
+impl From<Ipv4Addr> for IpAddr {
+    fn from(addr: Ipv4Addr) -> Self {
+        IpAddr::V4(addr)
+    }
+}
+
+impl From<Ipv6Addr> for IpAddr {
+    fn from(addr: Ipv6Addr) -> Self {
+        IpAddr::V6(addr)
+    }
+}
+
+impl Display for IpAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            IpAddr::V4(addr) => Display::fmt(addr, f),
+            IpAddr::V6(addr) => Display::fmt(addr, f),
+        }
+    }
+}
+
+impl Debug for IpAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Socket address of either family.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SocketAddr {
+    pub addr: IpAddr,
+    pub port: u16,
+}
+
+impl SocketAddr {
+    pub fn new(addr: impl Into<IpAddr>, port: u16) -> Self {
+        Self {
+            addr: addr.into(),
+            port,
+        }
+    }
+}
+
+impl Display for SocketAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.addr {
+            IpAddr::V4(addr) => write!(f, "{}:{}", addr, self.port),
+            IpAddr::V6(addr) => write!(f, "[{}]:{}", addr, self.port),
+        }
+    }
+}
+
+impl Debug for SocketAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,175 @@
+//! Address resolution protocol.
+//!
+//! Resolved addresses are cached for a while, packets to unresolved
+//! addresses wait in the pending queue until the reply or the timeout. The
+//! cache is shared by the IPv6 neighbour discovery.
+
+use core::{fmt::Display, time::Duration};
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::addr::{Ipv4Addr, MacAddr};
+
+const PACKET_LEN: usize = 28;
+const HTYPE_ETHERNET: u16 = 1;
+const OPER_REQUEST: u16 = 1;
+const OPER_REPLY: u16 = 2;
+
+/// How long a resolved address is valid.
+const ENTRY_LIFETIME: Duration = Duration::from_secs(300);
+/// Interval of the requests of an unresolved address.
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+/// Requests sent before the pending packets are dropped.
+const MAX_REQUESTS: usize = 3;
+/// Max packets waiting for an address.
+const MAX_PENDING: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Request,
+    Reply,
+}
+
+/// An ARP packet of IPv4 over ethernet.
+#[derive(Debug, Clone, Copy)]
+pub struct ArpPacket {
+    pub operation: Operation,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < PACKET_LEN
+            || u16::from_be_bytes([packet[0], packet[1]]) != HTYPE_ETHERNET
+            || u16::from_be_bytes([packet[2], packet[3]]) != crate::ethernet::ETHERTYPE_IPV4
+            || packet[4] != 6
+            || packet[5] != 4
+        {
+            return None;
+        }
+        let operation = match u16::from_be_bytes([packet[6], packet[7]]) {
+            OPER_REQUEST => Operation::Request,
+            OPER_REPLY => Operation::Reply,
+            _ => return None,
+        };
+        Some(Self {
+            operation,
+            sender_mac: MacAddr(packet[8..14].try_into().unwrap()),
+            sender_ip: Ipv4Addr::from_bytes(&packet[14..18]),
+            target_mac: MacAddr(packet[18..24].try_into().unwrap()),
+            target_ip: Ipv4Addr::from_bytes(&packet[24..28]),
+        })
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let operation = match self.operation {
+            Operation::Request => OPER_REQUEST,
+            Operation::Reply => OPER_REPLY,
+        };
+        let mut packet = Vec::with_capacity(PACKET_LEN);
+        packet.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        packet.extend_from_slice(&crate::ethernet::ETHERTYPE_IPV4.to_be_bytes());
+        packet.extend_from_slice(&[6, 4]);
+        packet.extend_from_slice(&operation.to_be_bytes());
+        packet.extend_from_slice(&self.sender_mac.0);
+        packet.extend_from_slice(&self.sender_ip.0);
+        packet.extend_from_slice(&self.target_mac.0);
+        packet.extend_from_slice(&self.target_ip.0);
+        packet
+    }
+}
+
+/// An address being resolved.
+struct Pending {
+    /// IP packets waiting for the address.
+    packets: Vec<Vec<u8>>,
+    /// Time of the last request.
+    requested: Duration,
+    requests: usize,
+}
+
+/// ARP cache of an interface, or the neighbour cache with IPv6 addresses.
+pub struct ArpCache<A = Ipv4Addr> {
+    entries: BTreeMap<A, (MacAddr, Duration)>,
+    pending: BTreeMap<A, Pending>,
+}
+
+impl<A> Default for ArpCache<A> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<A: Ord + Copy + Display> ArpCache<A> {
+    /// Get the resolved address.
+    pub fn lookup(&self, ip: A, now: Duration) -> Option<MacAddr> {
+        self.entries
+            .get(&ip)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(mac, _)| *mac)
+    }
+
+    /// Record the address, returns the packets waiting for it.
+    pub fn insert(&mut self, ip: A, mac: MacAddr, now: Duration) -> Vec<Vec<u8>> {
+        self.entries.insert(ip, (mac, now + ENTRY_LIFETIME));
+        self.pending
+            .remove(&ip)
+            .map(|x| x.packets)
+            .unwrap_or_default()
+    }
+
+    /// Queue the packet until the address is resolved.
+    ///
+    /// Returns true if a request should be sent.
+    pub fn enqueue(&mut self, ip: A, packet: Vec<u8>, now: Duration) -> bool {
+        match self.pending.get_mut(&ip) {
+            Some(pending) => {
+                if pending.packets.len() < MAX_PENDING {
+                    pending.packets.push(packet);
+                }
+                false
+            }
+            None => {
+                self.pending.insert(
+                    ip,
+                    Pending {
+                        packets: vec![packet],
+                        requested: now,
+                        requests: 1,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Drop the expired entries and the packets of the unresolvable addresses.
+    ///
+    /// Returns the addresses to request again.
+    pub fn poll(&mut self, now: Duration) -> Vec<A> {
+        self.entries.retain(|_, (_, expires)| *expires > now);
+        self.pending.retain(|ip, pending| {
+            let keep =
+                pending.requests < MAX_REQUESTS || now < pending.requested + REQUEST_INTERVAL;
+            if !keep {
+                debug!("neighbour {} is unreachable", ip);
+            }
+            keep
+        });
+        self.pending
+            .iter_mut()
+            .filter(|(_, pending)| now >= pending.requested + REQUEST_INTERVAL)
+            .map(|(ip, pending)| {
+                pending.requested = now;
+                pending.requests += 1;
+                *ip
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,42 @@
+//! The internet checksum.
+
+use crate::addr::IpAddr;
+
+/// Add the data to the 32-bit ones' complement sum.
+pub fn sum(data: &[u8], mut acc: u32) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        acc += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        acc += (*last as u32) << 8;
+    }
+    acc
+}
+
+/// Fold the sum to 16 bits and complement it.
+pub fn finish(mut acc: u32) -> u16 {
+    while acc >> 16 != 0 {
+        acc = (acc & 0xffff) + (acc >> 16);
+    }
+    !(acc as u16)
+}
+
+/// Checksum of the data, the checksum field in the data should be 0.
+pub fn checksum(data: &[u8]) -> u16 {
+    finish(sum(data, 0))
+}
+
+/// Sum of the IPv4 or IPv6 pseudo header of TCP, UDP and ICMPv6.
+///
+/// The headers of both versions sum to the same words except the addresses.
+pub fn pseudo_header(src: IpAddr, dst: IpAddr, protocol: u8, len: usize) -> u32 {
+    let acc = sum(src.as_bytes(), 0);
+    let acc = sum(dst.as_bytes(), acc);
+    acc + protocol as u32 + (len >> 16) as u32 + (len & 0xffff) as u32
+}
+
+/// Checksum of the TCP, UDP or ICMPv6 packet with the pseudo header.
+pub fn transport(src: IpAddr, dst: IpAddr, protocol: u8, packet: &[u8]) -> u16 {
+    finish(sum(packet, pseudo_header(src, dst, protocol, packet.len())))
+}
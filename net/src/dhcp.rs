@@ -0,0 +1,364 @@
+//! Dynamic host configuration protocol client.
+//!
+//! The client discovers a server, requests the offered address and renews
+//! the lease from the server at half of its time. It rebinds the lease from
+//! any server by broadcasting at 7/8 of the time. The address is dropped and
+//! the discovery restarts when the lease expires or the server refuses the
+//! request.
+
+use core::time::Duration;
+
+use alloc::vec::Vec;
+
+use crate::{
+    addr::{Ipv4Addr, Ipv4Cidr, MacAddr, SocketAddr},
+    ipv4::{self, Ipv4Header},
+    udp,
+};
+
+pub const CLIENT_PORT: u16 = 68;
+pub const SERVER_PORT: u16 = 67;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+/// Asks the server to broadcast the replies, we can't receive unicast
+/// packets before the address is configured.
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Length of the fixed fields before the options.
+const HEADER_LEN: usize = 240;
+/// Min length of the messages, some servers drop the shorter ones.
+const MIN_LEN: usize = 300;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAM_LIST: u8 = 55;
+const OPT_END: u8 = 255;
+
+/// The first retransmission timeout, it doubles up to [MAX_TIMEOUT].
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(4);
+const MAX_TIMEOUT: Duration = Duration::from_secs(64);
+/// The lease of the servers which don't tell it.
+const DEFAULT_LEASE: Duration = Duration::from_secs(3600);
+
+/// The configuration given by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpConfig {
+    pub ipv4: Ipv4Cidr,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+}
+
+/// Changes of the interface configuration.
+#[derive(Debug)]
+pub enum DhcpEvent {
+    Configured(DhcpConfig),
+    Deconfigured,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// Broadcasting the discovers.
+    Selecting,
+    /// Requesting the offered address.
+    Requesting { addr: Ipv4Addr, server: Ipv4Addr },
+    /// The address is leased by the server.
+    Bound { addr: Ipv4Addr, server: Ipv4Addr },
+    /// Renewing the lease of the address from the server by unicasting.
+    Renewing { addr: Ipv4Addr, server: Ipv4Addr },
+    /// Extending the lease from any server by broadcasting.
+    Rebinding { addr: Ipv4Addr },
+}
+
+struct Reply {
+    message_type: u8,
+    yiaddr: Ipv4Addr,
+    server: Option<Ipv4Addr>,
+    netmask: Option<Ipv4Addr>,
+    router: Option<Ipv4Addr>,
+    dns: Vec<Ipv4Addr>,
+    lease: Option<u32>,
+}
+
+/// The DHCP client of an interface.
+pub struct DhcpClient {
+    mac: MacAddr,
+    xid: u32,
+    state: State,
+    /// When the next message is sent.
+    send_at: Duration,
+    timeout: Duration,
+    /// When the lease is renewed, rebound and when it expires.
+    renew_at: Duration,
+    rebind_at: Duration,
+    expire_at: Duration,
+}
+
+impl DhcpClient {
+    pub fn new(mac: MacAddr, now: Duration) -> Self {
+        let mut client = Self {
+            mac,
+            xid: 0,
+            state: State::Selecting,
+            send_at: now,
+            timeout: INITIAL_TIMEOUT,
+            renew_at: Duration::ZERO,
+            rebind_at: Duration::ZERO,
+            expire_at: Duration::ZERO,
+        };
+        client.restart(now);
+        client
+    }
+
+    /// Start a new transaction, the replies of the older ones are ignored.
+    fn new_xid(&mut self, now: Duration) {
+        let mac = u32::from_be_bytes([self.mac.0[2], self.mac.0[3], self.mac.0[4], self.mac.0[5]]);
+        self.xid = mac ^ (now.as_micros() as u32) ^ self.xid.rotate_left(7);
+    }
+
+    /// Send the next message now.
+    fn send_now(&mut self, now: Duration) {
+        self.send_at = now;
+        self.timeout = INITIAL_TIMEOUT;
+    }
+
+    fn restart(&mut self, now: Duration) {
+        self.new_xid(now);
+        self.state = State::Selecting;
+        self.send_now(now);
+    }
+
+    /// Run the timers, returns the event if the lease expires.
+    pub fn poll(&mut self, now: Duration) -> Option<DhcpEvent> {
+        match self.state {
+            State::Bound { addr, server } if now >= self.renew_at => {
+                debug!("dhcp: renew the lease of {} from {}", addr, server);
+                self.new_xid(now);
+                self.state = State::Renewing { addr, server };
+                self.send_now(now);
+                None
+            }
+            State::Renewing { addr, .. } if now >= self.rebind_at && now < self.expire_at => {
+                debug!("dhcp: rebind the lease of {}", addr);
+                self.new_xid(now);
+                self.state = State::Rebinding { addr };
+                self.send_now(now);
+                None
+            }
+            State::Renewing { addr, .. } | State::Rebinding { addr } if now >= self.expire_at => {
+                warn!("dhcp: the lease of {} expires", addr);
+                self.restart(now);
+                Some(DhcpEvent::Deconfigured)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the IPv4 packet to send and its destination if the timer fires.
+    ///
+    /// The messages are broadcast before the address is leased, the
+    /// renewing requests are unicast to the server.
+    pub fn transmit(&mut self, now: Duration) -> Option<(Ipv4Addr, Vec<u8>)> {
+        if now < self.send_at {
+            return None;
+        }
+        // Discover again if the server doesn't answer the requests.
+        if matches!(self.state, State::Requesting { .. }) && self.timeout >= MAX_TIMEOUT {
+            self.restart(now);
+        }
+        let (src, dst, message) = match self.state {
+            State::Selecting => (
+                Ipv4Addr::UNSPECIFIED,
+                Ipv4Addr::BROADCAST,
+                self.build(DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[]),
+            ),
+            State::Requesting { addr, server } => (
+                Ipv4Addr::UNSPECIFIED,
+                Ipv4Addr::BROADCAST,
+                self.build(
+                    DHCPREQUEST,
+                    Ipv4Addr::UNSPECIFIED,
+                    &[(OPT_REQUESTED_IP, &addr.0), (OPT_SERVER_ID, &server.0)],
+                ),
+            ),
+            State::Bound { .. } => return None,
+            State::Renewing { addr, server } => (addr, server, self.build(DHCPREQUEST, addr, &[])),
+            State::Rebinding { addr } => (
+                addr,
+                Ipv4Addr::BROADCAST,
+                self.build(DHCPREQUEST, addr, &[]),
+            ),
+        };
+        self.send_at = now + self.timeout;
+        self.timeout = (self.timeout * 2).min(MAX_TIMEOUT);
+        let datagram = udp::build(
+            SocketAddr::new(src, CLIENT_PORT),
+            SocketAddr::new(dst, SERVER_PORT),
+            &message,
+        );
+        Some((dst, ipv4::build(src, dst, ipv4::PROTOCOL_UDP, 0, &datagram)))
+    }
+
+    /// Handle the message from the server.
+    pub fn input(&mut self, message: &[u8], now: Duration) -> Option<DhcpEvent> {
+        let reply = self.parse(message)?;
+        match (self.state, reply.message_type) {
+            (State::Selecting, DHCPOFFER) => {
+                let server = reply.server?;
+                debug!("dhcp: {} offers {}", server, reply.yiaddr);
+                self.state = State::Requesting {
+                    addr: reply.yiaddr,
+                    server,
+                };
+                self.send_now(now);
+                None
+            }
+            (
+                State::Requesting { addr, .. }
+                | State::Renewing { addr, .. }
+                | State::Rebinding { addr },
+                DHCPACK,
+            ) if reply.yiaddr == addr => {
+                // The server of a rebound lease may be another one.
+                let server = match (reply.server, self.state) {
+                    (Some(server), _) => server,
+                    (None, State::Requesting { server, .. } | State::Renewing { server, .. }) => {
+                        server
+                    }
+                    (None, _) => return None,
+                };
+                let lease = reply
+                    .lease
+                    .map_or(DEFAULT_LEASE, |x| Duration::from_secs(x as u64));
+                self.renew_at = now + lease / 2;
+                self.rebind_at = now + lease * 7 / 8;
+                self.expire_at = now + lease;
+                self.state = State::Bound { addr, server };
+                let ipv4 = reply
+                    .netmask
+                    .and_then(|x| Ipv4Cidr::from_netmask(addr, x))
+                    .unwrap_or_else(|| Ipv4Cidr::classful(addr));
+                Some(DhcpEvent::Configured(DhcpConfig {
+                    ipv4,
+                    gateway: reply.router,
+                    dns: reply.dns,
+                }))
+            }
+            (
+                State::Requesting { .. } | State::Renewing { .. } | State::Rebinding { .. },
+                DHCPNAK,
+            ) => {
+                warn!("dhcp: the request is refused");
+                let bound = !matches!(self.state, State::Requesting { .. });
+                self.restart(now);
+                bound.then_some(DhcpEvent::Deconfigured)
+            }
+            _ => None,
+        }
+    }
+
+    fn build(&self, message_type: u8, ciaddr: Ipv4Addr, options: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut message = vec![0u8; HEADER_LEN];
+        message[0] = OP_REQUEST;
+        message[1] = HTYPE_ETHERNET;
+        message[2] = self.mac.0.len() as u8;
+        message[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        if ciaddr.is_unspecified() {
+            message[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+        }
+        message[12..16].copy_from_slice(&ciaddr.0);
+        message[28..34].copy_from_slice(&self.mac.0);
+        message[236..240].copy_from_slice(&MAGIC_COOKIE);
+        message.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, message_type]);
+        for (code, value) in options {
+            message.extend_from_slice(&[*code, value.len() as u8]);
+            message.extend_from_slice(value);
+        }
+        message.extend_from_slice(&[
+            OPT_PARAM_LIST,
+            4,
+            OPT_SUBNET_MASK,
+            OPT_ROUTER,
+            OPT_DNS,
+            OPT_LEASE_TIME,
+        ]);
+        message.push(OPT_END);
+        if message.len() < MIN_LEN {
+            message.resize(MIN_LEN, OPT_PAD);
+        }
+        message
+    }
+
+    /// Parse the reply of our transaction.
+    fn parse(&self, message: &[u8]) -> Option<Reply> {
+        if message.len() < HEADER_LEN
+            || message[0] != OP_REPLY
+            || message[4..8] != self.xid.to_be_bytes()
+            || message[28..34] != self.mac.0
+            || message[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let mut reply = Reply {
+            message_type: 0,
+            yiaddr: Ipv4Addr::from_bytes(&message[16..20]),
+            server: None,
+            netmask: None,
+            router: None,
+            dns: Vec::new(),
+            lease: None,
+        };
+        let mut options = &message[HEADER_LEN..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                OPT_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPT_END => break,
+                _ => {}
+            }
+            let (&len, rest) = rest.split_first()?;
+            let value = rest.get(..len as usize)?;
+            options = &rest[len as usize..];
+            let addr = (value.len() >= 4).then(|| Ipv4Addr::from_bytes(&value[..4]));
+            match code {
+                OPT_MESSAGE_TYPE if len == 1 => reply.message_type = value[0],
+                OPT_SUBNET_MASK => reply.netmask = addr,
+                OPT_ROUTER => reply.router = addr,
+                OPT_SERVER_ID => reply.server = addr,
+                OPT_DNS => reply.dns = value.chunks_exact(4).map(Ipv4Addr::from_bytes).collect(),
+                OPT_LEASE_TIME if len == 4 => {
+                    reply.lease = Some(u32::from_be_bytes(value.try_into().unwrap()))
+                }
+                _ => {}
+            }
+        }
+        Some(reply)
+    }
+}
+
+/// Get the DHCP message of the IPv4 packet, returns None for the other packets.
+pub fn client_message(packet: &[u8], csum_valid: bool) -> Option<&[u8]> {
+    let (header, payload) = Ipv4Header::parse(packet)?;
+    if header.protocol != ipv4::PROTOCOL_UDP {
+        return None;
+    }
+    match udp::parse(header.src.into(), header.dst.into(), payload, csum_valid)? {
+        (SERVER_PORT, CLIENT_PORT, message) => Some(message),
+        _ => None,
+    }
+}
@@ -0,0 +1,46 @@
+//! Ethernet II frames.
+
+use alloc::vec::Vec;
+
+use crate::addr::MacAddr;
+
+pub const HEADER_LEN: usize = 14;
+/// Max payload of a frame.
+pub const MTU: usize = 1500;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/// Header of a received frame.
+#[derive(Debug, Clone, Copy)]
+pub struct EthernetHeader {
+    pub dst: MacAddr,
+    pub src: MacAddr,
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    /// Parse the frame, returns the header and the payload.
+    pub fn parse(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < HEADER_LEN {
+            return None;
+        }
+        let header = Self {
+            dst: MacAddr(frame[0..6].try_into().unwrap()),
+            src: MacAddr(frame[6..12].try_into().unwrap()),
+            ethertype: u16::from_be_bytes([frame[12], frame[13]]),
+        };
+        Some((header, &frame[HEADER_LEN..]))
+    }
+}
+
+/// Build the frame with the payload.
+pub fn build(dst: MacAddr, src: MacAddr, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&dst.0);
+    frame.extend_from_slice(&src.0);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
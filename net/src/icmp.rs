@@ -0,0 +1,53 @@
+//! Internet control message protocol.
+//!
+//! Only the echo requests are answered, the other messages are ignored.
+
+use alloc::vec::Vec;
+
+use crate::checksum;
+
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_DEST_UNREACHABLE: u8 = 3;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+
+pub const CODE_PORT_UNREACHABLE: u8 = 3;
+
+const HEADER_LEN: usize = 8;
+
+/// Build the reply of the echo request, returns None for the other messages.
+pub fn echo_reply(packet: &[u8]) -> Option<Vec<u8>> {
+    if packet.len() < HEADER_LEN
+        || packet[0] != TYPE_ECHO_REQUEST
+        || checksum::checksum(packet) != 0
+    {
+        return None;
+    }
+    // The identifier, the sequence and the data are echoed back.
+    let mut reply = packet.to_vec();
+    reply[0] = TYPE_ECHO_REPLY;
+    reply[2..4].fill(0);
+    let sum = checksum::checksum(&reply);
+    reply[2..4].copy_from_slice(&sum.to_be_bytes());
+    Some(reply)
+}
+
+/// Build the port unreachable message of the received IPv4 packet.
+pub fn port_unreachable(ip_packet: &[u8], header_len: usize) -> Vec<u8> {
+    // The IP header and 8 bytes of the payload are quoted.
+    let quoted = &ip_packet[..ip_packet.len().min(header_len + 8)];
+    let mut packet = Vec::with_capacity(HEADER_LEN + quoted.len());
+    packet.extend_from_slice(&[
+        TYPE_DEST_UNREACHABLE,
+        CODE_PORT_UNREACHABLE,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ]);
+    packet.extend_from_slice(quoted);
+    let sum = checksum::checksum(&packet);
+    packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
@@ -0,0 +1,206 @@
+//! Internet control message protocol for IPv6.
+//!
+//! The echo requests are answered and the neighbour discovery messages are
+//! parsed and built here, the other messages are ignored.
+
+use core::time::Duration;
+
+use alloc::vec::Vec;
+
+use crate::{
+    addr::{Ipv6Addr, Ipv6Cidr, MacAddr},
+    checksum,
+    ipv6::{self, NEXT_HEADER_ICMPV6},
+};
+
+pub const TYPE_DEST_UNREACHABLE: u8 = 1;
+pub const TYPE_ECHO_REQUEST: u8 = 128;
+pub const TYPE_ECHO_REPLY: u8 = 129;
+pub const TYPE_ROUTER_SOLICIT: u8 = 133;
+pub const TYPE_ROUTER_ADVERT: u8 = 134;
+pub const TYPE_NEIGHBOR_SOLICIT: u8 = 135;
+pub const TYPE_NEIGHBOR_ADVERT: u8 = 136;
+
+pub const CODE_PORT_UNREACHABLE: u8 = 4;
+
+const OPT_SOURCE_LINK_ADDR: u8 = 1;
+const OPT_TARGET_LINK_ADDR: u8 = 2;
+const OPT_PREFIX_INFO: u8 = 3;
+
+const FLAG_SOLICITED: u8 = 0x40;
+const FLAG_OVERRIDE: u8 = 0x20;
+const PREFIX_FLAG_ON_LINK: u8 = 0x80;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+
+const HEADER_LEN: usize = 8;
+/// The min MTU of IPv6, the errors are truncated to fit in it.
+const MIN_MTU: usize = 1280;
+
+/// Check the checksum of the received message.
+pub fn verify(src: Ipv6Addr, dst: Ipv6Addr, packet: &[u8]) -> bool {
+    checksum::transport(src.into(), dst.into(), NEXT_HEADER_ICMPV6, packet) == 0
+}
+
+/// Fill the checksum of the message to send.
+fn finish(src: Ipv6Addr, dst: Ipv6Addr, mut packet: Vec<u8>) -> Vec<u8> {
+    packet[2..4].fill(0);
+    let sum = checksum::transport(src.into(), dst.into(), NEXT_HEADER_ICMPV6, &packet);
+    packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+/// Build the reply from `src` to `dst` of the echo request, returns None for the other messages.
+pub fn echo_reply(src: Ipv6Addr, dst: Ipv6Addr, packet: &[u8]) -> Option<Vec<u8>> {
+    if packet.len() < HEADER_LEN || packet[0] != TYPE_ECHO_REQUEST {
+        return None;
+    }
+    // The identifier, the sequence and the data are echoed back.
+    let mut reply = packet.to_vec();
+    reply[0] = TYPE_ECHO_REPLY;
+    Some(finish(src, dst, reply))
+}
+
+/// Build the port unreachable message from `src` to `dst` of the received packet.
+pub fn port_unreachable(src: Ipv6Addr, dst: Ipv6Addr, ip_packet: &[u8]) -> Vec<u8> {
+    let quoted = &ip_packet[..ip_packet.len().min(MIN_MTU - ipv6::HEADER_LEN - HEADER_LEN)];
+    let mut packet = vec![0u8; HEADER_LEN];
+    packet[0] = TYPE_DEST_UNREACHABLE;
+    packet[1] = CODE_PORT_UNREACHABLE;
+    packet.extend_from_slice(quoted);
+    finish(src, dst, packet)
+}
+
+/// A prefix advertised by a router.
+#[derive(Debug, Clone, Copy)]
+pub struct PrefixInfo {
+    pub prefix: Ipv6Cidr,
+    /// The addresses in the prefix are on the link.
+    pub on_link: bool,
+    /// The addresses can be configured by SLAAC.
+    pub autonomous: bool,
+    pub valid_lifetime: Duration,
+}
+
+/// A received neighbour discovery message.
+#[derive(Debug)]
+pub enum NdpMessage {
+    RouterAdvert {
+        /// How long the router is the default router, zero if it isn't.
+        lifetime: Duration,
+        source_mac: Option<MacAddr>,
+        prefixes: Vec<PrefixInfo>,
+    },
+    NeighborSolicit {
+        target: Ipv6Addr,
+        source_mac: Option<MacAddr>,
+    },
+    NeighborAdvert {
+        target: Ipv6Addr,
+        target_mac: Option<MacAddr>,
+        solicited: bool,
+        override_: bool,
+    },
+}
+
+/// Convert the lifetime in seconds, all ones means infinity.
+fn lifetime(secs: u32) -> Duration {
+    match secs {
+        u32::MAX => Duration::MAX,
+        secs => Duration::from_secs(secs as u64),
+    }
+}
+
+impl NdpMessage {
+    /// Parse the message, returns None for the other messages and broken messages.
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let (body_len, options) = match *packet.first()? {
+            TYPE_ROUTER_ADVERT => (16, packet.get(16..)?),
+            TYPE_NEIGHBOR_SOLICIT | TYPE_NEIGHBOR_ADVERT => (24, packet.get(24..)?),
+            _ => return None,
+        };
+        let mut source_mac = None;
+        let mut target_mac = None;
+        let mut prefixes = Vec::new();
+        let mut options = options;
+        while options.len() >= 2 {
+            let len = options[1] as usize * 8;
+            if len == 0 || len > options.len() {
+                return None;
+            }
+            let option = &options[..len];
+            match option[0] {
+                OPT_SOURCE_LINK_ADDR if len >= 8 => {
+                    source_mac = Some(MacAddr(option[2..8].try_into().unwrap()))
+                }
+                OPT_TARGET_LINK_ADDR if len >= 8 => {
+                    target_mac = Some(MacAddr(option[2..8].try_into().unwrap()))
+                }
+                OPT_PREFIX_INFO if len == 32 => prefixes.push(PrefixInfo {
+                    prefix: Ipv6Cidr::new(Ipv6Addr::from_bytes(&option[16..32]), option[2]),
+                    on_link: option[3] & PREFIX_FLAG_ON_LINK != 0,
+                    autonomous: option[3] & PREFIX_FLAG_AUTONOMOUS != 0,
+                    valid_lifetime: lifetime(u32::from_be_bytes(option[4..8].try_into().unwrap())),
+                }),
+                _ => {}
+            }
+            options = &options[len..];
+        }
+        let message = match packet[0] {
+            TYPE_ROUTER_ADVERT => NdpMessage::RouterAdvert {
+                lifetime: Duration::from_secs(u16::from_be_bytes([packet[6], packet[7]]) as u64),
+                source_mac,
+                prefixes,
+            },
+            TYPE_NEIGHBOR_SOLICIT => NdpMessage::NeighborSolicit {
+                target: Ipv6Addr::from_bytes(&packet[8..body_len]),
+                source_mac,
+            },
+            _ => NdpMessage::NeighborAdvert {
+                target: Ipv6Addr::from_bytes(&packet[8..body_len]),
+                target_mac,
+                solicited: packet[4] & FLAG_SOLICITED != 0,
+                override_: packet[4] & FLAG_OVERRIDE != 0,
+            },
+        };
+        Some(message)
+    }
+}
+
+fn link_addr_option(packet: &mut Vec<u8>, kind: u8, mac: MacAddr) {
+    packet.extend_from_slice(&[kind, 1]);
+    packet.extend_from_slice(&mac.0);
+}
+
+/// Build the router solicitation, the unspecified source has no link address option.
+pub fn router_solicit(src: Ipv6Addr, dst: Ipv6Addr, mac: MacAddr) -> Vec<u8> {
+    let mut packet = vec![TYPE_ROUTER_SOLICIT, 0, 0, 0, 0, 0, 0, 0];
+    if !src.is_unspecified() {
+        link_addr_option(&mut packet, OPT_SOURCE_LINK_ADDR, mac);
+    }
+    finish(src, dst, packet)
+}
+
+/// Build the solicitation of the link address of `target`.
+pub fn neighbor_solicit(src: Ipv6Addr, dst: Ipv6Addr, target: Ipv6Addr, mac: MacAddr) -> Vec<u8> {
+    let mut packet = vec![TYPE_NEIGHBOR_SOLICIT, 0, 0, 0, 0, 0, 0, 0];
+    packet.extend_from_slice(&target.0);
+    if !src.is_unspecified() {
+        link_addr_option(&mut packet, OPT_SOURCE_LINK_ADDR, mac);
+    }
+    finish(src, dst, packet)
+}
+
+/// Build the advertisement of our link address of `target`.
+pub fn neighbor_advert(
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    target: Ipv6Addr,
+    mac: MacAddr,
+    solicited: bool,
+) -> Vec<u8> {
+    let flags = FLAG_OVERRIDE | if solicited { FLAG_SOLICITED } else { 0 };
+    let mut packet = vec![TYPE_NEIGHBOR_ADVERT, 0, 0, 0, flags, 0, 0, 0];
+    packet.extend_from_slice(&target.0);
+    link_addr_option(&mut packet, OPT_TARGET_LINK_ADDR, mac);
+    finish(src, dst, packet)
+}
@@ -0,0 +1,475 @@
+//! Network interfaces.
+
+use core::{cell::RefCell, time::Duration};
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use drivers_base::NetDriver;
+
+use crate::{
+    addr::{Ipv4Addr, Ipv4Cidr, Ipv6Addr, Ipv6Cidr, MacAddr},
+    arp::{ArpCache, ArpPacket, Operation},
+    dhcp::{DhcpClient, DhcpEvent},
+    ethernet,
+    icmpv6::{self, NdpMessage},
+    ipv6::{self, Ipv6Header},
+    SockError,
+};
+
+/// Router solicitations sent before a router advertises itself.
+const MAX_RTR_SOLICITATIONS: usize = 3;
+const RTR_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+/// The prefix length of the addresses configured by SLAAC.
+const SLAAC_PREFIX_LEN: u8 = 64;
+
+/// State of the IPv6 stateless address autoconfiguration.
+///
+/// Duplicate address detection isn't done, the addresses are used at once.
+#[derive(Default)]
+struct Slaac {
+    /// Router solicitations sent, and when the next one is sent.
+    solicitations: usize,
+    solicit_at: Duration,
+    /// Expiry of the addresses configured from the advertised prefixes.
+    expires: BTreeMap<Ipv6Addr, Duration>,
+    /// Expiry of the default router.
+    router_expires: Duration,
+}
+
+/// A network interface over a [NetDriver].
+pub struct Interface {
+    pub name: String,
+    driver: Arc<dyn NetDriver>,
+    pub mac: MacAddr,
+    /// The address and the subnet of the interface.
+    pub ipv4: Option<Ipv4Cidr>,
+    /// The default gateway of the destinations outside the subnet.
+    pub gateway: Option<Ipv4Addr>,
+    /// The name servers.
+    pub dns: Vec<Ipv4Addr>,
+    /// The IPv6 addresses, the link-local address is configured on creation.
+    pub ipv6: Vec<Ipv6Cidr>,
+    /// The default router of IPv6, learned from the router advertisements.
+    pub gateway6: Option<Ipv6Addr>,
+    /// The client configuring the interface by DHCP.
+    dhcp: Option<DhcpClient>,
+    /// The sent frames come back to the interface.
+    loopback: bool,
+    arp: ArpCache,
+    ndp: ArpCache<Ipv6Addr>,
+    slaac: Slaac,
+    /// The sent frames are kept for the packet sockets and the tap.
+    pub(crate) capture: bool,
+    sent: RefCell<Vec<Vec<u8>>>,
+}
+
+impl Interface {
+    /// Create an interface without an IPv4 address.
+    ///
+    /// The interfaces except the loopback have the IPv6 link-local address
+    /// and configure the global addresses by SLAAC.
+    pub fn new(name: &str, driver: Arc<dyn NetDriver>) -> Self {
+        let mac = MacAddr(driver.mac_address());
+        let loopback = driver.is_loopback();
+        let ipv6 = match loopback {
+            true => Vec::new(),
+            false => vec![Ipv6Cidr::new(Ipv6Addr::link_local(mac), SLAAC_PREFIX_LEN)],
+        };
+        Self {
+            name: String::from(name),
+            mac,
+            loopback,
+            driver,
+            ipv4: None,
+            gateway: None,
+            dns: Vec::new(),
+            ipv6,
+            gateway6: None,
+            dhcp: None,
+            arp: ArpCache::default(),
+            ndp: ArpCache::default(),
+            slaac: Slaac::default(),
+            capture: false,
+            sent: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn driver(&self) -> &Arc<dyn NetDriver> {
+        &self.driver
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.loopback
+    }
+
+    /// Get the address of the interface.
+    pub fn addr(&self) -> Option<Ipv4Addr> {
+        self.ipv4.map(|x| x.addr)
+    }
+
+    pub fn has_ipv6(&self, addr: Ipv6Addr) -> bool {
+        self.ipv6.iter().any(|x| x.addr == addr)
+    }
+
+    /// Select the IPv6 source address of the packets to the destination.
+    ///
+    /// The link-local address is used on the link, the others need a global address.
+    pub fn addr6(&self, dst: Ipv6Addr) -> Option<Ipv6Addr> {
+        let link_scope = dst.is_link_local() || dst.is_multicast();
+        self.ipv6
+            .iter()
+            .find(|x| x.addr.is_link_local() == link_scope || self.loopback)
+            .map(|x| x.addr)
+    }
+
+    /// Set the address of the interface, it stops the DHCP client.
+    pub fn set_ipv4(&mut self, ipv4: Option<Ipv4Cidr>) {
+        self.dhcp = None;
+        self.ipv4 = ipv4;
+    }
+
+    /// Configure the interface by DHCP, the address is dropped until the lease.
+    pub fn start_dhcp(&mut self, now: Duration) {
+        self.ipv4 = None;
+        self.gateway = None;
+        self.dns.clear();
+        self.dhcp = Some(DhcpClient::new(self.mac, now));
+    }
+
+    pub fn is_dhcp(&self) -> bool {
+        self.dhcp.is_some()
+    }
+
+    /// Get the address the packet to the destination is sent to on the link.
+    pub(crate) fn next_hop(&self, dst: Ipv4Addr) -> Option<Ipv4Addr> {
+        let cidr = self.ipv4?;
+        match cidr.contains(dst) || dst.is_broadcast() {
+            true => Some(dst),
+            false => self.gateway,
+        }
+    }
+
+    /// Get the IPv6 address the packet to the destination is sent to on the link.
+    fn next_hop6(&self, dst: Ipv6Addr) -> Option<Ipv6Addr> {
+        match dst.is_link_local() || self.ipv6.iter().any(|x| x.contains(dst)) {
+            true => Some(dst),
+            false => self.gateway6,
+        }
+    }
+
+    pub(crate) fn send_frame(&self, dst: MacAddr, ethertype: u16, payload: &[u8]) {
+        self.send_raw(ethernet::build(dst, self.mac, ethertype, payload));
+    }
+
+    /// Send the frame built by the caller.
+    pub(crate) fn send_raw(&self, frame: Vec<u8>) {
+        if let Err(err) = self.driver.send(&frame) {
+            debug!("{}: can't send the frame: {:?}", self.name, err);
+        }
+        if self.capture {
+            self.sent.borrow_mut().push(frame);
+        }
+    }
+
+    /// Take the frames sent since the last call.
+    pub(crate) fn take_sent(&mut self) -> Vec<Vec<u8>> {
+        core::mem::take(self.sent.get_mut())
+    }
+
+    fn send_arp(&self, operation: Operation, target_mac: MacAddr, target_ip: Ipv4Addr) {
+        let packet = ArpPacket {
+            operation,
+            sender_mac: self.mac,
+            sender_ip: self.addr().unwrap_or_default(),
+            target_mac,
+            target_ip,
+        };
+        let dst = match operation {
+            Operation::Request => MacAddr::BROADCAST,
+            Operation::Reply => target_mac,
+        };
+        self.send_frame(dst, ethernet::ETHERTYPE_ARP, &packet.build());
+    }
+
+    /// Send the IPv4 packet, it waits for the address resolution if needed.
+    pub(crate) fn send_ipv4(
+        &mut self,
+        dst: Ipv4Addr,
+        packet: Vec<u8>,
+        now: Duration,
+    ) -> Result<(), SockError> {
+        if self.loopback {
+            self.send_frame(self.mac, ethernet::ETHERTYPE_IPV4, &packet);
+            return Ok(());
+        }
+        let next_hop = self.next_hop(dst).ok_or(SockError::Unreachable)?;
+        let is_broadcast =
+            next_hop.is_broadcast() || self.ipv4.is_some_and(|x| x.broadcast() == next_hop);
+        if is_broadcast {
+            self.send_frame(MacAddr::BROADCAST, ethernet::ETHERTYPE_IPV4, &packet);
+            return Ok(());
+        }
+        match self.arp.lookup(next_hop, now) {
+            Some(mac) => self.send_frame(mac, ethernet::ETHERTYPE_IPV4, &packet),
+            None => {
+                if self.arp.enqueue(next_hop, packet, now) {
+                    self.send_arp(Operation::Request, MacAddr::default(), next_hop);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the IPv6 packet, it waits for the neighbour discovery if needed.
+    pub(crate) fn send_ipv6(
+        &mut self,
+        dst: Ipv6Addr,
+        packet: Vec<u8>,
+        now: Duration,
+    ) -> Result<(), SockError> {
+        if self.loopback {
+            self.send_frame(self.mac, ethernet::ETHERTYPE_IPV6, &packet);
+            return Ok(());
+        }
+        if dst.is_multicast() {
+            let mac = MacAddr::ipv6_multicast(dst);
+            self.send_frame(mac, ethernet::ETHERTYPE_IPV6, &packet);
+            return Ok(());
+        }
+        let next_hop = self.next_hop6(dst).ok_or(SockError::Unreachable)?;
+        match self.ndp.lookup(next_hop, now) {
+            Some(mac) => self.send_frame(mac, ethernet::ETHERTYPE_IPV6, &packet),
+            None => {
+                if self.ndp.enqueue(next_hop, packet, now) {
+                    self.solicit_neighbor(next_hop);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the neighbour discovery message, `dst_mac` is the link address of a unicast `dst`.
+    fn send_ndp(&self, src: Ipv6Addr, dst: Ipv6Addr, dst_mac: MacAddr, message: &[u8]) {
+        let packet = ipv6::build(
+            src,
+            dst,
+            ipv6::NEXT_HEADER_ICMPV6,
+            ipv6::NDP_HOP_LIMIT,
+            message,
+        );
+        self.send_frame(dst_mac, ethernet::ETHERTYPE_IPV6, &packet);
+    }
+
+    fn solicit_neighbor(&self, target: Ipv6Addr) {
+        let src = self.addr6(target).unwrap_or(Ipv6Addr::UNSPECIFIED);
+        let dst = target.solicited_node();
+        let message = icmpv6::neighbor_solicit(src, dst, target, self.mac);
+        self.send_ndp(src, dst, MacAddr::ipv6_multicast(dst), &message);
+    }
+
+    /// Handle the received neighbour discovery message.
+    ///
+    /// Returns false if the IPv6 packet isn't a neighbour discovery message.
+    pub(crate) fn handle_ndp(&mut self, packet: &[u8], now: Duration) -> bool {
+        let (header, payload) = match Ipv6Header::parse(packet) {
+            Some(x) if x.0.next_header == ipv6::NEXT_HEADER_ICMPV6 => x,
+            _ => return false,
+        };
+        let message = match NdpMessage::parse(payload) {
+            Some(message) => message,
+            None => return false,
+        };
+        // The messages from the other links are forged.
+        if header.hop_limit != ipv6::NDP_HOP_LIMIT
+            || !icmpv6::verify(header.src, header.dst, payload)
+        {
+            return true;
+        }
+        match message {
+            NdpMessage::NeighborSolicit { target, source_mac } => {
+                // Duplicate address detection of the other hosts is ignored.
+                if !self.has_ipv6(target) || header.src.is_unspecified() {
+                    return true;
+                }
+                let mac = match source_mac {
+                    Some(mac) => mac,
+                    None => return true,
+                };
+                self.learn_neighbor(header.src, mac, now);
+                let message = icmpv6::neighbor_advert(target, header.src, target, self.mac, true);
+                self.send_ndp(target, header.src, mac, &message);
+            }
+            NdpMessage::NeighborAdvert {
+                target,
+                target_mac: Some(mac),
+                solicited,
+                ..
+            } => {
+                // Refresh the known address, but don't learn the others.
+                if solicited || self.ndp.lookup(target, now).is_some() {
+                    self.learn_neighbor(target, mac, now);
+                }
+            }
+            NdpMessage::NeighborAdvert { .. } => {}
+            NdpMessage::RouterAdvert {
+                lifetime,
+                source_mac,
+                prefixes,
+            } => {
+                if !header.src.is_link_local() {
+                    return true;
+                }
+                if let Some(mac) = source_mac {
+                    self.learn_neighbor(header.src, mac, now);
+                }
+                self.slaac.solicitations = MAX_RTR_SOLICITATIONS;
+                if !lifetime.is_zero() {
+                    if self.gateway6 != Some(header.src) {
+                        info!("{}: ipv6 default router {}", self.name, header.src);
+                    }
+                    self.gateway6 = Some(header.src);
+                    self.slaac.router_expires = now.saturating_add(lifetime);
+                } else if self.gateway6 == Some(header.src) {
+                    self.gateway6 = None;
+                }
+                for info in prefixes {
+                    if !info.autonomous
+                        || info.prefix.prefix_len != SLAAC_PREFIX_LEN
+                        || info.prefix.addr.is_link_local()
+                        || info.valid_lifetime.is_zero()
+                    {
+                        continue;
+                    }
+                    let addr = Ipv6Addr::from_eui64(info.prefix.addr, self.mac);
+                    if !self.has_ipv6(addr) {
+                        info!("{}: slaac address {}/{}", self.name, addr, SLAAC_PREFIX_LEN);
+                        self.ipv6.push(Ipv6Cidr::new(addr, SLAAC_PREFIX_LEN));
+                    }
+                    let expires = now.saturating_add(info.valid_lifetime);
+                    self.slaac.expires.insert(addr, expires);
+                }
+            }
+        }
+        true
+    }
+
+    /// Record the link address of the neighbour and send the packets waiting for it.
+    fn learn_neighbor(&mut self, addr: Ipv6Addr, mac: MacAddr, now: Duration) {
+        for pending in self.ndp.insert(addr, mac, now) {
+            self.send_frame(mac, ethernet::ETHERTYPE_IPV6, &pending);
+        }
+    }
+
+    /// Solicit the routers, resolve the neighbours again and expire the
+    /// configured addresses.
+    pub(crate) fn poll_ndp(&mut self, now: Duration) {
+        if self.loopback {
+            return;
+        }
+        for addr in self.ndp.poll(now) {
+            self.solicit_neighbor(addr);
+        }
+        if self.slaac.solicitations < MAX_RTR_SOLICITATIONS && now >= self.slaac.solicit_at {
+            self.slaac.solicitations += 1;
+            self.slaac.solicit_at = now + RTR_SOLICITATION_INTERVAL;
+            let dst = Ipv6Addr::ALL_ROUTERS;
+            let src = self.addr6(dst).unwrap_or(Ipv6Addr::UNSPECIFIED);
+            let message = icmpv6::router_solicit(src, dst, self.mac);
+            self.send_ndp(src, dst, MacAddr::ipv6_multicast(dst), &message);
+        }
+        if self.gateway6.is_some() && now >= self.slaac.router_expires {
+            info!("{}: ipv6 default router expires", self.name);
+            self.gateway6 = None;
+        }
+        let expired: Vec<Ipv6Addr> = self
+            .slaac
+            .expires
+            .iter()
+            .filter(|(_, expires)| now >= **expires)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in expired {
+            info!("{}: slaac address {} expires", self.name, addr);
+            self.slaac.expires.remove(&addr);
+            self.ipv6.retain(|x| x.addr != addr);
+        }
+    }
+
+    /// Handle the received ARP packet.
+    pub(crate) fn handle_arp(&mut self, payload: &[u8], now: Duration) {
+        let packet = match ArpPacket::parse(payload) {
+            Some(packet) => packet,
+            None => return,
+        };
+        let addr = match self.addr() {
+            Some(addr) => addr,
+            None => return,
+        };
+        if packet.target_ip != addr {
+            // Refresh the known address, but don't learn the others.
+            if self.arp.lookup(packet.sender_ip, now).is_some() {
+                self.arp.insert(packet.sender_ip, packet.sender_mac, now);
+            }
+            return;
+        }
+        for pending in self.arp.insert(packet.sender_ip, packet.sender_mac, now) {
+            self.send_frame(packet.sender_mac, ethernet::ETHERTYPE_IPV4, &pending);
+        }
+        if packet.operation == Operation::Request {
+            self.send_arp(Operation::Reply, packet.sender_mac, packet.sender_ip);
+        }
+    }
+
+    /// Request the unresolved addresses again.
+    pub(crate) fn poll_arp(&mut self, now: Duration) {
+        for ip in self.arp.poll(now) {
+            self.send_arp(Operation::Request, MacAddr::default(), ip);
+        }
+    }
+
+    /// Handle the message from the DHCP server.
+    pub(crate) fn handle_dhcp(&mut self, message: &[u8], now: Duration) {
+        if let Some(event) = self.dhcp.as_mut().and_then(|x| x.input(message, now)) {
+            self.apply_dhcp(event);
+        }
+        self.poll_dhcp(now);
+    }
+
+    /// Run the DHCP timers and send the messages.
+    pub(crate) fn poll_dhcp(&mut self, now: Duration) {
+        if let Some(event) = self.dhcp.as_mut().and_then(|x| x.poll(now)) {
+            self.apply_dhcp(event);
+        }
+        if let Some((dst, packet)) = self.dhcp.as_mut().and_then(|x| x.transmit(now)) {
+            match dst.is_broadcast() {
+                true => self.send_frame(MacAddr::BROADCAST, ethernet::ETHERTYPE_IPV4, &packet),
+                false => {
+                    if let Err(err) = self.send_ipv4(dst, packet, now) {
+                        debug!("{}: can't send the dhcp request: {:?}", self.name, err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_dhcp(&mut self, event: DhcpEvent) {
+        match event {
+            DhcpEvent::Configured(config) => {
+                if self.ipv4 != Some(config.ipv4) {
+                    info!(
+                        "{}: dhcp address {:?} gateway {:?} dns {:?}",
+                        self.name, config.ipv4, config.gateway, config.dns
+                    );
+                }
+                self.ipv4 = Some(config.ipv4);
+                self.gateway = config.gateway;
+                self.dns = config.dns;
+            }
+            DhcpEvent::Deconfigured => {
+                info!("{}: dhcp address {:?} is dropped", self.name, self.ipv4);
+                self.ipv4 = None;
+                self.gateway = None;
+                self.dns.clear();
+            }
+        }
+    }
+}
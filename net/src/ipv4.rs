@@ -0,0 +1,79 @@
+//! IPv4 packets.
+//!
+//! Fragmented packets are not reassembled, they are dropped. Sent packets
+//! never exceed the MTU so they are not fragmented either.
+
+use alloc::vec::Vec;
+
+use crate::{addr::Ipv4Addr, checksum};
+
+pub const HEADER_LEN: usize = 20;
+pub const DEFAULT_TTL: u8 = 64;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+/// Don't fragment.
+const FLAG_DF: u16 = 0x4000;
+/// More fragments.
+const FLAG_MF: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1fff;
+
+/// Header of a received packet.
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv4Header {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+}
+
+impl Ipv4Header {
+    /// Parse and verify the packet, returns the header and the payload.
+    ///
+    /// Returns None for broken packets and fragments.
+    pub fn parse(packet: &[u8]) -> Option<(Self, &[u8])> {
+        if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (packet[0] & 0xf) as usize * 4;
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        if header_len < HEADER_LEN || total_len < header_len || total_len > packet.len() {
+            return None;
+        }
+        if checksum::checksum(&packet[..header_len]) != 0 {
+            return None;
+        }
+        let flags = u16::from_be_bytes([packet[6], packet[7]]);
+        if flags & FLAG_MF != 0 || flags & FRAGMENT_OFFSET != 0 {
+            debug!("drop the IPv4 fragment");
+            return None;
+        }
+        let header = Self {
+            src: Ipv4Addr::from_bytes(&packet[12..16]),
+            dst: Ipv4Addr::from_bytes(&packet[16..20]),
+            protocol: packet[9],
+            ttl: packet[8],
+        };
+        // Ethernet pads the short frames, the padding isn't a part of the payload.
+        Some((header, &packet[header_len..total_len]))
+    }
+}
+
+/// Build the packet with the payload.
+pub fn build(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let total_len = (HEADER_LEN + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&FLAG_DF.to_be_bytes());
+    packet.extend_from_slice(&[DEFAULT_TTL, protocol, 0, 0]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    let sum = checksum::checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
@@ -0,0 +1,84 @@
+//! IPv6 packets.
+//!
+//! The hop-by-hop, routing and destination options are skipped, fragmented
+//! packets are dropped. Sent packets never exceed the MTU.
+
+use alloc::vec::Vec;
+
+use crate::addr::Ipv6Addr;
+
+pub const HEADER_LEN: usize = 40;
+pub const DEFAULT_HOP_LIMIT: u8 = 64;
+/// The hop limit of the neighbour discovery messages, they never leave the link.
+pub const NDP_HOP_LIMIT: u8 = 255;
+
+pub const NEXT_HEADER_ICMPV6: u8 = 58;
+
+const NEXT_HEADER_HOP_BY_HOP: u8 = 0;
+const NEXT_HEADER_ROUTING: u8 = 43;
+const NEXT_HEADER_FRAGMENT: u8 = 44;
+const NEXT_HEADER_DEST_OPTIONS: u8 = 60;
+
+/// Header of a received packet.
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv6Header {
+    pub src: Ipv6Addr,
+    pub dst: Ipv6Addr,
+    /// The protocol of the payload after the extension headers.
+    pub next_header: u8,
+    pub hop_limit: u8,
+}
+
+impl Ipv6Header {
+    /// Parse the packet, returns the header and the payload.
+    ///
+    /// Returns None for broken packets and fragments.
+    pub fn parse(packet: &[u8]) -> Option<(Self, &[u8])> {
+        if packet.len() < HEADER_LEN || packet[0] >> 4 != 6 {
+            return None;
+        }
+        let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+        // Ethernet pads the short frames, the padding isn't a part of the payload.
+        let mut payload = packet.get(HEADER_LEN..HEADER_LEN + payload_len)?;
+        let mut next_header = packet[6];
+        loop {
+            match next_header {
+                NEXT_HEADER_HOP_BY_HOP | NEXT_HEADER_ROUTING | NEXT_HEADER_DEST_OPTIONS => {
+                    let len = (*payload.get(1)? as usize + 1) * 8;
+                    next_header = payload[0];
+                    payload = payload.get(len..)?;
+                }
+                NEXT_HEADER_FRAGMENT => {
+                    debug!("drop the IPv6 fragment");
+                    return None;
+                }
+                _ => break,
+            }
+        }
+        let header = Self {
+            src: Ipv6Addr::from_bytes(&packet[8..24]),
+            dst: Ipv6Addr::from_bytes(&packet[24..40]),
+            next_header,
+            hop_limit: packet[7],
+        };
+        Some((header, payload))
+    }
+}
+
+/// Build the packet with the payload.
+pub fn build(
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    next_header: u8,
+    hop_limit: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    packet.extend_from_slice(&[0x60, 0, 0, 0]);
+    packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&[next_header, hop_limit]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    packet.extend_from_slice(payload);
+    packet
+}
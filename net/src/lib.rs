@@ -0,0 +1,86 @@
+//! Network subsystem.
+//!
+//! A dual-stack TCP/IP stack over the [NetDriver]s, includes ethernet, ARP,
+//! IPv4, IPv6 with the neighbour discovery and SLAAC, ICMP echo, UDP, TCP,
+//! a DHCP client and the packet sockets of the raw frames. The stack has no
+//! thread of its own, it is driven by [NetStack::poll] from the device
+//! interrupts and the timer.
+
+#![no_std]
+
+#[macro_use]
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+pub mod addr;
+pub mod arp;
+pub mod checksum;
+pub mod dhcp;
+pub mod ethernet;
+pub mod icmp;
+pub mod icmpv6;
+pub mod iface;
+pub mod ipv4;
+pub mod ipv6;
+pub mod packet;
+pub mod pcap;
+pub mod socket;
+pub mod stack;
+pub mod tcp;
+pub mod udp;
+
+pub use addr::{IpAddr, Ipv4Addr, Ipv4Cidr, Ipv6Addr, Ipv6Cidr, MacAddr, SocketAddr};
+pub use drivers_base::NetDriver;
+pub use iface::Interface;
+pub use packet::{FrameTap, LinkAddr, PacketType};
+pub use socket::{PacketSocket, TcpSocket, UdpSocket};
+pub use stack::NetStack;
+pub use tcp::TcpState;
+
+/// Errors of the socket operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SockError {
+    /// The operation would block on a nonblocking socket.
+    WouldBlock,
+    /// The connection is being established.
+    InProgress,
+    AddrInUse,
+    AddrNotAvail,
+    ConnRefused,
+    ConnReset,
+    NotConnected,
+    IsConnected,
+    /// The sending side is shut down.
+    Shutdown,
+    InvalidState,
+    /// No route to the destination.
+    Unreachable,
+    TimedOut,
+    InvalidParam,
+    /// The message is too large for the buffer.
+    NoBuffer,
+}
+
+/// The first port of the ephemeral ports.
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// Allocate an ephemeral port which isn't in use.
+///
+/// `next` is the port to try first, it is advanced past the allocated port.
+pub(crate) fn ephemeral_port(
+    next: &mut u16,
+    in_use: impl Fn(u16) -> bool,
+) -> Result<u16, SockError> {
+    for _ in EPHEMERAL_PORT_START..=u16::MAX {
+        if *next < EPHEMERAL_PORT_START {
+            *next = EPHEMERAL_PORT_START;
+        }
+        let port = *next;
+        *next = next.wrapping_add(1);
+        if !in_use(port) {
+            return Ok(port);
+        }
+    }
+    Err(SockError::AddrInUse)
+}
@@ -0,0 +1,202 @@
+//! Packet sockets, the raw frames of the interfaces.
+//!
+//! The sockets of [ETH_P_ALL] see the received and the sent frames of every
+//! protocol, the other sockets see the received frames of their protocol.
+//! The frames are seen before the stack filters them by the destination.
+
+use core::time::Duration;
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+
+use crate::{addr::MacAddr, ethernet, SockError};
+
+/// The protocol of all the frames.
+pub const ETH_P_ALL: u16 = 0x0003;
+
+/// Max bytes of the frames waiting in a socket.
+const RECV_BUFFER_SIZE: usize = 0x40000;
+
+/// The class of a frame, `sll_pkttype`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum PacketType {
+    /// Received by this host.
+    #[default]
+    Host = 0,
+    Broadcast = 1,
+    Multicast = 2,
+    /// Received by the interface for another host.
+    OtherHost = 3,
+    /// Sent by this host.
+    Outgoing = 4,
+}
+
+impl PacketType {
+    /// Classify the frame to `dst` received by the interface of `mac`.
+    pub fn of(dst: MacAddr, mac: MacAddr) -> Self {
+        match dst {
+            _ if dst == mac => PacketType::Host,
+            _ if dst.is_broadcast() => PacketType::Broadcast,
+            _ if dst.is_multicast() => PacketType::Multicast,
+            _ => PacketType::OtherHost,
+        }
+    }
+}
+
+/// The link address of a frame, `sockaddr_ll`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkAddr {
+    /// The ethertype.
+    pub protocol: u16,
+    /// Index of the interface, None is any interface.
+    pub iface: Option<usize>,
+    pub pkttype: PacketType,
+    /// The source of the received frame, the destination of the sent frame.
+    pub mac: MacAddr,
+}
+
+/// A consumer of all the frames of the interfaces, such as a capture file.
+pub trait FrameTap: Send + Sync {
+    /// Take the received or sent frame of the interface.
+    fn tap(&self, iface: usize, frame: &[u8], now: Duration);
+}
+
+struct PacketState {
+    /// The frames include the ethernet header, `SOCK_RAW`.
+    raw: bool,
+    protocol: u16,
+    iface: Option<usize>,
+    /// Received frames with their addresses.
+    rx: VecDeque<(LinkAddr, Vec<u8>)>,
+    rx_bytes: usize,
+}
+
+/// The packet sockets.
+#[derive(Default)]
+pub struct Packet {
+    sockets: BTreeMap<usize, PacketState>,
+    next_handle: usize,
+    /// Sockets receive frames, their waiters should be woken up.
+    pub changed: bool,
+}
+
+impl Packet {
+    fn socket(&mut self, handle: usize) -> Result<&mut PacketState, SockError> {
+        self.sockets.get_mut(&handle).ok_or(SockError::InvalidState)
+    }
+
+    /// Create a socket receiving the frames of the protocol, zero receives nothing.
+    pub fn create(&mut self, raw: bool, protocol: u16) -> usize {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.sockets.insert(
+            handle,
+            PacketState {
+                raw,
+                protocol,
+                iface: None,
+                rx: VecDeque::new(),
+                rx_bytes: 0,
+            },
+        );
+        handle
+    }
+
+    /// Bind the socket to the protocol and the interface, zero keeps the protocol.
+    pub fn bind(
+        &mut self,
+        handle: usize,
+        protocol: u16,
+        iface: Option<usize>,
+    ) -> Result<(), SockError> {
+        let socket = self.socket(handle)?;
+        if protocol != 0 {
+            socket.protocol = protocol;
+        }
+        socket.iface = iface;
+        Ok(())
+    }
+
+    pub fn close(&mut self, handle: usize) {
+        self.sockets.remove(&handle);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    pub fn is_raw(&self, handle: usize) -> bool {
+        self.sockets.get(&handle).is_some_and(|x| x.raw)
+    }
+
+    pub fn local_addr(&self, handle: usize) -> Option<LinkAddr> {
+        self.sockets.get(&handle).map(|x| LinkAddr {
+            protocol: x.protocol,
+            iface: x.iface,
+            ..Default::default()
+        })
+    }
+
+    /// Take a frame, the part exceeding the buffer is discarded.
+    ///
+    /// Returns the length of the frame and its address.
+    pub fn recv(&mut self, handle: usize, buf: &mut [u8]) -> Result<(usize, LinkAddr), SockError> {
+        let socket = self.socket(handle)?;
+        let (addr, data) = socket.rx.pop_front().ok_or(SockError::WouldBlock)?;
+        socket.rx_bytes -= data.len();
+        let len = buf.len().min(data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((data.len(), addr))
+    }
+
+    /// Get the length of the next frame.
+    pub fn recv_queued(&self, handle: usize) -> usize {
+        self.sockets
+            .get(&handle)
+            .and_then(|x| x.rx.front())
+            .map_or(0, |(_, data)| data.len())
+    }
+
+    pub fn readable(&self, handle: usize) -> bool {
+        self.sockets.get(&handle).is_some_and(|x| !x.rx.is_empty())
+    }
+
+    /// Deliver the frame of the interface to the sockets of its protocol.
+    pub fn input(&mut self, iface: usize, frame: &[u8], pkttype: PacketType) {
+        let Some((header, payload)) = ethernet::EthernetHeader::parse(frame) else {
+            return;
+        };
+        let addr = LinkAddr {
+            protocol: header.ethertype,
+            iface: Some(iface),
+            pkttype,
+            mac: match pkttype {
+                PacketType::Outgoing => header.dst,
+                _ => header.src,
+            },
+        };
+        for socket in self.sockets.values_mut() {
+            let protocol_matches = match socket.protocol {
+                ETH_P_ALL => true,
+                // The sent frames are only seen by the sockets of all the protocols.
+                protocol => protocol == header.ethertype && pkttype != PacketType::Outgoing,
+            };
+            if !protocol_matches || socket.iface.is_some_and(|x| x != iface) {
+                continue;
+            }
+            let data = match socket.raw {
+                true => frame,
+                false => payload,
+            };
+            if socket.rx_bytes + data.len() > RECV_BUFFER_SIZE {
+                continue;
+            }
+            socket.rx_bytes += data.len();
+            socket.rx.push_back((addr, data.to_vec()));
+            self.changed = true;
+        }
+    }
+}
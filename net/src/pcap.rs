@@ -0,0 +1,41 @@
+//! The pcap capture file format of the ethernet frames.
+//!
+//! The file is the header followed by a record of each frame, the fields
+//! are little endian.
+
+use core::time::Duration;
+
+use alloc::vec::Vec;
+
+const MAGIC: u32 = 0xa1b2c3d4;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+/// Max bytes of a captured frame.
+const SNAPLEN: u32 = 0xffff;
+const LINKTYPE_ETHERNET: u32 = 1;
+
+pub const HEADER_LEN: usize = 24;
+
+/// Build the header of the file.
+pub fn header() -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    header[4..6].copy_from_slice(&VERSION_MAJOR.to_le_bytes());
+    header[6..8].copy_from_slice(&VERSION_MINOR.to_le_bytes());
+    // The timezone and the accuracy of the timestamps are zero.
+    header[16..20].copy_from_slice(&SNAPLEN.to_le_bytes());
+    header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    header
+}
+
+/// Build the record of the frame captured at `now`, the frame is truncated to the snap length.
+pub fn record(now: Duration, frame: &[u8]) -> Vec<u8> {
+    let captured = &frame[..frame.len().min(SNAPLEN as usize)];
+    let mut record = Vec::with_capacity(16 + captured.len());
+    record.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+    record.extend_from_slice(&now.subsec_micros().to_le_bytes());
+    record.extend_from_slice(&(captured.len() as u32).to_le_bytes());
+    record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    record.extend_from_slice(captured);
+    record
+}
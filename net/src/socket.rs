@@ -0,0 +1,454 @@
+//! Socket handles of the network stack.
+//!
+//! The socket operations block by polling the stack and sleeping in
+//! [DSched::sleep] unless the socket is nonblocking, the timeouts of the
+//! socket bound the wait.
+
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use alloc::sync::Arc;
+use drivers_base::DSched;
+use lock_api::RawMutex;
+
+use crate::{
+    addr::{IpAddr, SocketAddr},
+    ethernet, ipv4, ipv6,
+    packet::LinkAddr,
+    stack::{NetStack, StackInner},
+    tcp::TcpState,
+    udp, SockError,
+};
+
+/// Get the max payload of a datagram to the address, the datagrams are not fragmented.
+fn max_datagram(addr: IpAddr) -> usize {
+    let ip_header_len = match addr {
+        IpAddr::V4(_) => ipv4::HEADER_LEN,
+        IpAddr::V6(_) => ipv6::HEADER_LEN,
+    };
+    ethernet::MTU - ip_header_len - udp::HEADER_LEN
+}
+
+/// Check whether the socket can be bound to the address.
+fn check_bind(inner: &StackInner, local: SocketAddr) -> Result<(), SockError> {
+    match local.addr.is_unspecified() || inner.is_local(local.addr) {
+        true => Ok(()),
+        false => Err(SockError::AddrNotAvail),
+    }
+}
+
+/// Set the timeout of the blocking calls, it's stored in nanoseconds and 0 waits forever.
+fn set_timeout(timeout: &AtomicU64, value: Option<Duration>) {
+    let nanos = value.map_or(0, |x| (x.as_nanos() as u64).max(1));
+    timeout.store(nanos, Ordering::Relaxed);
+}
+
+/// Get the deadline of a blocking call by the timeout.
+fn deadline<S: DSched>(timeout: &AtomicU64) -> Option<Duration> {
+    match timeout.load(Ordering::Relaxed) {
+        0 => None,
+        nanos => S::now().checked_add(Duration::from_nanos(nanos)),
+    }
+}
+
+/// A UDP socket.
+pub struct UdpSocket<R: RawMutex, S: DSched> {
+    stack: Arc<NetStack<R, S>>,
+    handle: usize,
+    nonblocking: AtomicBool,
+    recv_timeout: AtomicU64,
+}
+
+impl<R: RawMutex, S: DSched> UdpSocket<R, S> {
+    /// Create a socket, `ipv6` sockets are dual-stack and bound to `::` by default.
+    pub fn new(stack: &Arc<NetStack<R, S>>, ipv6: bool) -> Self {
+        let handle = stack.lock().udp.create(ipv6);
+        Self {
+            stack: stack.clone(),
+            handle,
+            nonblocking: AtomicBool::new(false),
+            recv_timeout: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    /// Set the timeout of the blocking receive, None waits forever.
+    pub fn set_recv_timeout(&self, timeout: Option<Duration>) {
+        set_timeout(&self.recv_timeout, timeout);
+    }
+
+    pub fn bind(&self, local: SocketAddr) -> Result<(), SockError> {
+        let mut inner = self.stack.lock();
+        check_bind(&inner, local)?;
+        inner.udp.bind(self.handle, local)
+    }
+
+    /// Set the default destination, only the datagrams from it are received.
+    pub fn connect(&self, remote: SocketAddr) -> Result<(), SockError> {
+        self.stack.lock().udp.connect(self.handle, remote)
+    }
+
+    /// Send the datagram to `remote` or the connected address.
+    pub fn send_to(&self, buf: &[u8], remote: Option<SocketAddr>) -> Result<usize, SockError> {
+        // Sending never blocks, the packet is dropped if the device is busy.
+        self.stack.block_on(true, None, |inner, now| {
+            let (mut local, remote) = inner.udp.endpoints(self.handle, remote)?;
+            if buf.len() > max_datagram(remote.addr) {
+                return Err(SockError::NoBuffer);
+            }
+            if local.addr.is_unspecified() {
+                local.addr = inner.source_addr(remote.addr)?;
+            }
+            let packet = udp::build(local, remote, buf);
+            inner.send_ip(local.addr, remote.addr, ipv4::PROTOCOL_UDP, &packet, now)?;
+            Ok(buf.len())
+        })
+    }
+
+    /// Receive a datagram, returns its length and its source.
+    ///
+    /// The part exceeding the buffer is discarded.
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr), SockError> {
+        let deadline = deadline::<S>(&self.recv_timeout);
+        self.stack
+            .block_on(self.is_nonblocking(), deadline, |inner, _| {
+                inner.udp.recv(self.handle, buf)
+            })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.stack.lock().udp.local_addr(self.handle)
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stack.lock().udp.remote_addr(self.handle)
+    }
+
+    pub fn readable(&self) -> bool {
+        self.stack.lock().udp.readable(self.handle)
+    }
+
+    /// Get the length of the next datagram.
+    pub fn recv_queued(&self) -> usize {
+        self.stack.lock().udp.recv_queued(self.handle)
+    }
+}
+
+impl<R: RawMutex, S: DSched> Drop for UdpSocket<R, S> {
+    fn drop(&mut self) {
+        self.stack.lock().udp.close(self.handle);
+    }
+}
+
+/// A TCP socket.
+pub struct TcpSocket<R: RawMutex, S: DSched> {
+    stack: Arc<NetStack<R, S>>,
+    handle: usize,
+    nonblocking: AtomicBool,
+    recv_timeout: AtomicU64,
+    send_timeout: AtomicU64,
+}
+
+impl<R: RawMutex, S: DSched> TcpSocket<R, S> {
+    /// Create a socket, `ipv6` sockets are dual-stack and bound to `::` by default.
+    pub fn new(stack: &Arc<NetStack<R, S>>, ipv6: bool) -> Self {
+        let handle = stack.lock().tcp.create(ipv6);
+        Self {
+            stack: stack.clone(),
+            handle,
+            nonblocking: AtomicBool::new(false),
+            recv_timeout: AtomicU64::new(0),
+            send_timeout: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    /// Set the timeout of the blocking receive and accept, None waits forever.
+    pub fn set_recv_timeout(&self, timeout: Option<Duration>) {
+        set_timeout(&self.recv_timeout, timeout);
+    }
+
+    /// Set the timeout of the blocking send and connect, None waits forever.
+    pub fn set_send_timeout(&self, timeout: Option<Duration>) {
+        set_timeout(&self.send_timeout, timeout);
+    }
+
+    /// Bind the socket, `reuse_addr` is the `SO_REUSEADDR` option.
+    pub fn bind(&self, local: SocketAddr, reuse_addr: bool) -> Result<(), SockError> {
+        let mut inner = self.stack.lock();
+        check_bind(&inner, local)?;
+        inner.tcp.bind(self.handle, local, reuse_addr)
+    }
+
+    pub fn listen(&self, backlog: usize) -> Result<(), SockError> {
+        self.stack.lock().tcp.listen(self.handle, backlog)
+    }
+
+    /// Take an established connection of the listening socket.
+    pub fn accept(&self) -> Result<Self, SockError> {
+        let deadline = deadline::<S>(&self.recv_timeout);
+        let handle = self
+            .stack
+            .block_on(self.is_nonblocking(), deadline, |inner, _| {
+                inner.tcp.accept(self.handle)
+            })?;
+        Ok(Self {
+            stack: self.stack.clone(),
+            handle,
+            nonblocking: AtomicBool::new(false),
+            recv_timeout: AtomicU64::new(0),
+            send_timeout: AtomicU64::new(0),
+        })
+    }
+
+    /// Connect to the remote address.
+    ///
+    /// A nonblocking socket returns [SockError::InProgress] once the SYN is sent,
+    /// a blocking socket returns it when the send timeout expires.
+    pub fn connect(&self, remote: SocketAddr) -> Result<(), SockError> {
+        self.stack.block_on(true, None, |inner, now| {
+            let bound = inner.tcp.local_addr(self.handle).unwrap_or_default().addr;
+            let local = match bound.is_unspecified() {
+                true => inner.source_addr(remote.addr)?,
+                false => bound,
+            };
+            inner.tcp.connect(self.handle, local, remote, now)
+        })?;
+        if self.is_nonblocking() {
+            return Err(SockError::InProgress);
+        }
+        let deadline = deadline::<S>(&self.send_timeout);
+        self.stack
+            .block_on(false, deadline, |inner, _| {
+                match inner.tcp.state(self.handle) {
+                    Some(TcpState::SynSent | TcpState::SynReceived) => Err(SockError::WouldBlock),
+                    Some(TcpState::Closed) | None => Err(inner
+                        .tcp
+                        .take_error(self.handle)
+                        .unwrap_or(SockError::ConnRefused)),
+                    Some(_) => Ok(()),
+                }
+            })
+            .map_err(|err| match err {
+                SockError::WouldBlock => SockError::InProgress,
+                err => err,
+            })
+    }
+
+    /// Send the data, a blocking socket waits until all the data is queued.
+    pub fn send(&self, buf: &[u8]) -> Result<usize, SockError> {
+        let nonblocking = self.is_nonblocking();
+        let deadline = deadline::<S>(&self.send_timeout);
+        let mut sent = 0;
+        while sent < buf.len() {
+            let result = self.stack.block_on(nonblocking, deadline, |inner, now| {
+                inner.tcp.send(self.handle, &buf[sent..], now)
+            });
+            match result {
+                Ok(len) => sent += len,
+                Err(_) if sent > 0 => break,
+                Err(err) => return Err(err),
+            }
+            if nonblocking {
+                break;
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Receive the data, returns 0 once the peer closes the connection.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize, SockError> {
+        let deadline = deadline::<S>(&self.recv_timeout);
+        self.stack
+            .block_on(self.is_nonblocking(), deadline, |inner, now| {
+                inner.tcp.recv(self.handle, buf, now)
+            })
+    }
+
+    /// Shut down the sending side.
+    pub fn shutdown(&self) -> Result<(), SockError> {
+        self.stack.block_on(true, None, |inner, now| {
+            inner.tcp.shutdown(self.handle, now)
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.stack.lock().tcp.local_addr(self.handle)
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stack.lock().tcp.remote_addr(self.handle)
+    }
+
+    pub fn state(&self) -> TcpState {
+        self.stack
+            .lock()
+            .tcp
+            .state(self.handle)
+            .unwrap_or(TcpState::Closed)
+    }
+
+    /// Take the pending error of the socket.
+    pub fn take_error(&self) -> Option<SockError> {
+        self.stack.lock().tcp.take_error(self.handle)
+    }
+
+    /// Check whether the socket has a pending error.
+    pub fn has_error(&self) -> bool {
+        self.stack.lock().tcp.has_error(self.handle)
+    }
+
+    pub fn readable(&self) -> bool {
+        self.stack.lock().tcp.readable(self.handle)
+    }
+
+    /// Get the length of the received data.
+    pub fn recv_queued(&self) -> usize {
+        self.stack.lock().tcp.recv_queued(self.handle)
+    }
+
+    pub fn writable(&self) -> bool {
+        self.stack.lock().tcp.writable(self.handle)
+    }
+}
+
+impl<R: RawMutex, S: DSched> Drop for TcpSocket<R, S> {
+    fn drop(&mut self) {
+        let _ = self.stack.block_on(true, None, |inner, now| {
+            inner.tcp.close(self.handle, now);
+            Ok(())
+        });
+    }
+}
+
+/// A packet socket, it sends and receives the frames of the interfaces.
+pub struct PacketSocket<R: RawMutex, S: DSched> {
+    stack: Arc<NetStack<R, S>>,
+    handle: usize,
+    nonblocking: AtomicBool,
+    recv_timeout: AtomicU64,
+}
+
+impl<R: RawMutex, S: DSched> PacketSocket<R, S> {
+    /// Create a socket of the protocol, `raw` sockets include the ethernet header.
+    pub fn new(stack: &Arc<NetStack<R, S>>, raw: bool, protocol: u16) -> Self {
+        let mut inner = stack.lock();
+        let handle = inner.packet.create(raw, protocol);
+        inner.update_capture();
+        Self {
+            stack: stack.clone(),
+            handle,
+            nonblocking: AtomicBool::new(false),
+            recv_timeout: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    /// Set the timeout of the blocking receive, None waits forever.
+    pub fn set_recv_timeout(&self, timeout: Option<Duration>) {
+        set_timeout(&self.recv_timeout, timeout);
+    }
+
+    /// Bind the socket to the protocol and the interface, zero keeps the protocol.
+    pub fn bind(&self, protocol: u16, iface: Option<usize>) -> Result<(), SockError> {
+        let mut inner = self.stack.lock();
+        if iface.is_some_and(|x| inner.interface(x).is_none()) {
+            return Err(SockError::AddrNotAvail);
+        }
+        inner.packet.bind(self.handle, protocol, iface)
+    }
+
+    /// Send the frame on the interface of `remote` or the bound interface.
+    ///
+    /// The ethernet header is built from `remote` unless the socket is raw.
+    pub fn send_to(&self, buf: &[u8], remote: Option<LinkAddr>) -> Result<usize, SockError> {
+        self.stack.block_on(true, None, |inner, _| {
+            let local = inner
+                .packet
+                .local_addr(self.handle)
+                .ok_or(SockError::InvalidState)?;
+            let index = remote
+                .and_then(|x| x.iface)
+                .or(local.iface)
+                .ok_or(SockError::InvalidParam)?;
+            let iface = inner.interface(index).ok_or(SockError::AddrNotAvail)?;
+            let frame = match inner.packet.is_raw(self.handle) {
+                true if buf.len() < ethernet::HEADER_LEN => return Err(SockError::InvalidParam),
+                true => buf.to_vec(),
+                false => {
+                    let remote = remote.ok_or(SockError::NotConnected)?;
+                    let protocol = match remote.protocol {
+                        0 => local.protocol,
+                        protocol => protocol,
+                    };
+                    ethernet::build(remote.mac, iface.mac, protocol, buf)
+                }
+            };
+            if frame.len() > ethernet::HEADER_LEN + ethernet::MTU {
+                return Err(SockError::NoBuffer);
+            }
+            iface.send_raw(frame);
+            Ok(buf.len())
+        })
+    }
+
+    /// Receive a frame, returns its length and its address.
+    ///
+    /// The part exceeding the buffer is discarded.
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, LinkAddr), SockError> {
+        let deadline = deadline::<S>(&self.recv_timeout);
+        self.stack
+            .block_on(self.is_nonblocking(), deadline, |inner, _| {
+                inner.packet.recv(self.handle, buf)
+            })
+    }
+
+    /// Check whether the frames include the ethernet header.
+    pub fn is_raw(&self) -> bool {
+        self.stack.lock().packet.is_raw(self.handle)
+    }
+
+    pub fn local_addr(&self) -> Option<LinkAddr> {
+        self.stack.lock().packet.local_addr(self.handle)
+    }
+
+    pub fn readable(&self) -> bool {
+        self.stack.lock().packet.readable(self.handle)
+    }
+
+    /// Get the length of the next frame.
+    pub fn recv_queued(&self) -> usize {
+        self.stack.lock().packet.recv_queued(self.handle)
+    }
+}
+
+impl<R: RawMutex, S: DSched> Drop for PacketSocket<R, S> {
+    fn drop(&mut self) {
+        let mut inner = self.stack.lock();
+        inner.packet.close(self.handle);
+        inner.update_capture();
+    }
+}
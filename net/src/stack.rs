@@ -0,0 +1,423 @@
+//! The network stack.
+//!
+//! IPv4 and IPv6 share the interfaces and the sockets, the sockets bound to
+//! the IPv6 wildcard address receive the packets of both.
+//!
+//! All the interfaces and the sockets are behind one lock. Received frames
+//! are handled and the timers are run by [NetStack::poll], the socket
+//! operations take the lock and send their packets directly.
+//!
+//! The received and the sent frames are seen by the packet sockets and the
+//! [FrameTap] before the protocols handle them.
+
+use core::{marker::PhantomData, time::Duration};
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use drivers_base::DSched;
+use lock_api::{Mutex, MutexGuard, RawMutex};
+
+use crate::{
+    addr::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    dhcp,
+    ethernet::{self, EthernetHeader},
+    icmp, icmpv6,
+    iface::Interface,
+    ipv4::{self, Ipv4Header},
+    ipv6::{self, Ipv6Header},
+    packet::{FrameTap, Packet, PacketType},
+    tcp::Tcp,
+    udp::{self, Udp},
+    SockError,
+};
+
+/// Max frames received from an interface in a poll.
+const RX_BUDGET: usize = 64;
+
+pub(crate) struct StackInner {
+    ifaces: Vec<Interface>,
+    pub(crate) tcp: Tcp,
+    pub(crate) udp: Udp,
+    pub(crate) packet: Packet,
+    /// The consumer of all the frames.
+    tap: Option<Arc<dyn FrameTap>>,
+    /// Identification of the next IPv4 packet.
+    ip_id: u16,
+    /// Packets to the local addresses without a loopback interface, they
+    /// are handled in the next poll.
+    local: VecDeque<Vec<u8>>,
+}
+
+/// The network stack over the interfaces.
+pub struct NetStack<R: RawMutex, S: DSched> {
+    inner: Mutex<R, StackInner>,
+    _sched: PhantomData<S>,
+}
+
+unsafe impl<R: RawMutex, S: DSched> Sync for NetStack<R, S> {}
+unsafe impl<R: RawMutex, S: DSched> Send for NetStack<R, S> {}
+
+impl<R: RawMutex, S: DSched> NetStack<R, S> {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(StackInner {
+                ifaces: Vec::new(),
+                tcp: Tcp::default(),
+                udp: Udp::default(),
+                packet: Packet::default(),
+                tap: None,
+                ip_id: 0,
+                local: VecDeque::new(),
+            }),
+            _sched: PhantomData,
+        })
+    }
+
+    pub fn add_interface(&self, iface: Interface) {
+        info!(
+            "net: add interface {} {} {:?}",
+            iface.name, iface.mac, iface.ipv4
+        );
+        let mut inner = self.inner.lock();
+        inner.ifaces.push(iface);
+        inner.update_capture();
+    }
+
+    /// Set the secret key of the TCP initial sequence numbers.
+    pub fn set_isn_key(&self, key: [u64; 2]) {
+        self.inner.lock().tcp.set_isn_key(key);
+    }
+
+    /// Set the consumer of all the received and sent frames.
+    pub fn set_tap(&self, tap: Option<Arc<dyn FrameTap>>) {
+        let mut inner = self.inner.lock();
+        inner.tap = tap;
+        inner.update_capture();
+    }
+
+    /// Access the interfaces, to configure their addresses.
+    pub fn with_interfaces<T>(&self, f: impl FnOnce(&mut [Interface]) -> T) -> T {
+        f(&mut self.inner.lock().ifaces)
+    }
+
+    /// Handle the received frames and run the timers.
+    ///
+    /// Called from the interrupt handlers, it does nothing if the stack is
+    /// being used by a socket operation.
+    pub fn poll(&self) {
+        let changed = match self.inner.try_lock() {
+            Some(mut inner) => inner.poll(S::now()),
+            None => return,
+        };
+        if changed {
+            S::wake();
+        }
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, R, StackInner> {
+        self.inner.lock()
+    }
+
+    /// Run the socket operation, wait while it would block unless `nonblocking`.
+    ///
+    /// The wait ends with [SockError::WouldBlock] once the `deadline` passes.
+    pub(crate) fn block_on<T>(
+        &self,
+        nonblocking: bool,
+        deadline: Option<Duration>,
+        mut f: impl FnMut(&mut StackInner, Duration) -> Result<T, SockError>,
+    ) -> Result<T, SockError> {
+        loop {
+            let result = {
+                let mut inner = self.inner.lock();
+                let now = S::now();
+                let result = f(&mut inner, now);
+                inner.flush(now);
+                result
+            };
+            match result {
+                Err(SockError::WouldBlock)
+                    if !nonblocking && !deadline.is_some_and(|x| S::now() >= x) =>
+                {
+                    self.poll();
+                    S::sleep();
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl StackInner {
+    /// Returns true if the sockets change.
+    fn poll(&mut self, now: Duration) -> bool {
+        for index in 0..self.ifaces.len() {
+            let driver = self.ifaces[index].driver().clone();
+            for _ in 0..RX_BUDGET {
+                let buf = match driver.recv() {
+                    Ok(buf) => buf,
+                    Err(_) => break,
+                };
+                self.receive(index, buf.packet(), buf.csum_valid, now);
+                driver.recycle(buf);
+            }
+            self.ifaces[index].poll_arp(now);
+            self.ifaces[index].poll_dhcp(now);
+            self.ifaces[index].poll_ndp(now);
+        }
+        // The replies to the local packets are handled in the next poll.
+        for _ in 0..self.local.len() {
+            let packet = self.local.pop_front().unwrap();
+            match packet[0] >> 4 {
+                4 => self.handle_ipv4(&packet, true, now),
+                _ => self.handle_ipv6(&packet, true, now),
+            }
+        }
+        self.tcp.poll(now);
+        self.flush(now);
+        let changed = self.tcp.changed || self.udp.changed || self.packet.changed;
+        self.tcp.changed = false;
+        self.udp.changed = false;
+        self.packet.changed = false;
+        changed
+    }
+
+    /// Keep the sent frames of the interfaces while the frames are captured.
+    pub(crate) fn update_capture(&mut self) {
+        let capture = self.tap.is_some() || !self.packet.is_empty();
+        for iface in self.ifaces.iter_mut() {
+            iface.capture = capture;
+        }
+    }
+
+    /// Pass the frame of the interface to the tap and the packet sockets.
+    fn capture(&mut self, index: usize, frame: &[u8], pkttype: PacketType, now: Duration) {
+        if let Some(tap) = &self.tap {
+            tap.tap(index, frame, now);
+        }
+        self.packet.input(index, frame, pkttype);
+    }
+
+    pub(crate) fn interface(&self, index: usize) -> Option<&Interface> {
+        self.ifaces.get(index)
+    }
+
+    fn receive(&mut self, index: usize, frame: &[u8], csum_valid: bool, now: Duration) {
+        let (header, payload) = match EthernetHeader::parse(frame) {
+            Some(x) => x,
+            None => return,
+        };
+        if self.ifaces[index].capture {
+            let pkttype = PacketType::of(header.dst, self.ifaces[index].mac);
+            self.capture(index, frame, pkttype, now);
+        }
+        let iface = &mut self.ifaces[index];
+        if header.dst != iface.mac && !header.dst.is_multicast() {
+            return;
+        }
+        match header.ethertype {
+            ethernet::ETHERTYPE_ARP => iface.handle_arp(payload, now),
+            ethernet::ETHERTYPE_IPV4 => {
+                if iface.is_dhcp() {
+                    if let Some(message) = dhcp::client_message(payload, csum_valid) {
+                        iface.handle_dhcp(message, now);
+                        return;
+                    }
+                }
+                self.handle_ipv4(payload, csum_valid, now)
+            }
+            // The neighbour discovery messages are handled by the interface.
+            ethernet::ETHERTYPE_IPV6 => match iface.handle_ndp(payload, now) {
+                true => {}
+                false => self.handle_ipv6(payload, csum_valid, now),
+            },
+            _ => {}
+        }
+    }
+
+    fn handle_ipv4(&mut self, packet: &[u8], csum_valid: bool, now: Duration) {
+        let (header, payload) = match Ipv4Header::parse(packet) {
+            Some(x) => x,
+            None => return,
+        };
+        let is_broadcast = header.dst.is_broadcast()
+            || self
+                .ifaces
+                .iter()
+                .any(|x| x.ipv4.is_some_and(|x| x.broadcast() == header.dst));
+        // Packets to the other hosts aren't forwarded.
+        if !is_broadcast && !self.is_local(header.dst.into()) {
+            return;
+        }
+        let (src, dst) = (IpAddr::V4(header.src), IpAddr::V4(header.dst));
+        match header.protocol {
+            ipv4::PROTOCOL_ICMP if !is_broadcast => {
+                if let Some(reply) = icmp::echo_reply(payload) {
+                    let _ = self.send_ip(dst, src, ipv4::PROTOCOL_ICMP, &reply, now);
+                }
+            }
+            ipv4::PROTOCOL_UDP
+                if !self.handle_udp(src, dst, payload, csum_valid) && !is_broadcast =>
+            {
+                let header_len = (packet[0] & 0xf) as usize * 4;
+                let reply = icmp::port_unreachable(packet, header_len);
+                let _ = self.send_ip(dst, src, ipv4::PROTOCOL_ICMP, &reply, now);
+            }
+            ipv4::PROTOCOL_TCP if !is_broadcast => {
+                self.tcp.input(src, dst, payload, csum_valid, now)
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_ipv6(&mut self, packet: &[u8], csum_valid: bool, now: Duration) {
+        let (header, payload) = match Ipv6Header::parse(packet) {
+            Some(x) => x,
+            None => return,
+        };
+        let is_multicast = header.dst.is_multicast();
+        // Packets to the other hosts aren't forwarded.
+        if !is_multicast && !self.is_local(header.dst.into()) {
+            return;
+        }
+        let (src, dst) = (IpAddr::V6(header.src), IpAddr::V6(header.dst));
+        match header.next_header {
+            ipv6::NEXT_HEADER_ICMPV6 if !is_multicast => {
+                if !csum_valid && !icmpv6::verify(header.src, header.dst, payload) {
+                    return;
+                }
+                if let Some(reply) = icmpv6::echo_reply(header.dst, header.src, payload) {
+                    let _ = self.send_ip(dst, src, ipv6::NEXT_HEADER_ICMPV6, &reply, now);
+                }
+            }
+            ipv4::PROTOCOL_UDP
+                if !self.handle_udp(src, dst, payload, csum_valid) && !is_multicast =>
+            {
+                let reply = icmpv6::port_unreachable(header.dst, header.src, packet);
+                let _ = self.send_ip(dst, src, ipv6::NEXT_HEADER_ICMPV6, &reply, now);
+            }
+            ipv4::PROTOCOL_TCP if !is_multicast => {
+                self.tcp.input(src, dst, payload, csum_valid, now)
+            }
+            _ => {}
+        }
+    }
+
+    /// Deliver the UDP datagram, returns false if no socket takes it.
+    fn handle_udp(&mut self, src: IpAddr, dst: IpAddr, packet: &[u8], csum_valid: bool) -> bool {
+        match udp::parse(src, dst, packet, csum_valid) {
+            Some((src_port, dst_port, data)) => self.udp.input(
+                SocketAddr::new(src, src_port),
+                SocketAddr::new(dst, dst_port),
+                data,
+            ),
+            // The broken datagrams are dropped silently.
+            None => true,
+        }
+    }
+
+    /// Check whether the address is an address of this host.
+    pub(crate) fn is_local(&self, addr: IpAddr) -> bool {
+        match addr {
+            IpAddr::V4(addr) => {
+                addr.is_loopback() || self.ifaces.iter().any(|x| x.addr() == Some(addr))
+            }
+            IpAddr::V6(addr) => addr.is_loopback() || self.ifaces.iter().any(|x| x.has_ipv6(addr)),
+        }
+    }
+
+    /// Get the interface to the destination.
+    fn route(&self, dst: IpAddr) -> Option<usize> {
+        match dst {
+            IpAddr::V4(dst) => self.route_ipv4(dst),
+            IpAddr::V6(dst) => self.route_ipv6(dst),
+        }
+    }
+
+    fn route_ipv4(&self, dst: Ipv4Addr) -> Option<usize> {
+        self.ifaces
+            .iter()
+            .position(|x| x.ipv4.is_some_and(|x| x.contains(dst)))
+            .or_else(|| self.ifaces.iter().position(|x| x.next_hop(dst).is_some()))
+    }
+
+    /// The link-local destinations have no scope, they are on the first link.
+    fn route_ipv6(&self, dst: Ipv6Addr) -> Option<usize> {
+        let on_link = |x: &Interface| !x.is_loopback() && !x.ipv6.is_empty();
+        if dst.is_link_local() || dst.is_multicast() {
+            return self.ifaces.iter().position(on_link);
+        }
+        self.ifaces
+            .iter()
+            .position(|x| x.ipv6.iter().any(|x| x.contains(dst)))
+            .or_else(|| self.ifaces.iter().position(|x| x.gateway6.is_some()))
+    }
+
+    /// Select the source address of the packets to the destination.
+    pub(crate) fn source_addr(&self, dst: IpAddr) -> Result<IpAddr, SockError> {
+        if self.is_local(dst) {
+            return Ok(dst);
+        }
+        let iface = &self.ifaces[self.route(dst).ok_or(SockError::Unreachable)?];
+        match dst {
+            IpAddr::V4(_) => iface.addr().map(IpAddr::V4),
+            IpAddr::V6(dst) => iface.addr6(dst).map(IpAddr::V6),
+        }
+        .ok_or(SockError::Unreachable)
+    }
+
+    /// Send the IPv4 or IPv6 packet with the payload.
+    pub(crate) fn send_ip(
+        &mut self,
+        src: IpAddr,
+        dst: IpAddr,
+        protocol: u8,
+        payload: &[u8],
+        now: Duration,
+    ) -> Result<(), SockError> {
+        let packet = match (src, dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                self.ip_id = self.ip_id.wrapping_add(1);
+                ipv4::build(src, dst, protocol, self.ip_id, payload)
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                ipv6::build(src, dst, protocol, ipv6::DEFAULT_HOP_LIMIT, payload)
+            }
+            _ => return Err(SockError::InvalidParam),
+        };
+        let index = match self.is_local(dst) {
+            true => match self.ifaces.iter().position(Interface::is_loopback) {
+                Some(index) => index,
+                None => {
+                    self.local.push_back(packet);
+                    return Ok(());
+                }
+            },
+            false => self.route(dst).ok_or(SockError::Unreachable)?,
+        };
+        match dst {
+            IpAddr::V4(dst) => self.ifaces[index].send_ipv4(dst, packet, now),
+            IpAddr::V6(dst) => self.ifaces[index].send_ipv6(dst, packet, now),
+        }
+    }
+
+    /// Send the segments queued by TCP and capture the sent frames.
+    pub(crate) fn flush(&mut self, now: Duration) {
+        for segment in core::mem::take(&mut self.tcp.outbox) {
+            let result = self.send_ip(
+                segment.src,
+                segment.dst,
+                ipv4::PROTOCOL_TCP,
+                &segment.data,
+                now,
+            );
+            if let Err(err) = result {
+                debug!("tcp: can't send the segment to {}: {:?}", segment.dst, err);
+            }
+        }
+        for index in 0..self.ifaces.len() {
+            for frame in self.ifaces[index].take_sent() {
+                self.capture(index, &frame, PacketType::Outgoing, now);
+            }
+        }
+    }
+}
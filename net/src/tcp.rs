@@ -0,0 +1,1158 @@
+//! Transmission control protocol.
+//!
+//! Out of order segments are dropped and acknowledged with the expected
+//! sequence, the peer retransmits them. Unacknowledged data is retransmitted
+//! from the first unacknowledged byte when the retransmission timer expires,
+//! the timeout is estimated from the round trip time and backs off
+//! exponentially. A zero window is probed by the persist timer. The window
+//! isn't scaled.
+
+use core::time::Duration;
+
+use alloc::{collections::BTreeMap, collections::VecDeque, vec::Vec};
+
+use crate::{
+    addr::{IpAddr, Ipv6Addr, SocketAddr},
+    checksum, ipv4, ipv6, SockError,
+};
+
+pub const HEADER_LEN: usize = 20;
+
+const FLAG_FIN: u8 = 1 << 0;
+const FLAG_SYN: u8 = 1 << 1;
+const FLAG_RST: u8 = 1 << 2;
+const FLAG_PSH: u8 = 1 << 3;
+const FLAG_ACK: u8 = 1 << 4;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// The MSS if the peer doesn't tell it.
+const DEFAULT_MSS: u16 = 536;
+/// Get the MSS of the ethernet MTU, the IPv6 header is longer.
+fn local_mss(addr: IpAddr) -> u16 {
+    let ip_header_len = match addr {
+        IpAddr::V4(_) => ipv4::HEADER_LEN,
+        IpAddr::V6(_) => ipv6::HEADER_LEN,
+    };
+    (crate::ethernet::MTU - ip_header_len - HEADER_LEN) as u16
+}
+/// Size of the send and receive buffers.
+const BUFFER_SIZE: usize = 0x10000 - 1;
+
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MIN_RTO: Duration = Duration::from_millis(200);
+const MAX_RTO: Duration = Duration::from_secs(60);
+/// Retransmissions before the connection times out.
+const MAX_RETRIES: u32 = 8;
+/// Twice the maximum segment lifetime.
+const TIME_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time a closed socket waits for the FIN of the peer in FIN_WAIT_2.
+const FIN_WAIT_2_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// SipHash-2-4 of the data with the 128-bit key.
+fn siphash(key: [u64; 2], data: &[u8]) -> u64 {
+    let mut v = [
+        key[0] ^ 0x736f6d6570736575,
+        key[1] ^ 0x646f72616e646f6d,
+        key[0] ^ 0x6c7967656e657261,
+        key[1] ^ 0x7465646279746573,
+    ];
+    let round = |v: &mut [u64; 4]| {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    };
+    let mut compress = |m: u64| {
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    };
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        compress(u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    // The last word has the remaining bytes and the length in the top byte.
+    let mut last = [0; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    compress(u64::from_le_bytes(last));
+    v[2] ^= 0xff;
+    (0..4).for_each(|_| round(&mut v));
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[inline]
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+#[inline]
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Header of a received segment.
+#[derive(Debug, Clone, Copy)]
+struct TcpHeader {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+}
+
+impl TcpHeader {
+    /// Parse the segment, returns the header and the payload.
+    fn parse(packet: &[u8]) -> Option<(Self, &[u8])> {
+        if packet.len() < HEADER_LEN {
+            return None;
+        }
+        let header_len = (packet[12] >> 4) as usize * 4;
+        if header_len < HEADER_LEN || header_len > packet.len() {
+            return None;
+        }
+        let mut mss = None;
+        let mut options = &packet[HEADER_LEN..header_len];
+        while let Some(&kind) = options.first() {
+            match kind {
+                OPTION_END => break,
+                OPTION_NOP => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        return None;
+                    }
+                    if kind == OPTION_MSS && len == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        let header = Self {
+            src_port: u16::from_be_bytes([packet[0], packet[1]]),
+            dst_port: u16::from_be_bytes([packet[2], packet[3]]),
+            seq: u32::from_be_bytes(packet[4..8].try_into().unwrap()),
+            ack: u32::from_be_bytes(packet[8..12].try_into().unwrap()),
+            flags: packet[13],
+            window: u16::from_be_bytes([packet[14], packet[15]]),
+            mss,
+        };
+        Some((header, &packet[header_len..]))
+    }
+
+    /// Length of the segment in the sequence space.
+    fn seq_len(&self, payload: &[u8]) -> u32 {
+        payload.len() as u32
+            + (self.flags & FLAG_SYN != 0) as u32
+            + (self.flags & FLAG_FIN != 0) as u32
+    }
+}
+
+/// A segment waiting to be sent by the IP layer.
+pub struct Segment {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub data: Vec<u8>,
+}
+
+/// Build a segment with the checksum.
+#[allow(clippy::too_many_arguments)]
+fn build(
+    local: SocketAddr,
+    remote: SocketAddr,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &[u8],
+) -> Segment {
+    let header_len = HEADER_LEN + if mss.is_some() { 4 } else { 0 };
+    let mut data = Vec::with_capacity(header_len + payload.len());
+    data.extend_from_slice(&local.port.to_be_bytes());
+    data.extend_from_slice(&remote.port.to_be_bytes());
+    data.extend_from_slice(&seq.to_be_bytes());
+    data.extend_from_slice(&ack.to_be_bytes());
+    data.extend_from_slice(&[(header_len as u8 / 4) << 4, flags]);
+    data.extend_from_slice(&window.to_be_bytes());
+    // Checksum and urgent pointer.
+    data.extend_from_slice(&[0; 4]);
+    if let Some(mss) = mss {
+        data.extend_from_slice(&[OPTION_MSS, 4]);
+        data.extend_from_slice(&mss.to_be_bytes());
+    }
+    data.extend_from_slice(payload);
+    let sum = checksum::transport(local.addr, remote.addr, ipv4::PROTOCOL_TCP, &data);
+    data[16..18].copy_from_slice(&sum.to_be_bytes());
+    Segment {
+        src: local.addr,
+        dst: remote.addr,
+        data,
+    }
+}
+
+/// Reply a reset to the unexpected segment.
+fn reset(src: SocketAddr, dst: SocketAddr, header: &TcpHeader, payload: &[u8]) -> Segment {
+    match header.flags & FLAG_ACK {
+        0 => build(
+            dst,
+            src,
+            0,
+            header.seq.wrapping_add(header.seq_len(payload)),
+            FLAG_RST | FLAG_ACK,
+            0,
+            None,
+            &[],
+        ),
+        _ => build(dst, src, header.ack, 0, FLAG_RST, 0, None, &[]),
+    }
+}
+
+/// Transmission control block of a socket.
+pub struct Tcb {
+    state: TcpState,
+    local: SocketAddr,
+    remote: SocketAddr,
+
+    iss: u32,
+    /// The first unacknowledged sequence.
+    snd_una: u32,
+    /// The next sequence to send.
+    snd_nxt: u32,
+    /// The window of the peer.
+    snd_wnd: u32,
+    /// Data from snd_una, includes the sent and unsent data.
+    send_buf: VecDeque<u8>,
+    /// The sequence of the FIN once it is sent.
+    fin_seq: Option<u32>,
+    /// The user closes the sending side, FIN is sent after the data.
+    close_requested: bool,
+    /// The peer's MSS.
+    mss: u16,
+
+    /// The next sequence to receive.
+    rcv_nxt: u32,
+    recv_buf: VecDeque<u8>,
+    /// The last advertised window.
+    rcv_wnd: u32,
+    /// The peer sent FIN.
+    peer_closed: bool,
+    /// An ACK should be sent.
+    ack_needed: bool,
+
+    rto: Duration,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    /// The sequence and the send time of the segment measuring the round trip time.
+    rtt_sample: Option<(u32, Duration)>,
+    retransmit_at: Option<Duration>,
+    retries: u32,
+    /// The zero window is probed at the time, the interval backs off.
+    persist_at: Option<Duration>,
+    persist_interval: Duration,
+    /// The connection is closed at the time, in TIME_WAIT or orphaned FIN_WAIT_2.
+    close_at: Option<Duration>,
+
+    backlog: usize,
+    /// Established connections waiting for accept.
+    accept_queue: VecDeque<usize>,
+    /// The listener of the passive opened connection until it is accepted.
+    parent: Option<usize>,
+    /// The connection is opened by a listener.
+    passive: bool,
+    /// The socket is closed by the user, the block is freed once the connection closes.
+    orphan: bool,
+    error: Option<SockError>,
+}
+
+impl Tcb {
+    fn new() -> Self {
+        Self {
+            state: TcpState::Closed,
+            local: SocketAddr::default(),
+            remote: SocketAddr::default(),
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            send_buf: VecDeque::new(),
+            fin_seq: None,
+            close_requested: false,
+            mss: DEFAULT_MSS,
+            rcv_nxt: 0,
+            recv_buf: VecDeque::new(),
+            rcv_wnd: 0,
+            peer_closed: false,
+            ack_needed: false,
+            rto: INITIAL_RTO,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rtt_sample: None,
+            retransmit_at: None,
+            retries: 0,
+            persist_at: None,
+            persist_interval: INITIAL_RTO,
+            close_at: None,
+            backlog: 0,
+            accept_queue: VecDeque::new(),
+            parent: None,
+            passive: false,
+            orphan: false,
+            error: None,
+        }
+    }
+
+    /// The window to advertise, the free space of the receive buffer.
+    fn window(&self) -> u32 {
+        (BUFFER_SIZE - self.recv_buf.len()).min(u16::MAX as usize) as u32
+    }
+
+    fn segment(&mut self, seq: u32, flags: u8, payload: &[u8]) -> Segment {
+        self.rcv_wnd = self.window();
+        self.ack_needed = false;
+        let mss = match flags & FLAG_SYN {
+            0 => None,
+            _ => Some(local_mss(self.local.addr)),
+        };
+        build(
+            self.local,
+            self.remote,
+            seq,
+            self.rcv_nxt,
+            flags,
+            self.rcv_wnd as u16,
+            mss,
+            payload,
+        )
+    }
+
+    /// The FIN is sent and not acknowledged by a retransmission.
+    fn fin_in_flight(&self) -> bool {
+        self.fin_seq.is_some_and(|fin| seq_lt(fin, self.snd_nxt))
+    }
+
+    /// Send the unsent data, FIN and the pending ACK as the window allows.
+    fn output(&mut self, outbox: &mut Vec<Segment>, now: Duration) {
+        match self.state {
+            TcpState::SynSent | TcpState::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = match self.state {
+                        TcpState::SynSent => FLAG_SYN,
+                        _ => FLAG_SYN | FLAG_ACK,
+                    };
+                    let segment = self.segment(self.iss, flags, &[]);
+                    outbox.push(segment);
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                    self.retransmit_at.get_or_insert(now + self.rto);
+                }
+                return;
+            }
+            TcpState::Closed | TcpState::Listen => return,
+            _ => {}
+        }
+
+        let mut sent = false;
+        // Probe the zero window with a byte after the retransmission timeout.
+        let window = match self.retries {
+            0 => self.snd_wnd,
+            _ => self.snd_wnd.max(1),
+        };
+        loop {
+            let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let usable = self.snd_una.wrapping_add(window).wrapping_sub(self.snd_nxt) as i32;
+            if offset >= self.send_buf.len() || usable <= 0 || self.fin_in_flight() {
+                break;
+            }
+            let len = (self.send_buf.len() - offset)
+                .min(self.mss as usize)
+                .min(usable as usize);
+            let payload: Vec<u8> = self.send_buf.range(offset..offset + len).copied().collect();
+            let flags = match offset + len == self.send_buf.len() {
+                true => FLAG_ACK | FLAG_PSH,
+                false => FLAG_ACK,
+            };
+            let segment = self.segment(self.snd_nxt, flags, &payload);
+            outbox.push(segment);
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+            // Karn's algorithm, only the new data measures the round trip time.
+            if self.rtt_sample.is_none() && self.retries == 0 {
+                self.rtt_sample = Some((self.snd_nxt, now));
+            }
+            self.retransmit_at.get_or_insert(now + self.rto);
+            sent = true;
+        }
+
+        let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        if self.close_requested && offset >= self.send_buf.len() && !self.fin_in_flight() {
+            let fin = self.snd_nxt;
+            let segment = self.segment(fin, FLAG_FIN | FLAG_ACK, &[]);
+            outbox.push(segment);
+            self.fin_seq = Some(fin);
+            self.snd_nxt = fin.wrapping_add(1);
+            self.retransmit_at.get_or_insert(now + self.rto);
+            self.state = match self.state {
+                TcpState::Established => TcpState::FinWait1,
+                TcpState::CloseWait => TcpState::LastAck,
+                state => state,
+            };
+            sent = true;
+        }
+
+        if self.ack_needed && !sent {
+            let segment = self.segment(self.snd_nxt, FLAG_ACK, &[]);
+            outbox.push(segment);
+        }
+
+        // Nothing is in flight to bring a window update, probe the zero window.
+        if self.snd_wnd == 0 && self.snd_una == self.snd_nxt && !self.send_buf.is_empty() {
+            if self.persist_at.is_none() {
+                self.persist_interval = self.rto;
+                self.persist_at = Some(now + self.rto);
+            }
+        } else {
+            self.persist_at = None;
+        }
+    }
+
+    /// Probe the zero window with an old sequence, the peer replies an ACK with its window.
+    fn probe_window(&mut self, outbox: &mut Vec<Segment>, now: Duration) {
+        let segment = self.segment(self.snd_una.wrapping_sub(1), FLAG_ACK, &[]);
+        outbox.push(segment);
+        self.persist_interval = (self.persist_interval * 2).min(MAX_RTO);
+        self.persist_at = Some(now + self.persist_interval);
+    }
+
+    /// Update the retransmission timeout by the round trip time.
+    fn update_rtt(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let delta = match srtt > rtt {
+                    true => srtt - rtt,
+                    false => rtt - srtt,
+                };
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        self.rto = (self.srtt.unwrap() + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Handle the acknowledgement, returns false if the ACK is unacceptable.
+    fn process_ack(&mut self, header: &TcpHeader, now: Duration) -> bool {
+        let ack = header.ack;
+        if seq_lt(self.snd_nxt, ack) {
+            // Acknowledges the data not sent yet.
+            self.ack_needed = true;
+            return false;
+        }
+        if seq_lt(self.snd_una, ack) {
+            let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+            if self.fin_seq.is_some_and(|fin| seq_lt(fin, ack)) {
+                acked -= 1;
+            }
+            self.send_buf.drain(..acked.min(self.send_buf.len()));
+            self.snd_una = ack;
+            self.retries = 0;
+            if let Some((seq, sent)) = self.rtt_sample {
+                if seq_le(seq, ack) {
+                    self.update_rtt(now.saturating_sub(sent));
+                    self.rtt_sample = None;
+                }
+            }
+            self.retransmit_at = match self.snd_una == self.snd_nxt {
+                true => None,
+                false => Some(now + self.rto),
+            };
+        }
+        if seq_le(self.snd_una, ack) {
+            self.snd_wnd = header.window as u32;
+        }
+        true
+    }
+
+    fn enter_time_wait(&mut self, now: Duration) {
+        self.state = TcpState::TimeWait;
+        self.retransmit_at = None;
+        self.close_at = Some(now + TIME_WAIT_TIMEOUT);
+    }
+
+    /// The closed socket doesn't wait for the FIN of the peer forever.
+    fn start_fin_wait_2_timer(&mut self, now: Duration) {
+        if self.orphan && self.state == TcpState::FinWait2 {
+            self.close_at.get_or_insert(now + FIN_WAIT_2_TIMEOUT);
+        }
+    }
+
+    fn close_with(&mut self, error: Option<SockError>) {
+        self.state = TcpState::Closed;
+        self.retransmit_at = None;
+        self.persist_at = None;
+        self.error = self.error.or(error);
+    }
+}
+
+/// The TCP sockets.
+#[derive(Default)]
+pub struct Tcp {
+    sockets: BTreeMap<usize, Tcb>,
+    next_handle: usize,
+    next_port: u16,
+    /// The secret key of the initial sequence numbers.
+    isn_key: [u64; 2],
+    /// Segments to send.
+    pub outbox: Vec<Segment>,
+    /// Sockets whose state changes, their waiters should be woken up.
+    pub changed: bool,
+}
+
+impl Tcp {
+    fn tcb(&mut self, handle: usize) -> Result<&mut Tcb, SockError> {
+        self.sockets.get_mut(&handle).ok_or(SockError::InvalidState)
+    }
+
+    /// Another socket uses the address, `reuse_addr` allows the connections
+    /// in TIME_WAIT and the connections accepted from a listener.
+    fn addr_in_use(&self, local: SocketAddr, reuse_addr: bool) -> bool {
+        self.sockets.values().any(|x| {
+            x.local.port == local.port
+                && (x.local.addr.accepts(local.addr) || local.addr.accepts(x.local.addr))
+                && !(reuse_addr && (x.state == TcpState::TimeWait || x.passive))
+        })
+    }
+
+    /// Set the secret key of the initial sequence numbers.
+    pub fn set_isn_key(&mut self, key: [u64; 2]) {
+        self.isn_key = key;
+    }
+
+    /// The initial sequence number of the connection (RFC 6528).
+    ///
+    /// A clock increasing every 4 microseconds is offset by a keyed hash of
+    /// the connection, the sequences of other connections don't tell it.
+    fn iss(key: [u64; 2], local: SocketAddr, remote: SocketAddr, now: Duration) -> u32 {
+        let mut data = Vec::with_capacity(36);
+        data.extend_from_slice(local.addr.as_bytes());
+        data.extend_from_slice(&local.port.to_be_bytes());
+        data.extend_from_slice(remote.addr.as_bytes());
+        data.extend_from_slice(&remote.port.to_be_bytes());
+        let hash = siphash(key, &data) as u32;
+        ((now.as_micros() / 4) as u32).wrapping_add(hash)
+    }
+
+    /// Create a socket, `ipv6` sockets are bound to the IPv6 wildcard address by default.
+    pub fn create(&mut self, ipv6: bool) -> usize {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        let mut tcb = Tcb::new();
+        if ipv6 {
+            tcb.local.addr = Ipv6Addr::UNSPECIFIED.into();
+        }
+        self.sockets.insert(handle, tcb);
+        handle
+    }
+
+    /// Bind the socket to an ephemeral port of its address.
+    fn autobind(&mut self, handle: usize) -> Result<(), SockError> {
+        let addr = self.tcb(handle)?.local.addr;
+        self.bind(handle, SocketAddr::new(addr, 0), false)
+    }
+
+    /// Bind the socket to the address, `reuse_addr` is the `SO_REUSEADDR` option.
+    pub fn bind(
+        &mut self,
+        handle: usize,
+        mut local: SocketAddr,
+        reuse_addr: bool,
+    ) -> Result<(), SockError> {
+        if self.tcb(handle)?.local.port != 0 {
+            return Err(SockError::InvalidParam);
+        }
+        if local.port == 0 {
+            let sockets = &self.sockets;
+            local.port = crate::ephemeral_port(&mut self.next_port, |port| {
+                sockets.values().any(|x| x.local.port == port)
+            })?;
+        } else if self.addr_in_use(local, reuse_addr) {
+            return Err(SockError::AddrInUse);
+        }
+        self.tcb(handle)?.local = local;
+        Ok(())
+    }
+
+    pub fn listen(&mut self, handle: usize, backlog: usize) -> Result<(), SockError> {
+        if self.tcb(handle)?.local.port == 0 {
+            self.autobind(handle)?;
+        }
+        let tcb = self.tcb(handle)?;
+        match tcb.state {
+            TcpState::Closed | TcpState::Listen => {
+                tcb.state = TcpState::Listen;
+                tcb.backlog = backlog.max(1);
+                Ok(())
+            }
+            _ => Err(SockError::InvalidState),
+        }
+    }
+
+    /// Take an established connection of the listener.
+    pub fn accept(&mut self, handle: usize) -> Result<usize, SockError> {
+        let tcb = self.tcb(handle)?;
+        if tcb.state != TcpState::Listen {
+            return Err(SockError::InvalidState);
+        }
+        // The connections timed out before accept are gone.
+        while let Some(child) = self.tcb(handle)?.accept_queue.pop_front() {
+            if let Some(tcb) = self.sockets.get_mut(&child) {
+                tcb.parent = None;
+                return Ok(child);
+            }
+        }
+        Err(SockError::WouldBlock)
+    }
+
+    /// Start the active open, the local address is selected by the caller.
+    pub fn connect(
+        &mut self,
+        handle: usize,
+        local_addr: IpAddr,
+        remote: SocketAddr,
+        now: Duration,
+    ) -> Result<(), SockError> {
+        if self.tcb(handle)?.local.port == 0 {
+            self.autobind(handle)?;
+        }
+        let tcb = self
+            .sockets
+            .get_mut(&handle)
+            .ok_or(SockError::InvalidState)?;
+        match tcb.state {
+            TcpState::Closed if tcb.error.is_none() => {}
+            TcpState::SynSent => return Err(SockError::InProgress),
+            TcpState::Listen | TcpState::Closed => return Err(SockError::InvalidState),
+            _ => return Err(SockError::IsConnected),
+        }
+        tcb.local.addr = local_addr;
+        tcb.remote = remote;
+        tcb.iss = Self::iss(self.isn_key, tcb.local, remote, now);
+        tcb.snd_una = tcb.iss;
+        tcb.snd_nxt = tcb.iss;
+        tcb.state = TcpState::SynSent;
+        tcb.output(&mut self.outbox, now);
+        Ok(())
+    }
+
+    /// Queue the data to send, returns the queued length.
+    pub fn send(&mut self, handle: usize, data: &[u8], now: Duration) -> Result<usize, SockError> {
+        let tcb = self
+            .sockets
+            .get_mut(&handle)
+            .ok_or(SockError::InvalidState)?;
+        match tcb.state {
+            _ if tcb.close_requested => return Err(SockError::Shutdown),
+            TcpState::Established | TcpState::CloseWait => {}
+            TcpState::SynSent | TcpState::SynReceived => return Err(SockError::WouldBlock),
+            _ => return Err(tcb.error.unwrap_or(SockError::NotConnected)),
+        }
+        let len = data.len().min(BUFFER_SIZE - tcb.send_buf.len());
+        if len == 0 {
+            return Err(SockError::WouldBlock);
+        }
+        tcb.send_buf.extend(&data[..len]);
+        tcb.output(&mut self.outbox, now);
+        Ok(len)
+    }
+
+    /// Take the received data, returns 0 once the peer closes the connection.
+    pub fn recv(
+        &mut self,
+        handle: usize,
+        buf: &mut [u8],
+        now: Duration,
+    ) -> Result<usize, SockError> {
+        let tcb = self
+            .sockets
+            .get_mut(&handle)
+            .ok_or(SockError::InvalidState)?;
+        if tcb.recv_buf.is_empty() {
+            return match tcb.state {
+                _ if tcb.peer_closed => Ok(0),
+                _ if tcb.error.is_some() => Err(tcb.error.unwrap()),
+                TcpState::Closed | TcpState::Listen => Err(SockError::NotConnected),
+                _ => Err(SockError::WouldBlock),
+            };
+        }
+        let len = buf.len().min(tcb.recv_buf.len());
+        for (dst, src) in buf.iter_mut().zip(tcb.recv_buf.drain(..len)) {
+            *dst = src;
+        }
+        // Tell the peer the window opens if it was too small to send a segment.
+        if tcb.rcv_wnd < tcb.mss as u32 && tcb.window() >= tcb.mss as u32 {
+            tcb.ack_needed = true;
+            tcb.output(&mut self.outbox, now);
+        }
+        Ok(len)
+    }
+
+    /// Close the sending side, FIN is sent after the queued data.
+    pub fn shutdown(&mut self, handle: usize, now: Duration) -> Result<(), SockError> {
+        let tcb = self
+            .sockets
+            .get_mut(&handle)
+            .ok_or(SockError::InvalidState)?;
+        match tcb.state {
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {
+                tcb.close_requested = true;
+                tcb.output(&mut self.outbox, now);
+                Ok(())
+            }
+            TcpState::FinWait1
+            | TcpState::FinWait2
+            | TcpState::Closing
+            | TcpState::LastAck
+            | TcpState::TimeWait => Ok(()),
+            _ => Err(SockError::NotConnected),
+        }
+    }
+
+    /// Close the socket, the connection is closed gracefully in the background.
+    pub fn close(&mut self, handle: usize, now: Duration) {
+        let tcb = match self.sockets.get_mut(&handle) {
+            Some(tcb) => tcb,
+            None => return,
+        };
+        tcb.orphan = true;
+        match tcb.state {
+            TcpState::Listen => {
+                // Reset the connections never accepted.
+                let children: Vec<usize> = tcb.accept_queue.drain(..).collect();
+                for child in children {
+                    self.abort(child);
+                }
+                let orphans: Vec<usize> = self
+                    .sockets
+                    .iter()
+                    .filter(|(_, x)| x.parent == Some(handle))
+                    .map(|(handle, _)| *handle)
+                    .collect();
+                for child in orphans {
+                    self.abort(child);
+                }
+                self.sockets.remove(&handle);
+            }
+            TcpState::Closed | TcpState::SynSent => {
+                self.sockets.remove(&handle);
+            }
+            _ => {
+                let _ = self.shutdown(handle, now);
+                if let Some(tcb) = self.sockets.get_mut(&handle) {
+                    tcb.start_fin_wait_2_timer(now);
+                }
+            }
+        }
+    }
+
+    /// Reset the connection and free it.
+    fn abort(&mut self, handle: usize) {
+        if let Some(mut tcb) = self.sockets.remove(&handle) {
+            let segment = tcb.segment(tcb.snd_nxt, FLAG_RST | FLAG_ACK, &[]);
+            self.outbox.push(segment);
+        }
+    }
+
+    pub fn state(&self, handle: usize) -> Option<TcpState> {
+        self.sockets.get(&handle).map(|x| x.state)
+    }
+
+    pub fn local_addr(&self, handle: usize) -> Option<SocketAddr> {
+        self.sockets.get(&handle).map(|x| x.local)
+    }
+
+    pub fn remote_addr(&self, handle: usize) -> Option<SocketAddr> {
+        self.sockets
+            .get(&handle)
+            .filter(|x| !matches!(x.state, TcpState::Closed | TcpState::Listen))
+            .map(|x| x.remote)
+    }
+
+    /// Take the error of the socket.
+    pub fn take_error(&mut self, handle: usize) -> Option<SockError> {
+        self.sockets.get_mut(&handle).and_then(|x| x.error.take())
+    }
+
+    pub fn has_error(&self, handle: usize) -> bool {
+        self.sockets.get(&handle).is_some_and(|x| x.error.is_some())
+    }
+
+    /// Get the length of the received data.
+    pub fn recv_queued(&self, handle: usize) -> usize {
+        self.sockets.get(&handle).map_or(0, |x| x.recv_buf.len())
+    }
+
+    /// The socket has data, a connection to accept, EOF or an error.
+    pub fn readable(&self, handle: usize) -> bool {
+        self.sockets.get(&handle).is_some_and(|x| {
+            !x.recv_buf.is_empty()
+                || !x.accept_queue.is_empty()
+                || x.peer_closed
+                || x.error.is_some()
+        })
+    }
+
+    /// The socket can queue more data, or the connection fails.
+    pub fn writable(&self, handle: usize) -> bool {
+        self.sockets.get(&handle).is_some_and(|x| match x.state {
+            TcpState::Established | TcpState::CloseWait => {
+                !x.close_requested && x.send_buf.len() < BUFFER_SIZE
+            }
+            _ => x.error.is_some(),
+        })
+    }
+
+    /// Handle a received segment.
+    pub fn input(
+        &mut self,
+        src: IpAddr,
+        dst: IpAddr,
+        packet: &[u8],
+        csum_valid: bool,
+        now: Duration,
+    ) {
+        if !csum_valid && checksum::transport(src, dst, ipv4::PROTOCOL_TCP, packet) != 0 {
+            debug!("tcp: drop a segment with bad checksum from {}", src);
+            return;
+        }
+        let (header, payload) = match TcpHeader::parse(packet) {
+            Some(x) => x,
+            None => return,
+        };
+        let remote = SocketAddr::new(src, header.src_port);
+        let local = SocketAddr::new(dst, header.dst_port);
+
+        let connection = self.sockets.iter().find(|(_, x)| {
+            !matches!(x.state, TcpState::Closed | TcpState::Listen)
+                && x.local == local
+                && x.remote == remote
+        });
+        let listener = self.sockets.iter().find(|(_, x)| {
+            x.state == TcpState::Listen
+                && x.local.port == local.port
+                && x.local.addr.accepts(local.addr)
+        });
+        match (connection, listener) {
+            (Some((&handle, _)), _) => self.process(handle, &header, payload, now),
+            (None, Some((&handle, _))) => self.listen_input(handle, local, remote, &header, now),
+            (None, None) => {
+                if header.flags & FLAG_RST == 0 {
+                    self.outbox.push(reset(remote, local, &header, payload));
+                }
+            }
+        }
+        self.collect();
+    }
+
+    /// Handle a segment to the listener, a SYN creates a new connection.
+    fn listen_input(
+        &mut self,
+        handle: usize,
+        local: SocketAddr,
+        remote: SocketAddr,
+        header: &TcpHeader,
+        now: Duration,
+    ) {
+        if header.flags & FLAG_RST != 0 {
+            return;
+        }
+        if header.flags & FLAG_ACK != 0 {
+            self.outbox.push(reset(remote, local, header, &[]));
+            return;
+        }
+        if header.flags & FLAG_SYN == 0 {
+            return;
+        }
+        let pending = self
+            .sockets
+            .values()
+            .filter(|x| x.parent == Some(handle))
+            .count();
+        if pending >= self.sockets[&handle].backlog {
+            debug!(
+                "tcp: {} drops the SYN from {}, backlog is full",
+                local, remote
+            );
+            return;
+        }
+        let mut tcb = Tcb::new();
+        tcb.state = TcpState::SynReceived;
+        tcb.local = local;
+        tcb.remote = remote;
+        tcb.parent = Some(handle);
+        tcb.passive = true;
+        tcb.iss = Self::iss(self.isn_key, local, remote, now);
+        tcb.snd_una = tcb.iss;
+        tcb.snd_nxt = tcb.iss;
+        tcb.snd_wnd = header.window as u32;
+        tcb.rcv_nxt = header.seq.wrapping_add(1);
+        tcb.mss = header
+            .mss
+            .unwrap_or(DEFAULT_MSS)
+            .min(local_mss(tcb.local.addr));
+        tcb.output(&mut self.outbox, now);
+        let child = self.create(false);
+        self.sockets.insert(child, tcb);
+    }
+
+    /// Handle a segment of the connection.
+    fn process(&mut self, handle: usize, header: &TcpHeader, payload: &[u8], now: Duration) {
+        let tcb = self.sockets.get_mut(&handle).unwrap();
+        let flags = header.flags;
+
+        if tcb.state == TcpState::SynSent {
+            let ack_ok = flags & FLAG_ACK != 0
+                && seq_lt(tcb.iss, header.ack)
+                && seq_le(header.ack, tcb.snd_nxt);
+            if flags & FLAG_ACK != 0 && !ack_ok {
+                if flags & FLAG_RST == 0 {
+                    let (remote, local) = (tcb.remote, tcb.local);
+                    self.outbox.push(reset(remote, local, header, payload));
+                }
+                return;
+            }
+            if flags & FLAG_RST != 0 {
+                if ack_ok {
+                    tcb.close_with(Some(SockError::ConnRefused));
+                    self.changed = true;
+                }
+                return;
+            }
+            if flags & FLAG_SYN == 0 {
+                return;
+            }
+            tcb.rcv_nxt = header.seq.wrapping_add(1);
+            tcb.mss = header
+                .mss
+                .unwrap_or(DEFAULT_MSS)
+                .min(local_mss(tcb.local.addr));
+            tcb.snd_wnd = header.window as u32;
+            if ack_ok {
+                tcb.snd_una = header.ack;
+                tcb.retransmit_at = None;
+                tcb.retries = 0;
+                tcb.state = TcpState::Established;
+                self.changed = true;
+            } else {
+                // Simultaneous open, SYN is sent again with ACK.
+                tcb.state = TcpState::SynReceived;
+                tcb.snd_nxt = tcb.iss;
+            }
+            tcb.ack_needed = true;
+            tcb.output(&mut self.outbox, now);
+            return;
+        }
+
+        // Check whether the segment is in the receive window.
+        let seq_len = header.seq_len(payload);
+        let wnd = tcb.rcv_wnd.max(1);
+        let in_window =
+            |seq: u32| seq_le(tcb.rcv_nxt, seq) && seq_lt(seq, tcb.rcv_nxt.wrapping_add(wnd));
+        let acceptable = match seq_len {
+            0 => header.seq == tcb.rcv_nxt || in_window(header.seq),
+            len => in_window(header.seq) || in_window(header.seq.wrapping_add(len - 1)),
+        } || (seq_lt(header.seq, tcb.rcv_nxt) && seq_len > 0);
+        if !acceptable {
+            if flags & FLAG_RST == 0 {
+                tcb.ack_needed = true;
+                tcb.output(&mut self.outbox, now);
+            }
+            return;
+        }
+
+        if flags & FLAG_RST != 0 {
+            if header.seq != tcb.rcv_nxt {
+                // Challenge the reset out of the expected sequence.
+                tcb.ack_needed = true;
+                tcb.output(&mut self.outbox, now);
+                return;
+            }
+            let error = match (tcb.state, tcb.parent) {
+                (TcpState::SynReceived, Some(_)) => {
+                    // Nobody knows the connection, free it.
+                    tcb.orphan = true;
+                    None
+                }
+                (TcpState::SynReceived, None) => Some(SockError::ConnRefused),
+                (TcpState::TimeWait | TcpState::LastAck | TcpState::Closing, _) => None,
+                _ => Some(SockError::ConnReset),
+            };
+            tcb.close_with(error);
+            tcb.recv_buf.clear();
+            self.changed = true;
+            return;
+        }
+
+        if flags & FLAG_SYN != 0 && header.seq != tcb.rcv_nxt.wrapping_sub(1) {
+            let segment = tcb.segment(tcb.snd_nxt, FLAG_RST | FLAG_ACK, &[]);
+            self.outbox.push(segment);
+            tcb.close_with(Some(SockError::ConnReset));
+            self.changed = true;
+            return;
+        }
+
+        if flags & FLAG_ACK == 0 {
+            return;
+        }
+        if tcb.state == TcpState::SynReceived {
+            if !(seq_lt(tcb.snd_una, header.ack) && seq_le(header.ack, tcb.snd_nxt)) {
+                let (remote, local) = (tcb.remote, tcb.local);
+                self.outbox.push(reset(remote, local, header, payload));
+                return;
+            }
+            tcb.state = TcpState::Established;
+            if let Some(parent) = tcb.parent {
+                if let Some(listener) = self.sockets.get_mut(&parent) {
+                    listener.accept_queue.push_back(handle);
+                }
+            }
+            self.changed = true;
+        }
+        let tcb = self.sockets.get_mut(&handle).unwrap();
+        if !tcb.process_ack(header, now) {
+            tcb.output(&mut self.outbox, now);
+            return;
+        }
+        let fin_acked = tcb.fin_seq.is_some_and(|fin| seq_lt(fin, tcb.snd_una));
+        match tcb.state {
+            TcpState::FinWait1 if fin_acked => {
+                tcb.state = TcpState::FinWait2;
+                tcb.start_fin_wait_2_timer(now);
+            }
+            TcpState::Closing if fin_acked => tcb.enter_time_wait(now),
+            TcpState::LastAck if fin_acked => {
+                tcb.close_with(None);
+                self.changed = true;
+                return;
+            }
+            _ => {}
+        }
+
+        // Take the data in order, the part received before is skipped.
+        let skip = tcb.rcv_nxt.wrapping_sub(header.seq) as usize;
+        let data_state = matches!(
+            tcb.state,
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+        );
+        let mut taken_all = true;
+        if data_state && !payload.is_empty() && skip < payload.len() {
+            let data = &payload[skip..];
+            let len = data.len().min(BUFFER_SIZE - tcb.recv_buf.len());
+            tcb.recv_buf.extend(&data[..len]);
+            tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(len as u32);
+            taken_all = len == data.len();
+            tcb.ack_needed = true;
+            self.changed = true;
+        } else if !payload.is_empty() {
+            tcb.ack_needed = true;
+        }
+
+        let fin_seq = header.seq.wrapping_add(payload.len() as u32);
+        if flags & FLAG_FIN != 0 && taken_all && fin_seq == tcb.rcv_nxt {
+            tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(1);
+            tcb.peer_closed = true;
+            tcb.ack_needed = true;
+            self.changed = true;
+            match tcb.state {
+                TcpState::SynReceived | TcpState::Established => tcb.state = TcpState::CloseWait,
+                TcpState::FinWait1 if fin_acked => tcb.enter_time_wait(now),
+                TcpState::FinWait1 => tcb.state = TcpState::Closing,
+                TcpState::FinWait2 | TcpState::TimeWait => tcb.enter_time_wait(now),
+                _ => {}
+            }
+        } else if flags & FLAG_FIN != 0 && tcb.state == TcpState::TimeWait {
+            // The peer retransmits FIN, our ACK is lost.
+            tcb.ack_needed = true;
+            tcb.enter_time_wait(now);
+        }
+        tcb.output(&mut self.outbox, now);
+    }
+
+    /// Handle the timers of the connections.
+    pub fn poll(&mut self, now: Duration) {
+        let mut aborted = Vec::new();
+        for (handle, tcb) in self.sockets.iter_mut() {
+            if tcb.close_at.is_some_and(|x| x <= now) {
+                tcb.close_at = None;
+                tcb.close_with(None);
+                self.changed = true;
+                continue;
+            }
+            if tcb.persist_at.is_some_and(|x| x <= now) {
+                tcb.probe_window(&mut self.outbox, now);
+            }
+            if !matches!(tcb.retransmit_at, Some(x) if x <= now) {
+                continue;
+            }
+            tcb.retries += 1;
+            if tcb.retries > MAX_RETRIES {
+                debug!("tcp: {} -> {} timed out", tcb.local, tcb.remote);
+                let segment = tcb.segment(tcb.snd_nxt, FLAG_RST | FLAG_ACK, &[]);
+                self.outbox.push(segment);
+                match tcb.parent {
+                    Some(_) => aborted.push(*handle),
+                    None => tcb.close_with(Some(SockError::TimedOut)),
+                }
+                self.changed = true;
+                continue;
+            }
+            // Go back to the first unacknowledged byte.
+            tcb.rto = (tcb.rto * 2).min(MAX_RTO);
+            tcb.rtt_sample = None;
+            tcb.retransmit_at = None;
+            tcb.snd_nxt = tcb.snd_una;
+            if tcb.fin_seq.is_some_and(|fin| seq_le(tcb.snd_nxt, fin)) {
+                tcb.fin_seq = None;
+            }
+            tcb.output(&mut self.outbox, now);
+        }
+        for handle in aborted {
+            self.sockets.remove(&handle);
+        }
+        self.collect();
+    }
+
+    /// Free the closed connections of the closed sockets.
+    fn collect(&mut self) {
+        self.sockets
+            .retain(|_, x| !(x.orphan && x.state == TcpState::Closed));
+    }
+}
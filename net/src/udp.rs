@@ -0,0 +1,215 @@
+//! User datagram protocol.
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+
+use crate::{
+    addr::{IpAddr, Ipv6Addr, SocketAddr},
+    checksum, ipv4, SockError,
+};
+
+pub const HEADER_LEN: usize = 8;
+
+/// Max bytes of the datagrams waiting in a socket.
+const RECV_BUFFER_SIZE: usize = 0x40000;
+
+/// Parse the datagram, returns the source port, the destination port and the payload.
+///
+/// Returns None for broken datagrams and bad checksums.
+pub fn parse(
+    src: IpAddr,
+    dst: IpAddr,
+    packet: &[u8],
+    csum_valid: bool,
+) -> Option<(u16, u16, &[u8])> {
+    if packet.len() < HEADER_LEN {
+        return None;
+    }
+    let len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+    if len < HEADER_LEN || len > packet.len() {
+        return None;
+    }
+    let packet = &packet[..len];
+    // Zero checksum means the sender doesn't compute it, IPv6 requires it.
+    let has_checksum = packet[6] != 0 || packet[7] != 0;
+    if !has_checksum && !src.is_ipv4() {
+        return None;
+    }
+    if has_checksum && !csum_valid && checksum::transport(src, dst, ipv4::PROTOCOL_UDP, packet) != 0
+    {
+        debug!("udp: drop a datagram with bad checksum from {}", src);
+        return None;
+    }
+    Some((
+        u16::from_be_bytes([packet[0], packet[1]]),
+        u16::from_be_bytes([packet[2], packet[3]]),
+        &packet[HEADER_LEN..],
+    ))
+}
+
+/// Build the datagram with the checksum.
+pub fn build(local: SocketAddr, remote: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let len = (HEADER_LEN + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(len as usize);
+    packet.extend_from_slice(&local.port.to_be_bytes());
+    packet.extend_from_slice(&remote.port.to_be_bytes());
+    packet.extend_from_slice(&len.to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(payload);
+    let sum = match checksum::transport(local.addr, remote.addr, ipv4::PROTOCOL_UDP, &packet) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    packet[6..8].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+#[derive(Default)]
+struct UdpState {
+    local: SocketAddr,
+    remote: Option<SocketAddr>,
+    /// Received datagrams with their sources.
+    rx: VecDeque<(SocketAddr, Vec<u8>)>,
+    rx_bytes: usize,
+}
+
+/// The UDP sockets.
+#[derive(Default)]
+pub struct Udp {
+    sockets: BTreeMap<usize, UdpState>,
+    next_handle: usize,
+    next_port: u16,
+    /// Sockets receive datagrams, their waiters should be woken up.
+    pub changed: bool,
+}
+
+impl Udp {
+    fn socket(&mut self, handle: usize) -> Result<&mut UdpState, SockError> {
+        self.sockets.get_mut(&handle).ok_or(SockError::InvalidState)
+    }
+
+    fn port_in_use(&self, port: u16) -> bool {
+        self.sockets.values().any(|x| x.local.port == port)
+    }
+
+    /// Create a socket, `ipv6` sockets are bound to the IPv6 wildcard address by default.
+    pub fn create(&mut self, ipv6: bool) -> usize {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        let mut socket = UdpState::default();
+        if ipv6 {
+            socket.local.addr = Ipv6Addr::UNSPECIFIED.into();
+        }
+        self.sockets.insert(handle, socket);
+        handle
+    }
+
+    /// Bind the socket to an ephemeral port of its address.
+    fn autobind(&mut self, handle: usize) -> Result<(), SockError> {
+        let addr = self.socket(handle)?.local.addr;
+        self.bind(handle, SocketAddr::new(addr, 0))
+    }
+
+    pub fn bind(&mut self, handle: usize, mut local: SocketAddr) -> Result<(), SockError> {
+        if self.socket(handle)?.local.port != 0 {
+            return Err(SockError::InvalidParam);
+        }
+        if local.port == 0 {
+            let sockets = &self.sockets;
+            local.port = crate::ephemeral_port(&mut self.next_port, |port| {
+                sockets.values().any(|x| x.local.port == port)
+            })?;
+        } else if self.port_in_use(local.port) {
+            return Err(SockError::AddrInUse);
+        }
+        self.socket(handle)?.local = local;
+        Ok(())
+    }
+
+    /// Set the default destination and only receive from it.
+    pub fn connect(&mut self, handle: usize, remote: SocketAddr) -> Result<(), SockError> {
+        if self.socket(handle)?.local.port == 0 {
+            self.autobind(handle)?;
+        }
+        self.socket(handle)?.remote = Some(remote);
+        Ok(())
+    }
+
+    /// Get the addresses of a datagram to send, the socket is bound if it isn't.
+    pub fn endpoints(
+        &mut self,
+        handle: usize,
+        remote: Option<SocketAddr>,
+    ) -> Result<(SocketAddr, SocketAddr), SockError> {
+        let remote = remote
+            .or(self.socket(handle)?.remote)
+            .ok_or(SockError::NotConnected)?;
+        if self.socket(handle)?.local.port == 0 {
+            self.autobind(handle)?;
+        }
+        Ok((self.socket(handle)?.local, remote))
+    }
+
+    /// Take a datagram, the part exceeding the buffer is discarded.
+    ///
+    /// Returns the length of the datagram and its source.
+    pub fn recv(
+        &mut self,
+        handle: usize,
+        buf: &mut [u8],
+    ) -> Result<(usize, SocketAddr), SockError> {
+        let socket = self.socket(handle)?;
+        let (src, data) = socket.rx.pop_front().ok_or(SockError::WouldBlock)?;
+        socket.rx_bytes -= data.len();
+        let len = buf.len().min(data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((data.len(), src))
+    }
+
+    pub fn close(&mut self, handle: usize) {
+        self.sockets.remove(&handle);
+    }
+
+    pub fn local_addr(&self, handle: usize) -> Option<SocketAddr> {
+        self.sockets.get(&handle).map(|x| x.local)
+    }
+
+    pub fn remote_addr(&self, handle: usize) -> Option<SocketAddr> {
+        self.sockets.get(&handle).and_then(|x| x.remote)
+    }
+
+    /// Get the length of the next datagram.
+    pub fn recv_queued(&self, handle: usize) -> usize {
+        self.sockets
+            .get(&handle)
+            .and_then(|x| x.rx.front())
+            .map_or(0, |(_, data)| data.len())
+    }
+
+    pub fn readable(&self, handle: usize) -> bool {
+        self.sockets.get(&handle).is_some_and(|x| !x.rx.is_empty())
+    }
+
+    /// Deliver the datagram to the socket, returns false if no socket takes it.
+    pub fn input(&mut self, src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> bool {
+        let socket = self.sockets.values_mut().find(|x| {
+            x.local.port == dst.port
+                && (x.local.addr.accepts(dst.addr) || dst.addr.is_broadcast())
+                && (x.remote.is_none() || x.remote == Some(src))
+        });
+        let socket = match socket {
+            Some(socket) => socket,
+            None => return false,
+        };
+        if socket.rx_bytes + payload.len() > RECV_BUFFER_SIZE {
+            debug!("udp: {} drops a datagram, the buffer is full", socket.local);
+            return true;
+        }
+        socket.rx_bytes += payload.len();
+        socket.rx.push_back((src, payload.to_vec()));
+        self.changed = true;
+        true
+    }
+}